@@ -0,0 +1,33 @@
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/*
+ * Helpers of the benchmarks, the #[ignore]d tests that time a structure
+ * against a lock based equivalent. Run them in release mode, e.g.
+ * `cargo test --release -- --ignored --nocapture bench_`.
+ */
+
+
+/* Number of threads to run, at least 4 so that there is contention */
+pub fn bench_threads() -> usize {
+    thread::available_parallelism().map_or(4, |n| n.get()).max(4)
+}
+
+
+/* Run @f on @threads threads at once and return the elapsed time */
+pub fn bench_run<F: Fn() + Send + Sync + 'static>(threads: usize, f: F) -> Duration {
+    let f = Arc::new(f);
+    let start = Instant::now();
+    let handles: Vec<_> = (0..threads)
+        .map(|_| {
+            let f = f.clone();
+            thread::spawn(move || f())
+        })
+        .collect();
+
+    for handle in handles {
+        handle.join().unwrap();
+    }
+    start.elapsed()
+}
@@ -0,0 +1,941 @@
+use crate::errno::{EBUSY, EINVAL, ENOMEM, EOVERFLOW, ERANGE};
+
+pub const BITS_PER_LONG: usize = usize::BITS as usize;
+pub const PAGE_SIZE: usize = 4096;
+
+/* Bits printed per comma separated group by bitmap_print_to_pagebuf() */
+const CHUNKSZ: usize = 32;
+
+
+pub const fn bits_to_longs(nbits: usize) -> usize {
+    nbits.div_ceil(BITS_PER_LONG)
+}
+
+
+const fn bit_word(nr: usize) -> usize {
+    nr / BITS_PER_LONG
+}
+
+
+const fn bit_mask(nr: usize) -> usize {
+    1 << (nr % BITS_PER_LONG)
+}
+
+
+const fn bitmap_first_word_mask(start: usize) -> usize {
+    !0 << (start % BITS_PER_LONG)
+}
+
+
+const fn bitmap_last_word_mask(nbits: usize) -> usize {
+    !0 >> ((BITS_PER_LONG - nbits % BITS_PER_LONG) % BITS_PER_LONG)
+}
+
+
+/**
+ * declare_bitmap - the type of a fixed-size bitmap of @nbits bits
+ *
+ * Works around const generics being unable to compute the number of
+ * words from the number of bits, e.g. `let map = <declare_bitmap!(100)>::new();`.
+ */
+#[macro_export]
+macro_rules! declare_bitmap {
+    ($nbits:expr) => {
+        $crate::bitmap::FixedBitmap<{ $nbits }, { $crate::bitmap::bits_to_longs($nbits) }>
+    };
+}
+
+
+/**
+ * trait BitmapOps - operations shared by both bitmap flavors
+ *
+ * Implementors only provide the backing words and the number of valid
+ * bits; every kernel bitmap operation is built on top of them. Bits past
+ * nbits in the last word are kept clear.
+ *
+ * The find_* functions follow the kernel and return nbits when no bit
+ * is found.
+ */
+#[allow(dead_code)]
+pub trait BitmapOps {
+    fn bitmap_words(&self) -> &[usize];
+    fn bitmap_words_mut(&mut self) -> &mut [usize];
+    fn nbits(&self) -> usize;
+
+
+    /* Clear the bits past nbits after a whole-word operation */
+    fn bitmap_clear_tail(&mut self) {
+        let nbits = self.nbits();
+        if !nbits.is_multiple_of(BITS_PER_LONG) {
+            self.bitmap_words_mut()[bit_word(nbits)] &= bitmap_last_word_mask(nbits);
+        }
+    }
+
+
+    /**
+     * set_bit - Set a bit in memory
+     * @nr: the bit to set
+     */
+    fn set_bit(&mut self, nr: usize) {
+        assert!(nr < self.nbits());
+        self.bitmap_words_mut()[bit_word(nr)] |= bit_mask(nr);
+    }
+
+
+    /**
+     * clear_bit - Clears a bit in memory
+     * @nr: Bit to clear
+     */
+    fn clear_bit(&mut self, nr: usize) {
+        assert!(nr < self.nbits());
+        self.bitmap_words_mut()[bit_word(nr)] &= !bit_mask(nr);
+    }
+
+
+    /**
+     * change_bit - Toggle a bit in memory
+     * @nr: Bit to change
+     */
+    fn change_bit(&mut self, nr: usize) {
+        assert!(nr < self.nbits());
+        self.bitmap_words_mut()[bit_word(nr)] ^= bit_mask(nr);
+    }
+
+
+    /**
+     * test_bit - Determine whether a bit is set
+     * @nr: bit number to test
+     */
+    fn test_bit(&self, nr: usize) -> bool {
+        nr < self.nbits() && self.bitmap_words()[bit_word(nr)] & bit_mask(nr) != 0
+    }
+
+
+    /**
+     * test_and_set_bit - Set a bit and return its old value
+     * @nr: Bit to set
+     */
+    fn test_and_set_bit(&mut self, nr: usize) -> bool {
+        let old = self.test_bit(nr);
+        self.set_bit(nr);
+        old
+    }
+
+
+    /**
+     * test_and_clear_bit - Clear a bit and return its old value
+     * @nr: Bit to clear
+     */
+    fn test_and_clear_bit(&mut self, nr: usize) -> bool {
+        let old = self.test_bit(nr);
+        self.clear_bit(nr);
+        old
+    }
+
+
+    /* Common helper for find_next_bit() and find_next_zero_bit() */
+    fn find_next(&self, size: usize, start: usize, invert: bool) -> usize {
+        let words = self.bitmap_words();
+        let size = size.min(self.nbits());
+        let fetch = |idx: usize| if invert { !words[idx] } else { words[idx] };
+
+        if start >= size {
+            return size;
+        }
+
+        let mut idx = bit_word(start);
+        let mut tmp = fetch(idx) & bitmap_first_word_mask(start);
+        while tmp == 0 {
+            idx += 1;
+            if idx * BITS_PER_LONG >= size {
+                return size;
+            }
+            tmp = fetch(idx);
+        }
+        (idx * BITS_PER_LONG + tmp.trailing_zeros() as usize).min(size)
+    }
+
+
+    /**
+     * find_next_bit - find the next set bit in a memory region
+     * @offset: The bitnumber to start searching at
+     *
+     * Return: the bit number for the next set bit
+     * If no bits are set, returns nbits.
+     */
+    fn find_next_bit(&self, offset: usize) -> usize {
+        self.find_next(self.nbits(), offset, false)
+    }
+
+
+    /**
+     * find_next_zero_bit - find the next cleared bit in a memory region
+     * @offset: The bitnumber to start searching at
+     *
+     * Return: the bit number of the next zero bit
+     * If no bits are zero, returns nbits.
+     */
+    fn find_next_zero_bit(&self, offset: usize) -> usize {
+        self.find_next(self.nbits(), offset, true)
+    }
+
+
+    /**
+     * find_first_bit - find the first set bit in a memory region
+     *
+     * Return: the bit number of the first set bit.
+     * If no bits are set, returns nbits.
+     */
+    fn find_first_bit(&self) -> usize {
+        self.find_next_bit(0)
+    }
+
+
+    /**
+     * find_first_zero_bit - find the first cleared bit in a memory region
+     *
+     * Return: the bit number of the first cleared bit.
+     * If no bits are zero, returns nbits.
+     */
+    fn find_first_zero_bit(&self) -> usize {
+        self.find_next_zero_bit(0)
+    }
+
+
+    /**
+     * find_last_bit - find the last set bit in a memory region
+     *
+     * Return: the index of the last set bit, or nbits if no bits are set.
+     */
+    fn find_last_bit(&self) -> usize {
+        let nbits = self.nbits();
+        let words = self.bitmap_words();
+
+        for idx in (0..bits_to_longs(nbits)).rev() {
+            let mut val = words[idx];
+            if idx == bit_word(nbits.saturating_sub(1)) {
+                val &= bitmap_last_word_mask(nbits);
+            }
+            if val != 0 {
+                return idx * BITS_PER_LONG + (BITS_PER_LONG - 1 - val.leading_zeros() as usize);
+            }
+        }
+        nbits
+    }
+
+
+    /**
+     * for_each_set_bit - iterate over every set bit in a memory region
+     */
+    fn for_each_set_bit(&self) -> impl Iterator<Item = usize> + '_ {
+        let mut bit = self.find_first_bit();
+
+        std::iter::from_fn(move || {
+            if bit >= self.nbits() {
+                return None;
+            }
+            let cur = bit;
+            bit = self.find_next_bit(bit + 1);
+            Some(cur)
+        })
+    }
+
+
+    fn bitmap_zero(&mut self) {
+        self.bitmap_words_mut().fill(0);
+    }
+
+
+    fn bitmap_fill(&mut self) {
+        self.bitmap_words_mut().fill(!0);
+        self.bitmap_clear_tail();
+    }
+
+
+    fn bitmap_copy<B: BitmapOps + ?Sized>(&mut self, src: &B) {
+        let n = bits_to_longs(self.nbits());
+        self.bitmap_words_mut()[..n].copy_from_slice(&src.bitmap_words()[..n]);
+        self.bitmap_clear_tail();
+    }
+
+
+    /* Common helper for the two-source bitmap operations, returns whether dst is non-zero */
+    fn bitmap_op<A, B, F>(&mut self, src1: &A, src2: &B, op: F) -> bool
+    where
+        A: BitmapOps + ?Sized,
+        B: BitmapOps + ?Sized,
+        F: Fn(usize, usize) -> usize,
+    {
+        let n = bits_to_longs(self.nbits());
+        let (s1, s2) = (&src1.bitmap_words()[..n], &src2.bitmap_words()[..n]);
+
+        for (k, dst) in self.bitmap_words_mut()[..n].iter_mut().enumerate() {
+            *dst = op(s1[k], s2[k]);
+        }
+        self.bitmap_clear_tail();
+        !self.bitmap_empty()
+    }
+
+
+    /**
+     * bitmap_and - *dst = *src1 & *src2
+     *
+     * Return: true if the result is non-zero.
+     */
+    fn bitmap_and<A: BitmapOps + ?Sized, B: BitmapOps + ?Sized>(&mut self, src1: &A, src2: &B) -> bool {
+        self.bitmap_op(src1, src2, |a, b| a & b)
+    }
+
+
+    /**
+     * bitmap_or - *dst = *src1 | *src2
+     */
+    fn bitmap_or<A: BitmapOps + ?Sized, B: BitmapOps + ?Sized>(&mut self, src1: &A, src2: &B) {
+        self.bitmap_op(src1, src2, |a, b| a | b);
+    }
+
+
+    /**
+     * bitmap_xor - *dst = *src1 ^ *src2
+     */
+    fn bitmap_xor<A: BitmapOps + ?Sized, B: BitmapOps + ?Sized>(&mut self, src1: &A, src2: &B) {
+        self.bitmap_op(src1, src2, |a, b| a ^ b);
+    }
+
+
+    /**
+     * bitmap_andnot - *dst = *src1 & ~(*src2)
+     *
+     * Return: true if the result is non-zero.
+     */
+    fn bitmap_andnot<A: BitmapOps + ?Sized, B: BitmapOps + ?Sized>(&mut self, src1: &A, src2: &B) -> bool {
+        self.bitmap_op(src1, src2, |a, b| a & !b)
+    }
+
+
+    /**
+     * bitmap_complement - *dst = ~(*src)
+     */
+    fn bitmap_complement<B: BitmapOps + ?Sized>(&mut self, src: &B) {
+        self.bitmap_op(src, src, |a, _| !a);
+    }
+
+
+    fn bitmap_equal<B: BitmapOps + ?Sized>(&self, other: &B) -> bool {
+        let nbits = self.nbits();
+        let n = bits_to_longs(nbits);
+
+        self.bitmap_words()[..n].iter().zip(&other.bitmap_words()[..n]).enumerate().all(|(k, (a, b))| {
+            let mask = if k == n - 1 { bitmap_last_word_mask(nbits) } else { !0 };
+            (a ^ b) & mask == 0
+        })
+    }
+
+
+    fn bitmap_empty(&self) -> bool {
+        self.find_first_bit() >= self.nbits()
+    }
+
+
+    fn bitmap_full(&self) -> bool {
+        self.find_first_zero_bit() >= self.nbits()
+    }
+
+
+    /**
+     * bitmap_weight - count the number of set bits
+     */
+    fn bitmap_weight(&self) -> usize {
+        let nbits = self.nbits();
+        let n = bits_to_longs(nbits);
+
+        self.bitmap_words()[..n]
+            .iter()
+            .enumerate()
+            .map(|(k, w)| {
+                let mask = if k == n - 1 { bitmap_last_word_mask(nbits) } else { !0 };
+                (w & mask).count_ones() as usize
+            })
+            .sum()
+    }
+
+
+    /**
+     * bitmap_shift_right - logical right shift of the bits in a bitmap
+     * @src: source bitmap
+     * @shift: shift by this many bits
+     *
+     * Shifting right (dividing) means moving bits in the MS -> LS bit
+     * direction. Zeros are fed into the vacated MS positions and the
+     * LS bits shifted off the bottom are lost.
+     */
+    fn bitmap_shift_right<B: BitmapOps + ?Sized>(&mut self, src: &B, shift: usize) {
+        let nbits = self.nbits();
+        let lim = bits_to_longs(nbits);
+        let (off, rem) = (shift / BITS_PER_LONG, shift % BITS_PER_LONG);
+        let mut words = src.bitmap_words()[..lim].to_vec();
+
+        if !nbits.is_multiple_of(BITS_PER_LONG) {
+            words[lim - 1] &= bitmap_last_word_mask(nbits);
+        }
+
+        let dst = self.bitmap_words_mut();
+        for k in 0..lim {
+            if k + off >= lim {
+                dst[k] = 0;
+                continue;
+            }
+            let lower = words[k + off];
+            let upper = if k + off + 1 >= lim { 0 } else { words[k + off + 1] };
+            dst[k] = if rem == 0 { lower } else { lower >> rem | upper << (BITS_PER_LONG - rem) };
+        }
+    }
+
+
+    /**
+     * bitmap_shift_left - logical left shift of the bits in a bitmap
+     * @src: source bitmap
+     * @shift: shift by this many bits
+     *
+     * Shifting left (multiplying) means moving bits in the LS -> MS
+     * direction. Zeros are fed into the vacated LS bit positions
+     * and those MS bits shifted off the top are lost.
+     */
+    fn bitmap_shift_left<B: BitmapOps + ?Sized>(&mut self, src: &B, shift: usize) {
+        let lim = bits_to_longs(self.nbits());
+        let (off, rem) = (shift / BITS_PER_LONG, shift % BITS_PER_LONG);
+        let words = src.bitmap_words()[..lim].to_vec();
+
+        let dst = self.bitmap_words_mut();
+        for k in (0..lim).rev() {
+            if k < off {
+                dst[k] = 0;
+                continue;
+            }
+            let upper = words[k - off];
+            let lower = if rem == 0 || k == off { 0 } else { words[k - off - 1] >> (BITS_PER_LONG - rem) };
+            dst[k] = upper << rem | lower;
+        }
+        self.bitmap_clear_tail();
+    }
+
+
+    /* Common helper for bitmap_set() and bitmap_clear() */
+    fn bitmap_range(&mut self, start: usize, len: usize, set: bool) {
+        let end = start + len;
+        assert!(end <= self.nbits());
+
+        let words = self.bitmap_words_mut();
+        let mut pos = start;
+        while pos < end {
+            let word_end = ((bit_word(pos) + 1) * BITS_PER_LONG).min(end);
+            let mut mask = bitmap_first_word_mask(pos);
+            if !word_end.is_multiple_of(BITS_PER_LONG) {
+                mask &= bitmap_last_word_mask(word_end);
+            }
+            if set {
+                words[bit_word(pos)] |= mask;
+            } else {
+                words[bit_word(pos)] &= !mask;
+            }
+            pos = word_end;
+        }
+    }
+
+
+    /**
+     * bitmap_set - set @len bits starting at @start
+     */
+    fn bitmap_set(&mut self, start: usize, len: usize) {
+        self.bitmap_range(start, len, true);
+    }
+
+
+    /**
+     * bitmap_clear - clear @len bits starting at @start
+     */
+    fn bitmap_clear(&mut self, start: usize, len: usize) {
+        self.bitmap_range(start, len, false);
+    }
+
+
+    /**
+     * bitmap_find_next_zero_area - find a contiguous aligned zero area
+     * @start: The bitnumber to start searching at
+     * @nr: The number of zeroed bits we're looking for
+     * @align_mask: Alignment mask for zero area
+     *
+     * The @align_mask should be one less than a power of 2; the effect is
+     * that the bit offset of all zero areas this function finds is
+     * multiples of that power of 2. A @align_mask of 0 means no alignment
+     * is required.
+     *
+     * Return: the start of the area. If no area fits, the returned value
+     * is greater than nbits - @nr.
+     */
+    fn bitmap_find_next_zero_area(&self, start: usize, nr: usize, align_mask: usize) -> usize {
+        let size = self.nbits();
+        let mut start = start;
+
+        loop {
+            let index = (self.find_next_zero_bit(start) + align_mask) & !align_mask;
+            let end = index + nr;
+            if end > size {
+                return end;
+            }
+
+            let i = self.find_next(end, index, false);
+            if i < end {
+                start = i + 1;
+                continue;
+            }
+            return index;
+        }
+    }
+
+
+    /**
+     * bitmap_find_free_region - find a contiguous aligned mem region
+     * @order: region size (log base 2 of number of bits) to find
+     *
+     * Find a region of free (zero) bits of 2^@order bits, aligned on a
+     * 2^@order boundary, and allocate it.
+     *
+     * Return: the bit offset of the region, -EINVAL if @order is too large
+     * for a region size, or -ENOMEM if none is free.
+     */
+    fn bitmap_find_free_region(&mut self, order: u32) -> Result<usize, i32> {
+        let len = 1usize.checked_shl(order).ok_or(-EINVAL)?;
+        let mut pos: usize = 0;
+
+        while pos.checked_add(len).is_some_and(|end| end <= self.nbits()) {
+            if self.find_next(pos + len, pos, false) >= pos + len {
+                self.bitmap_set(pos, len);
+                return Ok(pos);
+            }
+            pos += len;
+        }
+        Err(-ENOMEM)
+    }
+
+
+    /**
+     * bitmap_release_region - release allocated bitmap region
+     * @pos: beginning of bit region to release
+     * @order: region size (log base 2 of number of bits) to release
+     *
+     * Does nothing if the region size or its end overflows, as no such
+     * region can have been allocated.
+     */
+    fn bitmap_release_region(&mut self, pos: usize, order: u32) {
+        let Some(len) = 1usize.checked_shl(order) else {
+            return;
+        };
+
+        if pos.checked_add(len).is_some() {
+            self.bitmap_clear(pos, len);
+        }
+    }
+
+
+    /**
+     * bitmap_allocate_region - allocate bitmap region
+     * @pos: beginning of bit region to allocate
+     * @order: region size (log base 2 of number of bits) to allocate
+     *
+     * Return: 0 on success, -EINVAL if the region doesn't fit in the
+     * bitmap, or -EBUSY if specified region wasn't free (not all bits
+     * were zero).
+     */
+    fn bitmap_allocate_region(&mut self, pos: usize, order: u32) -> Result<(), i32> {
+        let len = 1usize.checked_shl(order).ok_or(-EINVAL)?;
+
+        if pos.checked_add(len).is_none_or(|end| end > self.nbits()) {
+            return Err(-EINVAL);
+        }
+        if self.find_next(pos + len, pos, false) < pos + len {
+            return Err(-EBUSY);
+        }
+        self.bitmap_set(pos, len);
+        Ok(())
+    }
+
+
+    /**
+     * bitmap_parselist - convert list format ASCII string to bitmap
+     * @buf: read user string from this buffer
+     *
+     * Input format is a comma-separated list of decimal numbers and
+     * ranges. Consecutively set bits are shown as two hyphen-separated
+     * decimal numbers, the smallest and largest bit numbers set in
+     * the range.
+     * Optionally each range can be postfixed to denote that only parts of it
+     * should be set. The range will divided to groups of specific size.
+     * From each group will be used only defined amount of bits.
+     * Syntax: range:used_size/group_size
+     * Example: 0-1023:2/256 ==> 0,1,256,257,512,513,768,769
+     * The value 'N' can be used as a dynamically substituted token for the
+     * maximum allowed value; i.e (nmaskbits - 1).
+     *
+     * Return: 0 on success, -errno on invalid input strings. Error values:
+     *
+     *   - ``-EINVAL``: wrong region format
+     *   - ``-EINVAL``: invalid character in string
+     *   - ``-ERANGE``: bit number specified too large for mask
+     *   - ``-EOVERFLOW``: integer overflow in the input parameters
+     */
+    fn bitmap_parselist(&mut self, buf: &str) -> Result<(), i32> {
+        let nbits = self.nbits();
+
+        self.bitmap_zero();
+
+        let buf = buf.trim_end_matches(['\n', '\0']);
+        for region in buf.split(',') {
+            let region = region.trim();
+            if region.is_empty() {
+                continue;
+            }
+
+            let (range, pattern) = match region.split_once(':') {
+                Some((range, pattern)) => (range, Some(pattern)),
+                None => (region, None),
+            };
+            let (start, end) = match range.split_once('-') {
+                Some((start, end)) => (bitmap_getnum(start, nbits)?, bitmap_getnum(end, nbits)?),
+                None => {
+                    let bit = bitmap_getnum(range, nbits)?;
+                    (bit, bit)
+                }
+            };
+            let (off, group_len) = match pattern {
+                Some(pattern) => {
+                    let (off, group_len) = pattern.split_once('/').ok_or(-EINVAL)?;
+                    (bitmap_getnum(off, nbits)?, bitmap_getnum(group_len, nbits)?)
+                }
+                None => (end + 1, end + 1),
+            };
+
+            if start > end || group_len == 0 || off > group_len {
+                return Err(-EINVAL);
+            }
+            if end >= nbits {
+                return Err(-ERANGE);
+            }
+
+            let mut pos = start;
+            while pos <= end {
+                self.bitmap_set(pos, (end - pos + 1).min(off));
+                pos += group_len;
+            }
+        }
+        Ok(())
+    }
+
+
+    /**
+     * bitmap_print_to_pagebuf - convert bitmap to list or hex format ASCII string
+     * @list: indicates whether the bitmap must be list
+     *
+     * Output format is a comma-separated list of decimal numbers and
+     * ranges if list is specified or hex digits grouped into comma-separated
+     * sets of 8 digits/set, followed by a newline. The output never
+     * exceeds PAGE_SIZE - 1 bytes.
+     */
+    fn bitmap_print_to_pagebuf(&self, list: bool) -> String {
+        let nbits = self.nbits();
+        let mut buf = String::new();
+
+        if list {
+            let mut bit = self.find_first_bit();
+            while bit < nbits {
+                let end = self.find_next_zero_bit(bit + 1);
+                if !buf.is_empty() {
+                    buf.push(',');
+                }
+                if end - 1 == bit {
+                    buf.push_str(&bit.to_string());
+                } else {
+                    buf.push_str(&format!("{}-{}", bit, end - 1));
+                }
+                bit = self.find_next_bit(end);
+            }
+        } else if nbits > 0 {
+            let words = self.bitmap_words();
+            let mut chunksz = match nbits % CHUNKSZ {
+                0 => CHUNKSZ,
+                rem => rem,
+            };
+            let mut i = nbits.div_ceil(CHUNKSZ) * CHUNKSZ;
+
+            while i >= CHUNKSZ {
+                i -= CHUNKSZ;
+                let val = (words[bit_word(i)] >> (i % BITS_PER_LONG)) & (u32::MAX as usize >> (CHUNKSZ - chunksz));
+                if !buf.is_empty() {
+                    buf.push(',');
+                }
+                buf.push_str(&format!("{:0width$x}", val, width = chunksz.div_ceil(4)));
+                chunksz = CHUNKSZ;
+            }
+        }
+
+        buf.truncate(PAGE_SIZE - 2);
+        buf.push('\n');
+        buf
+    }
+}
+
+
+/* Parse one number of a bitmap_parselist() region, 'N' stands for nbits - 1 */
+fn bitmap_getnum(s: &str, nbits: usize) -> Result<usize, i32> {
+    if s == "N" {
+        return nbits.checked_sub(1).ok_or(-EINVAL);
+    }
+    if s.is_empty() || !s.bytes().all(|c| c.is_ascii_digit()) {
+        return Err(-EINVAL);
+    }
+    s.parse::<u32>().map(|n| n as usize).map_err(|_| -EOVERFLOW)
+}
+
+
+/**
+ * struct FixedBitmap - bitmap of NBITS bits stored inline
+ *
+ * LONGS must be bits_to_longs(NBITS), which declare_bitmap!() computes.
+ */
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct FixedBitmap<const NBITS: usize, const LONGS: usize> {
+    words: [usize; LONGS],
+}
+
+impl<const NBITS: usize, const LONGS: usize> Default for FixedBitmap<NBITS, LONGS> {
+    fn default() -> Self {
+        FixedBitmap::new()
+    }
+}
+
+#[allow(dead_code)]
+impl<const NBITS: usize, const LONGS: usize> FixedBitmap<NBITS, LONGS> {
+    pub const fn new() -> Self {
+        const { assert!(LONGS == bits_to_longs(NBITS), "FixedBitmap: LONGS does not match NBITS") };
+        FixedBitmap { words: [0; LONGS] }
+    }
+}
+
+impl<const NBITS: usize, const LONGS: usize> BitmapOps for FixedBitmap<NBITS, LONGS> {
+    fn bitmap_words(&self) -> &[usize] {
+        &self.words
+    }
+
+
+    fn bitmap_words_mut(&mut self) -> &mut [usize] {
+        &mut self.words
+    }
+
+
+    fn nbits(&self) -> usize {
+        NBITS
+    }
+}
+
+
+/**
+ * struct Bitmap - heap allocated bitmap sized at runtime
+ */
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Bitmap {
+    words: Box<[usize]>,
+    nbits: usize,
+}
+
+#[allow(dead_code)]
+impl Bitmap {
+    /**
+     * bitmap_zalloc - allocate a zeroed bitmap of @nbits bits
+     */
+    pub fn bitmap_zalloc(nbits: usize) -> Self {
+        Bitmap {
+            words: vec![0; bits_to_longs(nbits)].into_boxed_slice(),
+            nbits,
+        }
+    }
+}
+
+impl BitmapOps for Bitmap {
+    fn bitmap_words(&self) -> &[usize] {
+        &self.words
+    }
+
+
+    fn bitmap_words_mut(&mut self) -> &mut [usize] {
+        &mut self.words
+    }
+
+
+    fn nbits(&self) -> usize {
+        self.nbits
+    }
+}
+
+
+/* Sections for Unit tests */
+#[cfg(test)]
+mod tests {
+    use rand::Rng;
+
+    use super::*;
+
+    #[test]
+    fn test_bit_ops() {
+        let mut map = <declare_bitmap!(100)>::new();
+
+        assert!(!map.test_and_set_bit(3));
+        assert!(map.test_and_set_bit(3));
+        map.set_bit(99);
+        map.change_bit(64);
+        assert!(map.test_bit(64));
+        assert!(!map.test_bit(100));
+        assert!(map.test_and_clear_bit(64));
+        assert!(!map.test_bit(64));
+        map.clear_bit(99);
+        assert_eq!(map.bitmap_weight(), 1);
+    }
+
+
+    #[test]
+    fn test_find_bit() {
+        let mut map = Bitmap::bitmap_zalloc(200);
+
+        assert_eq!(map.find_first_bit(), 200);
+        assert_eq!(map.find_last_bit(), 200);
+        for nr in [5, 63, 64, 130, 199] {
+            map.set_bit(nr);
+        }
+        assert_eq!(map.find_first_bit(), 5);
+        assert_eq!(map.find_next_bit(6), 63);
+        assert_eq!(map.find_next_bit(65), 130);
+        assert_eq!(map.find_next_bit(200), 200);
+        assert_eq!(map.find_last_bit(), 199);
+        assert_eq!(map.for_each_set_bit().collect::<Vec<_>>(), vec![5, 63, 64, 130, 199]);
+
+        map.bitmap_fill();
+        assert!(map.bitmap_full());
+        assert_eq!(map.bitmap_weight(), 200);
+        map.clear_bit(150);
+        assert_eq!(map.find_first_zero_bit(), 150);
+        assert_eq!(map.find_next_zero_bit(151), 200);
+    }
+
+
+    #[test]
+    fn test_bitmap_logic() {
+        let mut a = <declare_bitmap!(70)>::new();
+        let mut b = Bitmap::bitmap_zalloc(70);
+        let mut dst = Bitmap::bitmap_zalloc(70);
+
+        a.bitmap_set(0, 40);
+        b.bitmap_set(30, 40);
+
+        assert!(dst.bitmap_and(&a, &b));
+        assert_eq!(dst.bitmap_print_to_pagebuf(true), "30-39\n");
+        dst.bitmap_or(&a, &b);
+        assert!(dst.bitmap_full());
+        dst.bitmap_xor(&a, &b);
+        assert_eq!(dst.bitmap_print_to_pagebuf(true), "0-29,40-69\n");
+        assert!(dst.bitmap_andnot(&a, &b));
+        assert_eq!(dst.bitmap_print_to_pagebuf(true), "0-29\n");
+        dst.bitmap_complement(&a);
+        assert_eq!(dst.bitmap_print_to_pagebuf(true), "40-69\n");
+
+        let mut c = Bitmap::bitmap_zalloc(70);
+        assert!(!c.bitmap_and(&dst, &a));
+        c.bitmap_copy(&a);
+        assert!(c.bitmap_equal(&a));
+    }
+
+
+    #[test]
+    fn test_bitmap_shift() {
+        let mut rng = rand::thread_rng();
+        let mut src = Bitmap::bitmap_zalloc(300);
+        let mut dst = Bitmap::bitmap_zalloc(300);
+
+        for _ in 0..100 {
+            src.set_bit(rng.gen_range(0..300));
+        }
+
+        for shift in [0, 1, 63, 64, 65, 130, 299, 300] {
+            dst.bitmap_shift_left(&src, shift);
+            for nr in 0..300 {
+                assert_eq!(dst.test_bit(nr), nr >= shift && src.test_bit(nr - shift));
+            }
+
+            dst.bitmap_shift_right(&src, shift);
+            for nr in 0..300 {
+                assert_eq!(dst.test_bit(nr), nr + shift < 300 && src.test_bit(nr + shift));
+            }
+        }
+    }
+
+
+    #[test]
+    fn test_bitmap_find_next_zero_area() {
+        let mut map = Bitmap::bitmap_zalloc(128);
+
+        map.bitmap_set(0, 10);
+        map.set_bit(20);
+        assert_eq!(map.bitmap_find_next_zero_area(0, 8, 0), 10);
+        assert_eq!(map.bitmap_find_next_zero_area(0, 16, 0), 21);
+        assert_eq!(map.bitmap_find_next_zero_area(0, 8, 15), 32);
+        assert!(map.bitmap_find_next_zero_area(0, 128, 0) > 0);
+
+        map.bitmap_clear(0, 10);
+        assert_eq!(map.bitmap_find_next_zero_area(0, 8, 0), 0);
+    }
+
+
+    #[test]
+    fn test_bitmap_region() {
+        let mut map = <declare_bitmap!(64)>::new();
+
+        assert_eq!(map.bitmap_find_free_region(4), Ok(0));
+        assert_eq!(map.bitmap_allocate_region(32, 4), Ok(()));
+        assert_eq!(map.bitmap_allocate_region(36, 2), Err(-EBUSY));
+        assert_eq!(map.bitmap_find_free_region(4), Ok(16));
+        assert_eq!(map.bitmap_find_free_region(4), Ok(48));
+        assert_eq!(map.bitmap_find_free_region(0), Err(-ENOMEM));
+
+        map.bitmap_release_region(32, 4);
+        assert_eq!(map.bitmap_find_free_region(3), Ok(32));
+        assert_eq!(map.bitmap_weight(), 56);
+
+        /* Orders past the width of a region size are rejected, not wrapped */
+        assert_eq!(map.bitmap_find_free_region(usize::BITS), Err(-EINVAL));
+        assert_eq!(map.bitmap_find_free_region(7), Err(-ENOMEM));
+        map.bitmap_release_region(0, usize::BITS);
+        map.bitmap_release_region(usize::MAX, 1);
+        assert_eq!(map.bitmap_weight(), 56);
+    }
+
+
+    #[test]
+    fn test_bitmap_parselist() {
+        let mut map = Bitmap::bitmap_zalloc(40);
+
+        assert_eq!(map.bitmap_parselist("0-3,8,12-15\n"), Ok(()));
+        assert_eq!(map.bitmap_print_to_pagebuf(true), "0-3,8,12-15\n");
+        assert_eq!(map.bitmap_print_to_pagebuf(false), "00,0000f10f\n");
+
+        assert_eq!(map.bitmap_parselist("0-39:2/8"), Ok(()));
+        assert_eq!(map.bitmap_print_to_pagebuf(true), "0-1,8-9,16-17,24-25,32-33\n");
+        assert_eq!(map.bitmap_parselist("30-N"), Ok(()));
+        assert_eq!(map.bitmap_print_to_pagebuf(true), "30-39\n");
+        assert_eq!(map.bitmap_parselist(""), Ok(()));
+        assert_eq!(map.bitmap_print_to_pagebuf(true), "\n");
+
+        assert_eq!(map.bitmap_parselist("5-2"), Err(-EINVAL));
+        assert_eq!(map.bitmap_parselist("1,a"), Err(-EINVAL));
+        assert_eq!(map.bitmap_parselist("0-3:5/4"), Err(-EINVAL));
+        assert_eq!(map.bitmap_parselist("40"), Err(-ERANGE));
+        assert_eq!(map.bitmap_parselist("99999999999"), Err(-EOVERFLOW));
+    }
+}
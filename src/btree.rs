@@ -0,0 +1,1009 @@
+use std::cmp::Ordering;
+use std::marker::PhantomData;
+use std::mem;
+
+use crate::errno::{EEXIST, ENOENT};
+
+/*
+ * B+Tree for ordered key/value lookups
+ *
+ * Port of lib/btree.c. A node holds its keys in one contiguous array,
+ * followed by one slot per key, and is sized to a couple of cachelines.
+ * Keys are arrays of longs compared most significant long first and are
+ * kept in descending order inside a node. Empty slots sit at the end of a
+ * node and carry an all-zero key.
+ *
+ * The key of an inner node slot is a lower bound of the keys found under
+ * that child. Nodes are not stolen from on removal, only merged with a
+ * neighbour, so the invariant is "no two neighbouring nodes can be merged"
+ * rather than "all nodes are at least half full".
+ */
+
+/* MAX(L1_CACHE_BYTES, 128) */
+pub const NODESIZE: usize = 128;
+pub const MAX_KEYLEN: usize = 2;
+
+const LONG_SIZE: usize = mem::size_of::<u64>();
+
+
+/**
+ * struct BtreeGeo - Shape of the nodes of a btree
+ * @keylen: number of longs making up a key
+ * @no_pairs: number of key/slot pairs in a node
+ * @no_longs: number of longs holding the keys of a node
+ */
+pub struct BtreeGeo {
+    pub keylen: usize,
+    pub no_pairs: usize,
+    pub no_longs: usize,
+}
+
+const fn btree_geo(keylen: usize) -> BtreeGeo {
+    let no_pairs = NODESIZE / LONG_SIZE / (keylen + 1);
+
+    BtreeGeo {
+        keylen,
+        no_pairs,
+        no_longs: keylen * no_pairs,
+    }
+}
+
+pub static BTREE_GEO32: BtreeGeo = btree_geo(1);
+pub static BTREE_GEO64: BtreeGeo = btree_geo(1);
+pub static BTREE_GEO128: BtreeGeo = btree_geo(2);
+
+
+#[derive(Default)]
+enum BtreeSlot<V> {
+    #[default]
+    Empty,
+    Val(V),
+    Node(Box<BtreeNode<V>>),
+}
+
+struct BtreeNode<V> {
+    keys: Box<[u64]>,
+    slots: Box<[BtreeSlot<V>]>,
+}
+
+impl<V> BtreeNode<V> {
+    fn btree_node_alloc(geo: &BtreeGeo) -> Box<Self> {
+        Box::new(BtreeNode {
+            keys: vec![0; geo.no_longs].into_boxed_slice(),
+            slots: (0..geo.no_pairs).map(|_| BtreeSlot::Empty).collect(),
+        })
+    }
+
+
+    fn bkey(&self, geo: &BtreeGeo, n: usize) -> &[u64] {
+        &self.keys[n * geo.keylen..(n + 1) * geo.keylen]
+    }
+
+
+    fn setkey(&mut self, geo: &BtreeGeo, n: usize, key: &[u64]) {
+        self.keys[n * geo.keylen..(n + 1) * geo.keylen].copy_from_slice(key);
+    }
+
+
+    fn clearpair(&mut self, geo: &BtreeGeo, n: usize) {
+        self.keys[n * geo.keylen..(n + 1) * geo.keylen].fill(0);
+        self.slots[n] = BtreeSlot::Empty;
+    }
+
+
+    /* Move the pair at @src over the one at @dst, leaving @src empty */
+    fn movepair(&mut self, geo: &BtreeGeo, dst: usize, src: usize) {
+        self.keys.copy_within(src * geo.keylen..(src + 1) * geo.keylen, dst * geo.keylen);
+        self.slots[dst] = mem::take(&mut self.slots[src]);
+    }
+
+
+    fn keycmp(&self, geo: &BtreeGeo, pos: usize, key: &[u64]) -> Ordering {
+        self.bkey(geo, pos).cmp(key)
+    }
+
+
+    fn bval(&self, n: usize) -> Option<&V> {
+        match &self.slots[n] {
+            BtreeSlot::Val(val) => Some(val),
+            _ => None,
+        }
+    }
+
+
+    fn child(&self, n: usize) -> Option<&BtreeNode<V>> {
+        match &self.slots[n] {
+            BtreeSlot::Node(node) => Some(node),
+            _ => None,
+        }
+    }
+
+
+    fn child_ptr(&mut self, n: usize) -> *mut BtreeNode<V> {
+        match &mut self.slots[n] {
+            BtreeSlot::Node(node) => &mut **node,
+            _ => panic!("btree: slot {} holds no child node", n),
+        }
+    }
+
+
+    /* First slot whose key is not greater than @key */
+    fn getpos(&self, geo: &BtreeGeo, key: &[u64]) -> usize {
+        (0..geo.no_pairs)
+            .find(|&i| self.keycmp(geo, i, key) != Ordering::Greater)
+            .unwrap_or(geo.no_pairs)
+    }
+
+
+    /* First empty slot from @start on */
+    fn getfill(&self, geo: &BtreeGeo, start: usize) -> usize {
+        (start..geo.no_pairs)
+            .find(|&i| matches!(self.slots[i], BtreeSlot::Empty))
+            .unwrap_or(geo.no_pairs)
+    }
+}
+
+
+fn keyzero(key: &[u64]) -> bool {
+    key.iter().all(|&long| long == 0)
+}
+
+
+fn dec_key(key: &mut [u64]) {
+    for long in key.iter_mut().rev() {
+        let val = *long;
+        *long = val.wrapping_sub(1);
+        if val != 0 {
+            break;
+        }
+    }
+}
+
+
+/**
+ * struct BtreeHead - btree head
+ * @node: the root node of the tree
+ * @height: current height of the tree, 0 for an empty tree
+ *
+ * Every call takes the &struct BtreeGeo the tree was built with, a key is
+ * a slice of geo.keylen longs. The typed wrappers below take care of both.
+ */
+pub struct BtreeHead<V> {
+    node: Option<Box<BtreeNode<V>>>,
+    height: usize,
+}
+
+impl<V> Default for BtreeHead<V> {
+    fn default() -> Self {
+        BtreeHead::btree_init()
+    }
+}
+
+#[allow(dead_code)]
+impl<V> BtreeHead<V> {
+    pub fn btree_init() -> Self {
+        BtreeHead { node: None, height: 0 }
+    }
+
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+
+    /**
+     * btree_last - get last entry in btree
+     * @geo: btree geometry
+     * @key: last key
+     *
+     * Returns the last entry in the btree, and sets @key to the key of that
+     * entry; returns %None if the tree is empty, in that case key is not
+     * changed.
+     */
+    pub fn btree_last(&self, geo: &BtreeGeo, key: &mut [u64]) -> Option<&V> {
+        let mut node = self.node.as_deref()?;
+
+        for _ in 1..self.height {
+            node = node.child(0)?;
+        }
+        let val = node.bval(0)?;
+        key.copy_from_slice(node.bkey(geo, 0));
+        Some(val)
+    }
+
+
+    fn btree_lookup_node(&self, geo: &BtreeGeo, key: &[u64]) -> Option<&BtreeNode<V>> {
+        let mut node = self.node.as_deref()?;
+
+        for _ in 1..self.height {
+            let i = node.getpos(geo, key);
+            if i == geo.no_pairs {
+                return None;
+            }
+            node = node.child(i)?;
+        }
+        Some(node)
+    }
+
+
+    /**
+     * btree_lookup - look up a key in the btree
+     * @geo: the btree geometry
+     * @key: the key to look up
+     *
+     * This function returns the value for the given key, or %None.
+     */
+    pub fn btree_lookup(&self, geo: &BtreeGeo, key: &[u64]) -> Option<&V> {
+        let node = self.btree_lookup_node(geo, key)?;
+
+        (0..geo.no_pairs)
+            .find(|&i| node.keycmp(geo, i, key) == Ordering::Equal)
+            .and_then(|i| node.bval(i))
+    }
+
+
+    /**
+     * btree_update - change value for the given key
+     * @geo: the btree geometry
+     * @key: the key to update
+     * @val: the value to change it to
+     *
+     * This function changes the value for the given key. It returns 0 on
+     * success or -ENOENT if the key could not be found.
+     */
+    pub fn btree_update(&mut self, geo: &BtreeGeo, key: &[u64], val: V) -> Result<(), i32> {
+        let mut node = self.node.as_deref_mut().ok_or(-ENOENT)?;
+
+        for _ in 1..self.height {
+            let i = node.getpos(geo, key);
+            match node.slots.get_mut(i) {
+                Some(BtreeSlot::Node(child)) => node = child,
+                _ => return Err(-ENOENT),
+            }
+        }
+
+        for i in 0..geo.no_pairs {
+            if node.keycmp(geo, i, key) == Ordering::Equal {
+                if let BtreeSlot::Val(old) = &mut node.slots[i] {
+                    *old = val;
+                    return Ok(());
+                }
+            }
+        }
+        Err(-ENOENT)
+    }
+
+
+    /**
+     * btree_get_prev - get previous entry
+     * @geo: btree geometry
+     * @key: pointer to key
+     *
+     * The function returns the next item right before the value pointed to
+     * by @key, and updates @key with its key, or returns %None when there
+     * is no item right before @key.
+     *
+     * The key of a parent node may be smaller than the smallest key of all
+     * its siblings. In such a case we cannot just return %None, as we have
+     * only proven that no key smaller than @key, but larger than this
+     * parent key exists. So we set @key to the parent key and retry. We
+     * have to use the smallest such parent key, which is the last parent
+     * key we encountered.
+     */
+    pub fn btree_get_prev(&self, geo: &BtreeGeo, key: &mut [u64]) -> Option<&V> {
+        let root = self.node.as_deref()?;
+        if keyzero(key) {
+            return None;
+        }
+
+        let mut buf = [0; MAX_KEYLEN];
+        let mut retry_key: Option<[u64; MAX_KEYLEN]> = None;
+        buf[..geo.keylen].copy_from_slice(key);
+
+        loop {
+            let cur = &mut buf[..geo.keylen];
+            dec_key(cur);
+
+            let mut node = Some(root);
+            for _ in 1..self.height {
+                let parent = node.unwrap();
+                let i = parent.getpos(geo, cur);
+                if i == geo.no_pairs {
+                    node = None;
+                    break;
+                }
+                node = parent.child(i);
+                if node.is_none() {
+                    break;
+                }
+                let mut bound = [0; MAX_KEYLEN];
+                bound[..geo.keylen].copy_from_slice(parent.bkey(geo, i));
+                retry_key = Some(bound);
+            }
+
+            if let Some(node) = node {
+                if let Some(i) = (0..geo.no_pairs).find(|&i| node.keycmp(geo, i, cur) != Ordering::Greater) {
+                    if let Some(val) = node.bval(i) {
+                        key.copy_from_slice(node.bkey(geo, i));
+                        return Some(val);
+                    }
+                }
+            }
+
+            /* miss */
+            buf = retry_key.take()?;
+        }
+    }
+
+
+    /* Locate the node at @level covering @key, lowering too large bounds on the way */
+    fn find_level(&mut self, geo: &BtreeGeo, key: &[u64], level: usize) -> *mut BtreeNode<V> {
+        let mut node: *mut BtreeNode<V> = &mut **self.node.as_mut().expect("btree: no root node");
+
+        for _ in level..self.height {
+            let parent = unsafe { &mut *node };
+            let mut i = parent.getpos(geo, key);
+
+            if i == geo.no_pairs || matches!(parent.slots[i], BtreeSlot::Empty) {
+                /* right-most key is too large, update it */
+                assert!(i > 0, "btree: empty inner node");
+                i -= 1;
+                parent.setkey(geo, i, key);
+            }
+            node = parent.child_ptr(i);
+        }
+        node
+    }
+
+
+    fn btree_grow(&mut self, geo: &BtreeGeo) {
+        let mut node = BtreeNode::btree_node_alloc(geo);
+
+        if let Some(old) = self.node.take() {
+            let fill = old.getfill(geo, 0);
+            node.setkey(geo, 0, old.bkey(geo, fill - 1));
+            node.slots[0] = BtreeSlot::Node(old);
+        }
+        self.node = Some(node);
+        self.height += 1;
+    }
+
+
+    fn btree_shrink(&mut self, geo: &BtreeGeo) {
+        if self.height <= 1 {
+            return;
+        }
+
+        let mut node = self.node.take().unwrap();
+        assert!(node.getfill(geo, 0) <= 1);
+        match mem::take(&mut node.slots[0]) {
+            BtreeSlot::Node(child) => self.node = Some(child),
+            _ => unreachable!("btree: inner node without child"),
+        }
+        self.height -= 1;
+    }
+
+
+    fn btree_insert_level(&mut self, geo: &BtreeGeo, key: &[u64], val: BtreeSlot<V>, level: usize) -> Result<(), i32> {
+        if self.height < level {
+            self.btree_grow(geo);
+        }
+
+        loop {
+            let node = self.find_level(geo, key, level);
+            let (pos, fill) = unsafe {
+                let pos = (*node).getpos(geo, key);
+                (pos, (*node).getfill(geo, pos))
+            };
+            /* two identical keys are not allowed */
+            if pos < fill && unsafe { (*node).keycmp(geo, pos, key) } == Ordering::Equal {
+                return Err(-EEXIST);
+            }
+
+            if fill == geo.no_pairs {
+                /* need to split node */
+                let mut new = BtreeNode::btree_node_alloc(geo);
+                let new_ptr: *mut BtreeNode<V> = &mut *new;
+                let mut split = [0; MAX_KEYLEN];
+
+                split[..geo.keylen].copy_from_slice(unsafe { (*node).bkey(geo, fill / 2 - 1) });
+                self.btree_insert_level(geo, &split[..geo.keylen], BtreeSlot::Node(new), level + 1)?;
+
+                /* Nodes live in boxes, growing the parents did not move them */
+                let (node, new) = unsafe { (&mut *node, &mut *new_ptr) };
+                for i in 0..fill / 2 {
+                    new.setkey(geo, i, node.bkey(geo, i));
+                    new.slots[i] = mem::take(&mut node.slots[i]);
+                    node.movepair(geo, i, i + fill / 2);
+                    node.clearpair(geo, i + fill / 2);
+                }
+                if fill & 1 == 1 {
+                    node.movepair(geo, fill / 2, fill - 1);
+                    node.clearpair(geo, fill - 1);
+                }
+                continue;
+            }
+
+            /* shift and insert */
+            let node = unsafe { &mut *node };
+            for i in (pos + 1..=fill).rev() {
+                node.movepair(geo, i, i - 1);
+            }
+            node.setkey(geo, pos, key);
+            node.slots[pos] = val;
+            return Ok(());
+        }
+    }
+
+
+    /**
+     * btree_insert - insert an entry into the btree
+     * @geo: the btree geometry
+     * @key: the key of the entry
+     * @val: the value of the entry
+     *
+     * This function returns 0 if the item could be added, or -EEXIST if
+     * the key is already in the tree.
+     */
+    pub fn btree_insert(&mut self, geo: &BtreeGeo, key: &[u64], val: V) -> Result<(), i32> {
+        self.btree_insert_level(geo, key, BtreeSlot::Val(val), 1)
+    }
+
+
+    #[allow(clippy::too_many_arguments)]
+    fn merge(&mut self, geo: &BtreeGeo, level: usize,
+             left: *mut BtreeNode<V>, lfill: usize,
+             right: *mut BtreeNode<V>, rfill: usize,
+             parent: *mut BtreeNode<V>, lpos: usize) {
+        let (left, right, parent) = unsafe { (&mut *left, &mut *right, &mut *parent) };
+        let mut key = [0; MAX_KEYLEN];
+
+        for i in 0..rfill {
+            /* Move all keys to the left */
+            left.setkey(geo, lfill + i, right.bkey(geo, i));
+            left.slots[lfill + i] = mem::take(&mut right.slots[i]);
+        }
+        /* Exchange left and right child in parent */
+        parent.slots.swap(lpos, lpos + 1);
+        /* Remove left (formerly right) child from parent, which frees it */
+        key[..geo.keylen].copy_from_slice(parent.bkey(geo, lpos));
+        self.btree_remove_level(geo, &key[..geo.keylen], level + 1);
+    }
+
+
+    fn rebalance(&mut self, geo: &BtreeGeo, key: &[u64], level: usize, child: *mut BtreeNode<V>, fill: usize) {
+        if fill == 0 {
+            /*
+             * Because we don't steal entries from a neighbour, this case
+             * can happen. Parent node contains a single child, this node,
+             * so merging with a sibling never happens.
+             */
+            self.btree_remove_level(geo, key, level + 1);
+            return;
+        }
+
+        let parent = self.find_level(geo, key, level + 1);
+        let parent_ref = unsafe { &mut *parent };
+        let i = parent_ref.getpos(geo, key);
+        assert!(parent_ref.child_ptr(i) == child);
+
+        if i > 0 {
+            let left = parent_ref.child_ptr(i - 1);
+            let no_left = unsafe { (*left).getfill(geo, 0) };
+            if fill + no_left <= geo.no_pairs {
+                self.merge(geo, level, left, no_left, child, fill, parent, i - 1);
+                return;
+            }
+        }
+        if i + 1 < parent_ref.getfill(geo, i) {
+            let right = parent_ref.child_ptr(i + 1);
+            let no_right = unsafe { (*right).getfill(geo, 0) };
+            if fill + no_right <= geo.no_pairs {
+                self.merge(geo, level, child, fill, right, no_right, parent, i);
+            }
+        }
+        /*
+         * We could also try to steal one entry from the left or right
+         * neighbor. By not doing so we changed the invariant from "all
+         * nodes are at least half full" to "no two neighboring nodes can
+         * be merged". Which means that the average fill of all nodes is
+         * still half or better.
+         */
+    }
+
+
+    fn btree_remove_level(&mut self, geo: &BtreeGeo, key: &[u64], level: usize) -> BtreeSlot<V> {
+        if level > self.height {
+            /* we recursed all the way up */
+            self.height = 0;
+            self.node = None;
+            return BtreeSlot::Empty;
+        }
+
+        let node = self.find_level(geo, key, level);
+        let node_ref = unsafe { &mut *node };
+        let pos = node_ref.getpos(geo, key);
+        let fill = node_ref.getfill(geo, pos);
+        if pos >= fill || (level == 1 && node_ref.keycmp(geo, pos, key) != Ordering::Equal) {
+            return BtreeSlot::Empty;
+        }
+        let ret = mem::take(&mut node_ref.slots[pos]);
+
+        /* remove and shift */
+        for i in pos..fill - 1 {
+            node_ref.movepair(geo, i, i + 1);
+        }
+        node_ref.clearpair(geo, fill - 1);
+
+        if fill - 1 < geo.no_pairs / 2 {
+            if level < self.height {
+                self.rebalance(geo, key, level, node, fill - 1);
+            } else if fill - 1 == 1 {
+                self.btree_shrink(geo);
+            }
+        }
+        ret
+    }
+
+
+    /**
+     * btree_remove - remove an entry from the btree
+     * @geo: the btree geometry
+     * @key: the key of the entry to remove
+     *
+     * This function returns the removed entry, or %None if the key could
+     * not be found.
+     */
+    pub fn btree_remove(&mut self, geo: &BtreeGeo, key: &[u64]) -> Option<V> {
+        if self.height == 0 {
+            return None;
+        }
+
+        match self.btree_remove_level(geo, key, 1) {
+            BtreeSlot::Val(val) => Some(val),
+            _ => None,
+        }
+    }
+
+
+    /**
+     * btree_merge - merge two btrees
+     * @victim: the btree to merge into @self
+     * @geo: the btree geometry
+     *
+     * The two trees @self and @victim may not contain the same keys, that
+     * is a bug and triggers a -EEXIST return. On error the entries that
+     * were not moved yet stay in @victim.
+     */
+    pub fn btree_merge(&mut self, victim: &mut BtreeHead<V>, geo: &BtreeGeo) -> Result<(), i32> {
+        let mut key = [0; MAX_KEYLEN];
+        let key = &mut key[..geo.keylen];
+
+        if self.node.is_none() {
+            /* target is empty, just copy fields over */
+            mem::swap(self, victim);
+            return Ok(());
+        }
+
+        while victim.btree_last(geo, key).is_some() {
+            if self.btree_lookup(geo, key).is_some() {
+                return Err(-EEXIST);
+            }
+            let val = victim.btree_remove(geo, key).unwrap();
+            self.btree_insert(geo, key, val)?;
+        }
+        Ok(())
+    }
+
+
+    fn btree_for_each<F: FnMut(&V, &[u64], usize)>(geo: &BtreeGeo, node: &BtreeNode<V>, func: &mut F,
+                                                    mut count: usize) -> usize {
+        for i in 0..geo.no_pairs {
+            match &node.slots[i] {
+                BtreeSlot::Empty => break,
+                BtreeSlot::Node(child) => count = Self::btree_for_each(geo, child, func, count),
+                BtreeSlot::Val(val) => {
+                    func(val, node.bkey(geo, i), count);
+                    count += 1;
+                }
+            }
+        }
+        count
+    }
+
+
+    fn btree_reap<F: FnMut(V, &[u64], usize)>(geo: &BtreeGeo, mut node: BtreeNode<V>, func: &mut F,
+                                               mut count: usize) -> usize {
+        for i in 0..geo.no_pairs {
+            match mem::take(&mut node.slots[i]) {
+                BtreeSlot::Empty => break,
+                BtreeSlot::Node(child) => count = Self::btree_reap(geo, *child, func, count),
+                BtreeSlot::Val(val) => {
+                    func(val, node.bkey(geo, i), count);
+                    count += 1;
+                }
+            }
+        }
+        count
+    }
+
+
+    /**
+     * btree_visitor - visit every entry of the btree
+     * @geo: the btree geometry
+     * @func: called with each value, its key and its index
+     *
+     * Entries are visited in descending key order. Returns the number of
+     * entries visited.
+     */
+    pub fn btree_visitor<F: FnMut(&V, &[u64], usize)>(&self, geo: &BtreeGeo, mut func: F) -> usize {
+        match self.node.as_deref() {
+            Some(node) => Self::btree_for_each(geo, node, &mut func, 0),
+            None => 0,
+        }
+    }
+
+
+    /**
+     * btree_grim_visitor - visit every entry and tear down the btree
+     * @geo: the btree geometry
+     * @func: called with each value, which it now owns, its key and index
+     *
+     * The btree is empty afterwards. Returns the number of entries visited.
+     */
+    pub fn btree_grim_visitor<F: FnMut(V, &[u64], usize)>(&mut self, geo: &BtreeGeo, mut func: F) -> usize {
+        let count = match self.node.take() {
+            Some(node) => Self::btree_reap(geo, *node, &mut func, 0),
+            None => 0,
+        };
+        self.height = 0;
+        count
+    }
+}
+
+
+/**
+ * trait BtreeKey - Key type of a typed btree
+ *
+ * Ties a key type to its geometry and converts it from and to the longs
+ * stored in the nodes, most significant long first.
+ */
+pub trait BtreeKey: Copy {
+    const GEO: &'static BtreeGeo;
+
+    fn to_longs(self, longs: &mut [u64]);
+    fn from_longs(longs: &[u64]) -> Self;
+}
+
+impl BtreeKey for u32 {
+    const GEO: &'static BtreeGeo = &BTREE_GEO32;
+
+    fn to_longs(self, longs: &mut [u64]) {
+        longs[0] = self as u64;
+    }
+
+    fn from_longs(longs: &[u64]) -> Self {
+        longs[0] as u32
+    }
+}
+
+impl BtreeKey for u64 {
+    const GEO: &'static BtreeGeo = &BTREE_GEO64;
+
+    fn to_longs(self, longs: &mut [u64]) {
+        longs[0] = self;
+    }
+
+    fn from_longs(longs: &[u64]) -> Self {
+        longs[0]
+    }
+}
+
+impl BtreeKey for u128 {
+    const GEO: &'static BtreeGeo = &BTREE_GEO128;
+
+    fn to_longs(self, longs: &mut [u64]) {
+        longs[0] = (self >> 64) as u64;
+        longs[1] = self as u64;
+    }
+
+    fn from_longs(longs: &[u64]) -> Self {
+        ((longs[0] as u128) << 64) | longs[1] as u128
+    }
+}
+
+
+/**
+ * struct BtreeTyped - btree keyed by an integer type
+ *
+ * The counterpart of the btree_head32/64/128 wrappers, every operation
+ * uses the geometry of @K.
+ */
+pub struct BtreeTyped<K: BtreeKey, V> {
+    h: BtreeHead<V>,
+    _key: PhantomData<K>,
+}
+
+pub type BtreeHead32<V> = BtreeTyped<u32, V>;
+pub type BtreeHead64<V> = BtreeTyped<u64, V>;
+pub type BtreeHead128<V> = BtreeTyped<u128, V>;
+
+impl<K: BtreeKey, V> Default for BtreeTyped<K, V> {
+    fn default() -> Self {
+        BtreeTyped::btree_init()
+    }
+}
+
+#[allow(dead_code)]
+impl<K: BtreeKey, V> BtreeTyped<K, V> {
+    pub fn btree_init() -> Self {
+        BtreeTyped {
+            h: BtreeHead::btree_init(),
+            _key: PhantomData,
+        }
+    }
+
+
+    fn longs(key: K) -> [u64; MAX_KEYLEN] {
+        let mut longs = [0; MAX_KEYLEN];
+        key.to_longs(&mut longs[..K::GEO.keylen]);
+        longs
+    }
+
+
+    pub fn btree_lookup(&self, key: K) -> Option<&V> {
+        self.h.btree_lookup(K::GEO, &Self::longs(key)[..K::GEO.keylen])
+    }
+
+
+    pub fn btree_update(&mut self, key: K, val: V) -> Result<(), i32> {
+        self.h.btree_update(K::GEO, &Self::longs(key)[..K::GEO.keylen], val)
+    }
+
+
+    pub fn btree_insert(&mut self, key: K, val: V) -> Result<(), i32> {
+        self.h.btree_insert(K::GEO, &Self::longs(key)[..K::GEO.keylen], val)
+    }
+
+
+    pub fn btree_remove(&mut self, key: K) -> Option<V> {
+        self.h.btree_remove(K::GEO, &Self::longs(key)[..K::GEO.keylen])
+    }
+
+
+    pub fn btree_last(&self, key: &mut K) -> Option<&V> {
+        let mut longs = Self::longs(*key);
+        let val = self.h.btree_last(K::GEO, &mut longs[..K::GEO.keylen])?;
+
+        *key = K::from_longs(&longs[..K::GEO.keylen]);
+        Some(val)
+    }
+
+
+    pub fn btree_get_prev(&self, key: &mut K) -> Option<&V> {
+        let mut longs = Self::longs(*key);
+        let val = self.h.btree_get_prev(K::GEO, &mut longs[..K::GEO.keylen])?;
+
+        *key = K::from_longs(&longs[..K::GEO.keylen]);
+        Some(val)
+    }
+
+
+    pub fn btree_merge(&mut self, victim: &mut Self) -> Result<(), i32> {
+        self.h.btree_merge(&mut victim.h, K::GEO)
+    }
+
+
+    pub fn btree_visitor<F: FnMut(&V, K, usize)>(&self, mut func: F) -> usize {
+        self.h.btree_visitor(K::GEO, |val, key, index| func(val, K::from_longs(key), index))
+    }
+
+
+    pub fn btree_grim_visitor<F: FnMut(V, K, usize)>(&mut self, mut func: F) -> usize {
+        self.h.btree_grim_visitor(K::GEO, |val, key, index| func(val, K::from_longs(key), index))
+    }
+}
+
+
+/* Sections for Unit tests */
+#[cfg(test)]
+mod tests {
+    use std::collections::btree_map::Entry;
+    use std::collections::BTreeMap;
+
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    use super::*;
+
+    /* Check ordering, bounds and depth of the subtree, return its entry count */
+    fn btree_check_node<V>(geo: &BtreeGeo, node: &BtreeNode<V>, height: usize, bound: &[u64]) -> usize {
+        let fill = node.getfill(geo, 0);
+        let mut count = 0;
+
+        for i in 0..fill {
+            assert!(node.bkey(geo, i) >= bound);
+            if i > 0 {
+                assert!(node.bkey(geo, i - 1) > node.bkey(geo, i));
+            }
+            match &node.slots[i] {
+                BtreeSlot::Node(child) => {
+                    assert!(height > 1);
+                    count += btree_check_node(geo, child, height - 1, node.bkey(geo, i));
+                }
+                BtreeSlot::Val(_) => {
+                    assert_eq!(height, 1);
+                    count += 1;
+                }
+                BtreeSlot::Empty => unreachable!(),
+            }
+        }
+        for i in fill..geo.no_pairs {
+            assert!(matches!(node.slots[i], BtreeSlot::Empty));
+            assert!(keyzero(node.bkey(geo, i)));
+        }
+        count
+    }
+
+
+    fn btree_check<K: BtreeKey, V>(tree: &BtreeTyped<K, V>) -> usize {
+        match tree.h.node.as_deref() {
+            Some(node) => btree_check_node(K::GEO, node, tree.h.height, &[0; MAX_KEYLEN][..K::GEO.keylen]),
+            None => 0,
+        }
+    }
+
+
+    #[test]
+    fn test_btree_geo() {
+        assert_eq!((BTREE_GEO32.keylen, BTREE_GEO32.no_pairs, BTREE_GEO32.no_longs), (1, 8, 8));
+        assert_eq!((BTREE_GEO64.keylen, BTREE_GEO64.no_pairs, BTREE_GEO64.no_longs), (1, 8, 8));
+        assert_eq!((BTREE_GEO128.keylen, BTREE_GEO128.no_pairs, BTREE_GEO128.no_longs), (2, 5, 10));
+    }
+
+
+    fn btree_random<K: BtreeKey + Ord + std::fmt::Debug>(key: impl Fn(u32) -> K) {
+        let mut rng = StdRng::seed_from_u64(38);
+        let mut tree = BtreeTyped::<K, u32>::btree_init();
+        let mut map = BTreeMap::new();
+
+        for round in 0..6000 {
+            let num = rng.gen_range(0..1500);
+            if rng.gen_bool(0.6) {
+                let ret = tree.btree_insert(key(num), round);
+                match map.entry(key(num)) {
+                    Entry::Occupied(_) => assert_eq!(ret, Err(-EEXIST)),
+                    Entry::Vacant(entry) => {
+                        assert_eq!(ret, Ok(()));
+                        entry.insert(round);
+                    }
+                }
+            } else {
+                assert_eq!(tree.btree_remove(key(num)), map.remove(&key(num)));
+            }
+            if round % 500 == 0 {
+                assert_eq!(btree_check(&tree), map.len());
+            }
+        }
+
+        assert_eq!(btree_check(&tree), map.len());
+        for num in 0..1500 {
+            assert_eq!(tree.btree_lookup(key(num)), map.get(&key(num)));
+        }
+
+        for num in 0..1500 {
+            tree.btree_remove(key(num));
+        }
+        assert_eq!(btree_check(&tree), 0);
+        assert_eq!(tree.h.height(), 1);
+    }
+
+
+    #[test]
+    fn test_btree_insert_remove() {
+        btree_random(|num| num);
+        btree_random(|num| (num as u64) << 40);
+        btree_random(|num| ((num as u128) << 64) | (num as u128 * 7));
+    }
+
+
+    #[test]
+    fn test_btree_last_get_prev() {
+        let mut tree = BtreeHead128::btree_init();
+        let mut key = 0;
+
+        assert!(tree.btree_last(&mut key).is_none());
+        for num in (1..300u128).rev() {
+            tree.btree_insert(num * 3 + (num << 64), num).unwrap();
+        }
+        for num in (1..300u128).step_by(4) {
+            tree.btree_remove(num * 3 + (num << 64));
+        }
+
+        /* Walk the tree backwards the way kernel code does */
+        let mut expected: Vec<u128> = (1..300).filter(|num| num % 4 != 1).collect();
+        let mut val = tree.btree_last(&mut key);
+        while let Some(&num) = val {
+            assert_eq!(Some(num), expected.pop());
+            assert_eq!(key, num * 3 + (num << 64));
+            val = tree.btree_get_prev(&mut key);
+        }
+        assert!(expected.is_empty());
+
+        let mut key = 3 * 6 + (6 << 64) + 1;
+        assert_eq!(tree.btree_get_prev(&mut key), Some(&6));
+        let mut key = 0;
+        assert_eq!(tree.btree_get_prev(&mut key), None);
+    }
+
+
+    #[test]
+    fn test_btree_update() {
+        let mut tree = BtreeHead32::btree_init();
+
+        assert_eq!(tree.btree_update(1, "one"), Err(-ENOENT));
+        for num in 0..100 {
+            tree.btree_insert(num, "old").unwrap();
+        }
+        assert_eq!(tree.btree_insert(42, "dup"), Err(-EEXIST));
+        assert_eq!(tree.btree_update(42, "new"), Ok(()));
+        assert_eq!(tree.btree_update(142, "new"), Err(-ENOENT));
+        assert_eq!(tree.btree_lookup(42), Some(&"new"));
+        assert_eq!(tree.btree_lookup(41), Some(&"old"));
+    }
+
+
+    #[test]
+    fn test_btree_merge() {
+        let mut target = BtreeHead64::btree_init();
+        let mut victim = BtreeHead64::btree_init();
+
+        for num in 0..200u64 {
+            if num % 3 == 0 {
+                target.btree_insert(num, num).unwrap();
+            } else {
+                victim.btree_insert(num, num).unwrap();
+            }
+        }
+        assert_eq!(target.btree_merge(&mut victim), Ok(()));
+        assert_eq!(btree_check(&target), 200);
+        assert_eq!(btree_check(&victim), 0);
+        assert!((0..200).all(|num| target.btree_lookup(num) == Some(&num)));
+
+        /* An empty target takes the victim over */
+        let mut empty = BtreeHead64::btree_init();
+        assert_eq!(empty.btree_merge(&mut target), Ok(()));
+        assert_eq!(btree_check(&empty), 200);
+        assert!(target.btree_lookup(0).is_none());
+
+        let mut dup = BtreeHead64::btree_init();
+        dup.btree_insert(7, 7).unwrap();
+        assert_eq!(empty.btree_merge(&mut dup), Err(-EEXIST));
+        assert_eq!(dup.btree_lookup(7), Some(&7));
+    }
+
+
+    #[test]
+    fn test_btree_visitor() {
+        let mut tree = BtreeHead32::btree_init();
+
+        for num in 0..100 {
+            tree.btree_insert(num * 2, num.to_string()).unwrap();
+        }
+
+        let mut prev = u32::MAX;
+        let count = tree.btree_visitor(|val, key, index| {
+            assert!(key < prev);
+            assert_eq!(val, &(key / 2).to_string());
+            assert_eq!(index as u32, 99 - key / 2);
+            prev = key;
+        });
+        assert_eq!(count, 100);
+
+        let mut owned = Vec::new();
+        assert_eq!(tree.btree_grim_visitor(|val, _, _| owned.push(val)), 100);
+        assert_eq!(owned.len(), 100);
+        assert!(tree.btree_lookup(0).is_none());
+        assert_eq!(tree.btree_visitor(|_, _, _| unreachable!()), 0);
+    }
+}
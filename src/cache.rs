@@ -0,0 +1,1139 @@
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hash;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use crate::errno::{E2BIG, EINVAL};
+use crate::sync_list::{SyncListHead, SyncListNode};
+
+/*
+ * Cache containers
+ *
+ * Every cache indexes its entries with a HashMap and orders them on one
+ * or more SyncListHead lists, the least recently used entry at the head
+ * and the most recent one at the tail. A lookup through the index finds
+ * the list node, which can then be moved or deleted in O(1).
+ *
+ *  - LruCache evicts the least recently used entry.
+ *  - LfuCache evicts the least frequently used entry, the least recently
+ *    used one among entries of the same frequency.
+ *  - ArcCache is the Adaptive Replacement Cache of Megiddo and Modha. It
+ *    splits the entries into seen once (T1) and seen twice (T2) and
+ *    remembers the keys of recently evicted entries (B1, B2) to adapt the
+ *    target size of T1 to the workload.
+ *  - TwoQCache is the full 2Q algorithm of Johnson and Shasha. New entries
+ *    wait in a FIFO (A1in) and only get into the LRU (Am) if they are
+ *    requested again shortly after leaving it (A1out).
+ *
+ * ArcCache and TwoQCache are scan resistant: a one-time pass over many
+ * keys does not flush the entries that are used over and over.
+ */
+
+
+/**
+ * struct CacheParams - cache construction parameters
+ * @capacity: Maximum number of entries, at least 1
+ * @max_weight: Maximum total weight of the entries, 0 for no limit
+ * @weigher: Function to get the weight of an entry
+ */
+pub struct CacheParams<K, V> {
+    pub capacity: usize,
+    pub max_weight: usize,
+    pub weigher: fn(&K, &V) -> usize,
+}
+
+impl<K, V> Clone for CacheParams<K, V> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<K, V> Copy for CacheParams<K, V> {}
+
+#[allow(dead_code)]
+impl<K, V> CacheParams<K, V> {
+    /* At most @capacity entries, all of weight 1 */
+    pub fn new(capacity: usize) -> Self {
+        CacheParams {
+            capacity,
+            max_weight: 0,
+            weigher: |_, _| 1,
+        }
+    }
+}
+
+
+/**
+ * struct CacheStats - cache statistics
+ * @hits: get() calls that found their key
+ * @misses: get() calls that did not
+ * @insertions: entries inserted, including replaced ones
+ * @evictions: entries evicted to make room
+ */
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub insertions: u64,
+    pub evictions: u64,
+}
+
+#[allow(dead_code)]
+impl CacheStats {
+    pub fn hit_ratio(&self) -> f64 {
+        let lookups = self.hits + self.misses;
+
+        if lookups == 0 {
+            0.0
+        } else {
+            self.hits as f64 / lookups as f64
+        }
+    }
+}
+
+
+/* Called with the key and the value of every evicted entry */
+pub type CacheEvictFn<K, V> = Box<dyn FnMut(K, V) + Send>;
+
+
+/*
+ * A cached entry. @freq counts the accesses for LfuCache, the other
+ * caches leave it alone.
+ */
+struct CacheEntry<K, V> {
+    key: K,
+    value: V,
+    weight: usize,
+    freq: AtomicU64,
+}
+
+type CacheNode<K, V> = Arc<SyncListNode<CacheEntry<K, V>>>;
+type CacheList<K, V> = SyncListHead<CacheEntry<K, V>>;
+
+
+/*
+ * Index, limits and accounting shared by all caches. The lists are left
+ * to each cache, the core only links and unlinks their nodes.
+ */
+struct CacheCore<K, V> {
+    map: HashMap<K, CacheNode<K, V>>,
+    params: CacheParams<K, V>,
+    weight: usize,
+    evict: Option<CacheEvictFn<K, V>>,
+    stats: CacheStats,
+}
+
+impl<K: Hash + Eq + Clone, V> CacheCore<K, V> {
+    fn new(params: CacheParams<K, V>) -> Result<Self, i32> {
+        if params.capacity == 0 {
+            return Err(-EINVAL);
+        }
+        Ok(CacheCore {
+            map: HashMap::new(),
+            params,
+            weight: 0,
+            evict: None,
+            stats: CacheStats::default(),
+        })
+    }
+
+
+    /* Weight of a new entry, -E2BIG if it could never fit */
+    fn weigh(&self, key: &K, value: &V) -> Result<usize, i32> {
+        let weight = (self.params.weigher)(key, value);
+
+        if self.params.max_weight != 0 && weight > self.params.max_weight {
+            return Err(-E2BIG);
+        }
+        Ok(weight)
+    }
+
+
+    /* Whether an entry of @weight can be added without evicting anything */
+    fn fits(&self, weight: usize) -> bool {
+        self.map.len() < self.params.capacity
+            && (self.params.max_weight == 0 || self.weight + weight <= self.params.max_weight)
+    }
+
+
+    /* Lookup for get(), accounting the hit or miss */
+    fn lookup(&mut self, key: &K) -> Option<CacheNode<K, V>> {
+        let node = self.map.get(key).cloned();
+
+        if node.is_some() {
+            self.stats.hits += 1;
+        } else {
+            self.stats.misses += 1;
+        }
+        node
+    }
+
+
+    fn value(&self, key: &K) -> Option<&V> {
+        self.map.get(key).map(|node| &node.item.value)
+    }
+
+
+    /* Add a new entry at the tail of @list */
+    fn link(&mut self, list: &CacheList<K, V>, key: K, value: V, weight: usize, freq: u64) {
+        let node = SyncListNode::new(CacheEntry {
+            key: key.clone(),
+            value,
+            weight,
+            freq: AtomicU64::new(freq),
+        });
+
+        list.list_add_tail(node.clone());
+        self.map.insert(key, node);
+        self.weight += weight;
+        self.stats.insertions += 1;
+    }
+
+
+    /*
+     * Drop an entry from the index. The caller already took @node off its
+     * list and holds no other reference, so the entry can be moved out.
+     */
+    fn unlink(&mut self, node: CacheNode<K, V>) -> CacheEntry<K, V> {
+        debug_assert!(node.list_unlinked());
+        drop(self.map.remove(&node.item.key));
+        self.weight -= node.item.weight;
+        Arc::try_unwrap(node).ok().expect("cache entry still referenced").item
+    }
+
+
+    /* Evict the head of @list, returning the key for the ghost lists */
+    fn evict_first(&mut self, list: &CacheList<K, V>) -> Option<K> {
+        let node = list.list_first_entry()?;
+
+        drop(list.list_del_init(&node));
+        let entry = self.unlink(node);
+        self.stats.evictions += 1;
+        if let Some(evict) = self.evict.as_mut() {
+            evict(entry.key.clone(), entry.value);
+        }
+        Some(entry.key)
+    }
+}
+
+
+/*
+ * Keys of recently evicted entries, oldest first, for the caches that
+ * adapt to re-requested keys.
+ */
+struct GhostList<K> {
+    list: SyncListHead<K>,
+    map: HashMap<K, Arc<SyncListNode<K>>>,
+}
+
+impl<K: Hash + Eq + Clone> GhostList<K> {
+    fn new() -> Self {
+        GhostList {
+            list: SyncListHead::new(),
+            map: HashMap::new(),
+        }
+    }
+
+
+    fn len(&self) -> usize {
+        self.map.len()
+    }
+
+
+    fn push(&mut self, key: K) {
+        let node = SyncListNode::new(key.clone());
+
+        self.list.list_add_tail(node.clone());
+        self.map.insert(key, node);
+    }
+
+
+    fn remove(&mut self, key: &K) -> bool {
+        let Some(node) = self.map.remove(key) else {
+            return false;
+        };
+        self.list.list_del_init(&node);
+        true
+    }
+
+
+    fn pop_oldest(&mut self) {
+        if let Some(node) = self.list.list_first_entry() {
+            self.list.list_del_init(&node);
+            self.map.remove(&node.item);
+        }
+    }
+}
+
+
+/**
+ * struct LruCache - least recently used cache
+ * @core: index and accounting
+ * @lru: entries, least recently used first
+ */
+pub struct LruCache<K, V> {
+    core: CacheCore<K, V>,
+    lru: CacheList<K, V>,
+}
+
+#[allow(dead_code)]
+impl<K: Hash + Eq + Clone, V> LruCache<K, V> {
+    /**
+     * new - create a cache of at most @capacity entries
+     *
+     * Return: the cache, or -EINVAL if @capacity is 0.
+     */
+    pub fn new(capacity: usize) -> Result<Self, i32> {
+        LruCache::with_params(CacheParams::new(capacity))
+    }
+
+
+    pub fn with_params(params: CacheParams<K, V>) -> Result<Self, i32> {
+        Ok(LruCache {
+            core: CacheCore::new(params)?,
+            lru: SyncListHead::new(),
+        })
+    }
+
+
+    /**
+     * set_evict_callback - call @evict on every entry evicted to make room
+     *
+     * Entries that are removed or replaced are returned to the caller
+     * instead.
+     */
+    pub fn set_evict_callback(&mut self, evict: CacheEvictFn<K, V>) {
+        self.core.evict = Some(evict);
+    }
+
+
+    /**
+     * get - look up @key and mark it as most recently used
+     */
+    pub fn get(&mut self, key: &K) -> Option<&V> {
+        let node = self.core.lookup(key)?;
+
+        self.lru.list_move_tail(&node);
+        self.core.value(key)
+    }
+
+
+    /**
+     * peek - look up @key without touching its position or the statistics
+     */
+    pub fn peek(&self, key: &K) -> Option<&V> {
+        self.core.value(key)
+    }
+
+
+    /**
+     * insert - add or replace the entry of @key
+     *
+     * Least recently used entries are evicted until the new entry fits.
+     *
+     * Return: the replaced value, or -E2BIG if the entry alone is heavier
+     * than the weight limit.
+     */
+    pub fn insert(&mut self, key: K, value: V) -> Result<Option<V>, i32> {
+        let weight = self.core.weigh(&key, &value)?;
+        let old = self.remove(&key);
+
+        while !self.core.fits(weight) {
+            self.core.evict_first(&self.lru);
+        }
+        self.core.link(&self.lru, key, value, weight, 0);
+        Ok(old)
+    }
+
+
+    /**
+     * remove - delete the entry of @key
+     *
+     * Return: its value, the eviction callback is not called.
+     */
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        let node = self.core.map.get(key)?.clone();
+
+        self.lru.list_del_init(&node);
+        Some(self.core.unlink(node).value)
+    }
+
+
+    pub fn contains(&self, key: &K) -> bool {
+        self.core.map.contains_key(key)
+    }
+
+
+    pub fn len(&self) -> usize {
+        self.core.map.len()
+    }
+
+
+    pub fn is_empty(&self) -> bool {
+        self.core.map.is_empty()
+    }
+
+
+    /**
+     * weight - total weight of the cached entries
+     */
+    pub fn weight(&self) -> usize {
+        self.core.weight
+    }
+
+
+    pub fn stats(&self) -> CacheStats {
+        self.core.stats
+    }
+}
+
+
+/**
+ * struct LfuCache - least frequently used cache
+ * @core: index and accounting
+ * @freq: entries of each access count, least recently used first
+ */
+pub struct LfuCache<K, V> {
+    core: CacheCore<K, V>,
+    freq: BTreeMap<u64, CacheList<K, V>>,
+}
+
+#[allow(dead_code)]
+impl<K: Hash + Eq + Clone, V> LfuCache<K, V> {
+    /**
+     * new - create a cache of at most @capacity entries
+     *
+     * Return: the cache, or -EINVAL if @capacity is 0.
+     */
+    pub fn new(capacity: usize) -> Result<Self, i32> {
+        LfuCache::with_params(CacheParams::new(capacity))
+    }
+
+
+    pub fn with_params(params: CacheParams<K, V>) -> Result<Self, i32> {
+        Ok(LfuCache {
+            core: CacheCore::new(params)?,
+            freq: BTreeMap::new(),
+        })
+    }
+
+
+    pub fn set_evict_callback(&mut self, evict: CacheEvictFn<K, V>) {
+        self.core.evict = Some(evict);
+    }
+
+
+    /* Take @node off its frequency list, dropping the list once empty */
+    fn freq_del(&mut self, node: &CacheNode<K, V>) -> u64 {
+        let freq = node.item.freq.load(Ordering::Relaxed);
+        let list = &self.freq[&freq];
+
+        list.list_del_init(node);
+        if list.list_empty() {
+            self.freq.remove(&freq);
+        }
+        freq
+    }
+
+
+    /**
+     * get - look up @key and count an access to it
+     */
+    pub fn get(&mut self, key: &K) -> Option<&V> {
+        let node = self.core.lookup(key)?;
+        let freq = self.freq_del(&node) + 1;
+
+        node.item.freq.store(freq, Ordering::Relaxed);
+        self.freq.entry(freq).or_default().list_add_tail(node);
+        self.core.value(key)
+    }
+
+
+    /**
+     * peek - look up @key without counting an access
+     */
+    pub fn peek(&self, key: &K) -> Option<&V> {
+        self.core.value(key)
+    }
+
+
+    /**
+     * frequency - number of accesses to @key, including its insertion
+     */
+    pub fn frequency(&self, key: &K) -> Option<u64> {
+        self.core.map.get(key).map(|node| node.item.freq.load(Ordering::Relaxed))
+    }
+
+
+    /* Evict the least recently used of the least frequently used entries */
+    fn evict_one(&mut self) {
+        let Some((&freq, list)) = self.freq.first_key_value() else {
+            return;
+        };
+
+        self.core.evict_first(list);
+        if list.list_empty() {
+            self.freq.remove(&freq);
+        }
+    }
+
+
+    /**
+     * insert - add or replace the entry of @key
+     *
+     * Replacing an entry counts as an access to it, a new entry starts
+     * with one access.
+     *
+     * Return: the replaced value, or -E2BIG if the entry alone is heavier
+     * than the weight limit.
+     */
+    pub fn insert(&mut self, key: K, value: V) -> Result<Option<V>, i32> {
+        let weight = self.core.weigh(&key, &value)?;
+        let mut freq = 1;
+        let mut old = None;
+
+        if let Some(node) = self.core.map.get(&key).cloned() {
+            freq = self.freq_del(&node) + 1;
+            old = Some(self.core.unlink(node).value);
+        }
+        while !self.core.fits(weight) {
+            self.evict_one();
+        }
+        let list = self.freq.entry(freq).or_default();
+        self.core.link(list, key, value, weight, freq);
+        Ok(old)
+    }
+
+
+    /**
+     * remove - delete the entry of @key
+     *
+     * Return: its value, the eviction callback is not called.
+     */
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        let node = self.core.map.get(key)?.clone();
+
+        self.freq_del(&node);
+        Some(self.core.unlink(node).value)
+    }
+
+
+    pub fn contains(&self, key: &K) -> bool {
+        self.core.map.contains_key(key)
+    }
+
+
+    pub fn len(&self) -> usize {
+        self.core.map.len()
+    }
+
+
+    pub fn is_empty(&self) -> bool {
+        self.core.map.is_empty()
+    }
+
+
+    pub fn weight(&self) -> usize {
+        self.core.weight
+    }
+
+
+    pub fn stats(&self) -> CacheStats {
+        self.core.stats
+    }
+}
+
+
+/**
+ * struct ArcCache - adaptive replacement cache
+ * @core: index and accounting
+ * @t1: entries seen once, least recently used first
+ * @t2: entries seen at least twice, least recently used first
+ * @b1: keys recently evicted from @t1
+ * @b2: keys recently evicted from @t2
+ * @t1_len: number of entries on @t1
+ * @t2_len: number of entries on @t2
+ * @p: target number of entries on @t1
+ */
+pub struct ArcCache<K, V> {
+    core: CacheCore<K, V>,
+    t1: CacheList<K, V>,
+    t2: CacheList<K, V>,
+    b1: GhostList<K>,
+    b2: GhostList<K>,
+    t1_len: usize,
+    t2_len: usize,
+    p: usize,
+}
+
+#[allow(dead_code)]
+impl<K: Hash + Eq + Clone, V> ArcCache<K, V> {
+    /**
+     * new - create a cache of at most @capacity entries
+     *
+     * Return: the cache, or -EINVAL if @capacity is 0.
+     */
+    pub fn new(capacity: usize) -> Result<Self, i32> {
+        ArcCache::with_params(CacheParams::new(capacity))
+    }
+
+
+    pub fn with_params(params: CacheParams<K, V>) -> Result<Self, i32> {
+        Ok(ArcCache {
+            core: CacheCore::new(params)?,
+            t1: SyncListHead::new(),
+            t2: SyncListHead::new(),
+            b1: GhostList::new(),
+            b2: GhostList::new(),
+            t1_len: 0,
+            t2_len: 0,
+            p: 0,
+        })
+    }
+
+
+    pub fn set_evict_callback(&mut self, evict: CacheEvictFn<K, V>) {
+        self.core.evict = Some(evict);
+    }
+
+
+    /**
+     * target - current target size of the recency side
+     */
+    pub fn target(&self) -> usize {
+        self.p
+    }
+
+
+    /**
+     * get - look up @key, promoting it to the frequency side
+     */
+    pub fn get(&mut self, key: &K) -> Option<&V> {
+        let node = self.core.lookup(key)?;
+
+        if let Some(node) = self.t1.list_del_init(&node) {
+            self.t1_len -= 1;
+            self.t2.list_add_tail(node);
+            self.t2_len += 1;
+        } else {
+            self.t2.list_move_tail(&node);
+        }
+        self.core.value(key)
+    }
+
+
+    /**
+     * peek - look up @key without promoting it
+     */
+    pub fn peek(&self, key: &K) -> Option<&V> {
+        self.core.value(key)
+    }
+
+
+    /*
+     * REPLACE: evict from @t1 if it is above its target, from @t2
+     * otherwise, remembering the key on the matching ghost list.
+     */
+    fn replace(&mut self, in_b2: bool) {
+        let from_t1 = self.t1_len > 0
+            && (self.t1_len > self.p || (in_b2 && self.t1_len == self.p) || self.t2_len == 0);
+
+        if from_t1 {
+            if let Some(key) = self.core.evict_first(&self.t1) {
+                self.t1_len -= 1;
+                self.b1.push(key);
+            }
+        } else if let Some(key) = self.core.evict_first(&self.t2) {
+            self.t2_len -= 1;
+            self.b2.push(key);
+        }
+    }
+
+
+    /* Keep |T1| + |B1| <= c and the whole directory <= 2c */
+    fn trim_ghosts(&mut self) {
+        let c = self.core.params.capacity;
+
+        while self.b1.len() > 0 && self.t1_len + self.b1.len() > c {
+            self.b1.pop_oldest();
+        }
+        while self.b2.len() > 0 && self.t1_len + self.t2_len + self.b1.len() + self.b2.len() > 2 * c {
+            self.b2.pop_oldest();
+        }
+    }
+
+
+    /* Take a resident entry off whichever list it is on */
+    fn detach(&mut self, node: &CacheNode<K, V>) {
+        if self.t1.list_del_init(node).is_some() {
+            self.t1_len -= 1;
+        } else if self.t2.list_del_init(node).is_some() {
+            self.t2_len -= 1;
+        }
+    }
+
+
+    /**
+     * insert - add or replace the entry of @key
+     *
+     * A key that was evicted recently goes straight to the frequency side
+     * and shifts the target towards the side it was evicted from.
+     *
+     * Return: the replaced value, or -E2BIG if the entry alone is heavier
+     * than the weight limit.
+     */
+    pub fn insert(&mut self, key: K, value: V) -> Result<Option<V>, i32> {
+        let weight = self.core.weigh(&key, &value)?;
+        let c = self.core.params.capacity;
+
+        if let Some(node) = self.core.map.get(&key).cloned() {
+            self.detach(&node);
+            let old = self.core.unlink(node).value;
+            while !self.core.fits(weight) {
+                self.replace(false);
+            }
+            self.core.link(&self.t2, key, value, weight, 0);
+            self.t2_len += 1;
+            return Ok(Some(old));
+        }
+
+        let (b1_len, b2_len) = (self.b1.len(), self.b2.len());
+        let frequent = if self.b1.remove(&key) {
+            self.p = (self.p + (b2_len / b1_len).max(1)).min(c);
+            while !self.core.fits(weight) {
+                self.replace(false);
+            }
+            true
+        } else if self.b2.remove(&key) {
+            self.p = self.p.saturating_sub((b1_len / b2_len).max(1));
+            while !self.core.fits(weight) {
+                self.replace(true);
+            }
+            true
+        } else {
+            while !self.core.fits(weight) {
+                self.replace(false);
+            }
+            false
+        };
+
+        if frequent {
+            self.core.link(&self.t2, key, value, weight, 0);
+            self.t2_len += 1;
+        } else {
+            self.core.link(&self.t1, key, value, weight, 0);
+            self.t1_len += 1;
+        }
+        self.trim_ghosts();
+        Ok(None)
+    }
+
+
+    /**
+     * remove - delete the entry of @key
+     *
+     * Return: its value, the eviction callback is not called.
+     */
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        let node = self.core.map.get(key)?.clone();
+
+        self.detach(&node);
+        Some(self.core.unlink(node).value)
+    }
+
+
+    pub fn contains(&self, key: &K) -> bool {
+        self.core.map.contains_key(key)
+    }
+
+
+    pub fn len(&self) -> usize {
+        self.core.map.len()
+    }
+
+
+    pub fn is_empty(&self) -> bool {
+        self.core.map.is_empty()
+    }
+
+
+    pub fn weight(&self) -> usize {
+        self.core.weight
+    }
+
+
+    pub fn stats(&self) -> CacheStats {
+        self.core.stats
+    }
+}
+
+
+/**
+ * struct TwoQCache - 2Q cache
+ * @core: index and accounting
+ * @a1in: entries seen once, oldest first
+ * @am: entries seen again, least recently used first
+ * @a1out: keys recently evicted from @a1in
+ * @a1in_len: number of entries on @a1in
+ * @kin: target number of entries on @a1in, a quarter of the capacity
+ * @kout: number of keys kept on @a1out, half of the capacity
+ */
+pub struct TwoQCache<K, V> {
+    core: CacheCore<K, V>,
+    a1in: CacheList<K, V>,
+    am: CacheList<K, V>,
+    a1out: GhostList<K>,
+    a1in_len: usize,
+    kin: usize,
+    kout: usize,
+}
+
+#[allow(dead_code)]
+impl<K: Hash + Eq + Clone, V> TwoQCache<K, V> {
+    /**
+     * new - create a cache of at most @capacity entries
+     *
+     * Return: the cache, or -EINVAL if @capacity is 0.
+     */
+    pub fn new(capacity: usize) -> Result<Self, i32> {
+        TwoQCache::with_params(CacheParams::new(capacity))
+    }
+
+
+    pub fn with_params(params: CacheParams<K, V>) -> Result<Self, i32> {
+        Ok(TwoQCache {
+            core: CacheCore::new(params)?,
+            a1in: SyncListHead::new(),
+            am: SyncListHead::new(),
+            a1out: GhostList::new(),
+            a1in_len: 0,
+            kin: (params.capacity / 4).max(1),
+            kout: (params.capacity / 2).max(1),
+        })
+    }
+
+
+    pub fn set_evict_callback(&mut self, evict: CacheEvictFn<K, V>) {
+        self.core.evict = Some(evict);
+    }
+
+
+    /**
+     * get - look up @key
+     *
+     * Only entries on the LRU side are moved, a hit on the FIFO side
+     * leaves the entry where it is.
+     */
+    pub fn get(&mut self, key: &K) -> Option<&V> {
+        let node = self.core.lookup(key)?;
+
+        self.am.list_move_tail(&node);
+        self.core.value(key)
+    }
+
+
+    /**
+     * peek - look up @key without touching its position or the statistics
+     */
+    pub fn peek(&self, key: &K) -> Option<&V> {
+        self.core.value(key)
+    }
+
+
+    /* Evict from the FIFO while it is above its target, else from the LRU */
+    fn reclaim(&mut self) {
+        if self.a1in_len > self.kin || self.core.map.len() == self.a1in_len {
+            if let Some(key) = self.core.evict_first(&self.a1in) {
+                self.a1in_len -= 1;
+                self.a1out.push(key);
+                if self.a1out.len() > self.kout {
+                    self.a1out.pop_oldest();
+                }
+            }
+        } else {
+            self.core.evict_first(&self.am);
+        }
+    }
+
+
+    /**
+     * insert - add or replace the entry of @key
+     *
+     * New keys go to the FIFO side, keys evicted from it not long ago go
+     * to the LRU side.
+     *
+     * Return: the replaced value, or -E2BIG if the entry alone is heavier
+     * than the weight limit.
+     */
+    pub fn insert(&mut self, key: K, value: V) -> Result<Option<V>, i32> {
+        let weight = self.core.weigh(&key, &value)?;
+        let mut old = None;
+        let mut frequent = self.a1out.remove(&key);
+
+        if let Some(node) = self.core.map.get(&key).cloned() {
+            if self.a1in.list_del_init(&node).is_some() {
+                self.a1in_len -= 1;
+            } else {
+                self.am.list_del_init(&node);
+                frequent = true;
+            }
+            old = Some(self.core.unlink(node).value);
+        }
+        while !self.core.fits(weight) {
+            self.reclaim();
+        }
+        if frequent {
+            self.core.link(&self.am, key, value, weight, 0);
+        } else {
+            self.core.link(&self.a1in, key, value, weight, 0);
+            self.a1in_len += 1;
+        }
+        Ok(old)
+    }
+
+
+    /**
+     * remove - delete the entry of @key
+     *
+     * Return: its value, the eviction callback is not called.
+     */
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        let node = self.core.map.get(key)?.clone();
+
+        if self.a1in.list_del_init(&node).is_some() {
+            self.a1in_len -= 1;
+        } else {
+            self.am.list_del_init(&node);
+        }
+        Some(self.core.unlink(node).value)
+    }
+
+
+    pub fn contains(&self, key: &K) -> bool {
+        self.core.map.contains_key(key)
+    }
+
+
+    pub fn len(&self) -> usize {
+        self.core.map.len()
+    }
+
+
+    pub fn is_empty(&self) -> bool {
+        self.core.map.is_empty()
+    }
+
+
+    pub fn weight(&self) -> usize {
+        self.core.weight
+    }
+
+
+    pub fn stats(&self) -> CacheStats {
+        self.core.stats
+    }
+}
+
+
+/* Sections for Unit tests */
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    use super::*;
+
+    /* Eviction callback recording the evicted keys */
+    fn recorder(evicted: &Arc<Mutex<Vec<u32>>>) -> CacheEvictFn<u32, String> {
+        let evicted = evicted.clone();
+        Box::new(move |key, _| evicted.lock().unwrap().push(key))
+    }
+
+
+    #[test]
+    fn test_lru_cache() {
+        let mut cache = LruCache::new(3).unwrap();
+        let evicted = Arc::new(Mutex::new(Vec::new()));
+
+        assert!(LruCache::<u32, String>::new(0).is_err());
+        cache.set_evict_callback(recorder(&evicted));
+        for i in 0..3 {
+            assert_eq!(cache.insert(i, i.to_string()), Ok(None));
+        }
+
+        /* 0 is used again, 1 is only peeked at */
+        assert_eq!(cache.get(&0).unwrap(), "0");
+        assert_eq!(cache.peek(&1).unwrap(), "1");
+        cache.insert(3, "3".into()).unwrap();
+        assert!(!cache.contains(&1));
+        assert_eq!(cache.insert(0, "zero".into()), Ok(Some("0".into())));
+        cache.insert(4, "4".into()).unwrap();
+        assert_eq!(*evicted.lock().unwrap(), vec![1, 2]);
+
+        assert_eq!(cache.get(&2), None);
+        assert_eq!(cache.remove(&3), Some("3".into()));
+        assert_eq!(cache.len(), 2);
+        assert_eq!(
+            cache.stats(),
+            CacheStats { hits: 1, misses: 1, insertions: 6, evictions: 2 }
+        );
+        assert_eq!(cache.stats().hit_ratio(), 0.5);
+    }
+
+
+    #[test]
+    fn test_cache_weight() {
+        let mut params = CacheParams::new(100);
+        params.max_weight = 10;
+        params.weigher = |_, v: &String| v.len();
+        let mut cache = LruCache::with_params(params).unwrap();
+        let evicted = Arc::new(Mutex::new(Vec::new()));
+
+        cache.set_evict_callback(recorder(&evicted));
+        cache.insert(1, "aaaa".into()).unwrap();
+        cache.insert(2, "bbbb".into()).unwrap();
+        assert_eq!(cache.weight(), 8);
+        assert_eq!(cache.insert(3, "c".repeat(11)), Err(-E2BIG));
+
+        /* Six bytes only need the oldest entry gone */
+        cache.insert(3, "cccccc".into()).unwrap();
+        assert_eq!(*evicted.lock().unwrap(), vec![1]);
+        cache.insert(4, "dddddddd".into()).unwrap();
+        assert_eq!(*evicted.lock().unwrap(), vec![1, 2, 3]);
+        assert_eq!((cache.len(), cache.weight()), (1, 8));
+
+        /* Replacing frees the old weight first */
+        cache.insert(4, "dddddddddd".into()).unwrap();
+        assert_eq!((cache.len(), cache.weight()), (1, 10));
+    }
+
+
+    #[test]
+    fn test_lfu_cache() {
+        let mut cache = LfuCache::new(3).unwrap();
+        let evicted = Arc::new(Mutex::new(Vec::new()));
+
+        cache.set_evict_callback(recorder(&evicted));
+        for i in 0..3 {
+            cache.insert(i, i.to_string()).unwrap();
+        }
+        cache.get(&0);
+        cache.get(&0);
+        cache.get(&2);
+        assert_eq!(cache.frequency(&0), Some(3));
+        assert_eq!(cache.peek(&1).unwrap(), "1");
+        assert_eq!(cache.frequency(&1), Some(1));
+
+        cache.insert(3, "3".into()).unwrap();
+        /* 2 and 3 are tied, 2 was used longer ago */
+        cache.get(&3);
+        cache.insert(4, "4".into()).unwrap();
+        assert_eq!(*evicted.lock().unwrap(), vec![1, 2]);
+
+        assert_eq!(cache.insert(4, "four".into()), Ok(Some("4".into())));
+        assert_eq!(cache.frequency(&4), Some(2));
+        assert_eq!(cache.remove(&0), Some("0".into()));
+        assert_eq!(cache.len(), 2);
+    }
+
+
+    #[test]
+    fn test_arc_cache() {
+        let mut cache = ArcCache::new(4).unwrap();
+        let evicted = Arc::new(Mutex::new(Vec::new()));
+
+        cache.set_evict_callback(recorder(&evicted));
+        /* A working set used twice moves to the frequency side */
+        for i in 0..2 {
+            cache.insert(i, i.to_string()).unwrap();
+            cache.get(&i);
+        }
+
+        /* A scan only churns the recency side */
+        for i in 100..120 {
+            cache.insert(i, i.to_string()).unwrap();
+        }
+        assert!(cache.contains(&0) && cache.contains(&1));
+        assert_eq!(cache.len(), 4);
+        assert_eq!(cache.target(), 0);
+
+        /* Re-requesting a key evicted from T1 grows the target */
+        let ghost = *evicted.lock().unwrap().last().unwrap();
+        cache.insert(ghost, "again".into()).unwrap();
+        assert!(cache.target() > 0);
+        assert_eq!(cache.peek(&ghost).unwrap(), "again");
+        assert_eq!(cache.len(), 4);
+        assert_eq!(cache.remove(&0), Some("0".into()));
+    }
+
+
+    #[test]
+    fn test_two_q_cache() {
+        let mut cache = TwoQCache::new(8).unwrap();
+
+        /* Keys re-inserted right after leaving the FIFO become hot */
+        for i in 0..4 {
+            cache.insert(i, i.to_string()).unwrap();
+        }
+        for i in 10..16 {
+            cache.insert(i, i.to_string()).unwrap();
+        }
+        assert!(!cache.contains(&0) && !cache.contains(&1));
+        cache.insert(0, "0".into()).unwrap();
+        cache.insert(1, "1".into()).unwrap();
+
+        /* A long scan passes through the FIFO only */
+        for i in 100..200 {
+            cache.insert(i, i.to_string()).unwrap();
+        }
+        assert_eq!(cache.get(&0).unwrap(), "0");
+        assert_eq!(cache.get(&1).unwrap(), "1");
+        assert!(!cache.contains(&150));
+        assert_eq!(cache.len(), 8);
+    }
+
+
+    #[test]
+    fn test_cache_random() {
+        let mut rng = StdRng::seed_from_u64(43);
+        let mut params = CacheParams::new(16);
+        params.max_weight = 40;
+        params.weigher = |_, v: &u32| (*v % 5) as usize + 1;
+        let mut lru = LruCache::with_params(params).unwrap();
+        let mut lfu = LfuCache::with_params(params).unwrap();
+        let mut arc = ArcCache::with_params(params).unwrap();
+        let mut two_q = TwoQCache::with_params(params).unwrap();
+        /* Latest value of every key as reference */
+        let mut latest = HashMap::new();
+
+        for _ in 0..5000 {
+            let key = rng.gen_range(0..64u32);
+            if rng.gen_bool(0.5) {
+                let value = rng.gen();
+                latest.insert(key, value);
+                lru.insert(key, value).unwrap();
+                lfu.insert(key, value).unwrap();
+                arc.insert(key, value).unwrap();
+                two_q.insert(key, value).unwrap();
+            } else {
+                for value in [lru.get(&key), lfu.get(&key), arc.get(&key), two_q.get(&key)].into_iter().flatten() {
+                    assert_eq!(Some(value), latest.get(&key));
+                }
+            }
+            for (len, weight) in [
+                (lru.len(), lru.weight()),
+                (lfu.len(), lfu.weight()),
+                (arc.len(), arc.weight()),
+                (two_q.len(), two_q.weight()),
+            ] {
+                assert!(len <= 16 && weight <= 40);
+            }
+            assert!(arc.b1.len() + arc.b2.len() <= 32 && two_q.a1out.len() <= 8);
+            assert_eq!(arc.t1_len + arc.t2_len, arc.len());
+        }
+        let stats = arc.stats();
+        assert!(stats.hits > 0 && stats.evictions > 0);
+    }
+}
@@ -0,0 +1,18 @@
+/*
+ * Error numbers shared by the data structures of this crate.
+ *
+ * Following the kernel convention, fallible operations report failure
+ * as a negated errno value, e.g. `Err(-ENOSPC)`.
+ */
+#![allow(dead_code)]
+
+pub const ENOENT: i32 = 2;
+pub const E2BIG: i32 = 7;
+pub const EAGAIN: i32 = 11;
+pub const ENOMEM: i32 = 12;
+pub const EBUSY: i32 = 16;
+pub const EEXIST: i32 = 17;
+pub const EINVAL: i32 = 22;
+pub const ENOSPC: i32 = 28;
+pub const ERANGE: i32 = 34;
+pub const EOVERFLOW: i32 = 75;
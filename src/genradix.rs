@@ -0,0 +1,546 @@
+use std::alloc::{self, Layout};
+use std::marker::PhantomData;
+use std::mem::size_of;
+use std::ptr;
+use std::sync::atomic::{AtomicPtr, Ordering};
+
+use crate::bitmap::PAGE_SIZE;
+use crate::errno::{ENOMEM, ERANGE};
+
+/*
+ * Generic radix trees
+ *
+ * Port of lib/generic-radix-tree.c. A GenRadix is a sparse array of
+ * objects that are allocated a page at a time and never move once
+ * allocated, so references to them stay valid for as long as the tree,
+ * even while other threads keep allocating.
+ *
+ * Every node is a page. A leaf holds as many objects as fit in a page,
+ * an interior node holds GENRADIX_ARY pointers to the nodes of the level
+ * below. The depth of the tree is encoded in the low bits of the root
+ * pointer, so the tree grows by a single cmpxchg that puts a new root
+ * above the old one, and missing nodes are filled in by a cmpxchg on
+ * the empty child pointer too, without any lock.
+ */
+
+const GENRADIX_ARY: usize = PAGE_SIZE / size_of::<usize>();
+const GENRADIX_ARY_SHIFT: usize = GENRADIX_ARY.ilog2() as usize;
+
+/* Enough levels to cover every usize index with one object per page */
+const GENRADIX_MAX_DEPTH: usize = (usize::BITS as usize).div_ceil(GENRADIX_ARY_SHIFT);
+const GENRADIX_DEPTH_MASK: usize = (GENRADIX_MAX_DEPTH + 1).next_power_of_two() - 1;
+
+
+/* An interior node */
+struct GenradixNode {
+    children: [AtomicPtr<u8>; GENRADIX_ARY],
+}
+
+
+/**
+ * struct GenRadix - sparse array of stable objects
+ * @root: the root node, tagged with the depth of the tree
+ *
+ * A tree of depth 0 is a single leaf, every level above multiplies the
+ * number of leaves by GENRADIX_ARY.
+ */
+pub struct GenRadix<T> {
+    root: AtomicPtr<u8>,
+    _marker: PhantomData<T>,
+}
+
+/* Objects are only created and dropped through the owner of the tree */
+unsafe impl<T: Send> Send for GenRadix<T> {}
+unsafe impl<T: Send + Sync> Sync for GenRadix<T> {}
+
+
+fn genradix_node_layout() -> Layout {
+    Layout::from_size_align(PAGE_SIZE, PAGE_SIZE).unwrap()
+}
+
+
+fn genradix_root_to_node(r: *mut u8) -> *mut u8 {
+    r.map_addr(|addr| addr & !GENRADIX_DEPTH_MASK)
+}
+
+
+fn genradix_root_to_depth(r: *mut u8) -> usize {
+    r.addr() & GENRADIX_DEPTH_MASK
+}
+
+
+/* Number of leaves under a node at @level, below GENRADIX_MAX_DEPTH */
+fn genradix_depth_leaves(level: usize) -> usize {
+    1 << (level * GENRADIX_ARY_SHIFT)
+}
+
+
+/* Whether a tree of depth @depth covers leaf @page */
+fn genradix_depth_covers(depth: usize, page: usize) -> bool {
+    page.checked_shr((depth * GENRADIX_ARY_SHIFT) as u32).unwrap_or(0) == 0
+}
+
+
+/* Slot of leaf @page in an interior node at @level */
+fn genradix_child_idx(page: usize, level: usize) -> usize {
+    (page >> ((level - 1) * GENRADIX_ARY_SHIFT)) & (GENRADIX_ARY - 1)
+}
+
+
+impl<T> Default for GenRadix<T> {
+    fn default() -> Self {
+        GenRadix::genradix_init()
+    }
+}
+
+#[allow(dead_code)]
+impl<T> GenRadix<T> {
+    /* Objects per leaf, a zero sized object takes a byte */
+    const OBJS_PER_PAGE: usize = {
+        assert!(size_of::<T>() <= PAGE_SIZE, "genradix objects must fit in a page");
+        PAGE_SIZE / if size_of::<T>() == 0 { 1 } else { size_of::<T>() }
+    };
+
+
+    /**
+     * genradix_init - create an empty tree
+     */
+    pub fn genradix_init() -> Self {
+        GenRadix {
+            root: AtomicPtr::new(ptr::null_mut()),
+            _marker: PhantomData,
+        }
+    }
+
+
+    fn genradix_alloc_node() -> Result<*mut u8, i32> {
+        let node = unsafe { alloc::alloc_zeroed(genradix_node_layout()) };
+
+        if node.is_null() {
+            return Err(-ENOMEM);
+        }
+        Ok(node)
+    }
+
+
+    fn genradix_alloc_leaf() -> Result<*mut u8, i32>
+    where
+        T: Default,
+    {
+        let leaf = GenRadix::<T>::genradix_alloc_node()?;
+
+        for i in 0..Self::OBJS_PER_PAGE {
+            unsafe { leaf.cast::<T>().add(i).write(T::default()) };
+        }
+        Ok(leaf)
+    }
+
+
+    unsafe fn genradix_free_leaf(leaf: *mut u8) {
+        for i in 0..Self::OBJS_PER_PAGE {
+            ptr::drop_in_place(leaf.cast::<T>().add(i));
+        }
+        alloc::dealloc(leaf, genradix_node_layout());
+    }
+
+
+    /* Free the subtree of @node, at @level */
+    unsafe fn genradix_free_recurse(node: *mut u8, level: usize) {
+        if level == 0 {
+            GenRadix::<T>::genradix_free_leaf(node);
+            return;
+        }
+        let interior = &*node.cast::<GenradixNode>();
+        for child in interior.children.iter() {
+            let child = child.load(Ordering::Relaxed);
+            if !child.is_null() {
+                GenRadix::<T>::genradix_free_recurse(child, level - 1);
+            }
+        }
+        alloc::dealloc(node, genradix_node_layout());
+    }
+
+
+    /**
+     * genradix_free - free all the objects of the tree
+     */
+    pub fn genradix_free(&mut self) {
+        let r = self.root.swap(ptr::null_mut(), Ordering::Acquire);
+        let n = genradix_root_to_node(r);
+
+        if !n.is_null() {
+            unsafe { GenRadix::<T>::genradix_free_recurse(n, genradix_root_to_depth(r)) };
+        }
+    }
+
+
+    /* The leaf holding page @page, or %None */
+    fn genradix_leaf(&self, page: usize) -> Option<*mut u8> {
+        let r = self.root.load(Ordering::Acquire);
+        let mut n = genradix_root_to_node(r);
+        let mut level = genradix_root_to_depth(r);
+
+        if n.is_null() || !genradix_depth_covers(level, page) {
+            return None;
+        }
+        while level > 0 {
+            let interior = unsafe { &*n.cast::<GenradixNode>() };
+            n = interior.children[genradix_child_idx(page, level)].load(Ordering::Acquire);
+            if n.is_null() {
+                return None;
+            }
+            level -= 1;
+        }
+        Some(n)
+    }
+
+
+    fn genradix_obj(&self, leaf: *mut u8, idx: usize) -> &T {
+        unsafe { &*leaf.cast::<T>().add(idx % Self::OBJS_PER_PAGE) }
+    }
+
+
+    /**
+     * genradix_ptr - get a reference to a genradix entry
+     * @idx: index to fetch
+     *
+     * Return: %None if that object hasn't been allocated yet.
+     */
+    pub fn genradix_ptr(&self, idx: usize) -> Option<&T> {
+        let leaf = self.genradix_leaf(idx / Self::OBJS_PER_PAGE)?;
+
+        Some(self.genradix_obj(leaf, idx))
+    }
+
+
+    /**
+     * genradix_ptr_mut - get a mutable reference to a genradix entry
+     * @idx: index to fetch
+     */
+    pub fn genradix_ptr_mut(&mut self, idx: usize) -> Option<&mut T> {
+        let leaf = self.genradix_leaf(idx / Self::OBJS_PER_PAGE)?;
+
+        Some(unsafe { &mut *leaf.cast::<T>().add(idx % Self::OBJS_PER_PAGE) })
+    }
+
+
+    /* Install a new node in the empty @slot, or take the one that won the race */
+    fn genradix_install(slot: &AtomicPtr<u8>, level: usize) -> Result<*mut u8, i32>
+    where
+        T: Default,
+    {
+        let n = slot.load(Ordering::Acquire);
+        if !n.is_null() {
+            return Ok(n);
+        }
+
+        let new_node = if level == 0 {
+            GenRadix::<T>::genradix_alloc_leaf()?
+        } else {
+            GenRadix::<T>::genradix_alloc_node()?
+        };
+        match slot.compare_exchange(ptr::null_mut(), new_node, Ordering::AcqRel, Ordering::Acquire) {
+            Ok(_) => Ok(new_node),
+            Err(n) => {
+                unsafe { GenRadix::<T>::genradix_free_recurse(new_node, level) };
+                Ok(n)
+            }
+        }
+    }
+
+
+    /**
+     * genradix_ptr_alloc - get a reference to a genradix entry, allocating
+     *                      it if necessary
+     * @idx: index to fetch
+     *
+     * The objects of a new leaf are built by T::default().
+     *
+     * Return: the object, or -ENOMEM if a node can't be allocated.
+     */
+    pub fn genradix_ptr_alloc(&self, idx: usize) -> Result<&T, i32>
+    where
+        T: Default,
+    {
+        let page = idx / Self::OBJS_PER_PAGE;
+        let mut new_node: *mut u8 = ptr::null_mut();
+
+        /* Grow the tree until it covers @page */
+        let (mut n, mut level) = loop {
+            let r = self.root.load(Ordering::Acquire);
+            let n = genradix_root_to_node(r);
+            let level = genradix_root_to_depth(r);
+
+            if !n.is_null() && genradix_depth_covers(level, page) {
+                break (n, level);
+            }
+            if n.is_null() {
+                /* The first node is the leaf of a tree of depth 0 */
+                let leaf = GenRadix::<T>::genradix_alloc_leaf()?;
+                if self.root.compare_exchange(r, leaf, Ordering::AcqRel, Ordering::Acquire).is_err() {
+                    unsafe { GenRadix::<T>::genradix_free_leaf(leaf) };
+                }
+                continue;
+            }
+
+            if new_node.is_null() {
+                new_node = GenRadix::<T>::genradix_alloc_node()?;
+            }
+            let interior = unsafe { &*new_node.cast::<GenradixNode>() };
+            interior.children[0].store(n, Ordering::Relaxed);
+            let new_root = new_node.map_addr(|addr| addr | (level + 1));
+            if self.root.compare_exchange(r, new_root, Ordering::AcqRel, Ordering::Acquire).is_ok() {
+                new_node = ptr::null_mut();
+            } else {
+                interior.children[0].store(ptr::null_mut(), Ordering::Relaxed);
+            }
+        };
+        if !new_node.is_null() {
+            unsafe { alloc::dealloc(new_node, genradix_node_layout()) };
+        }
+
+        while level > 0 {
+            let interior = unsafe { &*n.cast::<GenradixNode>() };
+            n = GenRadix::<T>::genradix_install(&interior.children[genradix_child_idx(page, level)], level - 1)?;
+            level -= 1;
+        }
+        Ok(self.genradix_obj(n, idx))
+    }
+
+
+    /**
+     * genradix_prealloc - preallocate entries in a generic radix tree
+     * @nr: number of entries to preallocate, from index 0
+     *
+     * Return: -ENOMEM on allocation failure, -ERANGE if @nr overflows.
+     */
+    pub fn genradix_prealloc(&self, nr: usize) -> Result<(), i32>
+    where
+        T: Default,
+    {
+        if nr == 0 {
+            return Ok(());
+        }
+        let last = nr.checked_sub(1).ok_or(-ERANGE)?;
+
+        for page in 0..=last / Self::OBJS_PER_PAGE {
+            self.genradix_ptr_alloc(page * Self::OBJS_PER_PAGE)?;
+        }
+        Ok(())
+    }
+
+
+    /**
+     * genradix_iter_peek - find the first allocated object at or after @idx
+     * @idx: index to start from
+     *
+     * Skips whole subtrees that aren't allocated.
+     *
+     * Return: the index and the object, or %None past the last leaf.
+     */
+    pub fn genradix_iter_peek(&self, mut idx: usize) -> Option<(usize, &T)> {
+        'restart: loop {
+            let r = self.root.load(Ordering::Acquire);
+            let mut n = genradix_root_to_node(r);
+            let mut level = genradix_root_to_depth(r);
+            let page = idx / Self::OBJS_PER_PAGE;
+
+            if n.is_null() || !genradix_depth_covers(level, page) {
+                return None;
+            }
+            while level > 0 {
+                let interior = unsafe { &*n.cast::<GenradixNode>() };
+                n = interior.children[genradix_child_idx(page, level)].load(Ordering::Acquire);
+                if n.is_null() {
+                    /* Skip to the first leaf of the next subtree at this level */
+                    let span = genradix_depth_leaves(level - 1);
+                    let next_page = (page / span + 1).checked_mul(span)?;
+                    idx = next_page.checked_mul(Self::OBJS_PER_PAGE)?;
+                    continue 'restart;
+                }
+                level -= 1;
+            }
+            return Some((idx, self.genradix_obj(n, idx)));
+        }
+    }
+
+
+    /**
+     * genradix_iter - iterate over the allocated objects
+     *
+     * Yields the index and the object of every slot of every allocated
+     * leaf, in index order.
+     */
+    pub fn genradix_iter(&self) -> GenradixIter<'_, T> {
+        GenradixIter { radix: self, pos: Some(0) }
+    }
+
+
+    /**
+     * genradix_iter_from - iterate over the allocated objects from @idx
+     */
+    pub fn genradix_iter_from(&self, idx: usize) -> GenradixIter<'_, T> {
+        GenradixIter { radix: self, pos: Some(idx) }
+    }
+}
+
+
+impl<T> Drop for GenRadix<T> {
+    fn drop(&mut self) {
+        self.genradix_free();
+    }
+}
+
+
+/**
+ * struct GenradixIter - iterator over the allocated objects of a GenRadix
+ * @pos: the next index to look at, %None once past the end
+ */
+pub struct GenradixIter<'a, T> {
+    radix: &'a GenRadix<T>,
+    pos: Option<usize>,
+}
+
+
+impl<'a, T> Iterator for GenradixIter<'a, T> {
+    type Item = (usize, &'a T);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (idx, obj) = self.radix.genradix_iter_peek(self.pos?)?;
+
+        self.pos = idx.checked_add(1);
+        Some((idx, obj))
+    }
+}
+
+
+/* Sections for Unit tests */
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+
+    use super::*;
+
+    #[test]
+    fn test_genradix_ptr_alloc() {
+        let mut radix: GenRadix<u64> = GenRadix::genradix_init();
+
+        assert!(radix.genradix_ptr(0).is_none());
+        assert_eq!(*radix.genradix_ptr_alloc(5).unwrap(), 0);
+        /* The whole leaf came with it */
+        assert_eq!(GenRadix::<u64>::OBJS_PER_PAGE, 512);
+        assert!(radix.genradix_ptr(511).is_some());
+        assert!(radix.genradix_ptr(512).is_none());
+
+        *radix.genradix_ptr_mut(5).unwrap() = 55;
+        assert_eq!(radix.genradix_ptr(5), Some(&55));
+        assert_eq!(radix.genradix_ptr_alloc(5), Ok(&55));
+
+        radix.genradix_free();
+        assert!(radix.genradix_ptr(5).is_none());
+    }
+
+
+    #[test]
+    fn test_genradix_stable() {
+        let radix: GenRadix<AtomicUsize> = GenRadix::genradix_init();
+        let first = radix.genradix_ptr_alloc(3).unwrap();
+        first.store(3, Ordering::Relaxed);
+
+        /* Growing the tree under the leaf doesn't move it */
+        let far = [1 << 20, 1 << 30, usize::MAX / 2, usize::MAX];
+        for &idx in &far {
+            radix.genradix_ptr_alloc(idx).unwrap().store(idx, Ordering::Relaxed);
+        }
+        assert!(ptr::eq(radix.genradix_ptr(3).unwrap(), first));
+        assert_eq!(first.load(Ordering::Relaxed), 3);
+        for &idx in &far {
+            assert_eq!(radix.genradix_ptr(idx).unwrap().load(Ordering::Relaxed), idx);
+        }
+        assert!(radix.genradix_ptr((1 << 20) + 512).is_none());
+        assert_eq!(genradix_root_to_depth(radix.root.load(Ordering::Relaxed)), 7);
+    }
+
+
+    #[test]
+    fn test_genradix_iter() {
+        let radix: GenRadix<u32> = GenRadix::genradix_init();
+
+        assert_eq!(radix.genradix_iter().count(), 0);
+        radix.genradix_prealloc(1025).unwrap();
+        assert_eq!(radix.genradix_iter().count(), 2 * 1024);
+        radix.genradix_ptr_alloc(1 << 24).unwrap();
+        radix.genradix_ptr_alloc(usize::MAX).unwrap();
+
+        let pages: Vec<_> = radix
+            .genradix_iter()
+            .filter(|(idx, _)| idx % 1024 == 0)
+            .map(|(idx, _)| idx)
+            .collect();
+        assert_eq!(pages, vec![0, 1024, 1 << 24, usize::MAX - 1023]);
+        assert_eq!(radix.genradix_iter().last().unwrap().0, usize::MAX);
+
+        assert_eq!(radix.genradix_iter_peek(2000).unwrap().0, 2000);
+        assert_eq!(radix.genradix_iter_peek(2048).unwrap().0, 1 << 24);
+        assert_eq!(radix.genradix_iter_from((1 << 24) + 1024).next().unwrap().0, usize::MAX - 1023);
+    }
+
+
+    #[test]
+    fn test_genradix_drop() {
+        let tracker = Arc::new(());
+
+        let radix: GenRadix<Mutex<Option<Arc<()>>>> = GenRadix::genradix_init();
+        for idx in [0, 7, 1000, 1 << 16] {
+            *radix.genradix_ptr_alloc(idx).unwrap().lock().unwrap() = Some(tracker.clone());
+        }
+        assert_eq!(Arc::strong_count(&tracker), 5);
+        drop(radix);
+        assert_eq!(Arc::strong_count(&tracker), 1);
+
+        /* Zero sized objects take a byte of a leaf */
+        let units: GenRadix<()> = GenRadix::genradix_init();
+        units.genradix_prealloc(PAGE_SIZE + 1).unwrap();
+        assert_eq!(units.genradix_iter().count(), 2 * PAGE_SIZE);
+    }
+
+
+    #[test]
+    fn test_genradix_concurrent() {
+        const THREADS: usize = 4;
+        let radix: Arc<GenRadix<AtomicUsize>> = Arc::new(GenRadix::genradix_init());
+
+        let handles: Vec<_> = (0..THREADS)
+            .map(|t| {
+                let radix = radix.clone();
+                thread::spawn(move || {
+                    let mut addrs = Vec::new();
+                    for i in 0..200 {
+                        /* Every thread allocates the same sparse indices */
+                        let idx = i * 4099 + (i % 7) * (1 << 22);
+                        let obj = radix.genradix_ptr_alloc(idx).unwrap();
+                        obj.fetch_add(1, Ordering::Relaxed);
+                        addrs.push((idx, obj as *const AtomicUsize as usize));
+                        if i % 20 == t {
+                            thread::yield_now();
+                        }
+                    }
+                    addrs
+                })
+            })
+            .collect();
+
+        let results: Vec<_> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+        for addrs in &results[1..] {
+            assert_eq!(addrs, &results[0]);
+        }
+        let distinct: HashSet<_> = results[0].iter().map(|&(_, addr)| addr).collect();
+        assert_eq!(distinct.len(), 200);
+        for &(idx, _) in &results[0] {
+            assert_eq!(radix.genradix_ptr(idx).unwrap().load(Ordering::Relaxed), THREADS);
+        }
+    }
+}
@@ -0,0 +1,582 @@
+use std::collections::BTreeMap;
+
+use crate::errno::{EINVAL, ENOENT, ENOSPC};
+
+/* Number of IDs tracked by a single IDA leaf bitmap */
+pub const IDA_BITMAP_BITS: u32 = 1024;
+const IDA_BITMAP_LONGS: usize = (IDA_BITMAP_BITS / u64::BITS) as usize;
+
+/* IDs handed out by both allocators never exceed INT_MAX */
+pub const ID_MAX: u32 = i32::MAX as u32;
+
+
+struct IdaBitmap {
+    bitmap: [u64; IDA_BITMAP_LONGS],
+}
+
+impl IdaBitmap {
+    fn new() -> Box<Self> {
+        Box::new(IdaBitmap { bitmap: [0; IDA_BITMAP_LONGS] })
+    }
+
+    fn test_bit(&self, bit: u32) -> bool {
+        self.bitmap[(bit / u64::BITS) as usize] & (1 << (bit % u64::BITS)) != 0
+    }
+
+    fn set_bit(&mut self, bit: u32) {
+        self.bitmap[(bit / u64::BITS) as usize] |= 1 << (bit % u64::BITS);
+    }
+
+    fn clear_bit(&mut self, bit: u32) {
+        self.bitmap[(bit / u64::BITS) as usize] &= !(1 << (bit % u64::BITS));
+    }
+
+    fn is_empty(&self) -> bool {
+        self.bitmap.iter().all(|&word| word == 0)
+    }
+
+    /* Find the first clear bit at or after @bit, or IDA_BITMAP_BITS if none */
+    fn find_next_zero_bit(&self, bit: u32) -> u32 {
+        let mut idx = (bit / u64::BITS) as usize;
+        let mut word = !self.bitmap[idx] & (!0u64 << (bit % u64::BITS));
+
+        loop {
+            if word != 0 {
+                return idx as u32 * u64::BITS + word.trailing_zeros();
+            }
+            idx += 1;
+            if idx == IDA_BITMAP_LONGS {
+                return IDA_BITMAP_BITS;
+            }
+            word = !self.bitmap[idx];
+        }
+    }
+}
+
+
+/**
+ * struct Ida - ID allocator
+ *
+ * Hands out unique IDs without associating them with a pointer. Allocated
+ * IDs are tracked in sparse 1024-bit leaf bitmaps, so an Ida costs one bit
+ * per ID in use rather than one map entry.
+ */
+#[derive(Default)]
+pub struct Ida {
+    leaves: BTreeMap<u32, Box<IdaBitmap>>,
+}
+
+#[allow(dead_code)]
+impl Ida {
+    pub fn new() -> Self {
+        Ida { leaves: BTreeMap::new() }
+    }
+
+
+    /**
+     * ida_alloc_range - Allocate an unused ID.
+     * @min: Lowest ID to allocate.
+     * @max: Highest ID to allocate.
+     *
+     * Allocate an ID between @min and @max, inclusive. The allocated ID will
+     * not exceed %INT_MAX, even if @max is larger.
+     *
+     * Return: The allocated ID, or -ENOSPC if there are no free IDs in the range.
+     */
+    pub fn ida_alloc_range(&mut self, min: u32, max: u32) -> Result<u32, i32> {
+        if min > ID_MAX {
+            return Err(-ENOSPC);
+        }
+        let max = max.min(ID_MAX);
+
+        let mut id = min;
+        while id <= max {
+            let index = id / IDA_BITMAP_BITS;
+            let bit = id % IDA_BITMAP_BITS;
+
+            let leaf = match self.leaves.get_mut(&index) {
+                Some(leaf) => leaf,
+                None => {
+                    let leaf = self.leaves.entry(index).or_insert_with(IdaBitmap::new);
+                    leaf.set_bit(bit);
+                    return Ok(id);
+                }
+            };
+
+            let bit = leaf.find_next_zero_bit(bit);
+            if bit < IDA_BITMAP_BITS {
+                id = index * IDA_BITMAP_BITS + bit;
+                if id > max {
+                    break;
+                }
+                leaf.set_bit(bit);
+                return Ok(id);
+            }
+
+            /* Leaf is full, carry on with the next one */
+            id = match (index + 1).checked_mul(IDA_BITMAP_BITS) {
+                Some(next) => next,
+                None => break,
+            };
+        }
+
+        Err(-ENOSPC)
+    }
+
+
+    /**
+     * ida_alloc - Allocate an unused ID.
+     *
+     * Allocate an ID between 0 and %INT_MAX, inclusive.
+     */
+    pub fn ida_alloc(&mut self) -> Result<u32, i32> {
+        self.ida_alloc_range(0, ID_MAX)
+    }
+
+
+    /**
+     * ida_alloc_min - Allocate an unused ID.
+     * @min: Lowest ID to allocate.
+     *
+     * Allocate an ID between @min and %INT_MAX, inclusive.
+     */
+    pub fn ida_alloc_min(&mut self, min: u32) -> Result<u32, i32> {
+        self.ida_alloc_range(min, ID_MAX)
+    }
+
+
+    /**
+     * ida_alloc_max - Allocate an unused ID.
+     * @max: Highest ID to allocate.
+     *
+     * Allocate an ID between 0 and @max, inclusive.
+     */
+    pub fn ida_alloc_max(&mut self, max: u32) -> Result<u32, i32> {
+        self.ida_alloc_range(0, max)
+    }
+
+
+    /**
+     * ida_free - Release an allocated ID.
+     * @id: Previously allocated ID.
+     *
+     * Freeing an ID which is not allocated is silently ignored.
+     */
+    pub fn ida_free(&mut self, id: u32) {
+        let index = id / IDA_BITMAP_BITS;
+
+        if let Some(leaf) = self.leaves.get_mut(&index) {
+            leaf.clear_bit(id % IDA_BITMAP_BITS);
+            if leaf.is_empty() {
+                self.leaves.remove(&index);
+            }
+        }
+    }
+
+
+    /**
+     * ida_exists - Check if an ID was allocated.
+     * @id: The ID to check.
+     */
+    pub fn ida_exists(&self, id: u32) -> bool {
+        self.leaves
+            .get(&(id / IDA_BITMAP_BITS))
+            .is_some_and(|leaf| leaf.test_bit(id % IDA_BITMAP_BITS))
+    }
+
+
+    /**
+     * ida_is_empty - Check whether no ID is allocated.
+     */
+    pub fn ida_is_empty(&self) -> bool {
+        self.leaves.is_empty()
+    }
+
+
+    /**
+     * ida_destroy - Free all IDs.
+     *
+     * After calling this function, the IDA is empty and may be reused.
+     */
+    pub fn ida_destroy(&mut self) {
+        self.leaves.clear();
+    }
+}
+
+
+/**
+ * struct Idr - ID to object mapping
+ *
+ * Associates small integer IDs with objects of type @T. IDs are allocated
+ * in the same way as by an Ida, lowest available first, and the Idr owns
+ * the object stored under each ID until it is removed.
+ */
+pub struct Idr<T> {
+    entries: BTreeMap<u32, T>,
+    ids: Ida,
+    idr_next: u32,
+}
+
+impl<T> Default for Idr<T> {
+    fn default() -> Self {
+        Idr::new()
+    }
+}
+
+#[allow(dead_code)]
+impl<T> Idr<T> {
+    pub fn new() -> Self {
+        Idr {
+            entries: BTreeMap::new(),
+            ids: Ida::new(),
+            idr_next: 0,
+        }
+    }
+
+
+    /* Allocate an ID in [*@nextid, @max] and store @ptr there */
+    fn idr_alloc_u32(&mut self, ptr: T, nextid: &mut u32, max: u32) -> Result<(), (T, i32)> {
+        match self.ids.ida_alloc_range(*nextid, max) {
+            Ok(id) => {
+                self.entries.insert(id, ptr);
+                *nextid = id;
+                Ok(())
+            }
+            Err(err) => Err((ptr, err)),
+        }
+    }
+
+
+    /**
+     * idr_alloc - Allocate an ID.
+     * @ptr: Object to be associated with the new ID.
+     * @start: The minimum ID (inclusive).
+     * @end: The maximum ID (exclusive).
+     *
+     * Allocates an unused ID in the range specified by @start and @end. If
+     * @end is <= 0, it is treated as one larger than %INT_MAX. This allows
+     * callers to use @start + N as @end as long as N is within integer range.
+     *
+     * Return: The newly allocated ID, -ENOSPC if no free IDs could be found,
+     * or -EINVAL if @start is negative.
+     */
+    pub fn idr_alloc(&mut self, ptr: T, start: i32, end: i32) -> Result<i32, i32> {
+        if start < 0 {
+            return Err(-EINVAL);
+        }
+        let max = if end > 0 { end as u32 - 1 } else { ID_MAX };
+        let mut id = start as u32;
+
+        self.idr_alloc_u32(ptr, &mut id, max).map_err(|(_, err)| err)?;
+
+        Ok(id as i32)
+    }
+
+
+    /**
+     * idr_alloc_cyclic - Allocate an ID cyclically.
+     * @ptr: Object to be associated with the new ID.
+     * @start: The minimum ID (inclusive).
+     * @end: The maximum ID (exclusive).
+     *
+     * Allocates an unused ID in the range specified by @start and @end. If
+     * @end is <= 0, it is treated as one larger than %INT_MAX. The search for
+     * an unused ID will start at the last ID allocated and will wrap around
+     * to @start if no free IDs are found before reaching @end.
+     *
+     * Return: The newly allocated ID, -ENOSPC if no free IDs could be found,
+     * or -EINVAL if @start is negative.
+     */
+    pub fn idr_alloc_cyclic(&mut self, ptr: T, start: i32, end: i32) -> Result<i32, i32> {
+        if start < 0 {
+            return Err(-EINVAL);
+        }
+        let max = if end > 0 { end as u32 - 1 } else { ID_MAX };
+        let mut id = self.idr_next.max(start as u32);
+
+        if let Err((ptr, err)) = self.idr_alloc_u32(ptr, &mut id, max) {
+            if err != -ENOSPC || id <= start as u32 {
+                return Err(err);
+            }
+            id = start as u32;
+            self.idr_alloc_u32(ptr, &mut id, max).map_err(|(_, err)| err)?;
+        }
+
+        self.idr_next = id.wrapping_add(1);
+        Ok(id as i32)
+    }
+
+
+    /**
+     * idr_find - Return pointer for given ID.
+     * @id: Pointer ID.
+     *
+     * Looks up the object associated with this ID.
+     */
+    pub fn idr_find(&self, id: i32) -> Option<&T> {
+        self.entries.get(&u32::try_from(id).ok()?)
+    }
+
+
+    /**
+     * idr_find_mut - Return mutable pointer for given ID.
+     * @id: Pointer ID.
+     */
+    pub fn idr_find_mut(&mut self, id: i32) -> Option<&mut T> {
+        self.entries.get_mut(&u32::try_from(id).ok()?)
+    }
+
+
+    /**
+     * idr_remove - Remove an ID from the IDR.
+     * @id: Pointer ID.
+     *
+     * Removes this ID from the IDR. If the ID was not previously in the IDR,
+     * this function returns %None.
+     *
+     * Return: The object formerly associated with this ID.
+     */
+    pub fn idr_remove(&mut self, id: i32) -> Option<T> {
+        let id = u32::try_from(id).ok()?;
+        let ptr = self.entries.remove(&id)?;
+
+        self.ids.ida_free(id);
+        Some(ptr)
+    }
+
+
+    /**
+     * idr_replace - replace pointer for given ID.
+     * @ptr: New object to associate with the ID.
+     * @id: ID to change.
+     *
+     * Replace the object registered with an ID and return the old value.
+     * This function can be called under the RCU read lock concurrently with
+     * idr_alloc() and idr_remove() (as long as the ID being removed is not
+     * the one being replaced!) in the kernel; here the borrow checker
+     * enforces exclusive access instead.
+     *
+     * Return: the old value on success. -ENOENT indicates that @id was not
+     * found. -EINVAL indicates that @id was negative.
+     */
+    pub fn idr_replace(&mut self, ptr: T, id: i32) -> Result<T, i32> {
+        let id = u32::try_from(id).map_err(|_| -EINVAL)?;
+
+        match self.entries.get_mut(&id) {
+            Some(slot) => Ok(std::mem::replace(slot, ptr)),
+            None => Err(-ENOENT),
+        }
+    }
+
+
+    /**
+     * idr_for_each - Iterate through all stored pointers.
+     * @func: Function to be called for each pointer.
+     *
+     * The callback function will be called for each entry in the IDR, passing
+     * the ID and the entry. If @func returns anything other than 0, the loop
+     * stops and that value is returned from this function.
+     */
+    pub fn idr_for_each<F>(&self, mut func: F) -> i32
+    where
+        F: FnMut(i32, &T) -> i32,
+    {
+        for (&id, ptr) in self.entries.iter() {
+            let ret = func(id as i32, ptr);
+            if ret != 0 {
+                return ret;
+            }
+        }
+        0
+    }
+
+
+    /**
+     * idr_get_next - Find next populated entry.
+     * @nextid: Pointer to an ID.
+     *
+     * Returns the next populated entry in the tree with an ID greater than
+     * or equal to the value pointed to by @nextid. On exit, @nextid is
+     * updated to the ID of the found value.
+     */
+    pub fn idr_get_next(&self, nextid: &mut i32) -> Option<&T> {
+        let start = u32::try_from(*nextid).ok()?;
+        let (&id, ptr) = self.entries.range(start..).next()?;
+
+        *nextid = id as i32;
+        Some(ptr)
+    }
+
+
+    /**
+     * idr_is_empty - Are there any IDs allocated?
+     */
+    pub fn idr_is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+
+    /**
+     * idr_get_cursor - Return the current position of the cyclic allocator.
+     *
+     * The value returned is the value that will be next returned from
+     * idr_alloc_cyclic() if it is free.
+     */
+    pub fn idr_get_cursor(&self) -> u32 {
+        self.idr_next
+    }
+
+
+    /**
+     * idr_set_cursor - Set the current position of the cyclic allocator.
+     * @val: The new position.
+     */
+    pub fn idr_set_cursor(&mut self, val: u32) {
+        self.idr_next = val;
+    }
+
+
+    /**
+     * idr_destroy - Release all internal memory from an IDR.
+     *
+     * All objects still stored in the IDR are dropped.
+     */
+    pub fn idr_destroy(&mut self) {
+        self.entries.clear();
+        self.ids.ida_destroy();
+    }
+}
+
+
+/* Sections for Unit tests */
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_idr_alloc() {
+        let mut idr = Idr::new();
+
+        assert_eq!(idr.idr_alloc("a", 0, 0), Ok(0));
+        assert_eq!(idr.idr_alloc("b", 0, 0), Ok(1));
+        assert_eq!(idr.idr_alloc("c", 10, 0), Ok(10));
+        assert_eq!(idr.idr_alloc("d", 0, 0), Ok(2));
+
+        assert_eq!(idr.idr_find(1), Some(&"b"));
+        assert_eq!(idr.idr_find(10), Some(&"c"));
+        assert_eq!(idr.idr_find(3), None);
+    }
+
+
+    #[test]
+    fn test_idr_alloc_range_exhaustion() {
+        let mut idr = Idr::new();
+
+        assert_eq!(idr.idr_alloc(1, 5, 7), Ok(5));
+        assert_eq!(idr.idr_alloc(2, 5, 7), Ok(6));
+        assert_eq!(idr.idr_alloc(3, 5, 7), Err(-ENOSPC));
+        assert_eq!(idr.idr_alloc(4, -1, 7), Err(-EINVAL));
+        assert_eq!(idr.idr_alloc(5, 8, 7), Err(-ENOSPC));
+
+        assert_eq!(idr.idr_remove(5), Some(1));
+        assert_eq!(idr.idr_alloc(6, 5, 7), Ok(5));
+    }
+
+
+    #[test]
+    fn test_idr_alloc_cyclic() {
+        let mut idr = Idr::new();
+
+        assert_eq!(idr.idr_alloc_cyclic('a', 1, 4), Ok(1));
+        assert_eq!(idr.idr_alloc_cyclic('b', 1, 4), Ok(2));
+        assert_eq!(idr.idr_remove(1), Some('a'));
+
+        /* Does not reuse the freed ID until it wraps around */
+        assert_eq!(idr.idr_alloc_cyclic('c', 1, 4), Ok(3));
+        assert_eq!(idr.idr_alloc_cyclic('d', 1, 4), Ok(1));
+        assert_eq!(idr.idr_alloc_cyclic('e', 1, 4), Err(-ENOSPC));
+
+        idr.idr_set_cursor(ID_MAX);
+        assert_eq!(idr.idr_remove(2), Some('b'));
+        assert_eq!(idr.idr_alloc_cyclic('f', 0, 0), Ok(ID_MAX as i32));
+        assert_eq!(idr.idr_alloc_cyclic('g', 0, 0), Ok(0));
+    }
+
+
+    #[test]
+    fn test_idr_replace_remove() {
+        let mut idr = Idr::new();
+
+        let id = idr.idr_alloc(String::from("old"), 0, 0).unwrap();
+
+        assert_eq!(idr.idr_replace(String::from("new"), id), Ok(String::from("old")));
+        assert_eq!(idr.idr_replace(String::from("none"), id + 1), Err(-ENOENT));
+        assert_eq!(idr.idr_replace(String::from("none"), -1), Err(-EINVAL));
+
+        assert_eq!(idr.idr_remove(id), Some(String::from("new")));
+        assert_eq!(idr.idr_remove(id), None);
+        assert!(idr.idr_is_empty());
+    }
+
+
+    #[test]
+    fn test_idr_for_each() {
+        let mut idr = Idr::new();
+
+        for i in 0..10 {
+            idr.idr_alloc(i * 10, 0, 0).unwrap();
+        }
+
+        let mut sum = 0;
+        assert_eq!(idr.idr_for_each(|_, &v| { sum += v; 0 }), 0);
+        assert_eq!(sum, 450);
+
+        let mut visited = Vec::new();
+        let ret = idr.idr_for_each(|id, _| {
+            visited.push(id);
+            if id == 3 { -1 } else { 0 }
+        });
+        assert_eq!(ret, -1);
+        assert_eq!(visited, vec![0, 1, 2, 3]);
+
+        let mut id = 5;
+        assert_eq!(idr.idr_get_next(&mut id), Some(&50));
+        assert_eq!(id, 5);
+    }
+
+
+    #[test]
+    fn test_ida_alloc_free() {
+        let mut ida = Ida::new();
+
+        for i in 0..3000 {
+            assert_eq!(ida.ida_alloc(), Ok(i));
+        }
+
+        ida.ida_free(1500);
+        ida.ida_free(7);
+        assert!(!ida.ida_exists(7));
+        assert_eq!(ida.ida_alloc(), Ok(7));
+        assert_eq!(ida.ida_alloc(), Ok(1500));
+        assert_eq!(ida.ida_alloc(), Ok(3000));
+
+        for i in 0..=3000 {
+            ida.ida_free(i);
+        }
+        assert!(ida.ida_is_empty());
+    }
+
+
+    #[test]
+    fn test_ida_alloc_range() {
+        let mut ida = Ida::new();
+
+        assert_eq!(ida.ida_alloc_range(1023, 1024), Ok(1023));
+        assert_eq!(ida.ida_alloc_range(1023, 1024), Ok(1024));
+        assert_eq!(ida.ida_alloc_range(1023, 1024), Err(-ENOSPC));
+        assert_eq!(ida.ida_alloc_range(ID_MAX + 1, u32::MAX), Err(-ENOSPC));
+        assert_eq!(ida.ida_alloc_min(u32::MAX - 1), Err(-ENOSPC));
+        assert_eq!(ida.ida_alloc_range(ID_MAX, u32::MAX), Ok(ID_MAX));
+        assert_eq!(ida.ida_alloc_range(ID_MAX, u32::MAX), Err(-ENOSPC));
+    }
+}
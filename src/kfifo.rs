@@ -0,0 +1,660 @@
+use std::cell::UnsafeCell;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use crate::errno::EINVAL;
+
+
+/*
+ * Shared state of a fifo. @in and @out are free running counters which
+ * are only masked when indexing the buffer, so in - out is always the
+ * number of used bytes. Only the producer advances @in and only the
+ * consumer advances @out; the release/acquire pairs on them order the
+ * buffer accesses, so no lock is needed with one producer and one consumer.
+ */
+struct KFifoInner {
+    data: Box<[UnsafeCell<u8>]>,
+    mask: usize,
+    recsize: usize,
+    r#in: AtomicUsize,
+    out: AtomicUsize,
+}
+
+/* Producer and consumer never touch the same bytes of @data at the same time */
+unsafe impl Sync for KFifoInner {}
+
+impl KFifoInner {
+    fn new(size: usize, recsize: usize) -> Result<Self, i32> {
+        if !(2..=(usize::MAX >> 1) + 1).contains(&size) {
+            return Err(-EINVAL);
+        }
+        let size = size.next_power_of_two();
+
+        Ok(KFifoInner {
+            data: (0..size).map(|_| UnsafeCell::new(0)).collect(),
+            mask: size - 1,
+            recsize,
+            r#in: AtomicUsize::new(0),
+            out: AtomicUsize::new(0),
+        })
+    }
+
+    fn size(&self) -> usize {
+        self.mask + 1
+    }
+
+    fn len(&self) -> usize {
+        self.r#in.load(Ordering::Acquire).wrapping_sub(self.out.load(Ordering::Acquire))
+    }
+
+    fn unused(&self) -> usize {
+        self.size() - self.len()
+    }
+
+    /* Largest record length which fits in the length header */
+    fn max_r(&self, len: usize) -> usize {
+        len.min((1 << (self.recsize << 3)) - 1)
+    }
+
+    fn buf(&self) -> *mut u8 {
+        UnsafeCell::raw_get(self.data.as_ptr())
+    }
+
+    fn copy_in(&self, src: &[u8], off: usize) {
+        let off = off & self.mask;
+        let l = src.len().min(self.size() - off);
+
+        unsafe {
+            let buf = self.buf();
+            std::ptr::copy_nonoverlapping(src.as_ptr(), buf.add(off), l);
+            std::ptr::copy_nonoverlapping(src.as_ptr().add(l), buf, src.len() - l);
+        }
+    }
+
+    fn copy_out(&self, dst: &mut [u8], off: usize) {
+        let off = off & self.mask;
+        let l = dst.len().min(self.size() - off);
+
+        unsafe {
+            let buf = self.buf();
+            std::ptr::copy_nonoverlapping(buf.add(off), dst.as_mut_ptr(), l);
+            std::ptr::copy_nonoverlapping(buf, dst.as_mut_ptr().add(l), dst.len() - l);
+        }
+    }
+
+    /* Producer side */
+
+    fn kfifo_in(&self, buf: &[u8]) -> usize {
+        let r#in = self.r#in.load(Ordering::Relaxed);
+
+        if self.recsize == 0 {
+            let len = buf.len().min(self.unused());
+            self.copy_in(&buf[..len], r#in);
+            self.r#in.store(r#in.wrapping_add(len), Ordering::Release);
+            return len;
+        }
+
+        let len = buf.len();
+        if len != self.max_r(len) || len + self.recsize > self.unused() {
+            return 0;
+        }
+        let header = (len as u16).to_le_bytes();
+        self.copy_in(&header[..self.recsize], r#in);
+        self.copy_in(buf, r#in.wrapping_add(self.recsize));
+        self.r#in.store(r#in.wrapping_add(len + self.recsize), Ordering::Release);
+        len
+    }
+
+    fn kfifo_avail(&self) -> usize {
+        let avail = self.unused();
+
+        match self.recsize {
+            0 => avail,
+            recsize if avail <= recsize => 0,
+            recsize => self.max_r(avail - recsize),
+        }
+    }
+
+    /* Consumer side */
+
+    /* Length of the record at @out, the fifo must not be empty */
+    fn peek_n(&self, out: usize) -> usize {
+        let mut header = [0u8; 2];
+        self.copy_out(&mut header[..self.recsize], out);
+        u16::from_le_bytes(header) as usize
+    }
+
+    fn out_peek(&self, buf: &mut [u8]) -> usize {
+        let out = self.out.load(Ordering::Relaxed);
+        let used = self.r#in.load(Ordering::Acquire).wrapping_sub(out);
+
+        if self.recsize == 0 {
+            let len = buf.len().min(used);
+            self.copy_out(&mut buf[..len], out);
+            return len;
+        }
+
+        if used == 0 {
+            return 0;
+        }
+        let len = buf.len().min(self.peek_n(out));
+        self.copy_out(&mut buf[..len], out.wrapping_add(self.recsize));
+        len
+    }
+
+    fn kfifo_skip_count(&self, count: usize) {
+        let out = self.out.load(Ordering::Relaxed);
+        let used = self.r#in.load(Ordering::Acquire).wrapping_sub(out);
+
+        let skip = match self.recsize {
+            0 => count.min(used),
+            _ if used == 0 => 0,
+            recsize => self.peek_n(out) + recsize,
+        };
+        self.out.store(out.wrapping_add(skip), Ordering::Release);
+    }
+
+    fn kfifo_out(&self, buf: &mut [u8]) -> usize {
+        let len = self.out_peek(buf);
+
+        match self.recsize {
+            0 => self.kfifo_skip_count(len),
+            _ => self.kfifo_skip_count(1),
+        }
+        len
+    }
+
+    fn kfifo_peek_len(&self) -> usize {
+        let out = self.out.load(Ordering::Relaxed);
+
+        match self.recsize {
+            0 => self.len(),
+            _ if self.is_empty() => 0,
+            _ => self.peek_n(out),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+
+/**
+ * struct KFifo - bounded byte or record ring buffer
+ *
+ * The buffer size is always a power of two so that positions can be
+ * masked instead of divided. A byte fifo moves plain bytes, a record fifo
+ * (allocated with kfifo_alloc_rec()) prefixes every kfifo_in() with a 1 or 2
+ * byte length header so that kfifo_out() hands back whole records.
+ *
+ * A KFifo owns both ends. To use it from two threads without locking,
+ * split it into a KFifoProducer and a KFifoConsumer with kfifo_split().
+ */
+pub struct KFifo {
+    inner: Arc<KFifoInner>,
+}
+
+#[allow(dead_code)]
+impl KFifo {
+    /**
+     * kfifo_alloc - dynamically allocates a new fifo buffer
+     * @size: the number of bytes in the fifo, rounded up to a power of 2
+     *
+     * Return: the fifo, or -EINVAL if @size is smaller than 2.
+     */
+    pub fn kfifo_alloc(size: usize) -> Result<Self, i32> {
+        Ok(KFifo { inner: Arc::new(KFifoInner::new(size, 0)?) })
+    }
+
+
+    /**
+     * kfifo_alloc_rec - dynamically allocates a new record fifo buffer
+     * @size: the number of bytes in the fifo, rounded up to a power of 2
+     * @recsize: size of the record length header, 1 or 2 bytes
+     *
+     * Return: the fifo, or -EINVAL if @size or @recsize is invalid.
+     */
+    pub fn kfifo_alloc_rec(size: usize, recsize: usize) -> Result<Self, i32> {
+        if recsize != 1 && recsize != 2 {
+            return Err(-EINVAL);
+        }
+        Ok(KFifo { inner: Arc::new(KFifoInner::new(size, recsize)?) })
+    }
+
+
+    /**
+     * kfifo_split - split the fifo into its producer and consumer ends
+     *
+     * Each end may be moved to its own thread.
+     */
+    pub fn kfifo_split(self) -> (KFifoProducer, KFifoConsumer) {
+        (
+            KFifoProducer { inner: self.inner.clone() },
+            KFifoConsumer { inner: self.inner },
+        )
+    }
+
+
+    /**
+     * kfifo_recsize - returns the size of the record length field
+     */
+    pub fn kfifo_recsize(&self) -> usize {
+        self.inner.recsize
+    }
+
+
+    /**
+     * kfifo_size - returns the size of the fifo in bytes
+     */
+    pub fn kfifo_size(&self) -> usize {
+        self.inner.size()
+    }
+
+
+    /**
+     * kfifo_len - returns the number of used bytes in the fifo
+     */
+    pub fn kfifo_len(&self) -> usize {
+        self.inner.len()
+    }
+
+
+    /**
+     * kfifo_avail - returns the number of unused bytes in the fifo
+     *
+     * For a record fifo this is the largest record which can still be put.
+     */
+    pub fn kfifo_avail(&self) -> usize {
+        self.inner.kfifo_avail()
+    }
+
+
+    /**
+     * kfifo_is_empty - returns true if the fifo is empty
+     */
+    pub fn kfifo_is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+
+
+    /**
+     * kfifo_is_full - returns true if the fifo is full
+     */
+    pub fn kfifo_is_full(&self) -> bool {
+        self.inner.len() > self.inner.mask
+    }
+
+
+    /**
+     * kfifo_reset - removes the entire fifo content
+     */
+    pub fn kfifo_reset(&mut self) {
+        self.inner.r#in.store(0, Ordering::Relaxed);
+        self.inner.out.store(0, Ordering::Relaxed);
+    }
+
+
+    /**
+     * kfifo_in - put data into the fifo
+     * @buf: the data to be added
+     *
+     * Copies as many bytes of @buf as fit into a byte fifo. A record fifo
+     * stores @buf as one record, or nothing if it does not fit.
+     *
+     * Return: the number of bytes copied.
+     */
+    pub fn kfifo_in(&mut self, buf: &[u8]) -> usize {
+        self.inner.kfifo_in(buf)
+    }
+
+
+    /**
+     * kfifo_put - put a single byte into a byte fifo
+     * @val: the byte to be added
+     *
+     * Return: false if the fifo was full.
+     */
+    pub fn kfifo_put(&mut self, val: u8) -> bool {
+        self.inner.kfifo_in(&[val]) == 1
+    }
+
+
+    /**
+     * kfifo_out - get data from the fifo
+     * @buf: where the data must be copied
+     *
+     * Copies at most @buf.len() bytes out of a byte fifo. A record fifo
+     * copies out the next record, truncated to @buf, and drops it entirely.
+     *
+     * Return: the number of bytes copied.
+     */
+    pub fn kfifo_out(&mut self, buf: &mut [u8]) -> usize {
+        self.inner.kfifo_out(buf)
+    }
+
+
+    /**
+     * kfifo_get - get a single byte from a byte fifo
+     */
+    pub fn kfifo_get(&mut self) -> Option<u8> {
+        let mut val = [0u8];
+        (self.inner.kfifo_out(&mut val) == 1).then_some(val[0])
+    }
+
+
+    /**
+     * kfifo_out_peek - gets some data from the fifo without removing it
+     * @buf: where the data must be copied
+     *
+     * Return: the number of bytes copied.
+     */
+    pub fn kfifo_out_peek(&self, buf: &mut [u8]) -> usize {
+        self.inner.out_peek(buf)
+    }
+
+
+    /**
+     * kfifo_peek - get a single byte from a byte fifo without removing it
+     */
+    pub fn kfifo_peek(&self) -> Option<u8> {
+        let mut val = [0u8];
+        (self.inner.out_peek(&mut val) == 1).then_some(val[0])
+    }
+
+
+    /**
+     * kfifo_peek_len - gets the size of the next fifo record
+     *
+     * For a byte fifo this is the number of used bytes.
+     */
+    pub fn kfifo_peek_len(&self) -> usize {
+        self.inner.kfifo_peek_len()
+    }
+
+
+    /**
+     * kfifo_skip - skip output data
+     *
+     * Drops the next byte of a byte fifo or the next record of a record fifo.
+     */
+    pub fn kfifo_skip(&mut self) {
+        self.inner.kfifo_skip_count(1);
+    }
+
+
+    /**
+     * kfifo_skip_count - skip output data
+     * @count: count of bytes to skip
+     */
+    pub fn kfifo_skip_count(&mut self, count: usize) {
+        self.inner.kfifo_skip_count(count);
+    }
+}
+
+
+/**
+ * struct KFifoProducer - the input end of a split fifo
+ */
+pub struct KFifoProducer {
+    inner: Arc<KFifoInner>,
+}
+
+#[allow(dead_code)]
+impl KFifoProducer {
+    pub fn kfifo_size(&self) -> usize {
+        self.inner.size()
+    }
+
+    pub fn kfifo_len(&self) -> usize {
+        self.inner.len()
+    }
+
+    pub fn kfifo_avail(&self) -> usize {
+        self.inner.kfifo_avail()
+    }
+
+    pub fn kfifo_is_full(&self) -> bool {
+        self.inner.len() > self.inner.mask
+    }
+
+    pub fn kfifo_in(&mut self, buf: &[u8]) -> usize {
+        self.inner.kfifo_in(buf)
+    }
+
+    pub fn kfifo_put(&mut self, val: u8) -> bool {
+        self.inner.kfifo_in(&[val]) == 1
+    }
+}
+
+
+/**
+ * struct KFifoConsumer - the output end of a split fifo
+ */
+pub struct KFifoConsumer {
+    inner: Arc<KFifoInner>,
+}
+
+#[allow(dead_code)]
+impl KFifoConsumer {
+    pub fn kfifo_size(&self) -> usize {
+        self.inner.size()
+    }
+
+    pub fn kfifo_len(&self) -> usize {
+        self.inner.len()
+    }
+
+    pub fn kfifo_is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+
+    pub fn kfifo_out(&mut self, buf: &mut [u8]) -> usize {
+        self.inner.kfifo_out(buf)
+    }
+
+    pub fn kfifo_get(&mut self) -> Option<u8> {
+        let mut val = [0u8];
+        (self.inner.kfifo_out(&mut val) == 1).then_some(val[0])
+    }
+
+    pub fn kfifo_out_peek(&self, buf: &mut [u8]) -> usize {
+        self.inner.out_peek(buf)
+    }
+
+    pub fn kfifo_peek(&self) -> Option<u8> {
+        let mut val = [0u8];
+        (self.inner.out_peek(&mut val) == 1).then_some(val[0])
+    }
+
+    pub fn kfifo_peek_len(&self) -> usize {
+        self.inner.kfifo_peek_len()
+    }
+
+    pub fn kfifo_skip(&mut self) {
+        self.inner.kfifo_skip_count(1);
+    }
+
+    pub fn kfifo_skip_count(&mut self, count: usize) {
+        self.inner.kfifo_skip_count(count);
+    }
+}
+
+
+/* Sections for Unit tests */
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+
+    #[test]
+    fn test_kfifo_alloc() {
+        assert_eq!(KFifo::kfifo_alloc(1).err(), Some(-EINVAL));
+        assert_eq!(KFifo::kfifo_alloc_rec(16, 3).err(), Some(-EINVAL));
+
+        let fifo = KFifo::kfifo_alloc(100).unwrap();
+        assert_eq!(fifo.kfifo_size(), 128);
+        assert_eq!(fifo.kfifo_avail(), 128);
+        assert!(fifo.kfifo_is_empty());
+    }
+
+
+    #[test]
+    fn test_kfifo_in_out() {
+        let mut fifo = KFifo::kfifo_alloc(8).unwrap();
+        let mut buf = [0u8; 8];
+
+        assert_eq!(fifo.kfifo_in(b"hello"), 5);
+        assert_eq!(fifo.kfifo_in(b"world"), 3);
+        assert!(fifo.kfifo_is_full());
+        assert_eq!(fifo.kfifo_avail(), 0);
+
+        assert_eq!(fifo.kfifo_out(&mut buf[..4]), 4);
+        assert_eq!(&buf[..4], b"hell");
+
+        /* Wraps around the end of the buffer */
+        assert_eq!(fifo.kfifo_in(b"!!!"), 3);
+        assert_eq!(fifo.kfifo_len(), 7);
+        assert_eq!(fifo.kfifo_out(&mut buf), 7);
+        assert_eq!(&buf[..7], b"owor!!!");
+        assert!(fifo.kfifo_is_empty());
+    }
+
+
+    #[test]
+    fn test_kfifo_peek_skip() {
+        let mut fifo = KFifo::kfifo_alloc(16).unwrap();
+        let mut buf = [0u8; 4];
+
+        assert!(fifo.kfifo_put(1));
+        assert!(fifo.kfifo_put(2));
+        fifo.kfifo_in(&[3, 4, 5]);
+
+        assert_eq!(fifo.kfifo_peek(), Some(1));
+        assert_eq!(fifo.kfifo_out_peek(&mut buf), 4);
+        assert_eq!(buf, [1, 2, 3, 4]);
+        assert_eq!(fifo.kfifo_len(), 5);
+
+        fifo.kfifo_skip();
+        assert_eq!(fifo.kfifo_get(), Some(2));
+        fifo.kfifo_skip_count(2);
+        assert_eq!(fifo.kfifo_get(), Some(5));
+        assert_eq!(fifo.kfifo_get(), None);
+
+        fifo.kfifo_in(&[6, 7]);
+        fifo.kfifo_reset();
+        assert!(fifo.kfifo_is_empty());
+    }
+
+
+    #[test]
+    fn test_kfifo_record() {
+        let mut fifo = KFifo::kfifo_alloc_rec(16, 1).unwrap();
+        let mut buf = [0u8; 16];
+
+        assert_eq!(fifo.kfifo_in(b"abc"), 3);
+        assert_eq!(fifo.kfifo_in(b"defgh"), 5);
+        assert_eq!(fifo.kfifo_len(), 10);
+        assert_eq!(fifo.kfifo_avail(), 5);
+        assert_eq!(fifo.kfifo_in(b"ijklmn"), 0);
+
+        assert_eq!(fifo.kfifo_peek_len(), 3);
+        assert_eq!(fifo.kfifo_out(&mut buf), 3);
+        assert_eq!(&buf[..3], b"abc");
+
+        /* A short buffer truncates the record but still consumes it */
+        assert_eq!(fifo.kfifo_out(&mut buf[..2]), 2);
+        assert_eq!(&buf[..2], b"de");
+        assert!(fifo.kfifo_is_empty());
+
+        fifo.kfifo_in(b"xy");
+        fifo.kfifo_in(b"z");
+        fifo.kfifo_skip();
+        assert_eq!(fifo.kfifo_out(&mut buf), 1);
+        assert_eq!(buf[0], b'z');
+        assert_eq!(fifo.kfifo_out(&mut buf), 0);
+    }
+
+
+    #[test]
+    fn test_kfifo_record_max_len() {
+        let mut fifo = KFifo::kfifo_alloc_rec(1024, 1).unwrap();
+
+        assert_eq!(fifo.kfifo_in(&[0; 256]), 0);
+        assert_eq!(fifo.kfifo_in(&[0; 255]), 255);
+
+        let mut fifo = KFifo::kfifo_alloc_rec(1024, 2).unwrap();
+        let mut buf = [0u8; 1024];
+        assert_eq!(fifo.kfifo_in(&[7; 300]), 300);
+        assert_eq!(fifo.kfifo_peek_len(), 300);
+        assert_eq!(fifo.kfifo_out(&mut buf), 300);
+        assert!(buf[..300].iter().all(|&b| b == 7));
+    }
+
+
+    #[test]
+    fn test_kfifo_spsc() {
+        const COUNT: usize = 1 << 16;
+        let fifo = KFifo::kfifo_alloc(64).unwrap();
+        let (mut producer, mut consumer) = fifo.kfifo_split();
+
+        let writer = thread::spawn(move || {
+            let mut next = 0usize;
+            while next < COUNT {
+                let chunk: Vec<u8> = (next..COUNT.min(next + 13)).map(|i| i as u8).collect();
+                match producer.kfifo_in(&chunk) {
+                    0 => thread::yield_now(),
+                    len => next += len,
+                }
+            }
+        });
+
+        let mut buf = [0u8; 17];
+        let mut expected = 0usize;
+        while expected < COUNT {
+            let len = consumer.kfifo_out(&mut buf);
+            if len == 0 {
+                thread::yield_now();
+            }
+            for &b in &buf[..len] {
+                assert_eq!(b, expected as u8);
+                expected += 1;
+            }
+        }
+
+        writer.join().unwrap();
+        assert!(consumer.kfifo_is_empty());
+    }
+
+
+    #[test]
+    fn test_kfifo_spsc_record() {
+        const COUNT: usize = 10_000;
+        let fifo = KFifo::kfifo_alloc_rec(256, 1).unwrap();
+        let (mut producer, mut consumer) = fifo.kfifo_split();
+
+        let writer = thread::spawn(move || {
+            for i in 0..COUNT {
+                let record = vec![i as u8; i % 32 + 1];
+                while producer.kfifo_in(&record) == 0 {
+                    thread::yield_now();
+                }
+            }
+        });
+
+        let mut buf = [0u8; 32];
+        for i in 0..COUNT {
+            let len = loop {
+                match consumer.kfifo_out(&mut buf) {
+                    0 => thread::yield_now(),
+                    len => break len,
+                }
+            };
+            assert_eq!(len, i % 32 + 1);
+            assert!(buf[..len].iter().all(|&b| b == i as u8));
+        }
+
+        writer.join().unwrap();
+    }
+}
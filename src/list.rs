@@ -1,5 +1,6 @@
 use std::{cell::RefCell, cmp::Ordering, rc::Rc};
-use std::mem::offset_of;
+
+use crate::min_heap::{MinHeap, MinHeapCallbacks};
 
 type Link<T> = Option<Rc<RefCell<T>>>;
 type ListCmpFunc = fn(Rc<RefCell<ListHead>>, Rc<RefCell<ListHead>>) -> Ordering;
@@ -8,7 +9,7 @@ type ListCmpFunc = fn(Rc<RefCell<ListHead>>, Rc<RefCell<ListHead>>) -> Ordering;
 #[macro_export]
 macro_rules! container_of {
     ($ptr:expr, $type:ty, $member:ident) => {{
-        let offset = offset_of!($type, $member);
+        let offset = std::mem::offset_of!($type, $member);
         let ptr = ($ptr as *const _ as *const u8).wrapping_sub(offset);
         ptr as *const $type as *mut $type
     }};
@@ -87,6 +88,30 @@ impl ListHead {
     }
 
 
+    /**
+     * list_next - get the entry following @entry
+     * @entry: the entry to start from
+     *
+     * Wraps around to the list head after the last entry.
+     */
+    #[allow(dead_code)]
+    pub fn list_next(entry: Rc<RefCell<Self>>) -> Rc<RefCell<Self>> {
+        entry.borrow().next.as_ref().unwrap().clone()
+    }
+
+
+    /**
+     * list_prev - get the entry preceding @entry
+     * @entry: the entry to start from
+     *
+     * Wraps around to the list head before the first entry.
+     */
+    #[allow(dead_code)]
+    pub fn list_prev(entry: Rc<RefCell<Self>>) -> Rc<RefCell<Self>> {
+        entry.borrow().prev.as_ref().unwrap().clone()
+    }
+
+
     /**
      * Insert a new entry between two known consecutive entries.
      * 
@@ -203,7 +228,7 @@ impl ListHead {
         ListHead::list_del_init(entry2.clone());
         ListHead::list_replace(entry1.clone(), entry2.clone());
 
-        if Rc::ptr_eq(&pos, &entry1) {
+        if Rc::ptr_eq(pos, &entry1) {
             pos = &entry2;
         }
         ListHead::list_add(entry1.clone(), pos.clone());
@@ -272,12 +297,12 @@ impl ListHead {
 
 
     pub fn list_show(head: Rc<RefCell<Self>>) {
-        let mut current = Some(Rc::clone(&head.borrow().next.as_ref().unwrap()));
+        let mut current = Some(Rc::clone(head.borrow().next.as_ref().unwrap()));
         while let Some(node) = current {
             print!("{} -> ", node.borrow().item);
             current = node.borrow().next.clone();
             
-            if Rc::ptr_eq(&current.as_ref().unwrap(), &head) {
+            if Rc::ptr_eq(current.as_ref().unwrap(), &head) {
                 break;
             }
         }
@@ -285,101 +310,176 @@ impl ListHead {
     }
 
     
-    /* TODO: Refactor in the future, too much duplicated code */
-    pub fn merge(cmp: ListCmpFunc, mut a: Link<Self>, mut b: Link<Self>) -> Link<ListHead> {
-        let head = Some(ListHead::new(0));
-
-        let a_next = a.as_ref().unwrap().borrow().next.clone();
-        let b_next = b.as_ref().unwrap().borrow().next.clone();
-        ListHead::list_del_init(a.as_ref().unwrap().clone());
-        ListHead::list_del_init(b.as_ref().unwrap().clone());
-        a = a_next;
-        b = b_next;
-
-        loop {
-            match (a.clone(), b.clone()) {
-                (Some(a_node), Some(b_node )) => {
-                    if cmp(a_node.clone(), b_node.clone()) != Ordering::Greater {
-                        let a_next = a_node.borrow().next.clone();
-                        ListHead::list_move_tail(a_node.clone(), head.as_ref().unwrap().clone());
-                        if Rc::ptr_eq(&a_node, a_next.as_ref().unwrap()) {
-                            a = None;
-                        } else {
-                            a = a_next;
-                        }
-                    } else {
-                        let b_next = b_node.borrow().next.clone();
-                        ListHead::list_move_tail(b_node.clone(), head.as_ref().unwrap().clone());
-                        if Rc::ptr_eq(&b_node, b_next.as_ref().unwrap()) {
-                            b = None;
-                        } else {
-                            b = b_next;
-                        }
-                    }
-                }
-                (Some(_), None) => {
-                    let tmp = ListHead::new(-1);
-                    ListHead::list_add_tail(tmp.clone(), a.as_ref().unwrap().clone());
-                    ListHead::list_splice_tail(tmp.clone(), head.as_ref().unwrap().clone());
-                    break;
-                }
-                (None, Some(_)) => {
-                    let tmp = ListHead::new(-1);
-                    ListHead::list_add_tail(tmp.clone(), b.as_ref().unwrap().clone());
-                    ListHead::list_splice_tail(tmp.clone(), head.as_ref().unwrap().clone());
-                    break;
-                }
-                (None, None) => break,
+    /*
+     * Merge the sorted, None-terminated lists @a and @b, linked through
+     * their next links only, and return the head of the result.
+     */
+    fn merge(cmp: ListCmpFunc, a: Rc<RefCell<Self>>, b: Rc<RefCell<Self>>) -> Rc<RefCell<Self>> {
+        let (mut a, mut b) = (Some(a), Some(b));
+        let mut head: Link<Self> = None;
+        let mut tail: Link<Self> = None;
+
+        while let (Some(a_node), Some(b_node)) = (a.clone(), b.clone()) {
+            /* if equal, take 'a' -- important for sort stability */
+            let node = if cmp(a_node.clone(), b_node.clone()) != Ordering::Greater {
+                a = a_node.borrow().next.clone();
+                a_node
+            } else {
+                b = b_node.borrow().next.clone();
+                b_node
+            };
+            match &tail {
+                Some(tail) => tail.borrow_mut().next = Some(node.clone()),
+                None => head = Some(node.clone()),
             }
+            tail = Some(node);
         }
+        tail.unwrap().borrow_mut().next = a.or(b);
 
-        head
+        head.unwrap()
     }
 
 
+    /**
+     * list_sort - sort a list
+     * @head: the list to sort
+     * @cmp: the elements comparison function
+     *
+     * The bottom-up merge sort of lib/list_sort.c: entries are pushed onto
+     * a stack of pending sorted sublists, linked through their prev links,
+     * and two sublists of size 2^k are merged as soon as a third one
+     * follows them. The sort is stable and done in place, relinking the
+     * entries of @head without allocating any list head.
+     *
+     * Return: @head, now sorted.
+     */
     pub fn list_sort(head: Rc<RefCell<Self>>, cmp: ListCmpFunc) -> Option<Rc<RefCell<ListHead>>> {
-        let mut count = 0;
+        let first = head.borrow().next.clone().unwrap();
+        let last = head.borrow().prev.clone().unwrap();
 
-        let mut list = head.borrow().next.as_ref().unwrap().clone();
-        let mut pending: Vec<Rc<RefCell<ListHead>>>= Vec::new();
+        /* Zero or one elements */
+        if Rc::ptr_eq(&first, &last) {
+            return Some(head);
+        }
 
-        loop {
-            let mut bits = count;
+        /* Convert to a None-terminated singly-linked list */
+        last.borrow_mut().next = None;
 
+        let mut list = Some(first);
+        let mut pending: Link<Self> = None;
+        let mut count: usize = 0;
+
+        while let Some(node) = list {
+            /* Find the least-significant clear bit in count, None being &pending */
+            let mut tail: Link<Self> = None;
+            let mut bits = count;
             while bits & 1 == 1 {
+                tail = match &tail {
+                    None => pending.clone(),
+                    Some(tail) => tail.borrow().prev.clone(),
+                };
                 bits >>= 1;
             }
 
+            /* Do the indicated merge */
             if bits != 0 {
-                let a = pending.pop();
-                let b = pending.pop();
-
-                pending.push(ListHead::merge(cmp, b, a).unwrap().clone());
+                let a = match &tail {
+                    None => pending.clone(),
+                    Some(tail) => tail.borrow().prev.clone(),
+                }
+                .unwrap();
+                let b = a.borrow().prev.clone().unwrap();
+                let b_prev = b.borrow().prev.clone();
+
+                let merged = ListHead::merge(cmp, b, a);
+                /* Install the merged result in place of the inputs */
+                merged.borrow_mut().prev = b_prev;
+                match &tail {
+                    None => pending = Some(merged),
+                    Some(tail) => tail.borrow_mut().prev = Some(merged),
+                }
             }
 
-            let list_next = list.borrow().next.as_ref().unwrap().clone();
-            ListHead::list_del_init(list.clone());
+            /* Move one element from input list to pending */
+            list = node.borrow().next.clone();
+            node.borrow_mut().next = None;
+            node.borrow_mut().prev = pending.take();
+            pending = Some(node);
+            count += 1;
+        }
+
+        /* End of input; merge together all the pending lists. */
+        let mut list = pending.unwrap();
+        let mut pending = list.borrow().prev.clone().unwrap();
+        loop {
+            let next = pending.borrow().prev.clone();
+            let Some(next) = next else {
+                break;
+            };
+            list = ListHead::merge(cmp, pending, list);
+            pending = next;
+        }
+        let list = ListHead::merge(cmp, pending, list);
+
+        /* Rebuild the prev links and close the circle */
+        let mut prev = head.clone();
+        let mut pos = Some(list);
+        while let Some(node) = pos {
+            prev.borrow_mut().next = Some(node.clone());
+            node.borrow_mut().prev = Some(prev);
+            pos = node.borrow().next.clone();
+            prev = node;
+        }
+        prev.borrow_mut().next = Some(head.clone());
+        head.borrow_mut().prev = Some(prev);
 
-            let tmp_head = ListHead::new(-1);
-            ListHead::list_add_tail(list.clone(), tmp_head.clone());
+        Some(head)
+    }
 
-            pending.push(tmp_head.clone());
 
-            if Rc::ptr_eq(&head, &list_next) {
-                break;
+    /**
+     * list_merge_heads - k-way merge of several sorted lists
+     * @heads: the sorted lists, all of them are emptied
+     * @cmp: the elements comparison function
+     *
+     * Keeps the first entry of every list in a min_heap, so merging n
+     * entries out of k lists costs O(n log k). Entries that compare equal
+     * keep the order of @heads.
+     *
+     * Return: a new list head holding every entry in sorted order.
+     */
+    #[allow(dead_code)]
+    pub fn list_merge_heads(heads: &[Rc<RefCell<Self>>], cmp: ListCmpFunc) -> Rc<RefCell<ListHead>> {
+        let func: MinHeapCallbacks<(Rc<RefCell<Self>>, usize), ListCmpFunc> = MinHeapCallbacks {
+            less: |a, b, cmp| match cmp(a.0.clone(), b.0.clone()) {
+                Ordering::Less => true,
+                Ordering::Equal => a.1 < b.1,
+                Ordering::Greater => false,
+            },
+            swp: None,
+        };
+        let merged = ListHead::new(-1);
+        let mut heap = MinHeap::min_heap_init(heads.len());
+
+        for (i, head) in heads.iter().enumerate() {
+            if !ListHead::list_empty(head.clone()) {
+                let first = head.borrow().next.as_ref().unwrap().clone();
+                let _ = heap.min_heap_push((first, i), &func, &cmp);
             }
-            list = list_next;
-            count += 1;
         }
 
-        while pending.len() > 1 {
-            let a = pending.pop();
-            let b = pending.pop();
+        while let Some((entry, i)) = heap.min_heap_peek().cloned() {
+            ListHead::list_move_tail(entry, merged.clone());
 
-            pending.push(ListHead::merge(cmp, b, a).unwrap().clone());
+            if ListHead::list_empty(heads[i].clone()) {
+                heap.min_heap_pop(&func, &cmp);
+            } else {
+                let next = heads[i].borrow().next.as_ref().unwrap().clone();
+                heap.min_heap_pop_push((next, i), &func, &cmp);
+            }
         }
 
-        pending.pop()
+        merged
     }
 
 }
@@ -589,7 +689,8 @@ mod tests {
         }
 
         let sorted_list = ListHead::list_sort(list.clone(), cmp_func);
-
+        /* Sorted in place, no new list head */
+        assert!(Rc::ptr_eq(sorted_list.as_ref().unwrap(), &list));
 
         let mut i: usize = 0;
         let mut current = sorted_list.as_ref().unwrap().borrow().next.clone();
@@ -598,7 +699,7 @@ mod tests {
 
             current = node.borrow().next.clone();
             i += 1;
-            if Rc::ptr_eq(current.as_ref().unwrap(), &sorted_list.as_ref().unwrap()) {
+            if Rc::ptr_eq(current.as_ref().unwrap(), sorted_list.as_ref().unwrap()) {
                 break;
             }
         }
@@ -612,14 +713,69 @@ mod tests {
 
             current = node.borrow().prev.clone();
 
-            if i != 0 {
-                i -= 1;
-            }
-            if Rc::ptr_eq(current.as_ref().unwrap(), &sorted_list.as_ref().unwrap()) {
+            i = i.saturating_sub(1);
+            if Rc::ptr_eq(current.as_ref().unwrap(), sorted_list.as_ref().unwrap()) {
                 break;
             }
         }
 
         assert_eq!(i, 0);
     }
+
+
+    #[test]
+    fn test_list_sort_stable() {
+        let list = ListHead::new(-1);
+        let nodes: Vec<_> = [3, 1, 3, 2, 1, 3].into_iter().map(ListHead::new).collect();
+
+        assert!(Rc::ptr_eq(&ListHead::list_sort(list.clone(), cmp_func).unwrap(), &list));
+        assert!(ListHead::list_empty(list.clone()));
+        for node in &nodes {
+            ListHead::list_add_tail(node.clone(), list.clone());
+        }
+        ListHead::list_sort(list.clone(), cmp_func);
+
+        /* Equal items keep their order */
+        let mut pos = ListHead::list_next(list.clone());
+        for i in [1, 4, 3, 0, 2, 5] {
+            assert!(Rc::ptr_eq(&pos, &nodes[i]));
+            assert!(Rc::ptr_eq(&ListHead::list_next(ListHead::list_prev(pos.clone())), &pos));
+            pos = ListHead::list_next(pos);
+        }
+        assert!(Rc::ptr_eq(&pos, &list));
+    }
+
+
+    #[test]
+    fn test_list_merge_heads() {
+        let mut rng = rand::thread_rng();
+        let mut map = SortedVec::new();
+        let mut heads = Vec::new();
+
+        for _ in 0..5 {
+            let mut nums: Vec<i32> = (0..20).map(|_| rng.gen_range(0..50)).collect();
+            nums.sort();
+
+            let head = ListHead::new(-1);
+            for num in nums {
+                map.insert(num);
+                ListHead::list_add_tail(ListHead::new(num), head.clone());
+            }
+            heads.push(head);
+        }
+        heads.push(ListHead::new(-1));
+
+        let merged = ListHead::list_merge_heads(&heads, cmp_func);
+        assert!(heads.iter().all(|head| ListHead::list_empty(head.clone())));
+
+        let mut i: usize = 0;
+        let mut current = merged.borrow().next.clone().unwrap();
+        while !Rc::ptr_eq(&current, &merged) {
+            assert_eq!(map[i], current.borrow().item);
+            let next = current.borrow().next.clone().unwrap();
+            current = next;
+            i += 1;
+        }
+        assert_eq!(i, 100);
+    }
 }
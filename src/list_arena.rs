@@ -0,0 +1,925 @@
+use std::cmp::Ordering;
+
+use crate::errno::{EBUSY, EINVAL, ENOENT, ENOSPC};
+
+/*
+ * Arena backed circular doubly linked lists
+ *
+ * The same lists as ListHead, but the nodes of any number of lists live
+ * in one Vec owned by a ListArena and link each other through u32
+ * indices, so there is no reference counting, no borrow flag and no
+ * allocation per node, and neighbouring nodes tend to be close in memory.
+ *
+ * Nodes are referred to by ListHandle, an index paired with the
+ * generation of its slot. Freeing a node bumps the generation, so every
+ * operation on a stale handle fails with -ENOENT instead of touching
+ * whatever node reuses the slot. A list head is a node without an item,
+ * an entry is a node with one, and only entries can be added to a list.
+ */
+
+const LIST_NIL: u32 = u32::MAX;
+
+
+/*
+ * A slot of the arena. A free slot has no item and is linked on the
+ * free list of the arena through @next.
+ */
+struct ListArenaNode<T> {
+    next: u32,
+    prev: u32,
+    gen: u32,
+    item: Option<T>,
+}
+
+
+/**
+ * struct ListHandle - reference to a node of a ListArena
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ListHandle {
+    idx: u32,
+    gen: u32,
+}
+
+
+/**
+ * struct ListArena - storage for the nodes of a set of lists
+ * @nodes: the slots
+ * @free: first free slot
+ * @nr_free: number of free slots
+ */
+pub struct ListArena<T> {
+    nodes: Vec<ListArenaNode<T>>,
+    free: u32,
+    nr_free: usize,
+}
+
+
+impl<T> Default for ListArena<T> {
+    fn default() -> Self {
+        ListArena::new()
+    }
+}
+
+#[allow(dead_code)]
+impl<T> ListArena<T> {
+    pub fn new() -> Self {
+        ListArena::with_capacity(0)
+    }
+
+
+    pub fn with_capacity(capacity: usize) -> Self {
+        ListArena {
+            nodes: Vec::with_capacity(capacity),
+            free: LIST_NIL,
+            nr_free: 0,
+        }
+    }
+
+
+    /**
+     * len - number of allocated nodes, list heads included
+     */
+    pub fn len(&self) -> usize {
+        self.nodes.len() - self.nr_free
+    }
+
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+
+    fn next(&self, idx: u32) -> u32 {
+        self.nodes[idx as usize].next
+    }
+
+
+    fn prev(&self, idx: u32) -> u32 {
+        self.nodes[idx as usize].prev
+    }
+
+
+    fn set_next(&mut self, idx: u32, next: u32) {
+        self.nodes[idx as usize].next = next;
+    }
+
+
+    fn set_prev(&mut self, idx: u32, prev: u32) {
+        self.nodes[idx as usize].prev = prev;
+    }
+
+
+    fn item(&self, idx: u32) -> &T {
+        self.nodes[idx as usize].item.as_ref().unwrap()
+    }
+
+
+    fn is_entry(&self, idx: u32) -> bool {
+        self.nodes[idx as usize].item.is_some()
+    }
+
+
+    /* Index of a handle that refers to a live node */
+    fn index(&self, handle: ListHandle) -> Result<u32, i32> {
+        match self.nodes.get(handle.idx as usize) {
+            Some(node) if node.gen == handle.gen => Ok(handle.idx),
+            _ => Err(-ENOENT),
+        }
+    }
+
+
+    /* Index of a handle that refers to a live entry */
+    fn entry_index(&self, handle: ListHandle) -> Result<u32, i32> {
+        let idx = self.index(handle)?;
+
+        if !self.is_entry(idx) {
+            return Err(-EINVAL);
+        }
+        Ok(idx)
+    }
+
+
+    /* Index of a handle that refers to a live list head */
+    fn head_index(&self, handle: ListHandle) -> Result<u32, i32> {
+        let idx = self.index(handle)?;
+
+        if self.is_entry(idx) {
+            return Err(-EINVAL);
+        }
+        Ok(idx)
+    }
+
+
+    fn handle(&self, idx: u32) -> ListHandle {
+        ListHandle {
+            idx,
+            gen: self.nodes[idx as usize].gen,
+        }
+    }
+
+
+    /* Allocate a node pointing to itself */
+    fn alloc_node(&mut self, item: Option<T>) -> Result<ListHandle, i32> {
+        let idx = if self.free != LIST_NIL {
+            let idx = self.free;
+            self.free = self.next(idx);
+            self.nr_free -= 1;
+            self.nodes[idx as usize].item = item;
+            idx
+        } else {
+            if self.nodes.len() >= LIST_NIL as usize {
+                return Err(-ENOSPC);
+            }
+            self.nodes.push(ListArenaNode {
+                next: LIST_NIL,
+                prev: LIST_NIL,
+                gen: 0,
+                item,
+            });
+            (self.nodes.len() - 1) as u32
+        };
+
+        self.set_next(idx, idx);
+        self.set_prev(idx, idx);
+        Ok(self.handle(idx))
+    }
+
+
+    /**
+     * list_head_init - allocate an empty list head
+     *
+     * Return: the head, or -ENOSPC if the arena has 2^32 - 1 nodes.
+     */
+    pub fn list_head_init(&mut self) -> Result<ListHandle, i32> {
+        self.alloc_node(None)
+    }
+
+
+    /**
+     * list_entry_init - allocate an entry that is on no list yet
+     * @item: the payload of the entry
+     *
+     * Return: the entry, or -ENOSPC if the arena has 2^32 - 1 nodes.
+     */
+    pub fn list_entry_init(&mut self, item: T) -> Result<ListHandle, i32> {
+        self.alloc_node(Some(item))
+    }
+
+
+    /**
+     * list_free - free a node
+     * @handle: the node to free
+     *
+     * An entry is deleted from its list first. A head that still has
+     * entries leaves them linked to each other, without a head.
+     *
+     * Return: the item of an entry, %None for a list head.
+     */
+    pub fn list_free(&mut self, handle: ListHandle) -> Result<Option<T>, i32> {
+        let idx = self.index(handle)?;
+
+        self.__list_del_entry(idx);
+        let node = &mut self.nodes[idx as usize];
+        node.gen = node.gen.wrapping_add(1);
+        node.prev = LIST_NIL;
+        node.next = self.free;
+        self.free = idx;
+        self.nr_free += 1;
+        Ok(node.item.take())
+    }
+
+
+    pub fn get(&self, handle: ListHandle) -> Option<&T> {
+        let idx = self.index(handle).ok()?;
+        self.nodes[idx as usize].item.as_ref()
+    }
+
+
+    pub fn get_mut(&mut self, handle: ListHandle) -> Option<&mut T> {
+        let idx = self.index(handle).ok()?;
+        self.nodes[idx as usize].item.as_mut()
+    }
+
+
+    /**
+     * Insert a new entry between two known consecutive entries.
+     */
+    fn __list_add(&mut self, new: u32, prev: u32, next: u32) {
+        self.set_prev(next, new);
+        self.set_next(new, next);
+        self.set_prev(new, prev);
+        self.set_next(prev, new);
+    }
+
+
+    /* Unlink @idx and make it point to itself */
+    fn __list_del_entry(&mut self, idx: u32) {
+        let (prev, next) = (self.prev(idx), self.next(idx));
+
+        self.set_prev(next, prev);
+        self.set_next(prev, next);
+        self.set_next(idx, idx);
+        self.set_prev(idx, idx);
+    }
+
+
+    /* Entry and head for an add, @new must not be on a list */
+    fn add_indices(&self, new: ListHandle, head: ListHandle) -> Result<(u32, u32), i32> {
+        let new = self.entry_index(new)?;
+        let head = self.index(head)?;
+
+        if self.next(new) != new {
+            return Err(-EBUSY);
+        }
+        Ok((new, head))
+    }
+
+
+    /**
+     * list_empty - tests whether a list is empty
+     * @head: the list to test
+     */
+    pub fn list_empty(&self, head: ListHandle) -> Result<bool, i32> {
+        let head = self.index(head)?;
+
+        Ok(self.next(head) == head)
+    }
+
+
+    /**
+     * list_add - add a new entry
+     * @new: new entry to be added, which must be on no list
+     * @head: list head to add it after
+     *
+     * Insert a new entry after the specified head.
+     * This is good for implementing stacks.
+     *
+     * Return: -EBUSY if @new is already on a list, -EINVAL if @new is a
+     * list head.
+     */
+    pub fn list_add(&mut self, new: ListHandle, head: ListHandle) -> Result<(), i32> {
+        let (new, head) = self.add_indices(new, head)?;
+
+        self.__list_add(new, head, self.next(head));
+        Ok(())
+    }
+
+
+    /**
+     * list_add_tail - add a new entry
+     * @new: new entry to be added, which must be on no list
+     * @head: list head to add it before
+     *
+     * Insert a new entry before the specified head.
+     * This is useful for implementing queues.
+     */
+    pub fn list_add_tail(&mut self, new: ListHandle, head: ListHandle) -> Result<(), i32> {
+        let (new, head) = self.add_indices(new, head)?;
+
+        self.__list_add(new, self.prev(head), head);
+        Ok(())
+    }
+
+
+    /**
+     * list_del_init - deletes entry from list and reinitialize it.
+     * @entry: the element to delete from the list.
+     */
+    pub fn list_del_init(&mut self, entry: ListHandle) -> Result<(), i32> {
+        let entry = self.entry_index(entry)?;
+
+        self.__list_del_entry(entry);
+        Ok(())
+    }
+
+
+    /**
+     * list_move - delete from one list and add as another's head
+     * @list: the entry to move
+     * @head: the head that will precede our entry
+     */
+    pub fn list_move(&mut self, list: ListHandle, head: ListHandle) -> Result<(), i32> {
+        let list = self.entry_index(list)?;
+        let head = self.index(head)?;
+
+        if list != head {
+            self.__list_del_entry(list);
+            self.__list_add(list, head, self.next(head));
+        }
+        Ok(())
+    }
+
+
+    /**
+     * list_move_tail - delete from one list and add as another's tail
+     * @list: the entry to move
+     * @head: the head that will follow our entry
+     */
+    pub fn list_move_tail(&mut self, list: ListHandle, head: ListHandle) -> Result<(), i32> {
+        let list = self.entry_index(list)?;
+        let head = self.index(head)?;
+
+        if list != head {
+            self.__list_del_entry(list);
+            self.__list_add(list, self.prev(head), head);
+        }
+        Ok(())
+    }
+
+
+    /* List and place of a splice, @head must not be inside @list */
+    fn splice_indices(&self, list: ListHandle, head: ListHandle) -> Result<(u32, u32), i32> {
+        let list = self.head_index(list)?;
+        let head = self.index(head)?;
+
+        if self.is_entry(head) {
+            let mut pos = self.next(list);
+            while pos != list {
+                if pos == head {
+                    return Err(-EINVAL);
+                }
+                pos = self.next(pos);
+            }
+        }
+        Ok((list, head))
+    }
+
+
+    /* Move the entries of @list between @prev and @next, and reinitialize @list */
+    fn __list_splice(&mut self, list: u32, prev: u32, next: u32) {
+        let (first, last) = (self.next(list), self.prev(list));
+
+        if first == list {
+            return;
+        }
+        self.set_prev(first, prev);
+        self.set_next(prev, first);
+        self.set_next(last, next);
+        self.set_prev(next, last);
+        self.set_next(list, list);
+        self.set_prev(list, list);
+    }
+
+
+    /**
+     * list_splice - join two lists, this is designed for stacks
+     * @list: the new list to add, left empty.
+     * @head: the place to add it in the first list.
+     *
+     * Return: -EINVAL if @list is an entry or @head is one of its entries.
+     */
+    pub fn list_splice(&mut self, list: ListHandle, head: ListHandle) -> Result<(), i32> {
+        let (list, head) = self.splice_indices(list, head)?;
+
+        if list != head {
+            self.__list_splice(list, head, self.next(head));
+        }
+        Ok(())
+    }
+
+
+    /**
+     * list_splice_tail - join two lists, each list being a queue
+     * @list: the new list to add, left empty.
+     * @head: the place to add it in the first list.
+     *
+     * Return: -EINVAL if @list is an entry or @head is one of its entries.
+     */
+    pub fn list_splice_tail(&mut self, list: ListHandle, head: ListHandle) -> Result<(), i32> {
+        let (list, head) = self.splice_indices(list, head)?;
+
+        if list != head {
+            self.__list_splice(list, self.prev(head), head);
+        }
+        Ok(())
+    }
+
+
+    /**
+     * list_first_entry - get the first entry of a list
+     * @head: the list to look at
+     */
+    pub fn list_first_entry(&self, head: ListHandle) -> Option<ListHandle> {
+        let head = self.head_index(head).ok()?;
+        let first = self.next(head);
+
+        (first != head).then(|| self.handle(first))
+    }
+
+
+    /**
+     * list_last_entry - get the last entry of a list
+     * @head: the list to look at
+     */
+    pub fn list_last_entry(&self, head: ListHandle) -> Option<ListHandle> {
+        let head = self.head_index(head).ok()?;
+        let last = self.prev(head);
+
+        (last != head).then(|| self.handle(last))
+    }
+
+
+    /**
+     * list_next_entry - get the entry following @entry
+     * @entry: the entry to start from
+     *
+     * Return: %None if @entry is the last entry of its list.
+     */
+    pub fn list_next_entry(&self, entry: ListHandle) -> Option<ListHandle> {
+        let entry = self.entry_index(entry).ok()?;
+        let next = self.next(entry);
+
+        (next != entry && self.is_entry(next)).then(|| self.handle(next))
+    }
+
+
+    /**
+     * list_prev_entry - get the entry preceding @entry
+     * @entry: the entry to start from
+     *
+     * Return: %None if @entry is the first entry of its list.
+     */
+    pub fn list_prev_entry(&self, entry: ListHandle) -> Option<ListHandle> {
+        let entry = self.entry_index(entry).ok()?;
+        let prev = self.prev(entry);
+
+        (prev != entry && self.is_entry(prev)).then(|| self.handle(prev))
+    }
+
+
+    /**
+     * list_for_each_entry - iterate over the entries of a list
+     * @head: the list to iterate over
+     *
+     * Yields nothing if @head is stale or not a list head.
+     */
+    pub fn list_for_each_entry(&self, head: ListHandle) -> ListArenaIter<'_, T> {
+        let head = self.head_index(head).unwrap_or(LIST_NIL);
+        let pos = if head == LIST_NIL { LIST_NIL } else { self.next(head) };
+
+        ListArenaIter { arena: self, head, pos }
+    }
+
+
+    /**
+     * list_count_nodes - count nodes in the list
+     * @head: the head for your list.
+     *
+     * Return: the count, -ENOENT if @head is stale or -EINVAL if @head is
+     * an entry.
+     */
+    pub fn list_count_nodes(&self, head: ListHandle) -> Result<usize, i32> {
+        self.head_index(head)?;
+
+        Ok(self.list_for_each_entry(head).count())
+    }
+
+
+    /*
+     * Merge the sorted, %LIST_NIL terminated lists @a and @b, linked
+     * through next only. On ties the entry of @a goes first.
+     */
+    fn merge<F>(&mut self, cmp: &mut F, mut a: u32, mut b: u32) -> u32
+    where
+        F: FnMut(&T, &T) -> Ordering,
+    {
+        let mut head = LIST_NIL;
+        let mut tail = LIST_NIL;
+
+        loop {
+            let take_a = cmp(self.item(a), self.item(b)) != Ordering::Greater;
+            let (taken, rest) = if take_a { (a, b) } else { (b, a) };
+
+            if tail == LIST_NIL {
+                head = taken;
+            } else {
+                self.set_next(tail, taken);
+            }
+            tail = taken;
+            let next = self.next(taken);
+            if take_a {
+                a = next;
+            } else {
+                b = next;
+            }
+            if next == LIST_NIL {
+                self.set_next(tail, rest);
+                return head;
+            }
+        }
+    }
+
+
+    /*
+     * Merge @a and @b as merge() does, and restore the prev links and the
+     * circular structure of @head on the way.
+     */
+    fn merge_final<F>(&mut self, cmp: &mut F, head: u32, mut a: u32, mut b: u32)
+    where
+        F: FnMut(&T, &T) -> Ordering,
+    {
+        let mut tail = head;
+
+        loop {
+            if cmp(self.item(a), self.item(b)) != Ordering::Greater {
+                self.set_next(tail, a);
+                self.set_prev(a, tail);
+                tail = a;
+                a = self.next(a);
+                if a == LIST_NIL {
+                    break;
+                }
+            } else {
+                self.set_next(tail, b);
+                self.set_prev(b, tail);
+                tail = b;
+                b = self.next(b);
+                if b == LIST_NIL {
+                    b = a;
+                    break;
+                }
+            }
+        }
+
+        /* Finish linking the remainder of the list */
+        self.set_next(tail, b);
+        while b != LIST_NIL {
+            self.set_prev(b, tail);
+            tail = b;
+            b = self.next(b);
+        }
+        self.set_next(tail, head);
+        self.set_prev(head, tail);
+    }
+
+
+    /**
+     * list_sort - sort a list
+     * @head: the list to sort
+     * @cmp: the elements comparison function
+     *
+     * The bottom-up merge sort of lib/list_sort.c: the entries are pushed
+     * onto a stack of pending sorted sublists, linked through their prev
+     * links, and two sublists of size 2^k are merged as soon as a third
+     * one follows them, keeping merges balanced at 2:1 at worst. The sort
+     * is stable and done in place, without allocating.
+     *
+     * Return: -ENOENT if @head is stale or -EINVAL if @head is an entry.
+     */
+    pub fn list_sort<F>(&mut self, head: ListHandle, mut cmp: F) -> Result<(), i32>
+    where
+        F: FnMut(&T, &T) -> Ordering,
+    {
+        let head = self.head_index(head)?;
+        let mut list = self.next(head);
+        let mut pending = LIST_NIL;
+        let mut count: usize = 0;
+
+        /* Zero or one elements */
+        if list == self.prev(head) {
+            return Ok(());
+        }
+
+        /* Convert to a null-terminated singly-linked list */
+        let last = self.prev(head);
+        self.set_next(last, LIST_NIL);
+
+        loop {
+            /* Find the least-significant clear bit in count, %LIST_NIL tail being &pending */
+            let mut tail = LIST_NIL;
+            let mut bits = count;
+            while bits & 1 == 1 {
+                tail = if tail == LIST_NIL { pending } else { self.prev(tail) };
+                bits >>= 1;
+            }
+
+            /* Do the indicated merge */
+            if bits != 0 {
+                let a = if tail == LIST_NIL { pending } else { self.prev(tail) };
+                let b = self.prev(a);
+                let merged = self.merge(&mut cmp, b, a);
+                /* Install the merged result in place of the inputs */
+                self.set_prev(merged, self.prev(b));
+                if tail == LIST_NIL {
+                    pending = merged;
+                } else {
+                    self.set_prev(tail, merged);
+                }
+            }
+
+            /* Move one element from input list to pending */
+            let next = self.next(list);
+            self.set_prev(list, pending);
+            pending = list;
+            self.set_next(pending, LIST_NIL);
+            list = next;
+            count += 1;
+            if list == LIST_NIL {
+                break;
+            }
+        }
+
+        /* End of input; merge together all the pending lists. */
+        list = pending;
+        pending = self.prev(pending);
+        loop {
+            let next = self.prev(pending);
+            if next == LIST_NIL {
+                break;
+            }
+            list = self.merge(&mut cmp, pending, list);
+            pending = next;
+        }
+        /* The final merge, rebuilding prev links */
+        self.merge_final(&mut cmp, head, pending, list);
+        Ok(())
+    }
+}
+
+
+/**
+ * struct ListArenaIter - iterator over the entries of a list
+ *
+ * Yields the handle and the item of every entry, in list order.
+ */
+pub struct ListArenaIter<'a, T> {
+    arena: &'a ListArena<T>,
+    head: u32,
+    pos: u32,
+}
+
+
+impl<'a, T> Iterator for ListArenaIter<'a, T> {
+    type Item = (ListHandle, &'a T);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.pos == self.head {
+            return None;
+        }
+        let pos = self.pos;
+
+        self.pos = self.arena.next(pos);
+        Some((self.arena.handle(pos), self.arena.item(pos)))
+    }
+}
+
+
+/* Sections for Unit tests */
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    use super::*;
+
+    fn items<T: Copy>(arena: &ListArena<T>, head: ListHandle) -> Vec<T> {
+        arena.list_for_each_entry(head).map(|(_, item)| *item).collect()
+    }
+
+
+    #[test]
+    fn test_list_arena_add_del() {
+        let mut arena = ListArena::new();
+        let head = arena.list_head_init().unwrap();
+        let entries: Vec<_> = (0..5).map(|i| arena.list_entry_init(i).unwrap()).collect();
+
+        assert_eq!(arena.list_empty(head), Ok(true));
+        arena.list_add(entries[0], head).unwrap();
+        arena.list_add(entries[1], head).unwrap();
+        arena.list_add_tail(entries[2], head).unwrap();
+        arena.list_add_tail(entries[3], head).unwrap();
+        assert_eq!(items(&arena, head), vec![1, 0, 2, 3]);
+        assert_eq!(arena.list_count_nodes(head), Ok(4));
+
+        assert_eq!(arena.list_add(entries[0], head), Err(-EBUSY));
+        assert_eq!(arena.list_add(head, entries[4]), Err(-EINVAL));
+
+        arena.list_del_init(entries[0]).unwrap();
+        arena.list_del_init(entries[0]).unwrap();
+        assert_eq!(items(&arena, head), vec![1, 2, 3]);
+        assert_eq!(arena.list_first_entry(head), Some(entries[1]));
+        assert_eq!(arena.list_last_entry(head), Some(entries[3]));
+        assert_eq!(arena.list_next_entry(entries[1]), Some(entries[2]));
+        assert_eq!(arena.list_next_entry(entries[3]), None);
+        assert_eq!(arena.list_prev_entry(entries[1]), None);
+        assert_eq!(arena.list_next_entry(entries[0]), None);
+
+        *arena.get_mut(entries[2]).unwrap() = 20;
+        assert_eq!(items(&arena, head), vec![1, 20, 3]);
+    }
+
+
+    #[test]
+    fn test_list_arena_stale_handle() {
+        let mut arena = ListArena::new();
+        let head = arena.list_head_init().unwrap();
+        let a = arena.list_entry_init("a").unwrap();
+        let b = arena.list_entry_init("b").unwrap();
+
+        arena.list_add_tail(a, head).unwrap();
+        arena.list_add_tail(b, head).unwrap();
+        assert_eq!(arena.list_free(a), Ok(Some("a")));
+        assert_eq!(arena.list_free(a), Err(-ENOENT));
+        assert_eq!(items(&arena, head), vec!["b"]);
+        assert_eq!(arena.len(), 2);
+
+        /* The slot of a is reused, a must not reach the new entry */
+        let c = arena.list_entry_init("c").unwrap();
+        assert_eq!(c.idx, a.idx);
+        assert_eq!(arena.get(a), None);
+        assert_eq!(arena.list_add(a, head), Err(-ENOENT));
+        assert_eq!(arena.list_del_init(a), Err(-ENOENT));
+        assert_eq!(arena.list_next_entry(a), None);
+        assert_eq!(arena.get(c), Some(&"c"));
+
+        assert_eq!(arena.list_free(head), Ok(None));
+        assert_eq!(arena.list_empty(head), Err(-ENOENT));
+        assert_eq!(arena.list_for_each_entry(head).count(), 0);
+        assert_eq!(arena.list_count_nodes(head), Err(-ENOENT));
+        assert_eq!(arena.len(), 2);
+    }
+
+
+    #[test]
+    fn test_list_arena_entry_as_head() {
+        let mut arena = ListArena::new();
+        let head = arena.list_head_init().unwrap();
+        let entries: Vec<_> = (0..3).map(|i| arena.list_entry_init(i).unwrap()).collect();
+
+        for &entry in &entries {
+            arena.list_add_tail(entry, head).unwrap();
+        }
+
+        /* Walking from an entry would reach the head, which has no item */
+        assert_eq!(arena.list_for_each_entry(entries[1]).count(), 0);
+        assert_eq!(arena.list_count_nodes(entries[1]), Err(-EINVAL));
+        assert_eq!(arena.list_sort(entries[1], |a, b| b.cmp(a)), Err(-EINVAL));
+        assert_eq!(arena.list_first_entry(entries[1]), None);
+        assert_eq!(arena.list_last_entry(entries[1]), None);
+        assert_eq!(items(&arena, head), vec![0, 1, 2]);
+
+        /* Splicing from an entry would move the head, into itself it breaks the ring */
+        let head2 = arena.list_head_init().unwrap();
+        assert_eq!(arena.list_splice(entries[1], head2), Err(-EINVAL));
+        assert_eq!(arena.list_splice_tail(entries[1], head2), Err(-EINVAL));
+        assert_eq!(arena.list_splice(head, entries[2]), Err(-EINVAL));
+        assert_eq!(arena.list_splice_tail(head, entries[0]), Err(-EINVAL));
+        assert_eq!(items(&arena, head), vec![0, 1, 2]);
+        assert_eq!(arena.list_count_nodes(head2), Ok(0));
+
+        /* An entry of another list is a fine place to splice at */
+        let other = arena.list_entry_init(9).unwrap();
+        arena.list_add(other, head2).unwrap();
+        arena.list_splice_tail(head, other).unwrap();
+        assert_eq!(items(&arena, head2), vec![0, 1, 2, 9]);
+        assert_eq!(arena.list_empty(head), Ok(true));
+    }
+
+
+    #[test]
+    fn test_list_arena_move_splice() {
+        let mut arena = ListArena::new();
+        let list1 = arena.list_head_init().unwrap();
+        let list2 = arena.list_head_init().unwrap();
+        let entries: Vec<_> = (0..6).map(|i| arena.list_entry_init(i).unwrap()).collect();
+
+        for &entry in &entries[..3] {
+            arena.list_add_tail(entry, list1).unwrap();
+        }
+        for &entry in &entries[3..] {
+            arena.list_add_tail(entry, list2).unwrap();
+        }
+
+        arena.list_move(entries[4], list1).unwrap();
+        arena.list_move_tail(entries[0], list2).unwrap();
+        assert_eq!(items(&arena, list1), vec![4, 1, 2]);
+        assert_eq!(items(&arena, list2), vec![3, 5, 0]);
+
+        arena.list_splice(list2, list1).unwrap();
+        assert_eq!(items(&arena, list1), vec![3, 5, 0, 4, 1, 2]);
+        assert_eq!(arena.list_empty(list2), Ok(true));
+
+        arena.list_move(entries[1], list2).unwrap();
+        arena.list_move(entries[2], list2).unwrap();
+        arena.list_splice_tail(list2, list1).unwrap();
+        assert_eq!(items(&arena, list1), vec![3, 5, 0, 4, 2, 1]);
+        arena.list_splice_tail(list2, list1).unwrap();
+        assert_eq!(arena.list_count_nodes(list1), Ok(6));
+    }
+
+
+    #[test]
+    fn test_list_arena_sort() {
+        let mut rng = StdRng::seed_from_u64(47);
+        let mut arena = ListArena::new();
+
+        for len in [0, 1, 2, 3, 7, 64, 100, 1000] {
+            let head = arena.list_head_init().unwrap();
+            let mut expected = Vec::new();
+
+            for seq in 0..len {
+                let key = rng.gen_range(0..len.max(1) / 3 + 1);
+                let entry = arena.list_entry_init((key, seq)).unwrap();
+                arena.list_add_tail(entry, head).unwrap();
+                expected.push((key, seq));
+            }
+
+            /* Sort by key only, ties must keep the insertion order */
+            arena.list_sort(head, |a, b| a.0.cmp(&b.0)).unwrap();
+            expected.sort_by_key(|&(key, _)| key);
+            assert_eq!(items(&arena, head), expected);
+
+            let mut backwards = Vec::new();
+            let mut pos = arena.list_last_entry(head);
+            while let Some(entry) = pos {
+                backwards.push(*arena.get(entry).unwrap());
+                pos = arena.list_prev_entry(entry);
+            }
+            backwards.reverse();
+            assert_eq!(backwards, expected);
+        }
+    }
+
+
+    #[test]
+    fn test_list_arena_random() {
+        let mut rng = StdRng::seed_from_u64(4747);
+        let mut arena = ListArena::with_capacity(64);
+        let heads = [arena.list_head_init().unwrap(), arena.list_head_init().unwrap()];
+        /* Reference model: contents of each list */
+        let mut model: [Vec<(ListHandle, u32)>; 2] = [Vec::new(), Vec::new()];
+
+        for i in 0..5000u32 {
+            let l = rng.gen_range(0..2);
+            match rng.gen_range(0..4) {
+                0 | 1 => {
+                    let entry = arena.list_entry_init(i).unwrap();
+                    if rng.gen_bool(0.5) {
+                        arena.list_add(entry, heads[l]).unwrap();
+                        model[l].insert(0, (entry, i));
+                    } else {
+                        arena.list_add_tail(entry, heads[l]).unwrap();
+                        model[l].push((entry, i));
+                    }
+                }
+                2 if !model[l].is_empty() => {
+                    let (entry, item) = model[l].remove(rng.gen_range(0..model[l].len()));
+                    assert_eq!(arena.list_free(entry), Ok(Some(item)));
+                }
+                3 if !model[l].is_empty() => {
+                    let (entry, item) = model[l].remove(rng.gen_range(0..model[l].len()));
+                    arena.list_move_tail(entry, heads[1 - l]).unwrap();
+                    model[1 - l].push((entry, item));
+                }
+                _ => (),
+            }
+        }
+
+        for l in 0..2 {
+            let got: Vec<_> = arena.list_for_each_entry(heads[l]).map(|(h, &item)| (h, item)).collect();
+            assert_eq!(got, model[l]);
+        }
+        assert_eq!(arena.len(), 2 + model[0].len() + model[1].len());
+    }
+}
@@ -0,0 +1,489 @@
+use std::sync::atomic::{AtomicIsize, Ordering};
+use std::sync::Arc;
+
+use crate::errno::EINVAL;
+use crate::sync_list::{SyncListHead, SyncListNode};
+
+/*
+ * Generic LRU list manager
+ *
+ * Port of mm/list_lru.c. Objects are kept on one of several sublists,
+ * the kernel's per-NUMA-node lists, each with its own lock, so adding
+ * and removing objects scales with the number of nodes. New objects go
+ * to the tail, reclaim walks from the head, i.e. from the least recently
+ * added object towards the most recent one.
+ *
+ * Unlike the kernel the isolate callback does not run under the sublist
+ * lock, it may call back into the ListLru. The walker only acts on the
+ * returned status, so an object that went away meanwhile is simply not
+ * isolated.
+ */
+
+
+/**
+ * enum LruStatus - what the isolate callback decided for an object
+ * @Removed: take the object off the list and hand it to the caller
+ * @Rotate: move the object to the tail, it was referenced recently
+ * @Skip: leave the object where it is
+ * @Retry: restart the walk from the head of the sublist
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LruStatus {
+    Removed,
+    Rotate,
+    Skip,
+    Retry,
+}
+
+pub const LRU_REMOVED: LruStatus = LruStatus::Removed;
+pub const LRU_ROTATE: LruStatus = LruStatus::Rotate;
+pub const LRU_SKIP: LruStatus = LruStatus::Skip;
+pub const LRU_RETRY: LruStatus = LruStatus::Retry;
+
+
+/**
+ * struct ShrinkControl - one reclaim request, as passed to a shrinker
+ * @nid: sublist to reclaim from
+ * @nr_to_scan: objects to look at, decremented by the walk
+ * @nr_scanned: objects looked at so far
+ */
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ShrinkControl {
+    pub nid: usize,
+    pub nr_to_scan: usize,
+    pub nr_scanned: usize,
+}
+
+
+/*
+ * One sublist. @nr_items is only updated after the list operation, so it
+ * can dip below zero for a moment while an add and a del race.
+ */
+struct ListLruOne<T> {
+    list: SyncListHead<T>,
+    nr_items: AtomicIsize,
+}
+
+
+/**
+ * struct ListLru - LRU of objects spread over per-node sublists
+ * @node: the sublists
+ */
+pub struct ListLru<T> {
+    node: Box<[ListLruOne<T>]>,
+}
+
+
+#[allow(dead_code)]
+impl<T> ListLru<T> {
+    /**
+     * list_lru_init - create an LRU with @nr_nodes sublists
+     *
+     * Return: the LRU, or -EINVAL if @nr_nodes is 0.
+     */
+    pub fn list_lru_init(nr_nodes: usize) -> Result<Self, i32> {
+        if nr_nodes == 0 {
+            return Err(-EINVAL);
+        }
+        Ok(ListLru {
+            node: (0..nr_nodes)
+                .map(|_| ListLruOne {
+                    list: SyncListHead::new(),
+                    nr_items: AtomicIsize::new(0),
+                })
+                .collect(),
+        })
+    }
+
+
+    pub fn nr_nodes(&self) -> usize {
+        self.node.len()
+    }
+
+
+    /*
+     * Sublist of an object, the counterpart of page_to_nid(): derived from
+     * the object's address so the same object always maps to the same node.
+     */
+    fn list_lru_obj_nid(&self, item: &SyncListNode<T>) -> usize {
+        let addr = item as *const SyncListNode<T> as u64;
+
+        ((addr >> 4).wrapping_mul(0x9e37_79b9_7f4a_7c15) >> 32) as usize % self.node.len()
+    }
+
+
+    /**
+     * list_lru_add - add an element to the lru list's tail
+     * @item: the object to add
+     * @nid: the sublist to add it to
+     *
+     * Return: false if @item is already on a list.
+     */
+    pub fn list_lru_add(&self, item: &Arc<SyncListNode<T>>, nid: usize) -> bool {
+        let l = &self.node[nid];
+
+        if !l.list.list_add_tail(item.clone()) {
+            return false;
+        }
+        l.nr_items.fetch_add(1, Ordering::Relaxed);
+        true
+    }
+
+
+    /**
+     * list_lru_add_obj - add an element to the lru list's tail
+     * @item: the object to add, its sublist is derived from its address
+     */
+    pub fn list_lru_add_obj(&self, item: &Arc<SyncListNode<T>>) -> bool {
+        self.list_lru_add(item, self.list_lru_obj_nid(item))
+    }
+
+
+    /**
+     * list_lru_del - delete an element from the lru list
+     * @item: the object to delete
+     * @nid: the sublist it was added to
+     *
+     * Return: false if @item is not on that sublist.
+     */
+    pub fn list_lru_del(&self, item: &SyncListNode<T>, nid: usize) -> bool {
+        let l = &self.node[nid];
+
+        if l.list.list_del_init(item).is_none() {
+            return false;
+        }
+        l.nr_items.fetch_sub(1, Ordering::Relaxed);
+        true
+    }
+
+
+    /**
+     * list_lru_del_obj - delete an element added with list_lru_add_obj()
+     */
+    pub fn list_lru_del_obj(&self, item: &SyncListNode<T>) -> bool {
+        self.list_lru_del(item, self.list_lru_obj_nid(item))
+    }
+
+
+    /**
+     * list_lru_count_one - number of objects on a sublist
+     */
+    pub fn list_lru_count_one(&self, nid: usize) -> usize {
+        self.node[nid].nr_items.load(Ordering::Relaxed).max(0) as usize
+    }
+
+
+    /**
+     * list_lru_shrink_count - objects a shrinker could reclaim for @sc
+     */
+    pub fn list_lru_shrink_count(&self, sc: &ShrinkControl) -> usize {
+        self.list_lru_count_one(sc.nid)
+    }
+
+
+    /**
+     * list_lru_count - number of objects on all sublists
+     */
+    pub fn list_lru_count(&self) -> usize {
+        (0..self.node.len()).map(|nid| self.list_lru_count_one(nid)).sum()
+    }
+
+
+    /**
+     * list_lru_walk_one - walk a sublist, isolating and disposing freeable items
+     * @nid: the sublist to walk
+     * @isolate: decides what happens to each object, see &enum LruStatus
+     * @nr_to_walk: how many objects to look at, decremented for each one
+     *
+     * Objects that are rotated come around again once the walk reaches
+     * the tail, so the walk is bounded by @nr_to_walk rather than by the
+     * length of the sublist.
+     *
+     * Return: the isolated objects, in walk order.
+     */
+    pub fn list_lru_walk_one<F>(&self, nid: usize, mut isolate: F, nr_to_walk: &mut usize) -> Vec<Arc<SyncListNode<T>>>
+    where
+        F: FnMut(&Arc<SyncListNode<T>>) -> LruStatus,
+    {
+        let mut dispose = Vec::new();
+
+        self.__list_lru_walk_one(nid, &mut isolate, nr_to_walk, &mut dispose);
+        dispose
+    }
+
+
+    fn __list_lru_walk_one<F>(&self, nid: usize, isolate: &mut F, nr_to_walk: &mut usize, dispose: &mut Vec<Arc<SyncListNode<T>>>)
+    where
+        F: FnMut(&Arc<SyncListNode<T>>) -> LruStatus,
+    {
+        let l = &self.node[nid];
+        let mut pos = l.list.list_first_entry();
+
+        while let Some(item) = pos {
+            if *nr_to_walk == 0 {
+                break;
+            }
+            *nr_to_walk -= 1;
+
+            /* Deleted since we looked it up, our cursor is gone */
+            if item.list_unlinked() {
+                pos = l.list.list_first_entry();
+                continue;
+            }
+
+            let next = l.list.list_next_entry(&item);
+            match isolate(&item) {
+                LruStatus::Removed => {
+                    if let Some(item) = l.list.list_del_init(&item) {
+                        l.nr_items.fetch_sub(1, Ordering::Relaxed);
+                        dispose.push(item);
+                    }
+                }
+                LruStatus::Rotate => {
+                    l.list.list_move_tail(&item);
+                }
+                LruStatus::Skip => {}
+                LruStatus::Retry => {
+                    pos = l.list.list_first_entry();
+                    continue;
+                }
+            }
+            pos = next;
+        }
+    }
+
+
+    /**
+     * list_lru_shrink_walk - walk the sublist of a reclaim request
+     * @sc: the request, @sc.nr_to_scan is consumed and @sc.nr_scanned
+     *      accounts for the objects looked at
+     * @isolate: see list_lru_walk_one()
+     */
+    pub fn list_lru_shrink_walk<F>(&self, sc: &mut ShrinkControl, isolate: F) -> Vec<Arc<SyncListNode<T>>>
+    where
+        F: FnMut(&Arc<SyncListNode<T>>) -> LruStatus,
+    {
+        let budget = sc.nr_to_scan;
+        let dispose = self.list_lru_walk_one(sc.nid, isolate, &mut sc.nr_to_scan);
+
+        sc.nr_scanned += budget - sc.nr_to_scan;
+        dispose
+    }
+
+
+    /**
+     * list_lru_walk - walk all sublists in node order
+     * @isolate: see list_lru_walk_one()
+     * @nr_to_walk: total number of objects to look at
+     *
+     * Return: the isolated objects.
+     */
+    pub fn list_lru_walk<F>(&self, mut isolate: F, mut nr_to_walk: usize) -> Vec<Arc<SyncListNode<T>>>
+    where
+        F: FnMut(&Arc<SyncListNode<T>>) -> LruStatus,
+    {
+        let mut dispose = Vec::new();
+
+        for nid in 0..self.node.len() {
+            if nr_to_walk == 0 {
+                break;
+            }
+            self.__list_lru_walk_one(nid, &mut isolate, &mut nr_to_walk, &mut dispose);
+        }
+        dispose
+    }
+}
+
+
+/* Sections for Unit tests */
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicBool;
+    use std::thread;
+
+    use super::*;
+
+    fn items(lru: &ListLru<i32>, nid: usize) -> Vec<i32> {
+        let mut items = Vec::new();
+        lru.node[nid].list.list_for_each_entry(|node| items.push(node.item));
+        items
+    }
+
+
+    #[test]
+    fn test_list_lru_add_del() {
+        let lru = ListLru::list_lru_init(2).unwrap();
+        let nodes: Vec<_> = (0..6).map(SyncListNode::new).collect();
+
+        assert!(ListLru::<i32>::list_lru_init(0).is_err());
+        for (i, node) in nodes.iter().enumerate() {
+            assert!(lru.list_lru_add(node, i % 2));
+        }
+        assert!(!lru.list_lru_add(&nodes[0], 1));
+        assert_eq!(items(&lru, 0), vec![0, 2, 4]);
+        assert_eq!(lru.list_lru_count_one(1), 3);
+        assert_eq!(lru.list_lru_count(), 6);
+
+        /* Deleting from the wrong sublist is refused */
+        assert!(!lru.list_lru_del(&nodes[1], 0));
+        assert!(lru.list_lru_del(&nodes[1], 1));
+        assert!(!lru.list_lru_del(&nodes[1], 1));
+        assert_eq!(items(&lru, 1), vec![3, 5]);
+        assert_eq!(lru.list_lru_count(), 5);
+    }
+
+
+    #[test]
+    fn test_list_lru_obj() {
+        let lru = ListLru::list_lru_init(4).unwrap();
+        let nodes: Vec<_> = (0..64).map(SyncListNode::new).collect();
+
+        for node in nodes.iter() {
+            assert!(lru.list_lru_add_obj(node));
+        }
+        assert_eq!(lru.list_lru_count(), 64);
+        for node in nodes.iter().step_by(2) {
+            assert!(lru.list_lru_del_obj(node));
+        }
+        assert_eq!(lru.list_lru_count(), 32);
+        assert!((0..4).all(|nid| items(&lru, nid).iter().all(|i| i % 2 == 1)));
+    }
+
+
+    #[test]
+    fn test_list_lru_walk_status() {
+        let lru = ListLru::list_lru_init(1).unwrap();
+        let mut retried = false;
+        let mut visited = Vec::new();
+
+        for i in 0..8 {
+            lru.list_lru_add(&SyncListNode::new(i), 0);
+        }
+
+        let mut nr_to_walk = 10;
+        let dispose = lru.list_lru_walk_one(
+            0,
+            |node| {
+                visited.push(node.item);
+                match node.item {
+                    1 => LRU_ROTATE,
+                    3 if !retried => {
+                        retried = true;
+                        LRU_RETRY
+                    }
+                    i if i % 2 == 0 => LRU_REMOVED,
+                    _ => LRU_SKIP,
+                }
+            },
+            &mut nr_to_walk,
+        );
+
+        /* 1 went to the tail and 0, 2 are gone, so the retry starts over at 3 */
+        assert_eq!(visited, vec![0, 1, 2, 3, 3, 4, 5, 6, 7, 1]);
+        assert_eq!(nr_to_walk, 0);
+        assert_eq!(dispose.iter().map(|n| n.item).collect::<Vec<_>>(), vec![0, 2, 4, 6]);
+        assert!(dispose.iter().all(|n| n.list_unlinked()));
+        assert_eq!(items(&lru, 0), vec![3, 5, 7, 1]);
+        assert_eq!(lru.list_lru_count(), 4);
+    }
+
+
+    #[test]
+    fn test_list_lru_walk_budget() {
+        let lru = ListLru::list_lru_init(3).unwrap();
+
+        for i in 0..12 {
+            lru.list_lru_add(&SyncListNode::new(i), i as usize % 3);
+        }
+
+        /* The budget runs out in the middle of the second sublist */
+        let dispose = lru.list_lru_walk(|_| LRU_REMOVED, 6);
+        assert_eq!(dispose.iter().map(|n| n.item).collect::<Vec<_>>(), vec![0, 3, 6, 9, 1, 4]);
+        assert_eq!(items(&lru, 1), vec![7, 10]);
+        assert_eq!(lru.list_lru_count(), 6);
+
+        /* Rotating everything keeps cycling until the budget is spent */
+        let mut visits = 0;
+        let mut nr_to_walk = 5;
+        lru.list_lru_walk_one(2, |_| { visits += 1; LRU_ROTATE }, &mut nr_to_walk);
+        assert_eq!(visits, 5);
+        assert_eq!(items(&lru, 2), vec![5, 8, 11, 2]);
+    }
+
+
+    #[test]
+    fn test_list_lru_shrink() {
+        let lru = ListLru::list_lru_init(2).unwrap();
+        let mut sc = ShrinkControl { nid: 1, nr_to_scan: 4, nr_scanned: 0 };
+
+        for i in 0..10 {
+            lru.list_lru_add(&SyncListNode::new(i), 1);
+        }
+        assert_eq!(lru.list_lru_shrink_count(&sc), 10);
+
+        /* Odd objects are busy and go back to the tail */
+        let freed = lru.list_lru_shrink_walk(&mut sc, |node| if node.item % 2 == 0 { LRU_REMOVED } else { LRU_ROTATE });
+        assert_eq!(freed.len(), 2);
+        assert_eq!(sc.nr_to_scan, 0);
+        assert_eq!(sc.nr_scanned, 4);
+        assert_eq!(items(&lru, 1), vec![4, 5, 6, 7, 8, 9, 1, 3]);
+
+        /* The callback may call back into the LRU */
+        sc.nr_to_scan = 100;
+        let freed = lru.list_lru_shrink_walk(&mut sc, |node| {
+            lru.list_lru_del(node, 1);
+            LRU_REMOVED
+        });
+        assert!(freed.is_empty());
+        assert_eq!(lru.list_lru_count(), 0);
+    }
+
+
+    #[test]
+    fn test_list_lru_concurrent() {
+        const THREADS: usize = 3;
+        const PER_THREAD: i32 = 300;
+        let lru = Arc::new(ListLru::list_lru_init(2).unwrap());
+        let done = Arc::new(AtomicBool::new(false));
+
+        let reclaimer = {
+            let (lru, done) = (lru.clone(), done.clone());
+            thread::spawn(move || {
+                let mut freed = 0;
+                while !done.load(Ordering::Acquire) || lru.list_lru_count() > 0 {
+                    freed += lru.list_lru_walk(|_| LRU_REMOVED, 16).len();
+                    thread::yield_now();
+                }
+                freed
+            })
+        };
+
+        let workers: Vec<_> = (0..THREADS)
+            .map(|t| {
+                let lru = lru.clone();
+                thread::spawn(move || {
+                    let mut deleted = 0;
+                    for i in 0..PER_THREAD {
+                        let node = SyncListNode::new(i);
+                        lru.list_lru_add_obj(&node);
+                        /* Take some back before the reclaimer gets them */
+                        if (i as usize + t).is_multiple_of(3) && lru.list_lru_del_obj(&node) {
+                            deleted += 1;
+                        }
+                        if i % 16 == 0 {
+                            thread::yield_now();
+                        }
+                    }
+                    deleted
+                })
+            })
+            .collect();
+
+        let deleted: usize = workers.into_iter().map(|w| w.join().unwrap()).sum();
+        done.store(true, Ordering::Release);
+        let freed = reclaimer.join().unwrap();
+
+        assert_eq!(deleted + freed, THREADS * PER_THREAD as usize);
+        assert_eq!(lru.list_lru_count(), 0);
+    }
+}
@@ -0,0 +1,503 @@
+use std::marker::PhantomData;
+use std::ptr;
+use std::sync::atomic::{AtomicPtr, Ordering};
+
+/*
+ * Lock-less NULL terminated single linked list
+ *
+ * Cases where locking is not needed:
+ * If there are multiple producers and multiple consumers, llist_add can be
+ * used in producers and llist_del_all can be used in consumers simultaneously
+ * without locking. Also a single consumer can use llist_del_first while
+ * multiple producers simultaneously use llist_add, without any locking.
+ *
+ * Cases where locking is needed:
+ * If we have multiple consumers with llist_del_first used in one consumer, and
+ * llist_del_first or llist_del_all used in other consumers, then a lock is
+ * needed. This is because llist_del_first depends on list->first->next not
+ * changing, but without lock protection, there's no way to be sure about that
+ * if a preemption happens in the middle of the delete operation and on being
+ * preempted back, the list->first is the same as before causing the cmpxchg in
+ * llist_del_first to succeed. For example, while a llist_del_first operation
+ * is in progress in one consumer, then a llist_del_first, llist_add,
+ * llist_add (or llist_del_all, llist_add, llist_add) sequence in another
+ * consumer may cause violations.
+ *
+ * This can be summarized as follows:
+ *
+ *           |   add    | del_first |  del_all
+ * add       |    -     |     -     |     -
+ * del_first |          |     L     |     L
+ * del_all   |          |           |     -
+ *
+ * Where, a particular row's operation can happen concurrently with a column's
+ * operation, with "-" being no lock needed, while "L" being lock is needed.
+ */
+
+
+pub struct LlistNode<T> {
+    next: *mut LlistNode<T>,
+    pub item: T,
+}
+
+impl<T> LlistNode<T> {
+    pub fn new(item: T) -> Box<Self> {
+        Box::new(LlistNode {
+            next: ptr::null_mut(),
+            item,
+        })
+    }
+}
+
+
+/**
+ * struct LlistNodes - a detached chain of llist nodes
+ *
+ * Returned by llist_del_all() and consumed by llist_add_batch(). The chain
+ * owns its nodes and yields them front to back when iterated.
+ */
+pub struct LlistNodes<T> {
+    first: *mut LlistNode<T>,
+    last: *mut LlistNode<T>,
+    _marker: PhantomData<Box<LlistNode<T>>>,
+}
+
+unsafe impl<T: Send> Send for LlistNodes<T> {}
+
+#[allow(dead_code)]
+impl<T> LlistNodes<T> {
+    pub fn new() -> Self {
+        LlistNodes {
+            first: ptr::null_mut(),
+            last: ptr::null_mut(),
+            _marker: PhantomData,
+        }
+    }
+
+
+    fn from_first(first: *mut LlistNode<T>) -> Self {
+        let mut last = first;
+        unsafe {
+            while !last.is_null() && !(*last).next.is_null() {
+                last = (*last).next;
+            }
+        }
+        LlistNodes { first, last, _marker: PhantomData }
+    }
+
+
+    /**
+     * llist_push_tail - link a node at the end of the chain
+     * @new: new entry to be added
+     */
+    pub fn llist_push_tail(&mut self, new: Box<LlistNode<T>>) {
+        let new = Box::into_raw(new);
+
+        unsafe {
+            (*new).next = ptr::null_mut();
+            if self.last.is_null() {
+                self.first = new;
+            } else {
+                (*self.last).next = new;
+            }
+        }
+        self.last = new;
+    }
+
+
+    pub fn is_empty(&self) -> bool {
+        self.first.is_null()
+    }
+
+
+    /**
+     * iter - iterate over the entries of the chain
+     *
+     * The equivalent of llist_for_each_entry().
+     */
+    pub fn iter(&self) -> LlistIter<'_, T> {
+        LlistIter {
+            pos: self.first,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T> Default for LlistNodes<T> {
+    fn default() -> Self {
+        LlistNodes::new()
+    }
+}
+
+impl<T> FromIterator<T> for LlistNodes<T> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        let mut nodes = LlistNodes::new();
+        for item in iter {
+            nodes.llist_push_tail(LlistNode::new(item));
+        }
+        nodes
+    }
+}
+
+impl<T> Iterator for LlistNodes<T> {
+    type Item = Box<LlistNode<T>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.first.is_null() {
+            return None;
+        }
+        let node = unsafe { Box::from_raw(self.first) };
+
+        self.first = node.next;
+        if self.first.is_null() {
+            self.last = ptr::null_mut();
+        }
+        Some(node)
+    }
+}
+
+impl<T> Drop for LlistNodes<T> {
+    fn drop(&mut self) {
+        for _ in self.by_ref() {}
+    }
+}
+
+
+pub struct LlistIter<'a, T> {
+    pos: *mut LlistNode<T>,
+    _marker: PhantomData<&'a LlistNode<T>>,
+}
+
+impl<'a, T> Iterator for LlistIter<'a, T> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.pos.is_null() {
+            return None;
+        }
+        let node = unsafe { &*self.pos };
+
+        self.pos = node.next;
+        Some(&node.item)
+    }
+}
+
+
+/**
+ * llist_reverse_order - reverse order of a llist chain
+ * @head: first item of the list to be reversed
+ *
+ * Return: the reversed chain, the new first item is the old last item.
+ */
+pub fn llist_reverse_order<T>(mut head: LlistNodes<T>) -> LlistNodes<T> {
+    let mut new_head = LlistNodes::new();
+    new_head.last = head.first;
+
+    let mut pos = std::mem::replace(&mut head.first, ptr::null_mut());
+    head.last = ptr::null_mut();
+    unsafe {
+        while !pos.is_null() {
+            let next = (*pos).next;
+            (*pos).next = new_head.first;
+            new_head.first = pos;
+            pos = next;
+        }
+    }
+    new_head
+}
+
+
+/**
+ * struct LlistHead - lock-less singly linked list head
+ *
+ * Producers on any number of threads push with llist_add(), consumers take
+ * the whole list at once with llist_del_all(). Nodes are pushed at the head,
+ * so a list taken with llist_del_all() is newest first; use
+ * llist_reverse_order() to process it in insertion order.
+ */
+pub struct LlistHead<T> {
+    first: AtomicPtr<LlistNode<T>>,
+    _marker: PhantomData<Box<LlistNode<T>>>,
+}
+
+unsafe impl<T: Send> Send for LlistHead<T> {}
+unsafe impl<T: Send> Sync for LlistHead<T> {}
+
+impl<T> Default for LlistHead<T> {
+    fn default() -> Self {
+        LlistHead::new()
+    }
+}
+
+#[allow(dead_code)]
+impl<T> LlistHead<T> {
+    /**
+     * init_llist_head - initialize lock-less list head
+     */
+    pub fn new() -> Self {
+        LlistHead {
+            first: AtomicPtr::new(ptr::null_mut()),
+            _marker: PhantomData,
+        }
+    }
+
+
+    /**
+     * llist_empty - tests whether a lock-less list is empty
+     *
+     * Not guaranteed to be accurate or up to date. Just a quick way to
+     * test whether the list is empty without deleting something from the
+     * list.
+     */
+    pub fn llist_empty(&self) -> bool {
+        self.first.load(Ordering::Relaxed).is_null()
+    }
+
+
+    /* Link the chain [@new_first, @new_last] in front of the list */
+    fn __llist_add_batch(&self, new_first: *mut LlistNode<T>, new_last: *mut LlistNode<T>) -> bool {
+        let mut first = self.first.load(Ordering::Relaxed);
+
+        loop {
+            unsafe {
+                (*new_last).next = first;
+            }
+            match self.first.compare_exchange_weak(first, new_first, Ordering::Release, Ordering::Relaxed) {
+                Ok(_) => return first.is_null(),
+                Err(cur) => first = cur,
+            }
+        }
+    }
+
+
+    /**
+     * llist_add - add a new entry
+     * @new: new entry to be added
+     *
+     * Return: whether list is empty before adding.
+     */
+    pub fn llist_add(&self, new: Box<LlistNode<T>>) -> bool {
+        let new = Box::into_raw(new);
+        self.__llist_add_batch(new, new)
+    }
+
+
+    /**
+     * llist_add_batch - add several linked entries in batch
+     * @batch: the chain of entries to add
+     *
+     * The chain is added in front of the list, keeping its order.
+     *
+     * Return: whether list is empty before adding.
+     */
+    pub fn llist_add_batch(&self, mut batch: LlistNodes<T>) -> bool {
+        if batch.is_empty() {
+            return self.llist_empty();
+        }
+
+        let new_first = std::mem::replace(&mut batch.first, ptr::null_mut());
+        let new_last = std::mem::replace(&mut batch.last, ptr::null_mut());
+        self.__llist_add_batch(new_first, new_last)
+    }
+
+
+    /**
+     * llist_del_all - delete all entries from lock-less list
+     *
+     * If list is empty, return an empty chain, otherwise, delete all entries
+     * and return them as a chain, newest entry first.
+     */
+    pub fn llist_del_all(&self) -> LlistNodes<T> {
+        LlistNodes::from_first(self.first.swap(ptr::null_mut(), Ordering::Acquire))
+    }
+
+
+    /**
+     * llist_del_first - delete the first entry of lock-less list
+     *
+     * If list is empty, return %None, otherwise, return the first entry
+     * deleted, this is the newest added one.
+     *
+     * # Safety
+     *
+     * Only one llist_del_first user can be used simultaneously with multiple
+     * llist_add users without lock. Because otherwise llist_del_first,
+     * llist_add, llist_add (or llist_del_all, llist_add, llist_add) sequence
+     * in another user may change @head->first->next, but keep @head->first.
+     * If multiple consumers are needed, please use llist_del_all or use a
+     * lock between consumers.
+     */
+    pub unsafe fn llist_del_first(&self) -> Option<Box<LlistNode<T>>> {
+        let mut entry = self.first.load(Ordering::Acquire);
+
+        loop {
+            if entry.is_null() {
+                return None;
+            }
+            let next = (*entry).next;
+            match self.first.compare_exchange_weak(entry, next, Ordering::Acquire, Ordering::Acquire) {
+                Ok(_) => break,
+                Err(cur) => entry = cur,
+            }
+        }
+
+        let mut node = Box::from_raw(entry);
+        node.next = ptr::null_mut();
+        Some(node)
+    }
+}
+
+impl<T> Drop for LlistHead<T> {
+    fn drop(&mut self) {
+        drop(self.llist_del_all());
+    }
+}
+
+
+/* Sections for Unit tests */
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::thread;
+
+    use super::*;
+
+    #[test]
+    fn test_llist_add_del_all() {
+        let head = LlistHead::new();
+
+        assert!(head.llist_empty());
+        assert!(head.llist_add(LlistNode::new(1)));
+        assert!(!head.llist_add(LlistNode::new(2)));
+        assert!(!head.llist_add(LlistNode::new(3)));
+
+        let nodes = head.llist_del_all();
+        assert!(head.llist_empty());
+        assert_eq!(nodes.iter().copied().collect::<Vec<_>>(), vec![3, 2, 1]);
+
+        let nodes = llist_reverse_order(nodes);
+        assert_eq!(nodes.map(|node| node.item).collect::<Vec<_>>(), vec![1, 2, 3]);
+
+        assert!(head.llist_del_all().is_empty());
+    }
+
+
+    #[test]
+    fn test_llist_add_batch() {
+        let head = LlistHead::new();
+
+        head.llist_add(LlistNode::new(0));
+        assert!(!head.llist_add_batch((1..4).collect()));
+
+        let items: Vec<_> = head.llist_del_all().iter().copied().collect();
+        assert_eq!(items, vec![1, 2, 3, 0]);
+
+        assert!(head.llist_add_batch((1..4).collect()));
+        assert!(!head.llist_add_batch(LlistNodes::new()));
+        assert_eq!(head.llist_del_all().count(), 3);
+    }
+
+
+    #[test]
+    fn test_llist_del_first() {
+        let head = LlistHead::new();
+
+        for i in 0..5 {
+            head.llist_add(LlistNode::new(i));
+        }
+
+        let node = unsafe { head.llist_del_first() }.unwrap();
+        assert_eq!(node.item, 4);
+
+        /* A deleted node can be added again */
+        head.llist_add(node);
+        let mut items = Vec::new();
+        while let Some(node) = unsafe { head.llist_del_first() } {
+            items.push(node.item);
+        }
+        assert_eq!(items, vec![4, 3, 2, 1, 0]);
+    }
+
+
+    #[test]
+    fn test_llist_reverse_order() {
+        let nodes: LlistNodes<i32> = llist_reverse_order(LlistNodes::new());
+        assert!(nodes.is_empty());
+
+        let mut nodes = llist_reverse_order((0..3).collect());
+        nodes.llist_push_tail(LlistNode::new(-1));
+        assert_eq!(nodes.iter().copied().collect::<Vec<_>>(), vec![2, 1, 0, -1]);
+    }
+
+
+    #[test]
+    fn test_llist_drop() {
+        let item = Arc::new(());
+        let head = LlistHead::new();
+
+        for _ in 0..10 {
+            head.llist_add(LlistNode::new(item.clone()));
+        }
+        let mut nodes = head.llist_del_all();
+        nodes.next();
+        head.llist_add_batch((0..5).map(|_| item.clone()).collect());
+
+        drop(nodes);
+        drop(head);
+        assert_eq!(Arc::strong_count(&item), 1);
+    }
+
+
+    #[test]
+    fn test_llist_stress() {
+        const PRODUCERS: usize = 4;
+        const COUNT: usize = 10_000;
+
+        let head = Arc::new(LlistHead::new());
+        let producers: Vec<_> = (0..PRODUCERS)
+            .map(|p| {
+                let head = head.clone();
+                thread::spawn(move || {
+                    for i in 0..COUNT {
+                        if i % 8 == 0 {
+                            head.llist_add_batch([(p, i)].into_iter().collect());
+                        } else {
+                            head.llist_add(LlistNode::new((p, i)));
+                        }
+                        if i % 64 == 0 {
+                            thread::yield_now();
+                        }
+                    }
+                })
+            })
+            .collect();
+
+        /* A single consumer mixes llist_del_first and llist_del_all */
+        let mut next = [0usize; PRODUCERS];
+        let mut received = 0;
+        while received < PRODUCERS * COUNT {
+            let batch: Vec<_> = match received % 3 {
+                0 => unsafe { head.llist_del_first() }.map(|node| node.item).into_iter().collect(),
+                _ => llist_reverse_order(head.llist_del_all()).map(|node| node.item).collect(),
+            };
+            if batch.is_empty() {
+                thread::yield_now();
+            }
+            received += batch.len();
+
+            /* Within a batch, each producer's items are in order */
+            let mut seen = [None; PRODUCERS];
+            for &(p, i) in batch.iter() {
+                assert!(seen[p].is_none_or(|prev| prev < i));
+                seen[p] = Some(i);
+                next[p] += 1;
+            }
+        }
+
+        for producer in producers {
+            producer.join().unwrap();
+        }
+        assert_eq!(next, [COUNT; PRODUCERS]);
+        assert!(head.llist_empty());
+    }
+}
@@ -1,4 +1,36 @@
+#[cfg(test)]
+pub mod bench;
+pub mod bitmap;
+pub mod btree;
+pub mod cache;
+pub mod errno;
+pub mod genradix;
+pub mod idr;
+pub mod kfifo;
 pub mod list;
+pub mod list_arena;
+pub mod list_lru;
+pub mod llist;
+pub mod maple_tree;
+pub mod min_heap;
+pub mod objpool;
+pub mod percpu_counter;
+pub mod plist;
+pub mod ptr_ring;
+pub mod rcu;
+pub mod rculist;
+pub mod rhashtable;
+pub mod ring_buffer;
+pub mod sbitmap;
+pub mod skiplist;
+#[cfg(feature = "lockfree-skiplist")]
+pub mod skiplist_lockfree;
+pub mod slab;
+pub mod spinlock;
+pub mod sync_list;
+pub mod timer;
+pub mod timerqueue;
+pub mod union_find;
 use list::{cmp_func, ListHead};
 
 use rand::Rng;
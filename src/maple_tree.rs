@@ -0,0 +1,1092 @@
+use crate::errno::{EBUSY, EEXIST, EINVAL};
+
+/* Maximum number of slots (leaf) or children (internal) per node */
+pub const MAPLE_NODE_SLOTS: usize = 16;
+const MAPLE_MIN_SLOTS: usize = MAPLE_NODE_SLOTS / 2;
+
+pub const ULONG_MAX: u64 = u64::MAX;
+
+
+/*
+ * A leaf slot covers the range [index, last]. The slots of the tree are
+ * contiguous and cover the whole index space, so a range without an entry
+ * is stored as an explicit empty slot. Adjacent empty slots are always
+ * coalesced, which keeps the gap of a slot equal to the free area around it.
+ */
+struct MapleSlot<T> {
+    index: u64,
+    last: u64,
+    entry: Option<T>,
+}
+
+impl<T> MapleSlot<T> {
+    fn gap(&self) -> u64 {
+        match self.entry {
+            Some(_) => 0,
+            None => (self.last - self.index).saturating_add(1),
+        }
+    }
+}
+
+
+struct MapleChild<T> {
+    last: u64,
+    gap: u64,
+    node: Box<MapleNode<T>>,
+}
+
+impl<T> MapleChild<T> {
+    fn new(node: Box<MapleNode<T>>) -> Self {
+        MapleChild {
+            last: node.last(),
+            gap: node.gap(),
+            node,
+        }
+    }
+
+    fn update(&mut self) {
+        self.last = self.node.last();
+        self.gap = self.node.gap();
+    }
+}
+
+
+/*
+ * Leaves hold the range slots, internal nodes hold their children together
+ * with the last index and the largest gap found below each of them, just
+ * like the pivots and gap array of a maple_arange_64 node.
+ */
+enum MapleNode<T> {
+    Leaf(Vec<MapleSlot<T>>),
+    Internal(Vec<MapleChild<T>>),
+}
+
+impl<T> MapleNode<T> {
+    fn len(&self) -> usize {
+        match self {
+            MapleNode::Leaf(slots) => slots.len(),
+            MapleNode::Internal(children) => children.len(),
+        }
+    }
+
+    fn last(&self) -> u64 {
+        match self {
+            MapleNode::Leaf(slots) => slots.last().map_or(0, |slot| slot.last),
+            MapleNode::Internal(children) => children.last().map_or(0, |child| child.last),
+        }
+    }
+
+    fn gap(&self) -> u64 {
+        match self {
+            MapleNode::Leaf(slots) => slots.iter().map(|slot| slot.gap()).max().unwrap_or(0),
+            MapleNode::Internal(children) => children.iter().map(|child| child.gap).max().unwrap_or(0),
+        }
+    }
+
+    /* Return the slot whose range contains @index */
+    fn find(&self, index: u64) -> Option<&MapleSlot<T>> {
+        match self {
+            MapleNode::Leaf(slots) => slots.get(slots.partition_point(|slot| slot.last < index)),
+            MapleNode::Internal(children) => {
+                let child = children.get(children.partition_point(|child| child.last < index))?;
+                child.node.find(index)
+            }
+        }
+    }
+
+    fn split(&mut self) -> Box<MapleNode<T>> {
+        let half = self.len() / 2;
+        match self {
+            MapleNode::Leaf(slots) => Box::new(MapleNode::Leaf(slots.split_off(half))),
+            MapleNode::Internal(children) => Box::new(MapleNode::Internal(children.split_off(half))),
+        }
+    }
+
+    fn append(&mut self, other: MapleNode<T>) {
+        match (self, other) {
+            (MapleNode::Leaf(slots), MapleNode::Leaf(mut more)) => slots.append(&mut more),
+            (MapleNode::Internal(children), MapleNode::Internal(mut more)) => children.append(&mut more),
+            _ => unreachable!("maple nodes at the same height must be of the same type"),
+        }
+    }
+
+    /* Insert @slot, returning the new right sibling if the node had to split */
+    fn insert(&mut self, slot: MapleSlot<T>) -> Option<Box<MapleNode<T>>> {
+        match self {
+            MapleNode::Leaf(slots) => {
+                let pos = slots.partition_point(|s| s.last < slot.last);
+                slots.insert(pos, slot);
+            }
+            MapleNode::Internal(children) => {
+                let pos = children
+                    .partition_point(|child| child.last < slot.last)
+                    .min(children.len() - 1);
+                let split = children[pos].node.insert(slot);
+                children[pos].update();
+                if let Some(node) = split {
+                    children.insert(pos + 1, MapleChild::new(node));
+                }
+            }
+        }
+
+        if self.len() > MAPLE_NODE_SLOTS {
+            Some(self.split())
+        } else {
+            None
+        }
+    }
+
+    /* Remove the slot ending at @last */
+    fn remove(&mut self, last: u64) -> Option<MapleSlot<T>> {
+        match self {
+            MapleNode::Leaf(slots) => {
+                let pos = slots.partition_point(|slot| slot.last < last);
+                if slots.get(pos)?.last != last {
+                    return None;
+                }
+                Some(slots.remove(pos))
+            }
+            MapleNode::Internal(children) => {
+                let pos = children.partition_point(|child| child.last < last);
+                let slot = children.get_mut(pos)?.node.remove(last)?;
+
+                if children[pos].node.len() == 0 {
+                    children.remove(pos);
+                } else if children[pos].node.len() < MAPLE_MIN_SLOTS && children.len() > 1 {
+                    MapleNode::rebalance(children, pos);
+                } else {
+                    children[pos].update();
+                }
+                Some(slot)
+            }
+        }
+    }
+
+    /* Merge an underflowing child with a sibling, splitting again if too large */
+    fn rebalance(children: &mut Vec<MapleChild<T>>, pos: usize) {
+        let left = if pos + 1 < children.len() { pos } else { pos - 1 };
+        let right = children.remove(left + 1);
+
+        children[left].node.append(*right.node);
+        if children[left].node.len() > MAPLE_NODE_SLOTS {
+            let node = children[left].node.split();
+            children.insert(left + 1, MapleChild::new(node));
+        }
+        children[left].update();
+    }
+
+    /* Find the lowest start of @size free indices in [@min, @max] */
+    fn empty_area(&self, min: u64, max: u64, size: u64) -> Option<u64> {
+        match self {
+            MapleNode::Leaf(slots) => {
+                for slot in &slots[slots.partition_point(|slot| slot.last < min)..] {
+                    if slot.index > max {
+                        break;
+                    }
+                    if slot.entry.is_none() {
+                        let start = slot.index.max(min);
+                        if slot.last.min(max) - start >= size - 1 {
+                            return Some(start);
+                        }
+                    }
+                }
+                None
+            }
+            MapleNode::Internal(children) => {
+                let first = children.partition_point(|child| child.last < min);
+                let mut child_min = match first {
+                    0 => 0,
+                    _ => children[first - 1].last + 1,
+                };
+                for child in &children[first..] {
+                    if child_min > max {
+                        break;
+                    }
+                    if child.gap >= size {
+                        if let Some(start) = child.node.empty_area(min, max, size) {
+                            return Some(start);
+                        }
+                    }
+                    child_min = child.last.wrapping_add(1);
+                }
+                None
+            }
+        }
+    }
+
+    /* Find the highest end of @size free indices in [@min, @max] */
+    fn empty_area_rev(&self, min: u64, max: u64, size: u64) -> Option<u64> {
+        match self {
+            MapleNode::Leaf(slots) => {
+                let end = slots.partition_point(|slot| slot.index <= max);
+                for slot in slots[..end].iter().rev() {
+                    if slot.last < min {
+                        break;
+                    }
+                    if slot.entry.is_none() {
+                        let last = slot.last.min(max);
+                        if last - slot.index.max(min) >= size - 1 {
+                            return Some(last);
+                        }
+                    }
+                }
+                None
+            }
+            MapleNode::Internal(children) => {
+                let end = (children.partition_point(|child| child.last < max) + 1).min(children.len());
+                for child in children[..end].iter().rev() {
+                    if child.last < min {
+                        break;
+                    }
+                    if child.gap >= size {
+                        if let Some(last) = child.node.empty_area_rev(min, max, size) {
+                            return Some(last);
+                        }
+                    }
+                }
+                None
+            }
+        }
+    }
+}
+
+
+/**
+ * struct MapleTree - range-indexed B-tree
+ *
+ * Stores entries over ranges of indices [index, last]. Every index of the
+ * tree maps to at most one entry, storing over a range overwrites whatever
+ * was stored there before. Internal nodes track the largest gap beneath
+ * them so that free areas can be found without walking every range.
+ *
+ * An entry which is partially overwritten keeps covering the remaining
+ * parts of its range, which requires the entry to be cloned. Store shared
+ * objects behind an `Rc` or `Arc` to get the kernel's pointer semantics.
+ */
+pub struct MapleTree<T> {
+    root: Box<MapleNode<T>>,
+}
+
+impl<T: Clone> Default for MapleTree<T> {
+    fn default() -> Self {
+        MapleTree::new()
+    }
+}
+
+#[allow(dead_code)]
+impl<T: Clone> MapleTree<T> {
+    /**
+     * mt_init - Initialise an empty maple tree
+     */
+    pub fn new() -> Self {
+        MapleTree {
+            root: Box::new(MapleNode::Leaf(vec![MapleSlot {
+                index: 0,
+                last: ULONG_MAX,
+                entry: None,
+            }])),
+        }
+    }
+
+
+    fn find_slot(&self, index: u64) -> &MapleSlot<T> {
+        self.root.find(index).expect("maple tree must cover the whole index space")
+    }
+
+
+    fn insert_slot(&mut self, slot: MapleSlot<T>) {
+        if let Some(right) = self.root.insert(slot) {
+            let left = std::mem::replace(&mut self.root, Box::new(MapleNode::Internal(Vec::new())));
+            *self.root = MapleNode::Internal(vec![MapleChild::new(left), MapleChild::new(right)]);
+        }
+    }
+
+
+    fn remove_slot(&mut self, index: u64) -> MapleSlot<T> {
+        let last = self.find_slot(index).last;
+        let slot = self.root.remove(last).expect("slot to remove must exist");
+
+        loop {
+            match self.root.as_mut() {
+                MapleNode::Internal(children) if children.len() == 1 => {
+                    self.root = children.pop().unwrap().node;
+                }
+                MapleNode::Internal(children) if children.is_empty() => {
+                    *self.root = MapleNode::Leaf(Vec::new());
+                }
+                _ => break,
+            }
+        }
+        slot
+    }
+
+
+    /*
+     * Store @entry over [@index, @last]. Overwritten slots are returned so
+     * that the caller can hand back the entries it displaced.
+     */
+    fn store(&mut self, index: u64, last: u64, entry: Option<T>) -> Vec<MapleSlot<T>> {
+        let mut removed = Vec::new();
+        let mut pos = index;
+        loop {
+            let slot = self.remove_slot(pos);
+            let done = slot.last >= last;
+            pos = slot.last.wrapping_add(1);
+            removed.push(slot);
+            if done {
+                break;
+            }
+        }
+
+        let (mut index, mut last) = (index, last);
+        let head = removed.first().unwrap();
+        let tail = removed.last().unwrap();
+        let mut head_rem = None;
+        let mut tail_rem = None;
+
+        if head.index < index {
+            head_rem = Some(MapleSlot { index: head.index, last: index - 1, entry: head.entry.clone() });
+        }
+        if tail.last > last {
+            tail_rem = Some(MapleSlot { index: last + 1, last: tail.last, entry: tail.entry.clone() });
+        }
+
+        /* Never leave two empty slots next to each other */
+        if entry.is_none() {
+            match head_rem.take() {
+                Some(rem) if rem.entry.is_none() => index = rem.index,
+                Some(rem) => head_rem = Some(rem),
+                None if index > 0 && self.find_slot(index - 1).entry.is_none() => {
+                    index = self.remove_slot(index - 1).index;
+                }
+                None => {}
+            }
+            match tail_rem.take() {
+                Some(rem) if rem.entry.is_none() => last = rem.last,
+                Some(rem) => tail_rem = Some(rem),
+                None if last < ULONG_MAX && self.find_slot(last + 1).entry.is_none() => {
+                    last = self.remove_slot(last + 1).last;
+                }
+                None => {}
+            }
+        }
+
+        for slot in [head_rem, Some(MapleSlot { index, last, entry }), tail_rem].into_iter().flatten() {
+            self.insert_slot(slot);
+        }
+
+        removed
+    }
+
+
+    /* Find the first slot holding an entry which intersects [@index, @max] */
+    fn find_entry(&self, index: u64, max: u64) -> Option<&MapleSlot<T>> {
+        let mut slot = self.find_slot(index);
+        loop {
+            if slot.index > max {
+                return None;
+            }
+            if slot.entry.is_some() {
+                return Some(slot);
+            }
+            if slot.last == ULONG_MAX {
+                return None;
+            }
+            slot = self.find_slot(slot.last + 1);
+        }
+    }
+
+
+    /* Find the last slot holding an entry which intersects [@min, @index] */
+    fn find_entry_rev(&self, index: u64, min: u64) -> Option<&MapleSlot<T>> {
+        let mut slot = self.find_slot(index);
+        loop {
+            if slot.last < min {
+                return None;
+            }
+            if slot.entry.is_some() {
+                return Some(slot);
+            }
+            if slot.index == 0 {
+                return None;
+            }
+            slot = self.find_slot(slot.index - 1);
+        }
+    }
+
+
+    /**
+     * mtree_store_range() - Store an entry at a given range.
+     * @index: The start of the range
+     * @last: The end of the range
+     * @entry: The entry to store
+     *
+     * Any entries previously stored over parts of the range are overwritten.
+     *
+     * Return: 0 on success, -EINVAL on invalid request.
+     */
+    pub fn mtree_store_range(&mut self, index: u64, last: u64, entry: T) -> Result<(), i32> {
+        if index > last {
+            return Err(-EINVAL);
+        }
+        self.store(index, last, Some(entry));
+        Ok(())
+    }
+
+
+    /**
+     * mtree_store() - Store an entry at a given index.
+     * @index: The index to store the value
+     * @entry: The entry to store
+     */
+    pub fn mtree_store(&mut self, index: u64, entry: T) -> Result<(), i32> {
+        self.mtree_store_range(index, index, entry)
+    }
+
+
+    /**
+     * mtree_insert_range() - Insert an entry at a given range if there is no value.
+     * @first: The start of the range
+     * @last: The end of the range
+     * @entry: The entry to store
+     *
+     * Return: 0 on success, -EEXIST if the range is occupied, -EINVAL on
+     * invalid request.
+     */
+    pub fn mtree_insert_range(&mut self, first: u64, last: u64, entry: T) -> Result<(), i32> {
+        if first > last {
+            return Err(-EINVAL);
+        }
+        if self.find_entry(first, last).is_some() {
+            return Err(-EEXIST);
+        }
+        self.store(first, last, Some(entry));
+        Ok(())
+    }
+
+
+    /**
+     * mtree_insert() - Insert an entry at a given index if there is no value.
+     * @index: The index to store the value
+     * @entry: The entry to store
+     */
+    pub fn mtree_insert(&mut self, index: u64, entry: T) -> Result<(), i32> {
+        self.mtree_insert_range(index, index, entry)
+    }
+
+
+    /**
+     * mtree_alloc_range() - Find a free area and store an entry over it.
+     * @startp: Set to the start of the allocated range
+     * @entry: The entry to store
+     * @size: The size of the range to allocate
+     * @min: The lowest index to allocate
+     * @max: The highest index the allocated range may reach
+     *
+     * Return: 0 on success, -EBUSY if no area is large enough, -EINVAL on
+     * invalid request.
+     */
+    pub fn mtree_alloc_range(&mut self, startp: &mut u64, entry: T, size: u64, min: u64, max: u64) -> Result<(), i32> {
+        let mut mas = MaState::new(self, 0, 0);
+
+        mas.mas_empty_area(min, max, size)?;
+        mas.mas_store(entry);
+        *startp = mas.index;
+        Ok(())
+    }
+
+
+    /**
+     * mtree_load() - Load a value stored in a maple tree
+     * @index: The index to load
+     *
+     * Return: the entry or %None
+     */
+    pub fn mtree_load(&self, index: u64) -> Option<&T> {
+        self.find_slot(index).entry.as_ref()
+    }
+
+
+    /**
+     * mtree_erase() - Find an index and erase the entire range.
+     * @index: The index to erase
+     *
+     * Erasing is the same as a walk to an entry then a store of %None over
+     * the whole range it covers.
+     *
+     * Return: The entry stored at the @index or %None
+     */
+    pub fn mtree_erase(&mut self, index: u64) -> Option<T> {
+        let slot = self.find_slot(index);
+        slot.entry.as_ref()?;
+        let (first, last) = (slot.index, slot.last);
+        self.store(first, last, None).pop().and_then(|slot| slot.entry)
+    }
+
+
+    /**
+     * mt_find() - Search from the start up until an entry is found.
+     * @index: Pointer which contains the start location of the search
+     * @max: The maximum value of the search range
+     *
+     * On success, @index is advanced past the found entry so that calling
+     * mt_find() again returns the next one. @index wraps to 0 once an entry
+     * reaching %ULONG_MAX has been returned.
+     *
+     * Return: The entry at or after the @index or %None
+     */
+    pub fn mt_find(&self, index: &mut u64, max: u64) -> Option<&T> {
+        if *index > max {
+            return None;
+        }
+        let slot = self.find_entry(*index, max)?;
+
+        *index = slot.last.wrapping_add(1);
+        slot.entry.as_ref()
+    }
+
+
+    /**
+     * mt_find_after() - Search from the start up until an entry is found.
+     * @index: Pointer which contains the start location of the search
+     * @max: The maximum value to check
+     *
+     * Same as mt_find() except that it checks @index for 0 before searching,
+     * which means the previous entry already reached %ULONG_MAX.
+     */
+    pub fn mt_find_after(&self, index: &mut u64, max: u64) -> Option<&T> {
+        if *index == 0 {
+            return None;
+        }
+        self.mt_find(index, max)
+    }
+
+
+    /**
+     * mtree_empty() - Determine if a tree has any present entries.
+     */
+    pub fn mtree_empty(&self) -> bool {
+        self.find_entry(0, ULONG_MAX).is_none()
+    }
+
+
+    /**
+     * mtree_destroy() - Destroy a maple tree
+     *
+     * Frees all resources used by the tree and drops every entry.
+     */
+    pub fn mtree_destroy(&mut self) {
+        *self = MapleTree::new();
+    }
+}
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MaStatus {
+    /* The cursor has not been walked yet */
+    Start,
+    /* The cursor points at the range [index, last] */
+    Active,
+    /* A search ran past its upper limit */
+    Overflow,
+    /* A search ran past its lower limit */
+    Underflow,
+}
+
+
+/**
+ * struct MaState - maple tree operation state
+ * @index: The start of the range being operated on
+ * @last: The end of the range being operated on
+ *
+ * Cursor over a maple tree. Searches update @index and @last to the range
+ * of the entry they return, stores and erases operate on [@index, @last].
+ */
+pub struct MaState<'a, T> {
+    tree: &'a mut MapleTree<T>,
+    pub index: u64,
+    pub last: u64,
+    status: MaStatus,
+}
+
+#[allow(dead_code)]
+impl<'a, T: Clone> MaState<'a, T> {
+    /**
+     * MA_STATE() - Create a maple state for the range [@first, @end]
+     */
+    pub fn new(tree: &'a mut MapleTree<T>, first: u64, end: u64) -> Self {
+        MaState {
+            tree,
+            index: first,
+            last: end,
+            status: MaStatus::Start,
+        }
+    }
+
+
+    pub fn status(&self) -> MaStatus {
+        self.status
+    }
+
+
+    /**
+     * mas_reset() - Reset a Maple Tree operation state.
+     *
+     * The next operation will start a fresh walk at @index.
+     */
+    pub fn mas_reset(&mut self) {
+        self.status = MaStatus::Start;
+    }
+
+
+    /**
+     * mas_set_range() - Set up Maple Tree operation state for a different index.
+     * @start: New start of range in the Maple Tree.
+     * @last: New end of range in the Maple Tree.
+     */
+    pub fn mas_set_range(&mut self, start: u64, last: u64) {
+        self.index = start;
+        self.last = last;
+        self.mas_reset();
+    }
+
+
+    /**
+     * mas_set() - Set up Maple Tree operation state for a different index.
+     * @index: New index into the Maple Tree.
+     */
+    pub fn mas_set(&mut self, index: u64) {
+        self.mas_set_range(index, index);
+    }
+
+
+    /* Point the cursor at @slot, or mark it @status if there is none */
+    fn mas_point(&mut self, slot: Option<(u64, u64)>, status: MaStatus) -> bool {
+        match slot {
+            Some((index, last)) => {
+                self.index = index;
+                self.last = last;
+                self.status = MaStatus::Active;
+                true
+            }
+            None => {
+                self.status = status;
+                false
+            }
+        }
+    }
+
+
+    fn mas_entry(&self) -> Option<&T> {
+        match self.status {
+            MaStatus::Active => self.tree.mtree_load(self.index),
+            _ => None,
+        }
+    }
+
+
+    /**
+     * mas_walk() - Search for @index in the tree.
+     *
+     * @index and @last are set to the range of the slot @index falls into,
+     * whether or not an entry is stored there.
+     *
+     * Return: the entry at the location or %None.
+     */
+    pub fn mas_walk(&mut self) -> Option<&T> {
+        let slot = self.tree.find_slot(self.index);
+        let range = (slot.index, slot.last);
+
+        self.mas_point(Some(range), MaStatus::Overflow);
+        self.mas_entry()
+    }
+
+
+    /**
+     * mas_find() - On the first call, find the entry at or after @index up
+     * to @max. Otherwise, find the entry after the current one.
+     * @max: The maximum value to check.
+     *
+     * Return: The entry or %None.
+     */
+    pub fn mas_find(&mut self, max: u64) -> Option<&T> {
+        let from = match self.status {
+            MaStatus::Start => self.index,
+            MaStatus::Active if self.last < ULONG_MAX => self.last + 1,
+            MaStatus::Underflow => self.index,
+            _ => return None,
+        };
+        if from > max {
+            self.status = MaStatus::Overflow;
+            return None;
+        }
+
+        let range = self.tree.find_entry(from, max).map(|slot| (slot.index, slot.last));
+        self.mas_point(range, MaStatus::Overflow);
+        self.mas_entry()
+    }
+
+
+    /**
+     * mas_find_rev() - On the first call, find the entry at or before
+     * @index down to @min. Otherwise, find the entry before the current one.
+     * @min: The minimum value to check.
+     *
+     * Return: The entry or %None.
+     */
+    pub fn mas_find_rev(&mut self, min: u64) -> Option<&T> {
+        let from = match self.status {
+            MaStatus::Start => self.index,
+            MaStatus::Active if self.index > 0 => self.index - 1,
+            MaStatus::Overflow => self.last,
+            _ => return None,
+        };
+        if from < min {
+            self.status = MaStatus::Underflow;
+            return None;
+        }
+
+        let range = self.tree.find_entry_rev(from, min).map(|slot| (slot.index, slot.last));
+        self.mas_point(range, MaStatus::Underflow);
+        self.mas_entry()
+    }
+
+
+    /**
+     * mas_next() - Get the next entry.
+     * @max: The maximum index to check.
+     *
+     * Returns the next entry after the range the cursor points to.
+     */
+    pub fn mas_next(&mut self, max: u64) -> Option<&T> {
+        if self.status == MaStatus::Start {
+            self.mas_walk();
+        }
+        self.mas_find(max)
+    }
+
+
+    /**
+     * mas_prev() - Get the previous entry.
+     * @min: The minimum index to check.
+     *
+     * Returns the previous entry before the range the cursor points to.
+     */
+    pub fn mas_prev(&mut self, min: u64) -> Option<&T> {
+        if self.status == MaStatus::Start {
+            self.mas_walk();
+        }
+        self.mas_find_rev(min)
+    }
+
+
+    /**
+     * mas_store() - Store an @entry.
+     * @entry: The entry to store.
+     *
+     * The @index and @last are used to set the range for the @entry.
+     *
+     * Return: the first entry that was overwritten, or %None.
+     */
+    pub fn mas_store(&mut self, entry: T) -> Option<T> {
+        let removed = self.tree.store(self.index, self.last, Some(entry));
+
+        self.status = MaStatus::Active;
+        removed.into_iter().find_map(|slot| slot.entry)
+    }
+
+
+    /**
+     * mas_erase() - Find the range in which index resides and erase the
+     * entire range.
+     *
+     * @index and @last are set to the erased range.
+     *
+     * Return: the entry that was erased or %None.
+     */
+    pub fn mas_erase(&mut self) -> Option<T> {
+        let slot = self.tree.find_slot(self.index);
+        let range = (slot.index, slot.last);
+
+        self.mas_point(Some(range), MaStatus::Overflow);
+        self.tree.mtree_erase(self.index)
+    }
+
+
+    /**
+     * mas_empty_area() - Get the lowest address within the range that is
+     * sufficient for the size requested.
+     * @min: The lowest value of the range
+     * @max: The highest value of the range
+     * @size: The size needed
+     *
+     * On success @index and @last are set to the free range found.
+     *
+     * Return: 0 on success, -EBUSY if no area is large enough, -EINVAL on
+     * invalid request.
+     */
+    pub fn mas_empty_area(&mut self, min: u64, max: u64, size: u64) -> Result<(), i32> {
+        if min > max || size == 0 || max - min < size - 1 {
+            return Err(-EINVAL);
+        }
+
+        let start = self.tree.root.empty_area(min, max, size).ok_or(-EBUSY)?;
+        self.mas_point(Some((start, start + (size - 1))), MaStatus::Overflow);
+        Ok(())
+    }
+
+
+    /**
+     * mas_empty_area_rev() - Get the highest address within the range that
+     * is sufficient for the size requested.
+     * @min: The lowest value of the range
+     * @max: The highest value of the range
+     * @size: The size needed
+     *
+     * On success @index and @last are set to the free range found.
+     *
+     * Return: 0 on success, -EBUSY if no area is large enough, -EINVAL on
+     * invalid request.
+     */
+    pub fn mas_empty_area_rev(&mut self, min: u64, max: u64, size: u64) -> Result<(), i32> {
+        if min > max || size == 0 || max - min < size - 1 {
+            return Err(-EINVAL);
+        }
+
+        let last = self.tree.root.empty_area_rev(min, max, size).ok_or(-EBUSY)?;
+        self.mas_point(Some((last - (size - 1), last)), MaStatus::Underflow);
+        Ok(())
+    }
+}
+
+
+/* Sections for Unit tests */
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use super::*;
+
+    /* Walk every slot and check that they cover the index space */
+    fn check_tree<T: Clone>(mt: &MapleTree<T>) -> usize {
+        let mut index = 0;
+        let mut count = 0;
+        let mut prev_empty = false;
+        loop {
+            let slot = mt.find_slot(index);
+            assert_eq!(slot.index, index);
+            assert!(!(prev_empty && slot.entry.is_none()));
+            prev_empty = slot.entry.is_none();
+            count += 1;
+            if slot.last == ULONG_MAX {
+                return count;
+            }
+            index = slot.last + 1;
+        }
+    }
+
+
+    #[test]
+    fn test_mtree_store_load() {
+        let mut mt = MapleTree::new();
+
+        mt.mtree_store_range(10, 19, 'a').unwrap();
+        mt.mtree_store_range(30, 39, 'b').unwrap();
+        mt.mtree_store(25, 'c').unwrap();
+
+        assert_eq!(mt.mtree_load(9), None);
+        assert_eq!(mt.mtree_load(10), Some(&'a'));
+        assert_eq!(mt.mtree_load(19), Some(&'a'));
+        assert_eq!(mt.mtree_load(25), Some(&'c'));
+        assert_eq!(mt.mtree_load(35), Some(&'b'));
+        assert_eq!(mt.mtree_load(40), None);
+        assert_eq!(mt.mtree_store_range(5, 4, 'x'), Err(-EINVAL));
+        assert_eq!(check_tree(&mt), 7);
+    }
+
+
+    #[test]
+    fn test_mtree_store_overwrite() {
+        let mut mt = MapleTree::new();
+
+        mt.mtree_store_range(0, 99, 'a').unwrap();
+        mt.mtree_store_range(40, 59, 'b').unwrap();
+
+        assert_eq!(mt.mtree_load(39), Some(&'a'));
+        assert_eq!(mt.mtree_load(40), Some(&'b'));
+        assert_eq!(mt.mtree_load(60), Some(&'a'));
+
+        /* Spanning store over three ranges */
+        mt.mtree_store_range(30, 70, 'c').unwrap();
+        assert_eq!(mt.mtree_load(29), Some(&'a'));
+        assert_eq!(mt.mtree_load(50), Some(&'c'));
+        assert_eq!(mt.mtree_load(71), Some(&'a'));
+        assert_eq!(check_tree(&mt), 4);
+    }
+
+
+    #[test]
+    fn test_mtree_insert_erase() {
+        let mut mt = MapleTree::new();
+
+        mt.mtree_insert_range(10, 20, 1).unwrap();
+        assert_eq!(mt.mtree_insert_range(20, 30, 2), Err(-EEXIST));
+        assert_eq!(mt.mtree_insert(15, 3), Err(-EEXIST));
+        mt.mtree_insert_range(21, 30, 2).unwrap();
+
+        assert_eq!(mt.mtree_erase(15), Some(1));
+        assert_eq!(mt.mtree_erase(15), None);
+        assert_eq!(mt.mtree_load(10), None);
+        assert_eq!(mt.mtree_erase(30), Some(2));
+        assert!(mt.mtree_empty());
+        assert_eq!(check_tree(&mt), 1);
+    }
+
+
+    #[test]
+    fn test_mt_find() {
+        let mut mt = MapleTree::new();
+
+        for i in 0..100u64 {
+            mt.mtree_store_range(i * 10, i * 10 + 4, i).unwrap();
+        }
+        mt.mtree_store_range(ULONG_MAX - 1, ULONG_MAX, 1000).unwrap();
+
+        let mut index = 13;
+        assert_eq!(mt.mt_find(&mut index, 100), Some(&1));
+        assert_eq!(index, 15);
+        assert_eq!(mt.mt_find(&mut index, 100), Some(&2));
+        assert_eq!(index, 25);
+
+        let mut index = 996;
+        assert_eq!(mt.mt_find(&mut index, 999), None);
+
+        let mut found = Vec::new();
+        let mut index = 0;
+        while let Some(&entry) = mt.mt_find(&mut index, ULONG_MAX) {
+            found.push(entry);
+            if index == 0 {
+                break;
+            }
+        }
+        assert_eq!(found.len(), 101);
+        assert_eq!(mt.mt_find_after(&mut index, ULONG_MAX), None);
+    }
+
+
+    #[test]
+    fn test_mas_empty_area() {
+        let mut mt = MapleTree::new();
+
+        mt.mtree_store_range(0, 9, 'a').unwrap();
+        mt.mtree_store_range(15, 19, 'b').unwrap();
+        mt.mtree_store_range(30, 49, 'c').unwrap();
+
+        let mut mas = MaState::new(&mut mt, 0, 0);
+        mas.mas_empty_area(0, 100, 5).unwrap();
+        assert_eq!((mas.index, mas.last), (10, 14));
+        mas.mas_empty_area(0, 100, 6).unwrap();
+        assert_eq!((mas.index, mas.last), (20, 25));
+        assert_eq!(mas.mas_empty_area(0, 29, 11), Err(-EBUSY));
+        assert_eq!(mas.mas_empty_area(10, 5, 1), Err(-EINVAL));
+
+        mas.mas_empty_area_rev(0, 100, 5).unwrap();
+        assert_eq!((mas.index, mas.last), (96, 100));
+        mas.mas_empty_area_rev(0, 29, 6).unwrap();
+        assert_eq!((mas.index, mas.last), (24, 29));
+        mas.mas_empty_area_rev(0, 14, 5).unwrap();
+        assert_eq!((mas.index, mas.last), (10, 14));
+        assert_eq!(mas.mas_empty_area_rev(0, 14, 6), Err(-EBUSY));
+
+        let mut start = 0;
+        mt.mtree_alloc_range(&mut start, 'd', 3, 0, 100).unwrap();
+        assert_eq!(start, 10);
+        assert_eq!(mt.mtree_load(12), Some(&'d'));
+    }
+
+
+    #[test]
+    fn test_mas_iterate() {
+        let mut mt = MapleTree::new();
+
+        for i in 1..50u64 {
+            mt.mtree_store_range(i * 100, i * 100 + 49, i).unwrap();
+        }
+
+        let mut mas = MaState::new(&mut mt, 0, 0);
+        let mut count = 0;
+        while let Some(&entry) = mas.mas_find(ULONG_MAX) {
+            count += 1;
+            assert_eq!(entry, count);
+            assert_eq!(mas.index, entry * 100);
+            assert_eq!(mas.last, entry * 100 + 49);
+        }
+        assert_eq!(count, 49);
+        assert_eq!(mas.status(), MaStatus::Overflow);
+
+        while let Some(&entry) = mas.mas_find_rev(0) {
+            assert_eq!(entry, count);
+            count -= 1;
+        }
+        assert_eq!(count, 0);
+
+        mas.mas_set(1075);
+        assert_eq!(mas.mas_walk(), None);
+        assert_eq!((mas.index, mas.last), (1050, 1099));
+        assert_eq!(mas.mas_next(ULONG_MAX), Some(&11));
+        assert_eq!(mas.mas_prev(0), Some(&10));
+
+        mas.mas_set_range(1000, 1099);
+        assert_eq!(mas.mas_store(99), Some(10));
+        mas.mas_set(1050);
+        assert_eq!(mas.mas_erase(), Some(99));
+        assert_eq!((mas.index, mas.last), (1000, 1099));
+        assert_eq!(mt.mtree_load(1000), None);
+    }
+
+
+    #[test]
+    fn test_mtree_random() {
+        let mut rng = StdRng::seed_from_u64(0x5eed);
+        let mut mt = MapleTree::new();
+        let mut model = vec![None; 4096];
+
+        for _ in 0..2000 {
+            let index = rng.gen_range(0..model.len());
+            let last = (index + rng.gen_range(0..64)).min(model.len() - 1);
+
+            if rng.gen_bool(0.7) {
+                let entry: u32 = rng.gen();
+                mt.mtree_store_range(index as u64, last as u64, entry).unwrap();
+                model[index..=last].fill(Some(entry));
+            } else if let Some(entry) = mt.mtree_erase(index as u64) {
+                let mut first = index;
+                while first > 0 && model[first - 1] == Some(entry) {
+                    first -= 1;
+                }
+                let mut end = index;
+                while end < model.len() && model[end] == Some(entry) {
+                    end += 1;
+                }
+                model[first..end].fill(None);
+            }
+        }
+
+        for (index, entry) in model.iter().enumerate() {
+            assert_eq!(mt.mtree_load(index as u64), entry.as_ref());
+        }
+        check_tree(&mt);
+
+        let mut mas = MaState::new(&mut mt, 0, 0);
+        for size in 1..16 {
+            match mas.mas_empty_area(0, model.len() as u64 - 1, size) {
+                Ok(()) => {
+                    let (start, last) = (mas.index as usize, mas.last as usize);
+                    assert!(model[start..=last].iter().all(|entry| entry.is_none()));
+                    assert!(model.windows(size as usize).take(start).all(|w| w.iter().any(|e| e.is_some())));
+                }
+                Err(err) => assert_eq!(err, -EBUSY),
+            }
+        }
+    }
+}
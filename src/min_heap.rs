@@ -0,0 +1,425 @@
+use std::cmp::Ordering;
+
+pub type MinHeapLessFunc<T, A> = fn(&T, &T, &A) -> bool;
+pub type MinHeapSwapFunc<T, A> = fn(&mut T, &mut T, &A);
+
+
+/**
+ * struct MinHeapCallbacks - Data/functions to customise the min_heap.
+ * @less: Partial order function for this heap.
+ * @swp: Swap elements function, or %None to swap them in place.
+ *
+ * Both callbacks receive the @args passed to the heap operation, which
+ * plays the role of the kernel's `void *args`.
+ */
+pub struct MinHeapCallbacks<T, A = ()> {
+    pub less: MinHeapLessFunc<T, A>,
+    pub swp: Option<MinHeapSwapFunc<T, A>>,
+}
+
+impl<T, A> Clone for MinHeapCallbacks<T, A> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T, A> Copy for MinHeapCallbacks<T, A> {}
+
+#[allow(dead_code)]
+impl<T: Ord> MinHeapCallbacks<T> {
+    /* Callbacks for a min-heap ordered by Ord */
+    pub fn min() -> Self {
+        MinHeapCallbacks {
+            less: |a, b, _| a < b,
+            swp: None,
+        }
+    }
+
+
+    /* Callbacks for a max-heap ordered by Ord */
+    pub fn max() -> Self {
+        MinHeapCallbacks {
+            less: |a, b, _| a > b,
+            swp: None,
+        }
+    }
+}
+
+
+/**
+ * struct MinHeap - Data structure to hold a min-heap.
+ *
+ * The backing array is allocated once with room for @size elements and
+ * never grows; pushing onto a full heap fails. Whether it behaves as a
+ * min-heap or a max-heap only depends on the less callback.
+ */
+pub struct MinHeap<T> {
+    data: Vec<T>,
+    size: usize,
+}
+
+#[allow(dead_code)]
+impl<T> MinHeap<T> {
+    /**
+     * min_heap_init - Initialize a heap with room for @size elements
+     * @size: capacity of the backing array
+     */
+    pub fn min_heap_init(size: usize) -> Self {
+        MinHeap {
+            data: Vec::with_capacity(size),
+            size,
+        }
+    }
+
+
+    /**
+     * min_heap_init_data - Initialize a heap over unordered elements
+     * @data: initial elements, in any order
+     * @size: capacity of the backing array, at least @data.len()
+     *
+     * The heap property does not hold until min_heapify_all() is called.
+     */
+    pub fn min_heap_init_data(mut data: Vec<T>, size: usize) -> Self {
+        assert!(data.len() <= size, "min_heap_init_data: more elements than room");
+        data.reserve_exact(size - data.len());
+        MinHeap { data, size }
+    }
+
+
+    /* Number of elements currently in the heap */
+    pub fn nr(&self) -> usize {
+        self.data.len()
+    }
+
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+
+    pub fn as_slice(&self) -> &[T] {
+        &self.data
+    }
+
+
+    /**
+     * min_heap_peek - Get the minimum element from the heap.
+     */
+    pub fn min_heap_peek(&self) -> Option<&T> {
+        self.data.first()
+    }
+
+
+    /**
+     * min_heap_full - Check if the heap is full.
+     */
+    pub fn min_heap_full(&self) -> bool {
+        self.data.len() == self.size
+    }
+
+
+    /**
+     * min_heap_empty - Check if the heap is empty.
+     */
+    pub fn min_heap_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+
+    fn swp<A>(&mut self, i: usize, j: usize, func: &MinHeapCallbacks<T, A>, args: &A) {
+        match func.swp {
+            Some(swp) => {
+                let (lo, hi) = (i.min(j), i.max(j));
+                let (left, right) = self.data.split_at_mut(hi);
+                swp(&mut left[lo], &mut right[0], args);
+            }
+            None => self.data.swap(i, j),
+        }
+    }
+
+
+    fn less<A>(&self, i: usize, j: usize, func: &MinHeapCallbacks<T, A>, args: &A) -> bool {
+        (func.less)(&self.data[i], &self.data[j], args)
+    }
+
+
+    /**
+     * min_heapify - Sift the element at @pos down to its place.
+     * @pos: index of the element to sift down
+     * @func: the heap callbacks
+     * @args: passed through to the callbacks
+     */
+    pub fn min_heapify<A>(&mut self, pos: usize, func: &MinHeapCallbacks<T, A>, args: &A) {
+        let nr = self.data.len();
+        let mut i = pos;
+
+        /* Find the sift-down path all the way to the leaves. */
+        while i * 2 + 2 < nr {
+            i = if self.less(i * 2 + 1, i * 2 + 2, func, args) { i * 2 + 1 } else { i * 2 + 2 };
+        }
+
+        /* Special case for the last leaf with no sibling. */
+        if i * 2 + 2 == nr {
+            i = i * 2 + 1;
+        }
+
+        /* Backtrack to the correct location. */
+        while i != pos && self.less(pos, i, func, args) {
+            i = (i - 1) / 2;
+        }
+
+        /* Shift the element into its correct place. */
+        let j = i;
+        while i != pos {
+            i = (i - 1) / 2;
+            self.swp(i, j, func, args);
+        }
+    }
+
+
+    /**
+     * min_heapify_all - Floyd's approach to heapification that is O(nr).
+     * @func: the heap callbacks
+     * @args: passed through to the callbacks
+     */
+    pub fn min_heapify_all<A>(&mut self, func: &MinHeapCallbacks<T, A>, args: &A) {
+        for i in (0..self.data.len() / 2).rev() {
+            self.min_heapify(i, func, args);
+        }
+    }
+
+
+    /**
+     * min_heap_pop - Remove the minimum element from the heap.
+     * @func: the heap callbacks
+     * @args: passed through to the callbacks
+     *
+     * Return: the removed element, or %None if the heap is empty.
+     */
+    pub fn min_heap_pop<A>(&mut self, func: &MinHeapCallbacks<T, A>, args: &A) -> Option<T> {
+        if self.data.is_empty() {
+            return None;
+        }
+
+        /* Place last element at the root (position 0) and then sift down. */
+        let min = self.data.swap_remove(0);
+        self.min_heapify(0, func, args);
+        Some(min)
+    }
+
+
+    /**
+     * min_heap_pop_push - Remove the minimum element and then push the
+     * given element.
+     * @element: the element to push
+     * @func: the heap callbacks
+     * @args: passed through to the callbacks
+     *
+     * The implementation performs 1 sift (up or down) as opposed to 2
+     * (up and down).
+     *
+     * Return: the removed minimum, or %None if the heap was empty, in
+     * which case @element is simply pushed.
+     */
+    pub fn min_heap_pop_push<A>(&mut self, element: T, func: &MinHeapCallbacks<T, A>, args: &A) -> Option<T> {
+        if self.data.is_empty() {
+            self.data.push(element);
+            return None;
+        }
+
+        let min = std::mem::replace(&mut self.data[0], element);
+        self.min_heapify(0, func, args);
+        Some(min)
+    }
+
+
+    /**
+     * min_heap_sift_up - Sift the element at @idx up to its place.
+     * @idx: index of the element to sift up
+     * @func: the heap callbacks
+     * @args: passed through to the callbacks
+     */
+    pub fn min_heap_sift_up<A>(&mut self, idx: usize, func: &MinHeapCallbacks<T, A>, args: &A) {
+        let mut pos = idx;
+
+        while pos > 0 {
+            let parent = (pos - 1) / 2;
+            if self.less(parent, pos, func, args) {
+                break;
+            }
+            self.swp(parent, pos, func, args);
+            pos = parent;
+        }
+    }
+
+
+    /**
+     * min_heap_push - Push an element on to the heap, O(log2(nr)).
+     * @element: the element to push
+     * @func: the heap callbacks
+     * @args: passed through to the callbacks
+     *
+     * Return: Err(@element) if the heap is full.
+     */
+    pub fn min_heap_push<A>(&mut self, element: T, func: &MinHeapCallbacks<T, A>, args: &A) -> Result<(), T> {
+        if self.min_heap_full() {
+            return Err(element);
+        }
+
+        /* Place at the end of data. */
+        self.data.push(element);
+
+        /* Sift child at pos up. */
+        self.min_heap_sift_up(self.data.len() - 1, func, args);
+        Ok(())
+    }
+
+
+    /**
+     * min_heap_del - Remove the element at @idx from the heap.
+     * @idx: index of the element to delete
+     * @func: the heap callbacks
+     * @args: passed through to the callbacks
+     *
+     * Return: the removed element, or %None if @idx is out of range.
+     */
+    pub fn min_heap_del<A>(&mut self, idx: usize, func: &MinHeapCallbacks<T, A>, args: &A) -> Option<T> {
+        let last = self.data.len().checked_sub(1)?;
+        if idx > last {
+            return None;
+        }
+
+        /* Place last element at @idx and then sift it to its place. */
+        if idx != last {
+            self.swp(idx, last, func, args);
+        }
+        let element = self.data.pop();
+        if idx != last {
+            self.min_heap_sift_up(idx, func, args);
+            self.min_heapify(idx, func, args);
+        }
+        element
+    }
+}
+
+
+/* Compare with Ord, a convenience for building MinHeapCallbacks by hand */
+#[allow(dead_code)]
+pub fn min_heap_less_ord<T: Ord, A>(a: &T, b: &T, _args: &A) -> bool {
+    a.cmp(b) == Ordering::Less
+}
+
+
+/* Sections for Unit tests */
+#[cfg(test)]
+mod tests {
+    use rand::Rng;
+
+    use super::*;
+
+    fn drain<T: Copy, A>(heap: &mut MinHeap<T>, func: &MinHeapCallbacks<T, A>, args: &A) -> Vec<T> {
+        let mut out = Vec::new();
+        while let Some(v) = heap.min_heap_pop(func, args) {
+            out.push(v);
+        }
+        out
+    }
+
+
+    #[test]
+    fn test_min_heap_push_pop() {
+        let func = MinHeapCallbacks::min();
+        let mut heap = MinHeap::min_heap_init(8);
+
+        for v in [5, 3, 8, 1, 9, 2] {
+            heap.min_heap_push(v, &func, &()).unwrap();
+        }
+        assert_eq!(heap.min_heap_peek(), Some(&1));
+        assert_eq!(drain(&mut heap, &func, &()), vec![1, 2, 3, 5, 8, 9]);
+        assert!(heap.min_heap_empty());
+        assert_eq!(heap.min_heap_pop(&func, &()), None);
+    }
+
+
+    #[test]
+    fn test_min_heap_full() {
+        let func = MinHeapCallbacks::max();
+        let mut heap = MinHeap::min_heap_init(3);
+
+        heap.min_heap_push(1, &func, &()).unwrap();
+        heap.min_heap_push(3, &func, &()).unwrap();
+        heap.min_heap_push(2, &func, &()).unwrap();
+        assert!(heap.min_heap_full());
+        assert_eq!(heap.min_heap_push(4, &func, &()), Err(4));
+        assert_eq!(heap.min_heap_peek(), Some(&3));
+    }
+
+
+    #[test]
+    fn test_min_heapify_all() {
+        let mut rng = rand::thread_rng();
+        let func = MinHeapCallbacks::min();
+        let values: Vec<i32> = (0..100).map(|_| rng.gen()).collect();
+
+        let mut heap = MinHeap::min_heap_init_data(values.clone(), 100);
+        heap.min_heapify_all(&func, &());
+
+        let mut sorted = values;
+        sorted.sort();
+        assert_eq!(drain(&mut heap, &func, &()), sorted);
+    }
+
+
+    #[test]
+    fn test_min_heap_pop_push() {
+        let func = MinHeapCallbacks::min();
+        let mut heap = MinHeap::min_heap_init(4);
+
+        assert_eq!(heap.min_heap_pop_push(5, &func, &()), None);
+        heap.min_heap_push(3, &func, &()).unwrap();
+        heap.min_heap_push(7, &func, &()).unwrap();
+
+        assert_eq!(heap.min_heap_pop_push(6, &func, &()), Some(3));
+        assert_eq!(heap.min_heap_pop_push(1, &func, &()), Some(5));
+        assert_eq!(drain(&mut heap, &func, &()), vec![1, 6, 7]);
+    }
+
+
+    #[test]
+    fn test_min_heap_del() {
+        let func = MinHeapCallbacks::min();
+        let mut heap = MinHeap::min_heap_init(16);
+
+        for v in [4, 8, 1, 9, 3, 7, 2] {
+            heap.min_heap_push(v, &func, &()).unwrap();
+        }
+        let idx = heap.as_slice().iter().position(|&v| v == 8).unwrap();
+        assert_eq!(heap.min_heap_del(idx, &func, &()), Some(8));
+        assert_eq!(heap.min_heap_del(heap.nr(), &func, &()), None);
+        assert_eq!(drain(&mut heap, &func, &()), vec![1, 2, 3, 4, 7, 9]);
+    }
+
+
+    #[test]
+    fn test_min_heap_callbacks() {
+        /* Order by the tuple field selected through @args */
+        let func: MinHeapCallbacks<(u32, u32), usize> = MinHeapCallbacks {
+            less: |a, b, &key| if key == 0 { a.0 < b.0 } else { a.1 < b.1 },
+            swp: Some(|a, b, _| std::mem::swap(a, b)),
+        };
+        let mut heap = MinHeap::min_heap_init(8);
+
+        for v in [(3, 0), (1, 2), (2, 1)] {
+            heap.min_heap_push(v, &func, &1).unwrap();
+        }
+        assert_eq!(heap.min_heap_peek(), Some(&(3, 0)));
+
+        heap.min_heapify_all(&func, &0);
+        assert_eq!(heap.min_heap_peek(), Some(&(1, 2)));
+
+        let less = MinHeapCallbacks { less: min_heap_less_ord, swp: None };
+        let mut heap = MinHeap::min_heap_init_data(vec![3, 1, 2], 3);
+        heap.min_heapify_all(&less, &());
+        assert_eq!(drain(&mut heap, &less, &()), vec![1, 2, 3]);
+    }
+}
@@ -0,0 +1,480 @@
+use std::cell::UnsafeCell;
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+
+use crate::errno::{EINVAL, ENOMEM};
+use crate::slab::smp_processor_id;
+
+/*
+ * Lock-less object pool
+ *
+ * Port of lib/objpool.c. The pool owns a fixed number of objects, built
+ * once by objpool_init(), and every cpu has a slot, a ring of free
+ * object indices. objpool_pop() takes an object from the slot of the
+ * current cpu and steals from the slots of the other cpus when it is
+ * empty, dropping the reference pushes the object back to the slot of
+ * the current cpu. A slot can hold every object of the pool, so a push
+ * never fails.
+ *
+ * In the kernel a slot is only pushed to by its own cpu with interrupts
+ * disabled. Threads share slots here, so a slot is a bounded MPMC ring
+ * where every entry carries a sequence number telling whether it is
+ * ready to be popped or pushed to, the push and pop sides each claim an
+ * entry by a cmpxchg on their index.
+ */
+
+
+/*
+ * An entry of a slot. @seq is the position the entry can be pushed at,
+ * and that position + 1 once @obj was written and it can be popped.
+ */
+struct ObjpoolEntry {
+    seq: AtomicUsize,
+    obj: AtomicU32,
+}
+
+
+#[repr(align(64))]
+struct ObjpoolIndex(AtomicUsize);
+
+
+/*
+ * struct ObjpoolSlot - per cpu ring of free objects
+ * @head: next position to pop
+ * @tail: next position to push
+ * @mask: capacity - 1
+ * @entries: the ring
+ */
+struct ObjpoolSlot {
+    head: ObjpoolIndex,
+    tail: ObjpoolIndex,
+    mask: usize,
+    entries: Box<[ObjpoolEntry]>,
+}
+
+
+impl ObjpoolSlot {
+    fn new(capacity: usize) -> Result<Self, i32> {
+        let mut entries = Vec::new();
+
+        entries.try_reserve_exact(capacity).map_err(|_| -ENOMEM)?;
+        entries.extend((0..capacity).map(|i| ObjpoolEntry {
+            seq: AtomicUsize::new(i),
+            obj: AtomicU32::new(0),
+        }));
+
+        Ok(ObjpoolSlot {
+            head: ObjpoolIndex(AtomicUsize::new(0)),
+            tail: ObjpoolIndex(AtomicUsize::new(0)),
+            mask: capacity - 1,
+            entries: entries.into_boxed_slice(),
+        })
+    }
+
+
+    /* Push @obj, false if the ring is full */
+    fn objpool_try_add_slot(&self, obj: u32) -> bool {
+        let mut tail = self.tail.0.load(Ordering::Relaxed);
+
+        loop {
+            let entry = &self.entries[tail & self.mask];
+            let seq = entry.seq.load(Ordering::Acquire);
+
+            if seq == tail {
+                match self.tail.0.compare_exchange_weak(tail, tail + 1, Ordering::Relaxed, Ordering::Relaxed) {
+                    Ok(_) => {
+                        entry.obj.store(obj, Ordering::Relaxed);
+                        entry.seq.store(tail + 1, Ordering::Release);
+                        return true;
+                    }
+                    Err(cur) => tail = cur,
+                }
+            } else if (seq.wrapping_sub(tail) as isize) < 0 {
+                return false;
+            } else {
+                tail = self.tail.0.load(Ordering::Relaxed);
+            }
+        }
+    }
+
+
+    /* Pop an object, %None if the ring is empty or its head is still being pushed */
+    fn objpool_try_get_slot(&self) -> Option<u32> {
+        let mut head = self.head.0.load(Ordering::Relaxed);
+
+        loop {
+            let entry = &self.entries[head & self.mask];
+            let seq = entry.seq.load(Ordering::Acquire);
+
+            if seq == head + 1 {
+                match self.head.0.compare_exchange_weak(head, head + 1, Ordering::Relaxed, Ordering::Relaxed) {
+                    Ok(_) => {
+                        let obj = entry.obj.load(Ordering::Relaxed);
+                        entry.seq.store(head + self.mask + 1, Ordering::Release);
+                        return Some(obj);
+                    }
+                    Err(cur) => head = cur,
+                }
+            } else if (seq.wrapping_sub(head + 1) as isize) < 0 {
+                return None;
+            } else {
+                head = self.head.0.load(Ordering::Relaxed);
+            }
+        }
+    }
+
+
+    fn nr_free(&self) -> usize {
+        let tail = self.tail.0.load(Ordering::Relaxed);
+        let head = self.head.0.load(Ordering::Relaxed);
+
+        tail.saturating_sub(head)
+    }
+}
+
+
+/**
+ * struct Objpool - pool of preallocated objects
+ * @objs: the objects, each owned by the slot it is free in or by the
+ *        ObjpoolObj it was popped as
+ * @cpu_slots: the per cpu slots
+ */
+pub struct Objpool<T> {
+    objs: Box<[UnsafeCell<T>]>,
+    cpu_slots: Box<[ObjpoolSlot]>,
+}
+
+/* An object is only reachable through the slot or the reference owning it */
+unsafe impl<T: Send> Send for Objpool<T> {}
+unsafe impl<T: Send> Sync for Objpool<T> {}
+
+
+/**
+ * struct ObjpoolObj - an object popped from an Objpool
+ *
+ * Gives exclusive access to the object, and pushes it back to the pool
+ * when dropped.
+ */
+pub struct ObjpoolObj<'a, T> {
+    pool: &'a Objpool<T>,
+    obj: u32,
+}
+
+unsafe impl<T: Send> Send for ObjpoolObj<'_, T> {}
+unsafe impl<T: Sync> Sync for ObjpoolObj<'_, T> {}
+
+
+#[allow(dead_code)]
+impl<T> Objpool<T> {
+    /**
+     * objpool_init - create a pool and build its objects
+     * @nr_objs: number of objects
+     * @nr_cpus: number of per cpu slots
+     * @objinit: constructor, called with the index of every object
+     *
+     * The objects are spread evenly over the slots.
+     *
+     * Return: the pool, -EINVAL if @nr_cpus is 0 or @nr_objs doesn't fit
+     * in a u32, or -ENOMEM if the slots can't be allocated.
+     */
+    pub fn objpool_init<F>(nr_objs: usize, nr_cpus: usize, mut objinit: F) -> Result<Self, i32>
+    where
+        F: FnMut(usize) -> T,
+    {
+        if nr_cpus == 0 || nr_objs > u32::MAX as usize {
+            return Err(-EINVAL);
+        }
+        /* Every slot can hold all the objects */
+        let capacity = nr_objs.max(1).checked_next_power_of_two().ok_or(-ENOMEM)?;
+        let mut cpu_slots = Vec::new();
+
+        cpu_slots.try_reserve_exact(nr_cpus).map_err(|_| -ENOMEM)?;
+        for _ in 0..nr_cpus {
+            cpu_slots.push(ObjpoolSlot::new(capacity)?);
+        }
+
+        let pool = Objpool {
+            objs: (0..nr_objs).map(|i| UnsafeCell::new(objinit(i))).collect(),
+            cpu_slots: cpu_slots.into_boxed_slice(),
+        };
+
+        /* objpool_init_percpu_slots(): the first slots get the remainder */
+        let mut obj = 0;
+        for (cpu, slot) in pool.cpu_slots.iter().enumerate() {
+            let nodes = nr_objs / nr_cpus + usize::from(cpu < nr_objs % nr_cpus);
+            for _ in 0..nodes {
+                slot.objpool_try_add_slot(obj);
+                obj += 1;
+            }
+        }
+        Ok(pool)
+    }
+
+
+    pub fn nr_objs(&self) -> usize {
+        self.objs.len()
+    }
+
+
+    pub fn nr_cpus(&self) -> usize {
+        self.cpu_slots.len()
+    }
+
+
+    /**
+     * objpool_nr_free - number of objects in the slots
+     *
+     * Only exact while no object is popped or pushed.
+     */
+    pub fn objpool_nr_free(&self) -> usize {
+        self.cpu_slots.iter().map(ObjpoolSlot::nr_free).sum()
+    }
+
+
+    /**
+     * objpool_pop - allocate an object from the pool
+     *
+     * Tries the slot of the current cpu first, then the slots of the
+     * other cpus in order.
+     *
+     * Return: the object, or %None if every slot looked empty.
+     */
+    pub fn objpool_pop(&self) -> Option<ObjpoolObj<'_, T>> {
+        let nr_cpus = self.cpu_slots.len();
+        let cpu = smp_processor_id() % nr_cpus;
+
+        (0..nr_cpus)
+            .find_map(|i| self.cpu_slots[(cpu + i) % nr_cpus].objpool_try_get_slot())
+            .map(|obj| ObjpoolObj { pool: self, obj })
+    }
+
+
+    /* Give @obj back to the slot of the current cpu */
+    fn objpool_push(&self, obj: u32) {
+        let nr_cpus = self.cpu_slots.len();
+        let cpu = smp_processor_id() % nr_cpus;
+
+        /* A slot is never full, a failure only means the ring is mid-wrap */
+        let mut i = 0;
+        while !self.cpu_slots[(cpu + i) % nr_cpus].objpool_try_add_slot(obj) {
+            i += 1;
+        }
+    }
+
+
+    /**
+     * objpool_fini - release the pool
+     *
+     * Return: the objects, in index order.
+     */
+    pub fn objpool_fini(self) -> Vec<T> {
+        self.objs.into_vec().into_iter().map(UnsafeCell::into_inner).collect()
+    }
+}
+
+
+#[allow(dead_code)]
+impl<T> ObjpoolObj<'_, T> {
+    /**
+     * objpool_index - index of the object, as passed to its constructor
+     */
+    pub fn objpool_index(this: &Self) -> usize {
+        this.obj as usize
+    }
+
+
+    /**
+     * objpool_push - give the object back to the pool
+     *
+     * Same as dropping it.
+     */
+    pub fn objpool_push(this: Self) {
+        drop(this);
+    }
+}
+
+
+impl<T> Deref for ObjpoolObj<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.pool.objs[self.obj as usize].get() }
+    }
+}
+
+
+impl<T> DerefMut for ObjpoolObj<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.pool.objs[self.obj as usize].get() }
+    }
+}
+
+
+impl<T> Drop for ObjpoolObj<'_, T> {
+    fn drop(&mut self) {
+        self.pool.objpool_push(self.obj);
+    }
+}
+
+
+/* Sections for Unit tests */
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+
+    use crate::bench::{bench_run, bench_threads};
+
+    use super::*;
+
+    #[test]
+    fn test_objpool_init() {
+        assert_eq!(Objpool::objpool_init(4, 0, |i| i).err(), Some(-EINVAL));
+        assert_eq!(Objpool::objpool_init(4, usize::MAX, |i| i).err(), Some(-ENOMEM));
+
+        let pool = Objpool::objpool_init(10, 4, |i| i * 10).unwrap();
+        assert_eq!((pool.nr_objs(), pool.nr_cpus()), (10, 4));
+        assert_eq!(pool.objpool_nr_free(), 10);
+        let nodes: Vec<_> = pool.cpu_slots.iter().map(ObjpoolSlot::nr_free).collect();
+        assert_eq!(nodes, vec![3, 3, 2, 2]);
+
+        let mut obj = pool.objpool_pop().unwrap();
+        assert_eq!(*obj, ObjpoolObj::objpool_index(&obj) * 10);
+        *obj += 1;
+        let index = ObjpoolObj::objpool_index(&obj);
+        ObjpoolObj::objpool_push(obj);
+        assert_eq!(pool.objpool_nr_free(), 10);
+
+        assert_eq!(pool.objpool_fini()[index], index * 10 + 1);
+    }
+
+
+    #[test]
+    fn test_objpool_steal() {
+        let pool = Objpool::objpool_init(10, 4, |i| i).unwrap();
+
+        /* A single thread drains its slot and then steals the others */
+        let objs: Vec<_> = (0..10).map(|_| pool.objpool_pop().unwrap()).collect();
+        let indices: HashSet<_> = objs.iter().map(|obj| **obj).collect();
+        assert_eq!(indices.len(), 10);
+        assert!(pool.objpool_pop().is_none());
+        assert_eq!(pool.objpool_nr_free(), 0);
+
+        /* Everything goes back to the slot of this thread */
+        drop(objs);
+        assert_eq!(pool.objpool_nr_free(), 10);
+        let cpu = smp_processor_id() % pool.nr_cpus();
+        assert_eq!(pool.cpu_slots[cpu].nr_free(), 10);
+
+        let objs: Vec<_> = (0..10).map(|_| pool.objpool_pop().unwrap()).collect();
+        assert_eq!(objs.len(), 10);
+        assert_eq!(pool.cpu_slots[cpu].nr_free(), 0);
+    }
+
+
+    #[test]
+    fn test_objpool_wrap() {
+        let pool = Objpool::objpool_init(3, 1, |i| i).unwrap();
+
+        /* Go around the 4 entry ring many times */
+        for round in 0..100 {
+            let a = pool.objpool_pop().unwrap();
+            let b = pool.objpool_pop().unwrap();
+            assert_ne!(*a, *b);
+            if round % 2 == 0 {
+                drop(a);
+                drop(b);
+            } else {
+                drop(b);
+                drop(a);
+            }
+        }
+        assert_eq!(pool.objpool_nr_free(), 3);
+        let mut indices: Vec<_> = (0..3).map(|_| *pool.objpool_pop().unwrap()).collect();
+        indices.sort();
+        assert_eq!(indices, vec![0, 1, 2]);
+    }
+
+
+    #[test]
+    fn test_objpool_drop() {
+        let tracker = Arc::new(());
+        let pool = Objpool::objpool_init(100, 2, |_| tracker.clone()).unwrap();
+
+        assert_eq!(Arc::strong_count(&tracker), 101);
+        drop(pool.objpool_pop());
+        drop(pool);
+        assert_eq!(Arc::strong_count(&tracker), 1);
+    }
+
+
+    #[test]
+    fn test_objpool_concurrent() {
+        const THREADS: usize = 4;
+        /* @1 is who owns the object, 0 being nobody */
+        let pool = Arc::new(Objpool::objpool_init(6, 2, |i| (i, 0)).unwrap());
+
+        let handles: Vec<_> = (1..=THREADS)
+            .map(|t| {
+                let pool = pool.clone();
+                thread::spawn(move || {
+                    let mut popped = 0;
+                    for i in 0..3000 {
+                        let Some(mut a) = pool.objpool_pop() else {
+                            thread::yield_now();
+                            continue;
+                        };
+                        let b = pool.objpool_pop();
+                        assert_eq!(a.1, 0);
+                        a.1 = t;
+                        if i % 50 == 0 {
+                            thread::yield_now();
+                        }
+                        assert_eq!(a.1, t);
+                        a.1 = 0;
+                        popped += 1 + usize::from(b.is_some());
+                    }
+                    popped
+                })
+            })
+            .collect();
+
+        let popped: usize = handles.into_iter().map(|h| h.join().unwrap()).sum();
+        assert!(popped > 0);
+        assert_eq!(pool.objpool_nr_free(), 6);
+        let pool = Arc::into_inner(pool).unwrap();
+        assert!(pool.objpool_fini().iter().enumerate().all(|(i, &obj)| obj == (i, 0)));
+    }
+
+
+    /* Run with `cargo test --release -- --ignored --nocapture bench_objpool` */
+    #[test]
+    #[ignore]
+    fn bench_objpool_vs_mutex_vec() {
+        const NR_OBJS: usize = 256;
+        const LOOPS: usize = 200_000;
+        let threads = bench_threads();
+
+        let pool = Arc::new(Objpool::objpool_init(NR_OBJS, threads, |i| [i; 8]).unwrap());
+        let objpool_time = bench_run(threads, move || {
+            for _ in 0..LOOPS {
+                let mut obj = pool.objpool_pop().unwrap();
+                obj[0] += 1;
+            }
+        });
+
+        let vec = Arc::new(Mutex::new((0..NR_OBJS).map(|i| [i; 8]).collect::<Vec<_>>()));
+        let mutex_time = bench_run(threads, move || {
+            for _ in 0..LOOPS {
+                let mut obj = vec.lock().unwrap().pop().unwrap();
+                obj[0] += 1;
+                vec.lock().unwrap().push(obj);
+            }
+        });
+
+        println!("{} threads x {} pop/push on {} objects", threads, LOOPS, NR_OBJS);
+        println!("Objpool: {:?}", objpool_time);
+        println!("Mutex<Vec>: {:?}", mutex_time);
+    }
+}
@@ -0,0 +1,380 @@
+use std::cmp::Ordering;
+use std::sync::atomic::{self, AtomicI32, AtomicI64};
+
+use crate::errno::EINVAL;
+use crate::slab::smp_processor_id;
+use crate::spinlock::SpinLock;
+
+/*
+ * Fast batching percpu counters.
+ *
+ * Port of lib/percpu_counter.c. Every cpu accumulates its updates in a
+ * local delta, and only folds it into the shared count once it reaches
+ * the batch, so the hot path touches a cache line of its own most of the
+ * time. Reading the shared count is cheap but may be off by up to
+ * batch - 1 per cpu, summing the deltas of every cpu under the lock is
+ * exact.
+ *
+ * Threads share the cpu they are bound to, see smp_processor_id(), so a
+ * local delta is updated with a cmpxchg rather than a plain add.
+ */
+
+
+#[repr(align(64))]
+struct PercpuCounterCpu(AtomicI32);
+
+
+/**
+ * struct PercpuCounter - counter with per cpu deltas
+ * @lock: serializes folding deltas into @count with exact sums
+ * @count: the shared count
+ * @counters: the per cpu deltas
+ * @batch: the delta a cpu may accumulate before folding it
+ */
+pub struct PercpuCounter {
+    lock: SpinLock<()>,
+    count: AtomicI64,
+    counters: Box<[PercpuCounterCpu]>,
+    batch: i32,
+}
+
+
+#[allow(dead_code)]
+impl PercpuCounter {
+    /**
+     * percpu_counter_init - create a counter with the default batch
+     * @amount: initial value
+     * @nr_cpus: number of per cpu deltas
+     *
+     * The batch is max(32, 2 * @nr_cpus), like percpu_counter_batch.
+     *
+     * Return: the counter, or -EINVAL if @nr_cpus is 0.
+     */
+    pub fn percpu_counter_init(amount: i64, nr_cpus: usize) -> Result<Self, i32> {
+        let batch = nr_cpus.saturating_mul(2).clamp(32, i32::MAX as usize) as i32;
+
+        PercpuCounter::percpu_counter_init_batch(amount, nr_cpus, batch)
+    }
+
+
+    /**
+     * percpu_counter_init_batch - create a counter with a given batch
+     * @amount: initial value
+     * @nr_cpus: number of per cpu deltas
+     * @batch: the delta a cpu may accumulate, 1 makes every update go
+     *         to the shared count
+     *
+     * Return: the counter, or -EINVAL if @nr_cpus is 0 or @batch isn't
+     * positive.
+     */
+    pub fn percpu_counter_init_batch(amount: i64, nr_cpus: usize, batch: i32) -> Result<Self, i32> {
+        if nr_cpus == 0 || batch <= 0 {
+            return Err(-EINVAL);
+        }
+
+        Ok(PercpuCounter {
+            lock: SpinLock::new(()),
+            count: AtomicI64::new(amount),
+            counters: (0..nr_cpus).map(|_| PercpuCounterCpu(AtomicI32::new(0))).collect(),
+            batch,
+        })
+    }
+
+
+    pub fn nr_cpus(&self) -> usize {
+        self.counters.len()
+    }
+
+
+    pub fn percpu_counter_batch(&self) -> i32 {
+        self.batch
+    }
+
+
+    fn this_cpu(&self) -> &AtomicI32 {
+        &self.counters[smp_processor_id() % self.counters.len()].0
+    }
+
+
+    /**
+     * percpu_counter_set - set the value of the counter
+     * @amount: the new value
+     *
+     * Updates racing with it may or may not be lost.
+     */
+    pub fn percpu_counter_set(&self, amount: i64) {
+        let _guard = self.lock.spin_lock();
+
+        for cpu in self.counters.iter() {
+            cpu.0.store(0, atomic::Ordering::Relaxed);
+        }
+        self.count.store(amount, atomic::Ordering::Relaxed);
+    }
+
+
+    /**
+     * percpu_counter_add_batch - add to the counter
+     * @amount: the value to add
+     * @batch: the delta this cpu may accumulate before folding it
+     *
+     * The delta of this cpu and @amount are folded into the shared count
+     * together once their sum reaches @batch in absolute value. The count
+     * saturates at the bounds of an i64 instead of wrapping.
+     */
+    pub fn percpu_counter_add_batch(&self, amount: i64, batch: i32) {
+        let cpu = self.this_cpu();
+        let mut count = cpu.load(atomic::Ordering::Relaxed);
+
+        loop {
+            let new = (count as i64).saturating_add(amount);
+
+            if new.unsigned_abs() >= batch.max(1) as u64 {
+                let _guard = self.lock.spin_lock();
+                let delta = (cpu.swap(0, atomic::Ordering::Relaxed) as i64).saturating_add(amount);
+                /* Only updated with the lock held, a plain load and store is enough */
+                let count = self.count.load(atomic::Ordering::Relaxed);
+                self.count.store(count.saturating_add(delta), atomic::Ordering::Relaxed);
+                return;
+            }
+            match cpu.compare_exchange_weak(count, new as i32, atomic::Ordering::Relaxed, atomic::Ordering::Relaxed) {
+                Ok(_) => return,
+                Err(cur) => count = cur,
+            }
+        }
+    }
+
+
+    /**
+     * percpu_counter_add - add to the counter with the batch of the counter
+     * @amount: the value to add
+     */
+    pub fn percpu_counter_add(&self, amount: i64) {
+        self.percpu_counter_add_batch(amount, self.batch);
+    }
+
+
+    pub fn percpu_counter_sub(&self, amount: i64) {
+        self.percpu_counter_add(amount.saturating_neg());
+    }
+
+
+    pub fn percpu_counter_inc(&self) {
+        self.percpu_counter_add(1);
+    }
+
+
+    pub fn percpu_counter_dec(&self) {
+        self.percpu_counter_add(-1);
+    }
+
+
+    /**
+     * percpu_counter_read - fast approximate value of the counter
+     *
+     * Off by less than the batch for every cpu.
+     */
+    pub fn percpu_counter_read(&self) -> i64 {
+        self.count.load(atomic::Ordering::Relaxed)
+    }
+
+
+    /**
+     * percpu_counter_read_positive - percpu_counter_read() clamped at 0
+     *
+     * For counters that can't be negative but whose approximation can.
+     */
+    pub fn percpu_counter_read_positive(&self) -> i64 {
+        self.percpu_counter_read().max(0)
+    }
+
+
+    /**
+     * percpu_counter_sum - exact value of the counter
+     *
+     * Adds up the deltas of every cpu with the lock held. Updates racing
+     * with it may or may not be counted.
+     */
+    pub fn percpu_counter_sum(&self) -> i64 {
+        let _guard = self.lock.spin_lock();
+
+        self.counters
+            .iter()
+            .fold(self.count.load(atomic::Ordering::Relaxed), |ret, cpu| {
+                ret.saturating_add(cpu.0.load(atomic::Ordering::Relaxed) as i64)
+            })
+    }
+
+
+    pub fn percpu_counter_sum_positive(&self) -> i64 {
+        self.percpu_counter_sum().max(0)
+    }
+
+
+    /**
+     * percpu_counter_compare_batch - compare the counter with @rhs
+     * @rhs: the value to compare with
+     * @batch: the batch the counter was updated with
+     *
+     * Answers from the approximate value when it is further from @rhs
+     * than the deltas of all cpus could account for, and falls back to
+     * an exact sum otherwise. A @batch below the batch of the counter is
+     * raised to it, as percpu_counter_add() lets the deltas grow that far.
+     */
+    pub fn percpu_counter_compare_batch(&self, rhs: i64, batch: i32) -> Ordering {
+        let count = self.percpu_counter_read();
+        let error = batch.max(self.batch) as u64 * self.counters.len() as u64;
+
+        /* Check to see if rough count will be sufficient for comparison */
+        if count.abs_diff(rhs) > error {
+            return count.cmp(&rhs);
+        }
+        /* Need to use precise count */
+        self.percpu_counter_sum().cmp(&rhs)
+    }
+
+
+    /**
+     * percpu_counter_compare - compare the counter with @rhs
+     * @rhs: the value to compare with
+     */
+    pub fn percpu_counter_compare(&self, rhs: i64) -> Ordering {
+        self.percpu_counter_compare_batch(rhs, self.batch)
+    }
+}
+
+
+/* Sections for Unit tests */
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::thread;
+
+    use super::*;
+
+    #[test]
+    fn test_percpu_counter_batch() {
+        assert_eq!(PercpuCounter::percpu_counter_init(0, 0).err(), Some(-EINVAL));
+        assert_eq!(PercpuCounter::percpu_counter_init_batch(0, 1, 0).err(), Some(-EINVAL));
+        assert_eq!(PercpuCounter::percpu_counter_init(0, 4).unwrap().percpu_counter_batch(), 32);
+        assert_eq!(PercpuCounter::percpu_counter_init(0, 64).unwrap().percpu_counter_batch(), 128);
+
+        let fbc = PercpuCounter::percpu_counter_init(100, 4).unwrap();
+        fbc.percpu_counter_add(10);
+        assert_eq!(fbc.percpu_counter_read(), 100);
+        assert_eq!(fbc.percpu_counter_sum(), 110);
+
+        /* The delta reaches the batch, and is folded with the update */
+        fbc.percpu_counter_add(22);
+        assert_eq!(fbc.percpu_counter_read(), 132);
+        assert_eq!(fbc.percpu_counter_sum(), 132);
+
+        fbc.percpu_counter_inc();
+        fbc.percpu_counter_dec();
+        fbc.percpu_counter_sub(2);
+        assert_eq!((fbc.percpu_counter_read(), fbc.percpu_counter_sum()), (132, 130));
+        fbc.percpu_counter_add(-1000);
+        assert_eq!((fbc.percpu_counter_read(), fbc.percpu_counter_sum()), (-870, -870));
+    }
+
+
+    #[test]
+    fn test_percpu_counter_set() {
+        let fbc = PercpuCounter::percpu_counter_init(0, 2).unwrap();
+
+        fbc.percpu_counter_add(-5);
+        assert_eq!(fbc.percpu_counter_sum(), -5);
+        assert_eq!(fbc.percpu_counter_sum_positive(), 0);
+        fbc.percpu_counter_add_batch(-40, 1000);
+        assert_eq!(fbc.percpu_counter_read_positive(), 0);
+        assert_eq!(fbc.percpu_counter_sum(), -45);
+
+        fbc.percpu_counter_set(7);
+        assert_eq!((fbc.percpu_counter_read(), fbc.percpu_counter_sum()), (7, 7));
+        fbc.percpu_counter_inc();
+        assert_eq!((fbc.percpu_counter_read_positive(), fbc.percpu_counter_sum_positive()), (7, 8));
+    }
+
+
+    #[test]
+    fn test_percpu_counter_exact_batch() {
+        let fbc = PercpuCounter::percpu_counter_init_batch(0, 3, 1).unwrap();
+
+        for i in 1..=50 {
+            fbc.percpu_counter_add(if i % 5 == 0 { -3 } else { 1 });
+            assert_eq!(fbc.percpu_counter_read(), fbc.percpu_counter_sum());
+        }
+        assert_eq!(fbc.percpu_counter_read(), 40 - 30);
+    }
+
+
+    #[test]
+    fn test_percpu_counter_compare() {
+        let fbc = PercpuCounter::percpu_counter_init_batch(1000, 2, 32).unwrap();
+
+        fbc.percpu_counter_add(20);
+        /* Far from rhs, the rough count answers */
+        assert_eq!(fbc.percpu_counter_compare(0), Ordering::Greater);
+        assert_eq!(fbc.percpu_counter_compare(5000), Ordering::Less);
+        /* Within 2 * 32 of rhs, only the exact sum can tell */
+        assert_eq!(fbc.percpu_counter_read(), 1000);
+        assert_eq!(fbc.percpu_counter_compare(1010), Ordering::Greater);
+        assert_eq!(fbc.percpu_counter_compare(1020), Ordering::Equal);
+        assert_eq!(fbc.percpu_counter_compare(1030), Ordering::Less);
+        /* A smaller batch can't make the rough count answer */
+        assert_eq!(fbc.percpu_counter_compare_batch(1010, 0), Ordering::Greater);
+        assert_eq!(fbc.percpu_counter_compare_batch(1030, 1), Ordering::Less);
+    }
+
+
+    #[test]
+    fn test_percpu_counter_saturate() {
+        let fbc = PercpuCounter::percpu_counter_init_batch(i64::MAX - 10, 2, 32).unwrap();
+
+        fbc.percpu_counter_add(20);
+        assert_eq!(fbc.percpu_counter_sum(), i64::MAX);
+        fbc.percpu_counter_add(i64::MAX);
+        assert_eq!((fbc.percpu_counter_read(), fbc.percpu_counter_sum()), (i64::MAX, i64::MAX));
+        assert_eq!(fbc.percpu_counter_compare(i64::MIN), Ordering::Greater);
+
+        fbc.percpu_counter_set(i64::MIN + 10);
+        fbc.percpu_counter_sub(5);
+        fbc.percpu_counter_sub(i64::MIN);
+        assert_eq!(fbc.percpu_counter_sum(), 4);
+        fbc.percpu_counter_add(i64::MIN);
+        fbc.percpu_counter_add(i64::MIN);
+        assert_eq!((fbc.percpu_counter_read(), fbc.percpu_counter_sum()), (i64::MIN, i64::MIN));
+        assert_eq!(fbc.percpu_counter_compare(i64::MAX), Ordering::Less);
+    }
+
+
+    #[test]
+    fn test_percpu_counter_concurrent() {
+        const THREADS: usize = 4;
+        const LOOPS: i64 = 10000;
+        let fbc = Arc::new(PercpuCounter::percpu_counter_init(0, 2).unwrap());
+
+        let handles: Vec<_> = (0..THREADS)
+            .map(|t| {
+                let fbc = fbc.clone();
+                thread::spawn(move || {
+                    for i in 0..LOOPS {
+                        fbc.percpu_counter_add(if i % 4 == 3 { -1 } else { 2 });
+                        if i % 1000 == t as i64 {
+                            assert!(fbc.percpu_counter_sum() >= 0);
+                            thread::yield_now();
+                        }
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        let expected = THREADS as i64 * (LOOPS / 4) * 5;
+        assert_eq!(fbc.percpu_counter_sum(), expected);
+        let error = (fbc.percpu_counter_batch() as i64 - 1) * fbc.nr_cpus() as i64;
+        assert!((fbc.percpu_counter_read() - expected).abs() <= error);
+        assert_eq!(fbc.percpu_counter_compare(expected), Ordering::Equal);
+    }
+}
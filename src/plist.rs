@@ -0,0 +1,440 @@
+use std::{cell::{Cell, RefCell}, collections::HashMap, rc::Rc};
+
+use crate::list::ListHead;
+
+type ListPtr = *const RefCell<ListHead>;
+
+
+/**
+ * struct PlistNode - a node of a priority sorted list
+ *
+ * Clones refer to the same node. A lower prio value means a higher
+ * priority, and the node keeps the two ListHead rings of the kernel's
+ * plist_node: prio_list links the first node of every distinct priority,
+ * node_list links all the nodes.
+ */
+#[derive(Clone)]
+pub struct PlistNode {
+    prio: Rc<Cell<i32>>,
+    prio_list: Rc<RefCell<ListHead>>,
+    node_list: Rc<RefCell<ListHead>>,
+}
+
+#[allow(dead_code)]
+impl PlistNode {
+    /**
+     * plist_node_init - Dynamic struct plist_node initializer
+     * @prio: initial node priority
+     */
+    pub fn plist_node_init(prio: i32) -> Self {
+        PlistNode {
+            prio: Rc::new(Cell::new(prio)),
+            prio_list: ListHead::new(prio),
+            node_list: ListHead::new(prio),
+        }
+    }
+
+
+    pub fn prio(&self) -> i32 {
+        self.prio.get()
+    }
+
+
+    /**
+     * plist_node_set_prio - change the priority of a node
+     * @prio: the new priority
+     *
+     * The node must not be on a plist, plist_del() it first.
+     */
+    pub fn plist_node_set_prio(&self, prio: i32) {
+        assert!(self.plist_node_empty(), "plist_node_set_prio: node is queued");
+        self.prio.set(prio);
+    }
+
+
+    /**
+     * plist_node_empty - return !0 if plist_node is not on a list
+     */
+    pub fn plist_node_empty(&self) -> bool {
+        ListHead::list_empty(self.node_list.clone())
+    }
+
+
+    /* Whether @self and @other are the same node */
+    pub fn ptr_eq(&self, other: &PlistNode) -> bool {
+        Rc::ptr_eq(&self.node_list, &other.node_list)
+    }
+}
+
+
+/**
+ * struct PlistHead - the head of a priority sorted list
+ *
+ * Nodes are kept sorted by ascending prio and in FIFO order within the
+ * same prio. Adding a node walks prio_list only, so the cost is bound by
+ * the number of distinct priorities rather than the number of nodes.
+ */
+pub struct PlistHead {
+    node_list: Rc<RefCell<ListHead>>,
+    /* container_of can't get from an Rc'd ListHead back to its node */
+    nodes: HashMap<ListPtr, PlistNode>,
+}
+
+impl Default for PlistHead {
+    fn default() -> Self {
+        PlistHead::plist_head_init()
+    }
+}
+
+#[allow(dead_code)]
+impl PlistHead {
+    /**
+     * plist_head_init - dynamic struct plist_head initializer
+     */
+    pub fn plist_head_init() -> Self {
+        PlistHead {
+            node_list: ListHead::new(-1),
+            nodes: HashMap::new(),
+        }
+    }
+
+
+    /**
+     * plist_head_empty - return !0 if a plist_head is empty
+     */
+    pub fn plist_head_empty(&self) -> bool {
+        ListHead::list_empty(self.node_list.clone())
+    }
+
+
+    fn entry(&self, list: &Rc<RefCell<ListHead>>) -> PlistNode {
+        self.nodes[&Rc::as_ptr(list)].clone()
+    }
+
+
+    /**
+     * plist_first - return the first node (and thus, highest priority)
+     *
+     * Return: %None if the list is empty.
+     */
+    pub fn plist_first(&self) -> Option<PlistNode> {
+        if self.plist_head_empty() {
+            return None;
+        }
+        Some(self.entry(&ListHead::list_next(self.node_list.clone())))
+    }
+
+
+    /**
+     * plist_last - return the last node (and thus, lowest priority)
+     *
+     * Return: %None if the list is empty.
+     */
+    pub fn plist_last(&self) -> Option<PlistNode> {
+        if self.plist_head_empty() {
+            return None;
+        }
+        Some(self.entry(&ListHead::list_prev(self.node_list.clone())))
+    }
+
+
+    /**
+     * plist_next - get the next entry in list
+     * @pos: the node on this list to start from
+     *
+     * Return: %None if @pos is the last node.
+     */
+    pub fn plist_next(&self, pos: &PlistNode) -> Option<PlistNode> {
+        let next = ListHead::list_next(pos.node_list.clone());
+        if Rc::ptr_eq(&next, &self.node_list) {
+            return None;
+        }
+        Some(self.entry(&next))
+    }
+
+
+    /**
+     * plist_for_each - iterate over the plist in priority order
+     */
+    pub fn plist_for_each(&self) -> impl Iterator<Item = PlistNode> + '_ {
+        let mut pos = ListHead::list_next(self.node_list.clone());
+
+        std::iter::from_fn(move || {
+            if Rc::ptr_eq(&pos, &self.node_list) {
+                return None;
+            }
+            let node = self.entry(&pos);
+            pos = ListHead::list_next(pos.clone());
+            Some(node)
+        })
+    }
+
+
+    /**
+     * plist_add - add @node to @head
+     * @node: &struct plist_node pointer
+     */
+    pub fn plist_add(&mut self, node: &PlistNode) {
+        let mut node_next = self.node_list.clone();
+
+        debug_assert!(node.plist_node_empty());
+        debug_assert!(ListHead::list_empty(node.prio_list.clone()));
+
+        self.nodes.insert(Rc::as_ptr(&node.prio_list), node.clone());
+        self.nodes.insert(Rc::as_ptr(&node.node_list), node.clone());
+
+        if !self.plist_head_empty() {
+            let first = self.plist_first().unwrap();
+            let last = self.entry(&ListHead::list_prev(first.prio_list.clone()));
+            let mut iter = first.clone();
+            let mut reverse_iter = last.clone();
+            let mut prev: Option<PlistNode> = None;
+
+            /* Walk prio_list from both ends at once */
+            loop {
+                if node.prio() < iter.prio() {
+                    node_next = iter.node_list.clone();
+                    break;
+                } else if node.prio() >= reverse_iter.prio() {
+                    iter = self.entry(&ListHead::list_next(reverse_iter.prio_list.clone()));
+                    if !reverse_iter.ptr_eq(&last) {
+                        node_next = iter.node_list.clone();
+                    }
+                    prev = Some(reverse_iter);
+                    break;
+                }
+
+                prev = Some(iter.clone());
+                iter = self.entry(&ListHead::list_next(iter.prio_list.clone()));
+                reverse_iter = self.entry(&ListHead::list_prev(reverse_iter.prio_list.clone()));
+                if iter.ptr_eq(&first) {
+                    break;
+                }
+            }
+
+            if prev.is_none_or(|prev| prev.prio() != node.prio()) {
+                ListHead::list_add_tail(node.prio_list.clone(), iter.prio_list.clone());
+            }
+        }
+
+        ListHead::list_add_tail(node.node_list.clone(), node_next);
+    }
+
+
+    /**
+     * plist_del - Remove a @node from plist.
+     * @node: &struct plist_node pointer - entry to be removed
+     */
+    pub fn plist_del(&mut self, node: &PlistNode) {
+        if !self.nodes.contains_key(&Rc::as_ptr(&node.node_list)) {
+            return;
+        }
+
+        if !ListHead::list_empty(node.prio_list.clone()) {
+            let next = ListHead::list_next(node.node_list.clone());
+            if !Rc::ptr_eq(&next, &self.node_list) {
+                let next = self.entry(&next);
+
+                /* add the next plist_node into prio_list */
+                if ListHead::list_empty(next.prio_list.clone()) {
+                    ListHead::list_add(next.prio_list.clone(), node.prio_list.clone());
+                }
+            }
+            ListHead::list_del_init(node.prio_list.clone());
+        }
+
+        ListHead::list_del_init(node.node_list.clone());
+        self.nodes.remove(&Rc::as_ptr(&node.prio_list));
+        self.nodes.remove(&Rc::as_ptr(&node.node_list));
+    }
+
+
+    /**
+     * plist_requeue - Requeue @node at end of same-prio entries.
+     * @node: &struct plist_node pointer - entry to be moved
+     *
+     * This is essentially an optimized plist_del() followed by
+     * plist_add(). It moves an entry already in the plist to
+     * after any other same-priority entries.
+     */
+    pub fn plist_requeue(&mut self, node: &PlistNode) {
+        let mut node_next = self.node_list.clone();
+
+        assert!(!self.plist_head_empty());
+        assert!(!node.plist_node_empty());
+
+        let Some(mut iter) = self.plist_next(node) else {
+            return;
+        };
+        if node.prio() != iter.prio() {
+            return;
+        }
+
+        self.plist_del(node);
+
+        while let Some(next) = self.plist_next(&iter) {
+            iter = next;
+            if node.prio() != iter.prio() {
+                node_next = iter.node_list.clone();
+                break;
+            }
+        }
+
+        self.nodes.insert(Rc::as_ptr(&node.prio_list), node.clone());
+        self.nodes.insert(Rc::as_ptr(&node.node_list), node.clone());
+        ListHead::list_add_tail(node.node_list.clone(), node_next);
+    }
+}
+
+
+/* Sections for Unit tests */
+#[cfg(test)]
+mod tests {
+    use rand::Rng;
+
+    use super::*;
+
+    /* plist_check_head - check prio_list holds the first node of each prio, in order */
+    fn plist_check_head(head: &PlistHead) {
+        let nodes: Vec<PlistNode> = head.plist_for_each().collect();
+
+        for pair in nodes.windows(2) {
+            assert!(pair[0].prio() <= pair[1].prio());
+        }
+        /* A lone priority leaves prio_list with nothing to link */
+        let single = nodes.first().is_none_or(|first| nodes.iter().all(|n| n.prio() == first.prio()));
+
+        for (i, node) in nodes.iter().enumerate() {
+            let leader = !single && (i == 0 || nodes[i - 1].prio() != node.prio());
+            assert_eq!(!ListHead::list_empty(node.prio_list.clone()), leader);
+            if leader {
+                let next = head.entry(&ListHead::list_next(node.prio_list.clone()));
+                let expect = nodes[i + 1..]
+                    .iter()
+                    .find(|n| n.prio() != node.prio())
+                    .unwrap_or(&nodes[0]);
+                assert!(next.ptr_eq(expect));
+            }
+        }
+    }
+
+
+    fn prios(head: &PlistHead) -> Vec<i32> {
+        head.plist_for_each().map(|node| node.prio()).collect()
+    }
+
+
+    #[test]
+    fn test_plist_add() {
+        let mut head = PlistHead::plist_head_init();
+        assert!(head.plist_first().is_none());
+
+        for prio in [5, 1, 9, 3, 7] {
+            head.plist_add(&PlistNode::plist_node_init(prio));
+            plist_check_head(&head);
+        }
+
+        assert_eq!(prios(&head), vec![1, 3, 5, 7, 9]);
+        assert_eq!(head.plist_first().unwrap().prio(), 1);
+        assert_eq!(head.plist_last().unwrap().prio(), 9);
+    }
+
+
+    #[test]
+    fn test_plist_fifo() {
+        let mut head = PlistHead::plist_head_init();
+        let a = PlistNode::plist_node_init(2);
+        let b = PlistNode::plist_node_init(2);
+        let c = PlistNode::plist_node_init(1);
+        let d = PlistNode::plist_node_init(2);
+
+        for node in [&a, &b, &c, &d] {
+            head.plist_add(node);
+        }
+        plist_check_head(&head);
+
+        let order: Vec<PlistNode> = head.plist_for_each().collect();
+        assert!(order[0].ptr_eq(&c));
+        assert!(order[1].ptr_eq(&a));
+        assert!(order[2].ptr_eq(&b));
+        assert!(order[3].ptr_eq(&d));
+    }
+
+
+    #[test]
+    fn test_plist_del() {
+        let mut head = PlistHead::plist_head_init();
+        let a = PlistNode::plist_node_init(1);
+        let b = PlistNode::plist_node_init(1);
+        let c = PlistNode::plist_node_init(3);
+
+        for node in [&a, &b, &c] {
+            head.plist_add(node);
+        }
+
+        /* b takes over a's place on prio_list */
+        head.plist_del(&a);
+        plist_check_head(&head);
+        assert!(a.plist_node_empty());
+        assert!(head.plist_first().unwrap().ptr_eq(&b));
+
+        head.plist_del(&a);
+        head.plist_del(&c);
+        head.plist_del(&b);
+        assert!(head.plist_head_empty());
+
+        a.plist_node_set_prio(7);
+        head.plist_add(&a);
+        assert_eq!(prios(&head), vec![7]);
+    }
+
+
+    #[test]
+    fn test_plist_requeue() {
+        let mut head = PlistHead::plist_head_init();
+        let a = PlistNode::plist_node_init(1);
+        let b = PlistNode::plist_node_init(1);
+        let c = PlistNode::plist_node_init(1);
+        let d = PlistNode::plist_node_init(4);
+
+        for node in [&a, &b, &c, &d] {
+            head.plist_add(node);
+        }
+
+        head.plist_requeue(&a);
+        plist_check_head(&head);
+        let order: Vec<PlistNode> = head.plist_for_each().collect();
+        assert!(order[0].ptr_eq(&b));
+        assert!(order[1].ptr_eq(&c));
+        assert!(order[2].ptr_eq(&a));
+        assert!(order[3].ptr_eq(&d));
+
+        /* The only node of its prio stays put */
+        head.plist_requeue(&d);
+        assert!(head.plist_last().unwrap().ptr_eq(&d));
+    }
+
+
+    #[test]
+    fn test_plist_random() {
+        let mut rng = rand::thread_rng();
+        let mut head = PlistHead::plist_head_init();
+        let mut queued: Vec<PlistNode> = Vec::new();
+
+        for _ in 0..1000 {
+            if queued.is_empty() || rng.gen_bool(0.6) {
+                let node = PlistNode::plist_node_init(rng.gen_range(0..16));
+                head.plist_add(&node);
+                queued.push(node);
+            } else if rng.gen_bool(0.5) {
+                let node = queued.swap_remove(rng.gen_range(0..queued.len()));
+                head.plist_del(&node);
+            } else {
+                let node = &queued[rng.gen_range(0..queued.len())];
+                head.plist_requeue(node);
+            }
+        }
+        plist_check_head(&head);
+        assert_eq!(head.plist_for_each().count(), queued.len());
+    }
+}
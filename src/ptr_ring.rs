@@ -0,0 +1,545 @@
+use std::alloc::Layout;
+use std::cell::UnsafeCell;
+use std::ptr;
+use std::sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
+
+use crate::errno::ENOMEM;
+use crate::spinlock::SpinLock;
+
+/*
+ * Fixed size producer/consumer ring of pointers
+ *
+ * Port of include/linux/ptr_ring.h. Every slot either is null or holds
+ * an entry, so the producer and the consumer never compare indices: the
+ * ring is full when the slot at @producer is still set and empty when
+ * the slot at @consumer_head is null. Each side has its own lock and its
+ * own cache line, which lets one producer and one consumer run without
+ * ever bouncing a shared line except for the slots themselves.
+ *
+ * Entries are owned boxes; the ring holds them as raw pointers from
+ * Box::into_raw() and hands the ownership back on consume.
+ */
+
+const SMP_CACHE_BYTES: usize = 64;
+
+
+#[repr(align(64))]
+struct PtrRingProducer {
+    producer: usize,
+}
+
+
+#[repr(align(64))]
+struct PtrRingConsumer {
+    consumer_head: usize,
+    consumer_tail: usize,
+}
+
+
+/*
+ * The queue with its geometry. Read with either lock held, replaced by
+ * ptr_ring_resize() with both locks held.
+ */
+#[repr(align(64))]
+struct PtrRingQueue<T> {
+    size: usize,
+    batch: usize,
+    queue: Box<[AtomicPtr<T>]>,
+}
+
+
+/**
+ * struct PtrRing - bounded ring of owned pointers
+ * @producer_lock: protects the next slot to fill
+ * @consumer_lock: protects the next slot to consume and the next slot to
+ *                 invalidate
+ * @ring: slots and geometry, see &struct PtrRingQueue
+ * @count: number of entries, only a hint for lockless readers
+ */
+pub struct PtrRing<T> {
+    producer_lock: SpinLock<PtrRingProducer>,
+    consumer_lock: SpinLock<PtrRingConsumer>,
+    ring: UnsafeCell<PtrRingQueue<T>>,
+    count: AtomicUsize,
+}
+
+/* @ring is only touched under the locks, see &struct PtrRingQueue */
+unsafe impl<T: Send> Send for PtrRing<T> {}
+unsafe impl<T: Send> Sync for PtrRing<T> {}
+
+
+fn ptr_ring_init_queue_alloc<T>(size: usize) -> Result<Box<[AtomicPtr<T>]>, i32> {
+    if Layout::array::<AtomicPtr<T>>(size).is_err() {
+        return Err(-ENOMEM);
+    }
+    Ok((0..size).map(|_| AtomicPtr::new(ptr::null_mut())).collect())
+}
+
+
+/*
+ * Consume enough entries to fill a couple of cache lines before giving
+ * their slots back to the producer.
+ */
+fn ptr_ring_batch(size: usize) -> usize {
+    let batch = SMP_CACHE_BYTES * 2 / size_of::<*mut ()>();
+
+    if batch > size / 2 || batch == 0 {
+        1
+    } else {
+        batch
+    }
+}
+
+
+#[allow(dead_code)]
+impl<T> PtrRing<T> {
+    /**
+     * ptr_ring_init - allocate a ring of @size slots
+     *
+     * A ring of size 0 is valid and always full.
+     *
+     * Return: the ring, or -ENOMEM if @size slots cannot be allocated.
+     */
+    pub fn ptr_ring_init(size: usize) -> Result<Self, i32> {
+        let queue = ptr_ring_init_queue_alloc(size)?;
+
+        Ok(PtrRing {
+            producer_lock: SpinLock::new(PtrRingProducer { producer: 0 }),
+            consumer_lock: SpinLock::new(PtrRingConsumer {
+                consumer_head: 0,
+                consumer_tail: 0,
+            }),
+            ring: UnsafeCell::new(PtrRingQueue {
+                size,
+                batch: ptr_ring_batch(size),
+                queue,
+            }),
+            count: AtomicUsize::new(0),
+        })
+    }
+
+
+    /*
+     * Caller holds at least one of the two locks, which keeps
+     * ptr_ring_resize() from swapping the queue under us.
+     */
+    fn queue(&self) -> &PtrRingQueue<T> {
+        unsafe { &*self.ring.get() }
+    }
+
+
+    fn __ptr_ring_full(&self, p: &PtrRingProducer) -> bool {
+        let r = self.queue();
+
+        r.size == 0 || !r.queue[p.producer].load(Ordering::Acquire).is_null()
+    }
+
+
+    fn __ptr_ring_produce(&self, p: &mut PtrRingProducer, ptr: Box<T>) -> Result<(), Box<T>> {
+        if self.__ptr_ring_full(p) {
+            return Err(ptr);
+        }
+
+        let r = self.queue();
+        self.count.fetch_add(1, Ordering::Relaxed);
+        /* Release pairs with the acquire in __ptr_ring_peek() */
+        r.queue[p.producer].store(Box::into_raw(ptr), Ordering::Release);
+        p.producer += 1;
+        if p.producer >= r.size {
+            p.producer = 0;
+        }
+        Ok(())
+    }
+
+
+    fn __ptr_ring_peek(&self, c: &PtrRingConsumer) -> *mut T {
+        let r = self.queue();
+
+        if r.size == 0 {
+            return ptr::null_mut();
+        }
+        r.queue[c.consumer_head].load(Ordering::Acquire)
+    }
+
+
+    fn __ptr_ring_discard_one(&self, c: &mut PtrRingConsumer) {
+        let r = self.queue();
+        let mut consumer_head = c.consumer_head;
+        let head = consumer_head;
+
+        consumer_head += 1;
+        /*
+         * Once we have processed enough entries invalidate them in the
+         * ring all at once so producer can reuse their space in the ring.
+         * We also do this when we reach end of the ring - not mandatory
+         * but helps keep the implementation simple.
+         */
+        if consumer_head - c.consumer_tail >= r.batch || consumer_head >= r.size {
+            /*
+             * Zero out entries in the reverse order: this way we touch the
+             * cache line that producer might currently be reading the last;
+             * producer won't make progress and touch other cache lines
+             * besides the first one until we write out all entries.
+             */
+            for slot in r.queue[c.consumer_tail..=head].iter().rev() {
+                slot.store(ptr::null_mut(), Ordering::Release);
+            }
+            c.consumer_tail = consumer_head;
+        }
+        if consumer_head >= r.size {
+            consumer_head = 0;
+            c.consumer_tail = 0;
+        }
+        c.consumer_head = consumer_head;
+    }
+
+
+    fn __ptr_ring_consume(&self, c: &mut PtrRingConsumer) -> Option<Box<T>> {
+        let ptr = self.__ptr_ring_peek(c);
+
+        if ptr.is_null() {
+            return None;
+        }
+        self.__ptr_ring_discard_one(c);
+        self.count.fetch_sub(1, Ordering::Relaxed);
+        /* The slot stays set until its batch is invalidated, but it is ours */
+        Some(unsafe { Box::from_raw(ptr) })
+    }
+
+
+    /**
+     * ptr_ring_produce - add @ptr at the producer end
+     *
+     * Return: %Err with @ptr handed back if the ring is full (-ENOSPC in
+     * the kernel).
+     */
+    pub fn ptr_ring_produce(&self, ptr: Box<T>) -> Result<(), Box<T>> {
+        let mut p = self.producer_lock.spin_lock();
+
+        self.__ptr_ring_produce(&mut p, ptr)
+    }
+
+
+    /**
+     * ptr_ring_consume - remove the oldest entry
+     *
+     * Return: the entry, or %None if the ring is empty.
+     */
+    pub fn ptr_ring_consume(&self) -> Option<Box<T>> {
+        let mut c = self.consumer_lock.spin_lock();
+
+        self.__ptr_ring_consume(&mut c)
+    }
+
+
+    /**
+     * ptr_ring_consume_batched - remove up to @n entries under one lock
+     * @array: consumed entries are appended here, oldest first
+     * @n: maximum number of entries to consume
+     *
+     * Return: number of entries consumed.
+     */
+    pub fn ptr_ring_consume_batched(&self, array: &mut Vec<Box<T>>, n: usize) -> usize {
+        let mut c = self.consumer_lock.spin_lock();
+        let mut i = 0;
+
+        while i < n {
+            let Some(ptr) = self.__ptr_ring_consume(&mut c) else {
+                break;
+            };
+            array.push(ptr);
+            i += 1;
+        }
+        i
+    }
+
+
+    /**
+     * ptr_ring_peek - call @f on the oldest entry without removing it
+     *
+     * Like PTR_RING_PEEK_CALL(), @f runs with the consumer lock held so
+     * the entry cannot be consumed and freed under it.
+     */
+    pub fn ptr_ring_peek<R>(&self, f: impl FnOnce(Option<&T>) -> R) -> R {
+        let c = self.consumer_lock.spin_lock();
+        let ptr = self.__ptr_ring_peek(&c);
+
+        f(unsafe { ptr.as_ref() })
+    }
+
+
+    /**
+     * ptr_ring_full - check whether the producer has no free slot
+     *
+     * Consumed slots are only handed back a batch at a time, so the ring
+     * can stay full for a while after the consumer made progress.
+     */
+    pub fn ptr_ring_full(&self) -> bool {
+        let p = self.producer_lock.spin_lock();
+
+        self.__ptr_ring_full(&p)
+    }
+
+
+    /**
+     * ptr_ring_empty - check whether there is nothing to consume
+     */
+    pub fn ptr_ring_empty(&self) -> bool {
+        let c = self.consumer_lock.spin_lock();
+
+        self.__ptr_ring_peek(&c).is_null()
+    }
+
+
+    /**
+     * ptr_ring_size - number of slots
+     */
+    pub fn ptr_ring_size(&self) -> usize {
+        let _c = self.consumer_lock.spin_lock();
+
+        self.queue().size
+    }
+
+
+    /**
+     * len - number of queued entries
+     *
+     * Only a snapshot when producers or consumers run concurrently.
+     */
+    pub fn len(&self) -> usize {
+        self.count.load(Ordering::Relaxed)
+    }
+
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+
+    /**
+     * ptr_ring_resize - move the entries to a queue of @size slots
+     * @destroy: called on the newest entries that do not fit
+     *
+     * Both locks are held while the entries are moved, so producers and
+     * consumers just wait for the resize to finish.
+     *
+     * Return: 0 on success, -ENOMEM if @size slots cannot be allocated.
+     */
+    pub fn ptr_ring_resize(&self, size: usize, mut destroy: impl FnMut(Box<T>)) -> Result<(), i32> {
+        let queue = ptr_ring_init_queue_alloc(size)?;
+        let mut c = self.consumer_lock.spin_lock();
+        let mut p = self.producer_lock.spin_lock();
+        let mut producer = 0;
+
+        while let Some(ptr) = self.__ptr_ring_consume(&mut c) {
+            if producer < size {
+                queue[producer].store(Box::into_raw(ptr), Ordering::Relaxed);
+                self.count.fetch_add(1, Ordering::Relaxed);
+                producer += 1;
+            } else {
+                destroy(ptr);
+            }
+        }
+        if producer >= size {
+            producer = 0;
+        }
+
+        /* Both locks are held, nobody else looks at the queue */
+        let r = unsafe { &mut *self.ring.get() };
+        r.size = size;
+        r.batch = ptr_ring_batch(size);
+        r.queue = queue;
+        p.producer = producer;
+        c.consumer_head = 0;
+        c.consumer_tail = 0;
+        Ok(())
+    }
+}
+
+
+impl<T> Drop for PtrRing<T> {
+    /* ptr_ring_cleanup() with a destructor that drops the boxes */
+    fn drop(&mut self) {
+        while self.ptr_ring_consume().is_some() {}
+    }
+}
+
+
+/* Sections for Unit tests */
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::thread;
+
+    use super::*;
+
+    #[test]
+    fn test_ptr_ring_produce_consume() {
+        let ring = PtrRing::ptr_ring_init(4).unwrap();
+
+        assert!(ring.ptr_ring_empty());
+        for i in 0..4 {
+            assert!(ring.ptr_ring_produce(Box::new(i)).is_ok());
+        }
+        assert!(ring.ptr_ring_full());
+        assert_eq!(*ring.ptr_ring_produce(Box::new(4)).unwrap_err(), 4);
+        assert_eq!(ring.len(), 4);
+
+        for round in 0..10 {
+            assert_eq!(*ring.ptr_ring_consume().unwrap(), round);
+            assert!(ring.ptr_ring_produce(Box::new(round + 4)).is_ok());
+        }
+        for i in 10..14 {
+            assert_eq!(*ring.ptr_ring_consume().unwrap(), i);
+        }
+        assert!(ring.ptr_ring_consume().is_none());
+        assert!(ring.is_empty());
+
+        let empty = PtrRing::ptr_ring_init(0).unwrap();
+        assert!(empty.ptr_ring_full() && empty.ptr_ring_empty());
+        assert!(empty.ptr_ring_produce(Box::new(0)).is_err());
+    }
+
+
+    #[test]
+    fn test_ptr_ring_batch_invalidate() {
+        let ring = PtrRing::ptr_ring_init(64).unwrap();
+        let batch = ptr_ring_batch(64);
+
+        assert_eq!(batch, 16);
+        for i in 0..64 {
+            ring.ptr_ring_produce(Box::new(i)).unwrap();
+        }
+
+        /* Consumed slots go back to the producer a whole batch at a time */
+        for i in 0..batch - 1 {
+            assert_eq!(*ring.ptr_ring_consume().unwrap(), i);
+            assert!(ring.ptr_ring_full());
+        }
+        assert_eq!(*ring.ptr_ring_consume().unwrap(), batch - 1);
+        assert!(!ring.ptr_ring_full());
+        for i in 0..batch {
+            ring.ptr_ring_produce(Box::new(64 + i)).unwrap();
+        }
+        assert!(ring.ptr_ring_full());
+        assert_eq!(ring.len(), 64);
+
+        for i in batch..64 + batch {
+            assert_eq!(*ring.ptr_ring_consume().unwrap(), i);
+        }
+        assert!(ring.ptr_ring_empty());
+    }
+
+
+    #[test]
+    fn test_ptr_ring_batched_and_peek() {
+        let ring = PtrRing::ptr_ring_init(8).unwrap();
+        let mut array = Vec::new();
+
+        assert_eq!(ring.ptr_ring_peek(|v| v.copied()), None);
+        for i in 0..6 {
+            ring.ptr_ring_produce(Box::new(i)).unwrap();
+        }
+        assert_eq!(ring.ptr_ring_peek(|v| v.copied()), Some(0));
+
+        assert_eq!(ring.ptr_ring_consume_batched(&mut array, 4), 4);
+        assert_eq!(ring.ptr_ring_peek(|v| v.copied()), Some(4));
+        assert_eq!(ring.ptr_ring_consume_batched(&mut array, 4), 2);
+        assert_eq!(ring.ptr_ring_consume_batched(&mut array, 4), 0);
+        assert_eq!(array.iter().map(|b| **b).collect::<Vec<_>>(), (0..6).collect::<Vec<_>>());
+    }
+
+
+    #[test]
+    fn test_ptr_ring_resize() {
+        let ring = PtrRing::ptr_ring_init(4).unwrap();
+        let mut destroyed = Vec::new();
+
+        for i in 0..4 {
+            ring.ptr_ring_produce(Box::new(i)).unwrap();
+        }
+        ring.ptr_ring_consume().unwrap();
+
+        /* Grow: order is kept and the producer continues after the entries */
+        ring.ptr_ring_resize(8, |p| destroyed.push(*p)).unwrap();
+        assert_eq!(ring.ptr_ring_size(), 8);
+        for i in 4..9 {
+            ring.ptr_ring_produce(Box::new(i)).unwrap();
+        }
+        assert!(ring.ptr_ring_full());
+
+        /* Shrink: the newest entries are destroyed */
+        ring.ptr_ring_resize(3, |p| destroyed.push(*p)).unwrap();
+        assert_eq!(destroyed, vec![4, 5, 6, 7, 8]);
+        assert!(ring.ptr_ring_full());
+        assert_eq!(ring.len(), 3);
+        for i in 1..4 {
+            assert_eq!(*ring.ptr_ring_consume().unwrap(), i);
+        }
+        assert!(ring.ptr_ring_empty());
+        assert!(ring.ptr_ring_resize(usize::MAX, drop).is_err());
+    }
+
+
+    #[test]
+    fn test_ptr_ring_drop_frees() {
+        let token = Arc::new(());
+        let ring = PtrRing::ptr_ring_init(32).unwrap();
+
+        for _ in 0..20 {
+            ring.ptr_ring_produce(Box::new(token.clone())).unwrap();
+        }
+        /* Consumed but not yet invalidated slots must not be freed twice */
+        for _ in 0..5 {
+            drop(ring.ptr_ring_consume());
+        }
+        assert_eq!(Arc::strong_count(&token), 16);
+        drop(ring);
+        assert_eq!(Arc::strong_count(&token), 1);
+    }
+
+
+    #[test]
+    fn test_ptr_ring_concurrent() {
+        const PRODUCERS: usize = 2;
+        const PER_PRODUCER: usize = 2000;
+        let ring = Arc::new(PtrRing::ptr_ring_init(16).unwrap());
+
+        let producers: Vec<_> = (0..PRODUCERS)
+            .map(|t| {
+                let ring = ring.clone();
+                thread::spawn(move || {
+                    for i in 0..PER_PRODUCER {
+                        let mut item = Box::new((t, i));
+                        while let Err(back) = ring.ptr_ring_produce(item) {
+                            item = back;
+                            thread::yield_now();
+                        }
+                    }
+                })
+            })
+            .collect();
+
+        let mut next = [0; PRODUCERS];
+        let mut array = Vec::new();
+        let mut received = 0;
+        while received < PRODUCERS * PER_PRODUCER {
+            array.clear();
+            if ring.ptr_ring_consume_batched(&mut array, 8) == 0 {
+                thread::yield_now();
+                continue;
+            }
+            for item in array.iter() {
+                let (t, i) = **item;
+                /* Per producer FIFO order */
+                assert_eq!(next[t], i);
+                next[t] += 1;
+                received += 1;
+            }
+        }
+        for producer in producers {
+            producer.join().unwrap();
+        }
+        assert!(ring.ptr_ring_empty());
+    }
+}
@@ -0,0 +1,482 @@
+use std::cell::Cell;
+use std::hint;
+use std::marker::PhantomData;
+use std::mem::ManuallyDrop;
+use std::ptr;
+use std::sync::atomic::{fence, AtomicPtr, AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex, OnceLock};
+use std::thread;
+
+/*
+ * Userspace read-copy update
+ *
+ * Readers mark their read-side critical section with rcu_read_lock() and
+ * rcu_read_unlock(), which only publish the current grace-period counter
+ * in a per-thread slot. synchronize_rcu() advances the counter and waits
+ * until every reader that may have observed the old counter has left its
+ * critical section. Readers never block and never write shared state other
+ * than their own slot.
+ *
+ * A reader stores its counter and then issues a full fence before loading
+ * any RCU-protected pointer, while an updater unpublishes a pointer and
+ * issues a full fence before scanning the reader slots. Either the updater
+ * sees the reader's counter and waits for it, or the reader sees the
+ * pointer already unpublished.
+ */
+
+
+/* Spin this many times before yielding the CPU to a lagging reader */
+const RCU_SPIN_LIMIT: u32 = 64;
+
+/* The counter starts at 1 so that 0 can mean "not in a critical section" */
+static RCU_GP_CTR: AtomicU64 = AtomicU64::new(1);
+static RCU_GP_LOCK: Mutex<()> = Mutex::new(());
+static RCU_READERS: Mutex<Vec<Arc<RcuReader>>> = Mutex::new(Vec::new());
+
+
+struct RcuReader {
+    ctr: AtomicU64,
+}
+
+
+/* Per-thread registration, undone when the thread exits */
+struct RcuReaderHandle {
+    reader: Arc<RcuReader>,
+    nesting: Cell<usize>,
+}
+
+impl RcuReaderHandle {
+    fn register() -> Self {
+        let reader = Arc::new(RcuReader { ctr: AtomicU64::new(0) });
+
+        RCU_READERS.lock().unwrap().push(reader.clone());
+        RcuReaderHandle {
+            reader,
+            nesting: Cell::new(0),
+        }
+    }
+}
+
+impl Drop for RcuReaderHandle {
+    fn drop(&mut self) {
+        if let Ok(mut readers) = RCU_READERS.lock() {
+            readers.retain(|reader| !Arc::ptr_eq(reader, &self.reader));
+        }
+    }
+}
+
+thread_local! {
+    static RCU_READER: RcuReaderHandle = RcuReaderHandle::register();
+}
+
+
+/**
+ * struct RcuReadGuard - an RCU read-side critical section
+ *
+ * Returned by rcu_read_lock(), the critical section ends when the guard
+ * is dropped. References to RCU-protected data borrow from the guard, so
+ * they cannot outlive the critical section. The guard stays on the thread
+ * which created it.
+ */
+pub struct RcuReadGuard {
+    _marker: PhantomData<*const ()>,
+}
+
+/**
+ * rcu_read_lock() - mark the beginning of an RCU read-side critical section
+ *
+ * Read-side critical sections may nest. synchronize_rcu() must not be
+ * called from within one, as it would wait for itself.
+ */
+pub fn rcu_read_lock() -> RcuReadGuard {
+    RCU_READER.with(|handle| {
+        let nesting = handle.nesting.get();
+
+        if nesting == 0 {
+            handle.reader.ctr.store(RCU_GP_CTR.load(Ordering::Relaxed), Ordering::Relaxed);
+            fence(Ordering::SeqCst);
+        }
+        handle.nesting.set(nesting + 1);
+    });
+    RcuReadGuard { _marker: PhantomData }
+}
+
+impl Drop for RcuReadGuard {
+    /* rcu_read_unlock() - marks the end of an RCU read-side critical section */
+    fn drop(&mut self) {
+        RCU_READER.with(|handle| {
+            let nesting = handle.nesting.get() - 1;
+
+            handle.nesting.set(nesting);
+            if nesting == 0 {
+                handle.reader.ctr.store(0, Ordering::Release);
+            }
+        });
+    }
+}
+
+
+/**
+ * synchronize_rcu - wait until a grace period has elapsed.
+ *
+ * Control will return to the caller some time after all RCU read-side
+ * critical sections which were in progress when synchronize_rcu() was
+ * called have completed. Critical sections which begin while it waits
+ * are not waited for.
+ */
+pub fn synchronize_rcu() {
+    let _gp = RCU_GP_LOCK.lock().unwrap();
+
+    fence(Ordering::SeqCst);
+    let target = RCU_GP_CTR.fetch_add(1, Ordering::SeqCst) + 1;
+    fence(Ordering::SeqCst);
+
+    let readers = RCU_READERS.lock().unwrap().clone();
+    for reader in readers {
+        let mut spins = 0;
+        loop {
+            let ctr = reader.ctr.load(Ordering::Acquire);
+            if ctr == 0 || ctr >= target {
+                break;
+            }
+            if spins < RCU_SPIN_LIMIT {
+                spins += 1;
+                hint::spin_loop();
+            } else {
+                thread::yield_now();
+            }
+        }
+    }
+    fence(Ordering::SeqCst);
+}
+
+
+type RcuCallback = Box<dyn FnOnce() + Send>;
+
+struct RcuCallbacks {
+    queue: Vec<RcuCallback>,
+    queued: u64,
+    done: u64,
+}
+
+struct CallRcu {
+    state: Mutex<RcuCallbacks>,
+    cond: Condvar,
+}
+
+static CALL_RCU: OnceLock<Arc<CallRcu>> = OnceLock::new();
+
+/* Lazily start the thread which invokes callbacks after each grace period */
+fn call_rcu_data() -> &'static Arc<CallRcu> {
+    CALL_RCU.get_or_init(|| {
+        let crdp = Arc::new(CallRcu {
+            state: Mutex::new(RcuCallbacks {
+                queue: Vec::new(),
+                queued: 0,
+                done: 0,
+            }),
+            cond: Condvar::new(),
+        });
+
+        let worker = crdp.clone();
+        thread::Builder::new()
+            .name("call_rcu".into())
+            .spawn(move || loop {
+                let batch = {
+                    let mut state = worker.state.lock().unwrap();
+                    while state.queue.is_empty() {
+                        state = worker.cond.wait(state).unwrap();
+                    }
+                    std::mem::take(&mut state.queue)
+                };
+                let count = batch.len() as u64;
+
+                synchronize_rcu();
+                for func in batch {
+                    func();
+                }
+
+                worker.state.lock().unwrap().done += count;
+                worker.cond.notify_all();
+            })
+            .expect("failed to spawn the call_rcu thread");
+        crdp
+    })
+}
+
+
+/**
+ * call_rcu() - Queue an RCU callback for invocation after a grace period.
+ * @func: actual callback function to be invoked after the grace period
+ *
+ * The callback function will be invoked some time after a full grace
+ * period elapses, in other words after all pre-existing RCU read-side
+ * critical sections have completed. Callbacks run on a dedicated thread.
+ */
+pub fn call_rcu<F: FnOnce() + Send + 'static>(func: F) {
+    let crdp = call_rcu_data();
+    let mut state = crdp.state.lock().unwrap();
+
+    state.queue.push(Box::new(func));
+    state.queued += 1;
+    crdp.cond.notify_all();
+}
+
+
+/**
+ * rcu_barrier - Wait until all in-flight call_rcu() callbacks complete.
+ */
+pub fn rcu_barrier() {
+    let Some(crdp) = CALL_RCU.get() else {
+        return;
+    };
+    let mut state = crdp.state.lock().unwrap();
+    let target = state.queued;
+
+    while state.done < target {
+        state = crdp.cond.wait(state).unwrap();
+    }
+}
+
+
+/**
+ * struct RcuRetired - an object unpublished from an RCU-protected structure
+ *
+ * Readers may still hold references to the object, so it can only be
+ * released after a grace period. Dropping it frees the object through
+ * call_rcu(), the equivalent of kfree_rcu(); rcu_retire_sync() waits for
+ * the grace period instead and hands the object back.
+ */
+pub struct RcuRetired<T: Send + 'static> {
+    ptr: *mut (),
+    into_inner: unsafe fn(*mut ()) -> T,
+}
+
+unsafe impl<T: Send + 'static> Send for RcuRetired<T> {}
+
+struct RcuSendPtr(*mut ());
+unsafe impl Send for RcuSendPtr {}
+
+#[allow(dead_code)]
+impl<T: Send + 'static> RcuRetired<T> {
+    /*
+     * @ptr must stay valid until @into_inner is called on it, which
+     * converts it back to the object and releases its storage.
+     */
+    pub(crate) unsafe fn new(ptr: *mut (), into_inner: unsafe fn(*mut ()) -> T) -> Self {
+        RcuRetired { ptr, into_inner }
+    }
+
+
+    /**
+     * rcu_retire_sync - wait for a grace period and take the object back
+     */
+    pub fn rcu_retire_sync(self) -> T {
+        let this = ManuallyDrop::new(self);
+
+        synchronize_rcu();
+        unsafe { (this.into_inner)(this.ptr) }
+    }
+}
+
+impl<T: Send + 'static> Drop for RcuRetired<T> {
+    fn drop(&mut self) {
+        let ptr = RcuSendPtr(self.ptr);
+        let into_inner = self.into_inner;
+
+        call_rcu(move || {
+            let ptr = ptr;
+            drop(unsafe { into_inner(ptr.0) });
+        });
+    }
+}
+
+
+unsafe fn rcu_box_into_inner<T>(ptr: *mut ()) -> T {
+    *Box::from_raw(ptr as *mut T)
+}
+
+
+/**
+ * struct RcuPointer - an RCU-protected pointer
+ *
+ * Readers fetch the current object with rcu_dereference() inside a
+ * read-side critical section, updaters publish a new one with
+ * rcu_assign_pointer() or rcu_replace_pointer(). Updaters must be
+ * serialized by the caller.
+ */
+pub struct RcuPointer<T: Send + 'static> {
+    ptr: AtomicPtr<T>,
+}
+
+unsafe impl<T: Send + Sync + 'static> Send for RcuPointer<T> {}
+unsafe impl<T: Send + Sync + 'static> Sync for RcuPointer<T> {}
+
+impl<T: Send + 'static> Default for RcuPointer<T> {
+    fn default() -> Self {
+        RcuPointer::new(None)
+    }
+}
+
+#[allow(dead_code)]
+impl<T: Send + 'static> RcuPointer<T> {
+    /**
+     * RCU_INITIALIZER - statically initialize an RCU-protected pointer
+     */
+    pub fn new(val: Option<T>) -> Self {
+        RcuPointer {
+            ptr: AtomicPtr::new(val.map_or(ptr::null_mut(), |val| Box::into_raw(Box::new(val)))),
+        }
+    }
+
+
+    /**
+     * rcu_dereference() - fetch RCU-protected pointer for dereferencing
+     * @_guard: the read-side critical section the object is used in
+     */
+    pub fn rcu_dereference<'a>(&'a self, _guard: &'a RcuReadGuard) -> Option<&'a T> {
+        unsafe { self.ptr.load(Ordering::Acquire).as_ref() }
+    }
+
+
+    /**
+     * rcu_access_pointer() - check whether an object is published
+     *
+     * The object itself must not be dereferenced.
+     */
+    pub fn rcu_access_pointer(&self) -> bool {
+        !self.ptr.load(Ordering::Relaxed).is_null()
+    }
+
+
+    /**
+     * rcu_replace_pointer() - replace an RCU pointer, returning its old value
+     * @val: the new object, or %None to unpublish
+     */
+    pub fn rcu_replace_pointer(&self, val: Option<T>) -> Option<RcuRetired<T>> {
+        let new = val.map_or(ptr::null_mut(), |val| Box::into_raw(Box::new(val)));
+        let old = self.ptr.swap(new, Ordering::AcqRel);
+
+        (!old.is_null()).then(|| unsafe { RcuRetired::new(old as *mut (), rcu_box_into_inner::<T>) })
+    }
+
+
+    /**
+     * rcu_assign_pointer() - assign to RCU-protected pointer
+     * @val: the new object, or %None to unpublish
+     *
+     * The old object is freed after a grace period.
+     */
+    pub fn rcu_assign_pointer(&self, val: Option<T>) {
+        drop(self.rcu_replace_pointer(val));
+    }
+}
+
+impl<T: Send + 'static> Drop for RcuPointer<T> {
+    fn drop(&mut self) {
+        let ptr = *self.ptr.get_mut();
+
+        if !ptr.is_null() {
+            drop(unsafe { Box::from_raw(ptr) });
+        }
+    }
+}
+
+
+/* Sections for Unit tests */
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, AtomicUsize};
+
+    use super::*;
+
+    #[test]
+    fn test_rcu_read_lock_nesting() {
+        let outer = rcu_read_lock();
+        let inner = rcu_read_lock();
+
+        drop(inner);
+        RCU_READER.with(|handle| assert_ne!(handle.reader.ctr.load(Ordering::Relaxed), 0));
+        drop(outer);
+        RCU_READER.with(|handle| assert_eq!(handle.reader.ctr.load(Ordering::Relaxed), 0));
+
+        /* No reader on this thread, so this must not wait for ourselves */
+        synchronize_rcu();
+    }
+
+
+    #[test]
+    fn test_synchronize_rcu_waits_for_readers() {
+        let entered = Arc::new(AtomicBool::new(false));
+        let release = Arc::new(AtomicBool::new(false));
+        let finished = Arc::new(AtomicBool::new(false));
+
+        let reader = {
+            let (entered, release, finished) = (entered.clone(), release.clone(), finished.clone());
+            thread::spawn(move || {
+                let guard = rcu_read_lock();
+                entered.store(true, Ordering::SeqCst);
+                while !release.load(Ordering::SeqCst) {
+                    thread::yield_now();
+                }
+                finished.store(true, Ordering::SeqCst);
+                drop(guard);
+            })
+        };
+
+        while !entered.load(Ordering::SeqCst) {
+            thread::yield_now();
+        }
+        let releaser = {
+            let release = release.clone();
+            thread::spawn(move || {
+                thread::sleep(std::time::Duration::from_millis(20));
+                release.store(true, Ordering::SeqCst);
+            })
+        };
+
+        synchronize_rcu();
+        assert!(finished.load(Ordering::SeqCst));
+
+        reader.join().unwrap();
+        releaser.join().unwrap();
+    }
+
+
+    #[test]
+    fn test_call_rcu() {
+        let count = Arc::new(AtomicUsize::new(0));
+
+        for _ in 0..10 {
+            let count = count.clone();
+            call_rcu(move || {
+                count.fetch_add(1, Ordering::SeqCst);
+            });
+        }
+        rcu_barrier();
+        assert_eq!(count.load(Ordering::SeqCst), 10);
+    }
+
+
+    #[test]
+    fn test_rcu_pointer() {
+        let item = Arc::new(1);
+        let ptr = RcuPointer::new(Some(item.clone()));
+
+        {
+            let guard = rcu_read_lock();
+            assert_eq!(**ptr.rcu_dereference(&guard).unwrap(), 1);
+        }
+
+        let old = ptr.rcu_replace_pointer(Some(Arc::new(2))).unwrap();
+        assert!(Arc::ptr_eq(&old.rcu_retire_sync(), &item));
+
+        ptr.rcu_assign_pointer(None);
+        assert!(!ptr.rcu_access_pointer());
+
+        ptr.rcu_assign_pointer(Some(item.clone()));
+        ptr.rcu_assign_pointer(None);
+        rcu_barrier();
+        assert_eq!(Arc::strong_count(&item), 1);
+    }
+}
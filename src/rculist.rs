@@ -0,0 +1,887 @@
+use std::marker::PhantomData;
+use std::ptr;
+use std::sync::atomic::{AtomicPtr, Ordering};
+
+use crate::container_of;
+use crate::rcu::{synchronize_rcu, RcuReadGuard, RcuRetired};
+use crate::spinlock::SpinLock;
+
+/*
+ * RCU-protected lists
+ *
+ * Readers traverse the lists inside an RCU read-side critical section
+ * without taking any lock, updaters are serialized by the spinlock that
+ * comes with each list head. An entry deleted from a list keeps its ->next
+ * pointer, so a reader standing on it can still walk back into the list,
+ * and the entry itself is only freed once a grace period has elapsed.
+ */
+
+
+struct RcuListLinks {
+    next: AtomicPtr<RcuListLinks>,
+    prev: AtomicPtr<RcuListLinks>,
+}
+
+impl RcuListLinks {
+    fn new() -> Self {
+        RcuListLinks {
+            next: AtomicPtr::new(ptr::null_mut()),
+            prev: AtomicPtr::new(ptr::null_mut()),
+        }
+    }
+}
+
+
+struct RcuListNode<T> {
+    links: RcuListLinks,
+    item: T,
+}
+
+impl<T> RcuListNode<T> {
+    fn alloc(item: T) -> *mut RcuListLinks {
+        let node = Box::into_raw(Box::new(RcuListNode {
+            links: RcuListLinks::new(),
+            item,
+        }));
+        unsafe { &mut (*node).links }
+    }
+
+
+    unsafe fn entry<'a>(links: *const RcuListLinks) -> &'a RcuListNode<T> {
+        &*container_of!(links, RcuListNode<T>, links)
+    }
+
+
+    unsafe fn retire(links: *mut RcuListLinks) -> RcuRetired<T>
+    where
+        T: Send + 'static,
+    {
+        let node = container_of!(links, RcuListNode<T>, links);
+        RcuRetired::new(node as *mut (), RcuListNode::<T>::into_inner)
+    }
+
+
+    unsafe fn into_inner(node: *mut ()) -> T {
+        Box::from_raw(node as *mut RcuListNode<T>).item
+    }
+}
+
+
+/**
+ * struct RcuListHead - RCU-protected circular doubly linked list
+ *
+ * Entries are owned by the list. Deleting or replacing an entry hands it
+ * back as an RcuRetired, which is freed after a grace period.
+ */
+pub struct RcuListHead<T> {
+    lock: SpinLock<()>,
+    head: Box<RcuListLinks>,
+    _marker: PhantomData<Box<RcuListNode<T>>>,
+}
+
+unsafe impl<T: Send + Sync> Send for RcuListHead<T> {}
+unsafe impl<T: Send + Sync> Sync for RcuListHead<T> {}
+
+impl<T: Send + Sync + 'static> Default for RcuListHead<T> {
+    fn default() -> Self {
+        RcuListHead::new()
+    }
+}
+
+#[allow(dead_code)]
+impl<T: Send + Sync + 'static> RcuListHead<T> {
+    /**
+     * INIT_LIST_HEAD_RCU - Initialize a list_head visible to RCU readers
+     */
+    pub fn new() -> Self {
+        let head = Box::new(RcuListLinks::new());
+        let ptr = &*head as *const RcuListLinks as *mut RcuListLinks;
+
+        head.next.store(ptr, Ordering::Relaxed);
+        head.prev.store(ptr, Ordering::Relaxed);
+        RcuListHead {
+            lock: SpinLock::new(()),
+            head,
+            _marker: PhantomData,
+        }
+    }
+
+
+    fn head(&self) -> *mut RcuListLinks {
+        &*self.head as *const RcuListLinks as *mut RcuListLinks
+    }
+
+
+    /**
+     * list_empty - tests whether a list is empty
+     */
+    pub fn list_empty(&self) -> bool {
+        ptr::eq(self.head.next.load(Ordering::Relaxed), self.head())
+    }
+
+
+    /*
+     * Insert a new entry between two known consecutive entries.
+     *
+     * This is only for internal list manipulation where we know
+     * the prev/next entries already!
+     */
+    unsafe fn __list_add_rcu(new: *mut RcuListLinks, prev: *mut RcuListLinks, next: *mut RcuListLinks) {
+        (*new).next.store(next, Ordering::Relaxed);
+        (*new).prev.store(prev, Ordering::Relaxed);
+        /* rcu_assign_pointer(list_next_rcu(prev), new) */
+        (*prev).next.store(new, Ordering::Release);
+        (*next).prev.store(new, Ordering::Relaxed);
+    }
+
+
+    /* Find the first entry matching @pred, the update lock must be held */
+    fn find<F: FnMut(&T) -> bool>(&self, mut pred: F) -> Option<*mut RcuListLinks> {
+        let mut pos = self.head.next.load(Ordering::Relaxed);
+
+        while !ptr::eq(pos, self.head()) {
+            unsafe {
+                if pred(&RcuListNode::<T>::entry(pos).item) {
+                    return Some(pos);
+                }
+                pos = (*pos).next.load(Ordering::Relaxed);
+            }
+        }
+        None
+    }
+
+
+    /**
+     * list_add_rcu - add a new entry to rcu-protected list
+     * @item: new entry to be added
+     *
+     * Insert a new entry after the head.
+     * This is good for implementing stacks.
+     *
+     * It is safe to run this concurrently with readers traversing the
+     * list with list_for_each_entry_rcu().
+     */
+    pub fn list_add_rcu(&self, item: T) {
+        let _guard = self.lock.spin_lock();
+        let head = self.head();
+
+        unsafe {
+            RcuListHead::<T>::__list_add_rcu(RcuListNode::alloc(item), head, (*head).next.load(Ordering::Relaxed));
+        }
+    }
+
+
+    /**
+     * list_add_tail_rcu - add a new entry to rcu-protected list
+     * @item: new entry to be added
+     *
+     * Insert a new entry before the head.
+     * This is useful for implementing queues.
+     */
+    pub fn list_add_tail_rcu(&self, item: T) {
+        let _guard = self.lock.spin_lock();
+        let head = self.head();
+
+        unsafe {
+            RcuListHead::<T>::__list_add_rcu(RcuListNode::alloc(item), (*head).prev.load(Ordering::Relaxed), head);
+        }
+    }
+
+
+    /**
+     * list_del_rcu - deletes entry from list without re-initialization
+     * @pred: selects the entry to delete, the first match is deleted
+     *
+     * The entry keeps pointing into the list, so that readers currently
+     * standing on it can continue their traversal. It is handed back as an
+     * RcuRetired and may only be freed after a grace period, which dropping
+     * the RcuRetired takes care of.
+     */
+    pub fn list_del_rcu<F: FnMut(&T) -> bool>(&self, pred: F) -> Option<RcuRetired<T>> {
+        let _guard = self.lock.spin_lock();
+        let entry = self.find(pred)?;
+
+        unsafe {
+            let prev = (*entry).prev.load(Ordering::Relaxed);
+            let next = (*entry).next.load(Ordering::Relaxed);
+
+            (*next).prev.store(prev, Ordering::Relaxed);
+            (*prev).next.store(next, Ordering::Release);
+            (*entry).prev.store(ptr::null_mut(), Ordering::Relaxed);
+            Some(RcuListNode::retire(entry))
+        }
+    }
+
+
+    /**
+     * list_replace_rcu - replace old entry by new one
+     * @pred: selects the element to be replaced, the first match is replaced
+     * @new: the new element to insert
+     *
+     * The @old entry will be replaced with the @new entry atomically from
+     * the perspective of concurrent readers.
+     */
+    pub fn list_replace_rcu<F: FnMut(&T) -> bool>(&self, pred: F, new: T) -> Option<RcuRetired<T>> {
+        let _guard = self.lock.spin_lock();
+        let old = self.find(pred)?;
+
+        unsafe {
+            let new = RcuListNode::alloc(new);
+            let prev = (*old).prev.load(Ordering::Relaxed);
+            let next = (*old).next.load(Ordering::Relaxed);
+
+            (*new).next.store(next, Ordering::Relaxed);
+            (*new).prev.store(prev, Ordering::Relaxed);
+            (*prev).next.store(new, Ordering::Release);
+            (*next).prev.store(new, Ordering::Relaxed);
+            (*old).prev.store(ptr::null_mut(), Ordering::Relaxed);
+            Some(RcuListNode::retire(old))
+        }
+    }
+
+
+    fn __list_splice_init_rcu(&self, list: &RcuListHead<T>, tail: bool) {
+        if ptr::eq(self, list) {
+            return;
+        }
+
+        let (first, last) = {
+            let _guard = list.lock.spin_lock();
+            let first = list.head.next.load(Ordering::Relaxed);
+            let last = list.head.prev.load(Ordering::Relaxed);
+
+            if ptr::eq(first, list.head()) {
+                return;
+            }
+
+            /* INIT_LIST_HEAD_RCU(list) */
+            list.head.next.store(list.head(), Ordering::Release);
+            list.head.prev.store(list.head(), Ordering::Relaxed);
+            (first, last)
+        };
+
+        /*
+         * At this point, the list body still points to the source list.
+         * Wait for any readers to finish using the list before splicing
+         * the list body into the new list. Any new readers will see
+         * an empty list.
+         */
+        synchronize_rcu();
+
+        let _guard = self.lock.spin_lock();
+        let (prev, next) = if tail {
+            (self.head.prev.load(Ordering::Relaxed), self.head())
+        } else {
+            (self.head(), self.head.next.load(Ordering::Relaxed))
+        };
+
+        /*
+         * Readers are finished with the source list, so perform splice.
+         * The order is important if the new list is global and accessible
+         * to concurrent RCU readers. Note that RCU readers are not
+         * permitted to traverse the prev pointers without excluding
+         * this function.
+         */
+        unsafe {
+            (*last).next.store(next, Ordering::Relaxed);
+            (*first).prev.store(prev, Ordering::Relaxed);
+            (*prev).next.store(first, Ordering::Release);
+            (*next).prev.store(last, Ordering::Relaxed);
+        }
+    }
+
+
+    /**
+     * list_splice_init_rcu - splice an RCU-protected list into an existing list,
+     *                        designed for stacks.
+     * @list: the RCU-protected list to splice
+     *
+     * The entries of @list are added at the front of this list and @list
+     * is reinitialised. This waits for a grace period, so it must not be
+     * called from within an RCU read-side critical section.
+     */
+    pub fn list_splice_init_rcu(&self, list: &RcuListHead<T>) {
+        self.__list_splice_init_rcu(list, false);
+    }
+
+
+    /**
+     * list_splice_tail_init_rcu - splice an RCU-protected list into an existing
+     *                             list, designed for queues.
+     * @list: the RCU-protected list to splice
+     */
+    pub fn list_splice_tail_init_rcu(&self, list: &RcuListHead<T>) {
+        self.__list_splice_init_rcu(list, true);
+    }
+
+
+    /**
+     * list_first_or_null_rcu - get the first element from a list
+     * @_guard: the read-side critical section the entry is used in
+     */
+    pub fn list_first_or_null_rcu<'a>(&'a self, _guard: &'a RcuReadGuard) -> Option<&'a T> {
+        let first = self.head.next.load(Ordering::Acquire);
+
+        (!ptr::eq(first, self.head())).then(|| unsafe { &RcuListNode::<T>::entry(first).item })
+    }
+
+
+    /**
+     * list_for_each_entry_rcu - iterate over rcu list of given type
+     * @_guard: the read-side critical section the traversal runs in
+     *
+     * This list-traversal primitive may safely run concurrently with
+     * the _rcu list-mutation primitives such as list_add_rcu().
+     */
+    pub fn list_for_each_entry_rcu<'a>(&'a self, _guard: &'a RcuReadGuard) -> RcuListIter<'a, T> {
+        RcuListIter {
+            head: self.head(),
+            pos: self.head.next.load(Ordering::Acquire),
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T> Drop for RcuListHead<T> {
+    fn drop(&mut self) {
+        let head = &*self.head as *const RcuListLinks;
+        let mut pos = self.head.next.load(Ordering::Relaxed);
+
+        while !ptr::eq(pos, head) {
+            unsafe {
+                let next = (*pos).next.load(Ordering::Relaxed);
+                drop(Box::from_raw(container_of!(pos, RcuListNode<T>, links)));
+                pos = next;
+            }
+        }
+    }
+}
+
+
+pub struct RcuListIter<'a, T> {
+    head: *const RcuListLinks,
+    pos: *const RcuListLinks,
+    _marker: PhantomData<&'a T>,
+}
+
+impl<'a, T> Iterator for RcuListIter<'a, T> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if ptr::eq(self.pos, self.head) {
+            return None;
+        }
+        unsafe {
+            let node = RcuListNode::<T>::entry(self.pos);
+            self.pos = node.links.next.load(Ordering::Acquire);
+            Some(&node.item)
+        }
+    }
+}
+
+
+/*
+ * ->pprev points at the ->next of the previous node, or at the ->first of
+ * the head for the first node, so that deletion does not need the head.
+ */
+struct RcuHlistLinks {
+    next: AtomicPtr<RcuHlistLinks>,
+    pprev: AtomicPtr<AtomicPtr<RcuHlistLinks>>,
+}
+
+
+struct RcuHlistNode<T> {
+    links: RcuHlistLinks,
+    item: T,
+}
+
+impl<T> RcuHlistNode<T> {
+    fn alloc(item: T) -> *mut RcuHlistLinks {
+        let node = Box::into_raw(Box::new(RcuHlistNode {
+            links: RcuHlistLinks {
+                next: AtomicPtr::new(ptr::null_mut()),
+                pprev: AtomicPtr::new(ptr::null_mut()),
+            },
+            item,
+        }));
+        unsafe { &mut (*node).links }
+    }
+
+
+    unsafe fn entry<'a>(links: *const RcuHlistLinks) -> &'a RcuHlistNode<T> {
+        &*container_of!(links, RcuHlistNode<T>, links)
+    }
+
+
+    unsafe fn retire(links: *mut RcuHlistLinks) -> RcuRetired<T>
+    where
+        T: Send + 'static,
+    {
+        let node = container_of!(links, RcuHlistNode<T>, links);
+        RcuRetired::new(node as *mut (), RcuHlistNode::<T>::into_inner)
+    }
+
+
+    unsafe fn into_inner(node: *mut ()) -> T {
+        Box::from_raw(node as *mut RcuHlistNode<T>).item
+    }
+}
+
+
+/**
+ * struct RcuHlistHead - RCU-protected hash list
+ *
+ * A NULL terminated list with a single pointer head, meant for hash
+ * table buckets where the head size matters.
+ */
+pub struct RcuHlistHead<T> {
+    lock: SpinLock<()>,
+    first: Box<AtomicPtr<RcuHlistLinks>>,
+    _marker: PhantomData<Box<RcuHlistNode<T>>>,
+}
+
+unsafe impl<T: Send + Sync> Send for RcuHlistHead<T> {}
+unsafe impl<T: Send + Sync> Sync for RcuHlistHead<T> {}
+
+impl<T: Send + Sync + 'static> Default for RcuHlistHead<T> {
+    fn default() -> Self {
+        RcuHlistHead::new()
+    }
+}
+
+#[allow(dead_code)]
+impl<T: Send + Sync + 'static> RcuHlistHead<T> {
+    /**
+     * INIT_HLIST_HEAD - Initialize an empty hlist
+     */
+    pub fn new() -> Self {
+        RcuHlistHead {
+            lock: SpinLock::new(()),
+            first: Box::new(AtomicPtr::new(ptr::null_mut())),
+            _marker: PhantomData,
+        }
+    }
+
+
+    /**
+     * hlist_empty - Is the specified hlist_head structure an empty hlist?
+     */
+    pub fn hlist_empty(&self) -> bool {
+        self.first.load(Ordering::Relaxed).is_null()
+    }
+
+
+    fn find<F: FnMut(&T) -> bool>(&self, mut pred: F) -> Option<*mut RcuHlistLinks> {
+        let mut pos = self.first.load(Ordering::Relaxed);
+
+        while !pos.is_null() {
+            unsafe {
+                if pred(&RcuHlistNode::<T>::entry(pos).item) {
+                    return Some(pos);
+                }
+                pos = (*pos).next.load(Ordering::Relaxed);
+            }
+        }
+        None
+    }
+
+
+    /**
+     * hlist_add_head_rcu
+     * @item: the element to add to the hash list.
+     *
+     * Adds the specified element to the beginning of the specified hlist,
+     * while permitting racing traversals.
+     */
+    pub fn hlist_add_head_rcu(&self, item: T) {
+        let _guard = self.lock.spin_lock();
+        let n = RcuHlistNode::alloc(item);
+        let first = self.first.load(Ordering::Relaxed);
+
+        unsafe {
+            (*n).next.store(first, Ordering::Relaxed);
+            (*n).pprev.store(&*self.first as *const _ as *mut _, Ordering::Relaxed);
+            self.first.store(n, Ordering::Release);
+            if !first.is_null() {
+                (*first).pprev.store(&mut (*n).next, Ordering::Relaxed);
+            }
+        }
+    }
+
+
+    /**
+     * hlist_add_tail_rcu
+     * @item: the element to add to the hash list.
+     *
+     * Adds the specified element to the end of the specified hlist,
+     * while permitting racing traversals. Walks the whole list.
+     */
+    pub fn hlist_add_tail_rcu(&self, item: T) {
+        let _guard = self.lock.spin_lock();
+        let n = RcuHlistNode::alloc(item);
+
+        let mut last: *mut RcuHlistLinks = ptr::null_mut();
+        let mut pos = self.first.load(Ordering::Relaxed);
+        while !pos.is_null() {
+            last = pos;
+            pos = unsafe { (*pos).next.load(Ordering::Relaxed) };
+        }
+
+        unsafe {
+            let pprev = if last.is_null() {
+                &*self.first as *const _ as *mut AtomicPtr<RcuHlistLinks>
+            } else {
+                &mut (*last).next
+            };
+            (*n).next.store(ptr::null_mut(), Ordering::Relaxed);
+            (*n).pprev.store(pprev, Ordering::Relaxed);
+            (*pprev).store(n, Ordering::Release);
+        }
+    }
+
+
+    /**
+     * hlist_del_rcu - deletes entry from hash list without re-initialization
+     * @pred: selects the element to delete, the first match is deleted
+     *
+     * The entry keeps its ->next pointer so that concurrent readers can
+     * continue their traversal, and is freed after a grace period.
+     */
+    pub fn hlist_del_rcu<F: FnMut(&T) -> bool>(&self, pred: F) -> Option<RcuRetired<T>> {
+        let _guard = self.lock.spin_lock();
+        let n = self.find(pred)?;
+
+        unsafe {
+            let next = (*n).next.load(Ordering::Relaxed);
+            let pprev = (*n).pprev.load(Ordering::Relaxed);
+
+            (*pprev).store(next, Ordering::Release);
+            if !next.is_null() {
+                (*next).pprev.store(pprev, Ordering::Relaxed);
+            }
+            (*n).pprev.store(ptr::null_mut(), Ordering::Relaxed);
+            Some(RcuHlistNode::retire(n))
+        }
+    }
+
+
+    /**
+     * hlist_replace_rcu - replace old entry by new one
+     * @pred: selects the element to be replaced, the first match is replaced
+     * @new: the new element to insert
+     *
+     * The @old entry will be replaced with the @new entry atomically from
+     * the perspective of concurrent readers.
+     */
+    pub fn hlist_replace_rcu<F: FnMut(&T) -> bool>(&self, pred: F, new: T) -> Option<RcuRetired<T>> {
+        let _guard = self.lock.spin_lock();
+        let old = self.find(pred)?;
+
+        unsafe {
+            let new = RcuHlistNode::alloc(new);
+            let next = (*old).next.load(Ordering::Relaxed);
+            let pprev = (*old).pprev.load(Ordering::Relaxed);
+
+            (*new).next.store(next, Ordering::Relaxed);
+            (*new).pprev.store(pprev, Ordering::Relaxed);
+            (*pprev).store(new, Ordering::Release);
+            if !next.is_null() {
+                (*next).pprev.store(&mut (*new).next, Ordering::Relaxed);
+            }
+            (*old).pprev.store(ptr::null_mut(), Ordering::Relaxed);
+            Some(RcuHlistNode::retire(old))
+        }
+    }
+
+
+    /**
+     * hlist_first_rcu - get the first element of the hash list
+     * @_guard: the read-side critical section the entry is used in
+     */
+    pub fn hlist_first_rcu<'a>(&'a self, _guard: &'a RcuReadGuard) -> Option<&'a T> {
+        let first = self.first.load(Ordering::Acquire);
+
+        (!first.is_null()).then(|| unsafe { &RcuHlistNode::<T>::entry(first).item })
+    }
+
+
+    /**
+     * hlist_for_each_entry_rcu - iterate over rcu list of given type
+     * @_guard: the read-side critical section the traversal runs in
+     *
+     * This list-traversal primitive may safely run concurrently with
+     * the _rcu list-mutation primitives such as hlist_add_head_rcu().
+     */
+    pub fn hlist_for_each_entry_rcu<'a>(&'a self, _guard: &'a RcuReadGuard) -> RcuHlistIter<'a, T> {
+        RcuHlistIter {
+            pos: self.first.load(Ordering::Acquire),
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T> Drop for RcuHlistHead<T> {
+    fn drop(&mut self) {
+        let mut pos = self.first.load(Ordering::Relaxed);
+
+        while !pos.is_null() {
+            unsafe {
+                let next = (*pos).next.load(Ordering::Relaxed);
+                drop(Box::from_raw(container_of!(pos, RcuHlistNode<T>, links)));
+                pos = next;
+            }
+        }
+    }
+}
+
+
+pub struct RcuHlistIter<'a, T> {
+    pos: *const RcuHlistLinks,
+    _marker: PhantomData<&'a T>,
+}
+
+impl<'a, T> Iterator for RcuHlistIter<'a, T> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.pos.is_null() {
+            return None;
+        }
+        unsafe {
+            let node = RcuHlistNode::<T>::entry(self.pos);
+            self.pos = node.links.next.load(Ordering::Acquire);
+            Some(&node.item)
+        }
+    }
+}
+
+
+/* Sections for Unit tests */
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, AtomicU32, AtomicUsize};
+    use std::sync::Arc;
+    use std::thread;
+
+    use crate::rcu::{rcu_barrier, rcu_read_lock};
+
+    use super::*;
+
+    const ALIVE: u32 = 0x600d;
+    const DEAD: u32 = 0xdead;
+
+    /* An item which poisons itself when freed, to catch early frees */
+    struct Item {
+        val: usize,
+        magic: AtomicU32,
+    }
+
+    impl Item {
+        fn new(val: usize) -> Self {
+            Item { val, magic: AtomicU32::new(ALIVE) }
+        }
+
+        fn check(&self) -> usize {
+            assert_eq!(self.magic.load(Ordering::Relaxed), ALIVE);
+            self.val
+        }
+    }
+
+    impl Drop for Item {
+        fn drop(&mut self) {
+            self.magic.store(DEAD, Ordering::Relaxed);
+        }
+    }
+
+
+    fn values(list: &RcuListHead<Item>) -> Vec<usize> {
+        let guard = rcu_read_lock();
+        list.list_for_each_entry_rcu(&guard).map(Item::check).collect()
+    }
+
+
+    #[test]
+    fn test_list_add_del_rcu() {
+        let list = RcuListHead::new();
+
+        assert!(list.list_empty());
+        list.list_add_rcu(Item::new(2));
+        list.list_add_rcu(Item::new(1));
+        list.list_add_tail_rcu(Item::new(3));
+        assert_eq!(values(&list), vec![1, 2, 3]);
+
+        let old = list.list_del_rcu(|item| item.val == 2).unwrap();
+        assert_eq!(old.rcu_retire_sync().val, 2);
+        assert!(list.list_del_rcu(|item| item.val == 2).is_none());
+        assert_eq!(values(&list), vec![1, 3]);
+
+        {
+            let guard = rcu_read_lock();
+            assert_eq!(list.list_first_or_null_rcu(&guard).unwrap().val, 1);
+        }
+    }
+
+
+    #[test]
+    fn test_list_del_rcu_reader_continues() {
+        let list = RcuListHead::new();
+
+        for i in 0..4 {
+            list.list_add_tail_rcu(Item::new(i));
+        }
+
+        let guard = rcu_read_lock();
+        let mut iter = list.list_for_each_entry_rcu(&guard);
+        assert_eq!(iter.next().unwrap().check(), 0);
+
+        /* Entry 1 is where the reader is going next */
+        drop(list.list_del_rcu(|item| item.val == 1));
+        drop(list.list_del_rcu(|item| item.val == 0));
+        assert_eq!(iter.map(Item::check).collect::<Vec<_>>(), vec![1, 2, 3]);
+        drop(guard);
+
+        rcu_barrier();
+        assert_eq!(values(&list), vec![2, 3]);
+    }
+
+
+    #[test]
+    fn test_list_replace_rcu() {
+        let list = RcuListHead::new();
+
+        for i in 0..3 {
+            list.list_add_tail_rcu(Item::new(i));
+        }
+
+        let old = list.list_replace_rcu(|item| item.val == 1, Item::new(10)).unwrap();
+        assert_eq!(values(&list), vec![0, 10, 2]);
+        assert_eq!(old.rcu_retire_sync().val, 1);
+        assert!(list.list_replace_rcu(|item| item.val == 1, Item::new(11)).is_none());
+    }
+
+
+    #[test]
+    fn test_list_splice_init_rcu() {
+        let list1 = RcuListHead::new();
+        let list2 = RcuListHead::new();
+
+        list1.list_add_tail_rcu(Item::new(0));
+        list2.list_add_tail_rcu(Item::new(1));
+        list2.list_add_tail_rcu(Item::new(2));
+
+        list1.list_splice_init_rcu(&list2);
+        assert!(list2.list_empty());
+        assert_eq!(values(&list1), vec![1, 2, 0]);
+
+        list2.list_add_tail_rcu(Item::new(3));
+        list1.list_splice_tail_init_rcu(&list2);
+        list1.list_splice_tail_init_rcu(&list2);
+        assert_eq!(values(&list1), vec![1, 2, 0, 3]);
+    }
+
+
+    #[test]
+    fn test_hlist_rcu() {
+        let hlist = RcuHlistHead::new();
+
+        assert!(hlist.hlist_empty());
+        hlist.hlist_add_head_rcu(Item::new(2));
+        hlist.hlist_add_head_rcu(Item::new(1));
+        hlist.hlist_add_tail_rcu(Item::new(3));
+
+        let hvalues = |hlist: &RcuHlistHead<Item>| {
+            let guard = rcu_read_lock();
+            hlist.hlist_for_each_entry_rcu(&guard).map(Item::check).collect::<Vec<_>>()
+        };
+        assert_eq!(hvalues(&hlist), vec![1, 2, 3]);
+
+        drop(hlist.hlist_replace_rcu(|item| item.val == 1, Item::new(10)));
+        assert_eq!(hvalues(&hlist), vec![10, 2, 3]);
+        drop(hlist.hlist_del_rcu(|item| item.val == 10));
+        drop(hlist.hlist_del_rcu(|item| item.val == 3));
+        assert_eq!(hvalues(&hlist), vec![2]);
+
+        /* ->pprev of the remaining entry must have been fixed up */
+        drop(hlist.hlist_del_rcu(|item| item.val == 2));
+        assert!(hlist.hlist_empty());
+        hlist.hlist_add_tail_rcu(Item::new(4));
+        {
+            let guard = rcu_read_lock();
+            assert_eq!(hlist.hlist_first_rcu(&guard).unwrap().val, 4);
+        }
+        rcu_barrier();
+    }
+
+
+    #[test]
+    fn test_rculist_stress() {
+        const READERS: usize = 3;
+        const UPDATES: usize = 2000;
+
+        let list = Arc::new(RcuListHead::new());
+        let hlist = Arc::new(RcuHlistHead::new());
+        let spare = Arc::new(RcuListHead::new());
+        let done = Arc::new(AtomicBool::new(false));
+        let traversals = Arc::new(AtomicUsize::new(0));
+
+        for i in 0..16 {
+            list.list_add_tail_rcu(Item::new(i));
+            hlist.hlist_add_head_rcu(Item::new(i));
+        }
+
+        let readers: Vec<_> = (0..READERS)
+            .map(|_| {
+                let (list, hlist, done, traversals) = (list.clone(), hlist.clone(), done.clone(), traversals.clone());
+                thread::spawn(move || {
+                    while !done.load(Ordering::Relaxed) {
+                        let guard = rcu_read_lock();
+                        /* Values only ever grow along the list */
+                        let mut prev = None;
+                        for item in list.list_for_each_entry_rcu(&guard) {
+                            let val = item.check();
+                            assert!(prev < Some(val));
+                            prev = Some(val);
+                        }
+                        for item in hlist.hlist_for_each_entry_rcu(&guard) {
+                            item.check();
+                        }
+                        drop(guard);
+                        traversals.fetch_add(1, Ordering::Relaxed);
+                        thread::yield_now();
+                    }
+                })
+            })
+            .collect();
+
+        let mut next = 16;
+        for i in 0..UPDATES {
+            let oldest = values(&list)[0];
+
+            list.list_add_tail_rcu(Item::new(next));
+            hlist.hlist_add_head_rcu(Item::new(next));
+            drop(list.list_del_rcu(|item| item.val == oldest));
+            drop(hlist.hlist_del_rcu(|item| item.val == oldest));
+            drop(hlist.hlist_replace_rcu(|item| item.val == next, Item::new(next)));
+            drop(list.list_replace_rcu(|item| item.val == next, Item::new(next)));
+            next += 1;
+
+            if i % 256 == 0 {
+                spare.list_add_rcu(Item::new(0));
+                list.list_splice_init_rcu(&spare);
+                drop(list.list_del_rcu(|item| item.val == 0));
+            }
+            if i % 64 == 0 {
+                thread::yield_now();
+            }
+        }
+
+        done.store(true, Ordering::Relaxed);
+        for reader in readers {
+            reader.join().unwrap();
+        }
+        rcu_barrier();
+
+        assert!(traversals.load(Ordering::Relaxed) > 0);
+        assert_eq!(values(&list), (next - 16..next).collect::<Vec<_>>());
+    }
+}
@@ -0,0 +1,1067 @@
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::marker::PhantomData;
+use std::ptr;
+use std::sync::atomic::{AtomicPtr, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex, Weak};
+use std::thread::{self, JoinHandle};
+
+use rand::Rng;
+
+use crate::errno::{E2BIG, EAGAIN, EEXIST, ENOENT};
+use crate::rcu::{rcu_read_lock, RcuReadGuard, RcuRetired};
+use crate::spinlock::SpinLock;
+
+/*
+ * Resizable, Scalable, Concurrent Hash Table
+ *
+ * Objects are chained into per-bucket hash lists. Lookups and walks run
+ * under rcu_read_lock() only, insertions and removals take the lock of
+ * the bucket they modify.
+ *
+ * Resizing attaches a new table to the current one through ->future_tbl,
+ * after which insertions go to the new table while a background worker
+ * moves the old buckets over one entry at a time. Readers search the old
+ * table first and then follow ->future_tbl, so an object is never missed
+ * while it moves. Once every bucket is moved, the new table is published
+ * and the old one is freed after a grace period.
+ */
+
+pub const HASH_DEFAULT_SIZE: usize = 64;
+pub const HASH_MIN_SIZE: usize = 4;
+
+
+/**
+ * rht_default_hashfn - hash any Hash key, seeded per table
+ */
+pub fn rht_default_hashfn<K: Hash + ?Sized>(key: &K, seed: u32) -> u32 {
+    let mut hasher = DefaultHasher::new();
+
+    seed.hash(&mut hasher);
+    key.hash(&mut hasher);
+    let hash = hasher.finish();
+    (hash ^ (hash >> 32)) as u32
+}
+
+
+/**
+ * struct RhashtableParams - Hash table construction parameters
+ * @nelem_hint: Hint on number of elements, should be 75% of desired size
+ * @max_size: Maximum size while expanding, 0 for no limit
+ * @min_size: Minimum size while shrinking
+ * @automatic_shrinking: Enable automatic shrinking of tables
+ * @key: Function to get the key out of an object
+ * @hashfn: Hash function, seeded with a random value per table
+ * @obj_cmpfn: Function to compare key with object, %None to use Eq
+ */
+pub struct RhashtableParams<T, K> {
+    pub nelem_hint: usize,
+    pub max_size: usize,
+    pub min_size: usize,
+    pub automatic_shrinking: bool,
+    pub key: fn(&T) -> &K,
+    pub hashfn: fn(&K, u32) -> u32,
+    pub obj_cmpfn: Option<fn(&K, &T) -> bool>,
+}
+
+impl<T, K> Clone for RhashtableParams<T, K> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T, K> Copy for RhashtableParams<T, K> {}
+
+#[allow(dead_code)]
+impl<T, K: Hash + Eq> RhashtableParams<T, K> {
+    /* Default parameters for objects keyed by @key */
+    pub fn new(key: fn(&T) -> &K) -> Self {
+        RhashtableParams {
+            nelem_hint: 0,
+            max_size: 0,
+            min_size: HASH_MIN_SIZE,
+            automatic_shrinking: false,
+            key,
+            hashfn: rht_default_hashfn::<K>,
+            obj_cmpfn: None,
+        }
+    }
+}
+
+
+struct RhashNode<T> {
+    next: AtomicPtr<RhashNode<T>>,
+    obj: T,
+}
+
+impl<T> RhashNode<T> {
+    unsafe fn into_inner(node: *mut ()) -> T {
+        Box::from_raw(node as *mut RhashNode<T>).obj
+    }
+}
+
+
+/**
+ * struct BucketTable - Table of hash buckets
+ * @size: Number of hash buckets
+ * @hash_rnd: Random seed to fold into hash
+ * @id: Identifies the table to walkers, never reused
+ * @future_tbl: Table under construction during rehashing
+ * @locks: One lock per bucket, serializing its updaters
+ */
+struct BucketTable<T> {
+    size: usize,
+    hash_rnd: u32,
+    id: u64,
+    future_tbl: AtomicPtr<BucketTable<T>>,
+    buckets: Box<[AtomicPtr<RhashNode<T>>]>,
+    locks: Box<[SpinLock<()>]>,
+}
+
+impl<T> BucketTable<T> {
+    fn bucket_table_alloc(size: usize, id: u64) -> *mut BucketTable<T> {
+        Box::into_raw(Box::new(BucketTable {
+            size,
+            hash_rnd: rand::thread_rng().gen(),
+            id,
+            future_tbl: AtomicPtr::new(ptr::null_mut()),
+            buckets: (0..size).map(|_| AtomicPtr::new(ptr::null_mut())).collect(),
+            locks: (0..size).map(|_| SpinLock::new(())).collect(),
+        }))
+    }
+
+
+    unsafe fn into_inner(tbl: *mut ()) -> BucketTable<T> {
+        *Box::from_raw(tbl as *mut BucketTable<T>)
+    }
+}
+
+
+#[derive(Default)]
+struct RhashtableWorkState {
+    pending: bool,
+    running: bool,
+    exit: bool,
+}
+
+
+/* The deferred resize work, run on a dedicated thread */
+#[derive(Default)]
+struct RhashtableWork {
+    state: Mutex<RhashtableWorkState>,
+    cond: Condvar,
+    thread: Mutex<Option<JoinHandle<()>>>,
+}
+
+
+struct RhashtableInner<T, K> {
+    tbl: AtomicPtr<BucketTable<T>>,
+    nelems: AtomicUsize,
+    p: RhashtableParams<T, K>,
+    max_elems: usize,
+    /* Serializes resizing */
+    mutex: Mutex<()>,
+    run_work: RhashtableWork,
+    next_id: AtomicU64,
+    this: Weak<RhashtableInner<T, K>>,
+    _marker: PhantomData<Box<RhashNode<T>>>,
+}
+
+
+/**
+ * struct Rhashtable - Hash table handle
+ *
+ * Dropping the handle is rhashtable_destroy(): the worker is stopped and
+ * every object left in the table is freed.
+ */
+pub struct Rhashtable<T: Send + Sync + 'static, K: 'static> {
+    ht: Arc<RhashtableInner<T, K>>,
+}
+
+
+impl<T: Send + Sync + 'static, K: 'static> RhashtableInner<T, K> {
+    fn obj_key<'a>(&self, obj: &'a T) -> &'a K {
+        (self.p.key)(obj)
+    }
+
+
+    fn obj_cmp(&self, key: &K, obj: &T) -> bool
+    where
+        K: Eq,
+    {
+        match self.p.obj_cmpfn {
+            Some(cmp) => cmp(key, obj),
+            None => key == self.obj_key(obj),
+        }
+    }
+
+
+    fn key_hashfn(&self, tbl: &BucketTable<T>, key: &K) -> usize {
+        (self.p.hashfn)(key, tbl.hash_rnd) as usize & (tbl.size - 1)
+    }
+
+
+    fn head_hashfn(&self, tbl: &BucketTable<T>, obj: &T) -> usize {
+        self.key_hashfn(tbl, self.obj_key(obj))
+    }
+
+
+    /* Expand when more than 75% full */
+    fn rht_grow_above_75(&self, tbl: &BucketTable<T>) -> bool {
+        self.nelems.load(Ordering::Relaxed) > tbl.size / 4 * 3
+            && (self.p.max_size == 0 || tbl.size < self.p.max_size)
+    }
+
+
+    /* Shrink when less than 30% full */
+    fn rht_shrink_below_30(&self, tbl: &BucketTable<T>) -> bool {
+        self.nelems.load(Ordering::Relaxed) < tbl.size * 3 / 10 && tbl.size > self.p.min_size
+    }
+
+
+    fn rht_grow_above_max(&self) -> bool {
+        self.max_elems != 0 && self.nelems.load(Ordering::Relaxed) >= self.max_elems
+    }
+
+
+    fn rhashtable_last_table(&self, mut tbl: *mut BucketTable<T>) -> *mut BucketTable<T> {
+        loop {
+            let future = unsafe { (*tbl).future_tbl.load(Ordering::Acquire) };
+            if future.is_null() {
+                return tbl;
+            }
+            tbl = future;
+        }
+    }
+
+
+    fn schedule_work(&self) {
+        let work = &self.run_work;
+        let mut state = work.state.lock().unwrap();
+
+        if state.pending || state.exit {
+            return;
+        }
+        state.pending = true;
+        work.cond.notify_all();
+        drop(state);
+
+        let mut thread = work.thread.lock().unwrap();
+        if thread.is_none() {
+            let this = self.this.clone();
+            *thread = Some(
+                thread::Builder::new()
+                    .name("rhashtable".into())
+                    .spawn(move || RhashtableInner::rht_worker_thread(this))
+                    .expect("failed to spawn the rhashtable worker"),
+            );
+        }
+    }
+
+
+    fn rht_worker_thread(this: Weak<RhashtableInner<T, K>>) {
+        loop {
+            let Some(ht) = this.upgrade() else {
+                return;
+            };
+            let work = &ht.run_work;
+
+            let mut state = work.state.lock().unwrap();
+            while !state.pending && !state.exit {
+                state = work.cond.wait(state).unwrap();
+            }
+            if state.exit {
+                return;
+            }
+            state.pending = false;
+            state.running = true;
+            drop(state);
+
+            let err = ht.rht_deferred_worker();
+
+            let mut state = work.state.lock().unwrap();
+            state.running = false;
+            if err.is_err() {
+                state.pending = true;
+            }
+            work.cond.notify_all();
+        }
+    }
+
+
+    fn rht_deferred_worker(&self) -> Result<(), i32> {
+        let _mutex = self.mutex.lock().unwrap();
+        let tbl = self.rhashtable_last_table(self.tbl.load(Ordering::Acquire));
+        let size = unsafe { (*tbl).size };
+
+        let err = if self.rht_grow_above_75(unsafe { &*tbl }) {
+            self.rhashtable_rehash_alloc(tbl, size * 2)
+        } else if self.p.automatic_shrinking && self.rht_shrink_below_30(unsafe { &*tbl }) {
+            self.rhashtable_shrink(tbl)
+        } else {
+            Ok(())
+        };
+
+        if err.is_ok() || err == Err(-EEXIST) {
+            self.rhashtable_rehash_table()?;
+
+            /* A burst of updates may need more than one resize, run again */
+            let tbl = unsafe { &*self.tbl.load(Ordering::Acquire) };
+            if self.rht_grow_above_75(tbl) || (self.p.automatic_shrinking && self.rht_shrink_below_30(tbl)) {
+                return Err(-EAGAIN);
+            }
+            return Ok(());
+        }
+        err
+    }
+
+
+    /* Attach a new table of @size buckets to @old_tbl */
+    fn rhashtable_rehash_alloc(&self, old_tbl: *mut BucketTable<T>, size: usize) -> Result<(), i32> {
+        let new_tbl = BucketTable::bucket_table_alloc(size, self.next_id.fetch_add(1, Ordering::Relaxed));
+
+        let attached = unsafe {
+            (*old_tbl)
+                .future_tbl
+                .compare_exchange(ptr::null_mut(), new_tbl, Ordering::AcqRel, Ordering::Acquire)
+        };
+        if attached.is_err() {
+            drop(unsafe { Box::from_raw(new_tbl) });
+            return Err(-EEXIST);
+        }
+        Ok(())
+    }
+
+
+    /*
+     * rhashtable_shrink - Shrink hash table while allowing concurrent lookups
+     *
+     * This function shrinks the hash table to fit, i.e., the smallest
+     * size would not cause it to expand right away automatically.
+     */
+    fn rhashtable_shrink(&self, old_tbl: *mut BucketTable<T>) -> Result<(), i32> {
+        let nelems = self.nelems.load(Ordering::Relaxed);
+        let size = (nelems * 3 / 2).next_power_of_two().max(self.p.min_size);
+
+        if unsafe { (*old_tbl).size } <= size {
+            return Ok(());
+        }
+        if unsafe { !(*old_tbl).future_tbl.load(Ordering::Acquire).is_null() } {
+            return Err(-EEXIST);
+        }
+        self.rhashtable_rehash_alloc(old_tbl, size)
+    }
+
+
+    /* Move the last entry of the @old_hash bucket to the newest table, its lock is held */
+    fn rhashtable_rehash_one(&self, old_tbl: &BucketTable<T>, old_hash: usize) -> Result<(), i32> {
+        let new_tbl = unsafe { &*self.rhashtable_last_table(old_tbl as *const _ as *mut _) };
+
+        let mut pprev = &old_tbl.buckets[old_hash];
+        let mut entry = pprev.load(Ordering::Relaxed);
+        if entry.is_null() {
+            return Err(-ENOENT);
+        }
+        unsafe {
+            loop {
+                let next = (*entry).next.load(Ordering::Relaxed);
+                if next.is_null() {
+                    break;
+                }
+                pprev = &(*entry).next;
+                entry = next;
+            }
+
+            let new_hash = self.head_hashfn(new_tbl, &(*entry).obj);
+            let _inner = new_tbl.locks[new_hash].spin_lock();
+
+            /* Publish in the new bucket before unlinking, readers try the old table first */
+            (*entry).next.store(new_tbl.buckets[new_hash].load(Ordering::Relaxed), Ordering::Relaxed);
+            new_tbl.buckets[new_hash].store(entry, Ordering::Release);
+        }
+        pprev.store(ptr::null_mut(), Ordering::Release);
+        Ok(())
+    }
+
+
+    fn rhashtable_rehash_chain(&self, old_tbl: &BucketTable<T>, old_hash: usize) {
+        let _lock = old_tbl.locks[old_hash].spin_lock();
+
+        while self.rhashtable_rehash_one(old_tbl, old_hash).is_ok() {}
+    }
+
+
+    /* Move every bucket of the current table to its future table, then publish the latter */
+    fn rhashtable_rehash_table(&self) -> Result<(), i32> {
+        let old_tbl = self.tbl.load(Ordering::Acquire);
+        let new_tbl = unsafe { (*old_tbl).future_tbl.load(Ordering::Acquire) };
+
+        if new_tbl.is_null() {
+            return Ok(());
+        }
+        if unsafe { !(*new_tbl).future_tbl.load(Ordering::Acquire).is_null() } {
+            return Err(-EAGAIN);
+        }
+
+        for old_hash in 0..unsafe { (*old_tbl).size } {
+            self.rhashtable_rehash_chain(unsafe { &*old_tbl }, old_hash);
+            thread::yield_now();
+        }
+
+        /* Publish the new table pointer. */
+        self.tbl.store(new_tbl, Ordering::Release);
+
+        /*
+         * Wait for readers. All new readers will see the new
+         * table, and thus no references to the old table will
+         * remain. Walkers notice the old table is gone by its id.
+         */
+        drop(unsafe { RcuRetired::new(old_tbl as *mut (), BucketTable::<T>::into_inner) });
+
+        if unsafe { (*new_tbl).future_tbl.load(Ordering::Acquire).is_null() } {
+            Ok(())
+        } else {
+            Err(-EAGAIN)
+        }
+    }
+
+
+    /* Find the table with @id among those readers can currently reach */
+    fn rht_find_table(&self, id: u64) -> Option<*mut BucketTable<T>> {
+        let mut tbl = self.tbl.load(Ordering::Acquire);
+
+        while !tbl.is_null() {
+            if unsafe { (*tbl).id } == id {
+                return Some(tbl);
+            }
+            tbl = unsafe { (*tbl).future_tbl.load(Ordering::Acquire) };
+        }
+        None
+    }
+}
+
+impl<T, K> Drop for RhashtableInner<T, K> {
+    fn drop(&mut self) {
+        let mut tbl = *self.tbl.get_mut();
+
+        while !tbl.is_null() {
+            let table = unsafe { Box::from_raw(tbl) };
+
+            for bucket in table.buckets.iter() {
+                let mut pos = bucket.load(Ordering::Relaxed);
+                while !pos.is_null() {
+                    let node = unsafe { Box::from_raw(pos) };
+                    pos = node.next.load(Ordering::Relaxed);
+                }
+            }
+            tbl = table.future_tbl.load(Ordering::Relaxed);
+        }
+    }
+}
+
+
+#[allow(dead_code)]
+impl<T: Send + Sync + 'static, K: Hash + Eq + 'static> Rhashtable<T, K> {
+    /**
+     * rhashtable_init - initialize a new hash table
+     * @params: configuration parameters
+     *
+     * The table grows by a factor of two once it is 75% full and, with
+     * @automatic_shrinking, shrinks when less than 30% full. Resizing runs
+     * on a worker thread while lookups and updates go on.
+     */
+    pub fn rhashtable_init(params: RhashtableParams<T, K>) -> Self {
+        let mut p = params;
+
+        p.min_size = p.min_size.max(HASH_MIN_SIZE).next_power_of_two();
+        if p.max_size != 0 {
+            p.max_size = p.max_size.next_power_of_two().max(p.min_size);
+        }
+
+        /* rounded_hashtable_size() */
+        let size = if p.nelem_hint != 0 {
+            (p.nelem_hint * 4 / 3).next_power_of_two().max(p.min_size)
+        } else {
+            HASH_DEFAULT_SIZE.max(p.min_size)
+        };
+        let size = if p.max_size != 0 { size.min(p.max_size) } else { size };
+
+        Rhashtable {
+            ht: Arc::new_cyclic(|this| RhashtableInner {
+                tbl: AtomicPtr::new(BucketTable::bucket_table_alloc(size, 0)),
+                nelems: AtomicUsize::new(0),
+                p,
+                max_elems: p.max_size.saturating_mul(2),
+                mutex: Mutex::new(()),
+                run_work: RhashtableWork::default(),
+                next_id: AtomicU64::new(1),
+                this: this.clone(),
+                _marker: PhantomData,
+            }),
+        }
+    }
+
+
+    /* Number of objects in the table */
+    pub fn nelems(&self) -> usize {
+        self.ht.nelems.load(Ordering::Relaxed)
+    }
+
+
+    /* Number of buckets of the current table */
+    pub fn size(&self) -> usize {
+        let _guard = rcu_read_lock();
+        unsafe { (*self.ht.tbl.load(Ordering::Acquire)).size }
+    }
+
+
+    /**
+     * rhashtable_lookup - search hash table
+     * @key: the pointer to the key
+     * @_guard: the read-side critical section the object is used in
+     *
+     * Computes the hash value for the key and traverses the bucket chain
+     * looking for an entry with an identical key. The first matching entry
+     * is returned.
+     */
+    pub fn rhashtable_lookup<'a>(&'a self, key: &K, _guard: &'a RcuReadGuard) -> Option<&'a T> {
+        let ht = &*self.ht;
+        let mut tbl = ht.tbl.load(Ordering::Acquire);
+
+        while !tbl.is_null() {
+            let t = unsafe { &*tbl };
+            let mut pos = t.buckets[ht.key_hashfn(t, key)].load(Ordering::Acquire);
+
+            while !pos.is_null() {
+                let node = unsafe { &*pos };
+                if ht.obj_cmp(key, &node.obj) {
+                    return Some(&node.obj);
+                }
+                pos = node.next.load(Ordering::Acquire);
+            }
+
+            /* Ensure we see any new tables. */
+            tbl = t.future_tbl.load(Ordering::Acquire);
+        }
+        None
+    }
+
+
+    /**
+     * rhashtable_lookup_fast - search hash table, without RCU read lock
+     * @key: the pointer to the key
+     *
+     * Only use this function when you have other mechanisms guaranteeing
+     * that the object won't go away after the RCU read lock is released,
+     * here it is cloned before that.
+     */
+    pub fn rhashtable_lookup_fast(&self, key: &K) -> Option<T>
+    where
+        T: Clone,
+    {
+        let guard = rcu_read_lock();
+        self.rhashtable_lookup(key, &guard).cloned()
+    }
+
+
+    /**
+     * rhashtable_insert_fast - insert object into hash table
+     * @obj: the object to insert
+     *
+     * Will trigger an automatic deferred table resizing if residency in the
+     * table grows beyond 75%.
+     *
+     * Return: 0 on success, -EEXIST if an object with the same key is
+     * already present, or -E2BIG if the table is at its maximum size. The
+     * object is dropped on failure.
+     */
+    pub fn rhashtable_insert_fast(&self, obj: T) -> Result<(), i32> {
+        let ht = &*self.ht;
+        let _guard = rcu_read_lock();
+        let mut tbl = ht.tbl.load(Ordering::Acquire);
+
+        loop {
+            let t = unsafe { &*tbl };
+            let hash = ht.head_hashfn(t, &obj);
+            let lock = t.locks[hash].spin_lock();
+
+            /* rhashtable_lookup_one */
+            let mut pos = t.buckets[hash].load(Ordering::Relaxed);
+            while !pos.is_null() {
+                let node = unsafe { &*pos };
+                if ht.obj_cmp(ht.obj_key(&obj), &node.obj) {
+                    return Err(-EEXIST);
+                }
+                pos = node.next.load(Ordering::Relaxed);
+            }
+
+            /* The bucket may be rehashed already, insert into the newest table */
+            let future = t.future_tbl.load(Ordering::Acquire);
+            if !future.is_null() {
+                drop(lock);
+                tbl = future;
+                continue;
+            }
+
+            if ht.rht_grow_above_max() {
+                return Err(-E2BIG);
+            }
+
+            let node = Box::into_raw(Box::new(RhashNode {
+                next: AtomicPtr::new(t.buckets[hash].load(Ordering::Relaxed)),
+                obj,
+            }));
+            t.buckets[hash].store(node, Ordering::Release);
+            ht.nelems.fetch_add(1, Ordering::Relaxed);
+            drop(lock);
+
+            if ht.rht_grow_above_75(t) {
+                ht.schedule_work();
+            }
+            return Ok(());
+        }
+    }
+
+
+    /**
+     * rhashtable_remove_fast - remove object from hash table
+     * @key: the key of the object to remove
+     *
+     * Since the hash chain is single linked, the removal operation needs to
+     * walk the bucket chain upon removal.
+     *
+     * Will automatically shrink the table if permitted when residency
+     * drops below 30%.
+     *
+     * Return: the removed object, freed after a grace period unless taken
+     * back with rcu_retire_sync(), or -ENOENT if no object has @key.
+     */
+    pub fn rhashtable_remove_fast(&self, key: &K) -> Result<RcuRetired<T>, i32> {
+        let ht = &*self.ht;
+        let _guard = rcu_read_lock();
+        let mut tbl = ht.tbl.load(Ordering::Acquire);
+
+        while !tbl.is_null() {
+            let t = unsafe { &*tbl };
+            let hash = ht.key_hashfn(t, key);
+            let lock = t.locks[hash].spin_lock();
+
+            let mut pprev = &t.buckets[hash];
+            let mut pos = pprev.load(Ordering::Relaxed);
+            while !pos.is_null() {
+                let node = unsafe { &*pos };
+                if ht.obj_cmp(key, &node.obj) {
+                    pprev.store(node.next.load(Ordering::Relaxed), Ordering::Release);
+                    ht.nelems.fetch_sub(1, Ordering::Relaxed);
+                    drop(lock);
+
+                    if ht.p.automatic_shrinking && ht.rht_shrink_below_30(t) {
+                        ht.schedule_work();
+                    }
+                    return Ok(unsafe { RcuRetired::new(pos as *mut (), RhashNode::<T>::into_inner) });
+                }
+                pprev = &node.next;
+                pos = pprev.load(Ordering::Relaxed);
+            }
+            drop(lock);
+
+            tbl = t.future_tbl.load(Ordering::Acquire);
+        }
+        Err(-ENOENT)
+    }
+
+
+    /**
+     * rhashtable_flush_work - wait for the pending resize work to finish
+     */
+    pub fn rhashtable_flush_work(&self) {
+        let work = &self.ht.run_work;
+        let mut state = work.state.lock().unwrap();
+
+        while state.pending || state.running {
+            state = work.cond.wait(state).unwrap();
+        }
+    }
+
+
+    /**
+     * rhashtable_walk_enter - Initialise an iterator
+     *
+     * This function prepares a hash table walk.
+     *
+     * Note that if you restart a walk after rhashtable_walk_stop you
+     * may see the same object twice. Also, you may miss objects if
+     * there are removals in between rhashtable_walk_stop and the next
+     * call to rhashtable_walk_start.
+     *
+     * For a completely stable walk you should construct your own data
+     * structure outside the hash table.
+     */
+    pub fn rhashtable_walk_enter(&self) -> RhashtableIter<'_, T, K> {
+        let _guard = rcu_read_lock();
+        let tbl = self.ht.tbl.load(Ordering::Acquire);
+
+        RhashtableIter {
+            ht: self,
+            tbl,
+            tbl_id: unsafe { (*tbl).id },
+            slot: 0,
+            skip: 0,
+            end_of_table: false,
+            guard: None,
+        }
+    }
+}
+
+impl<T: Send + Sync + 'static, K: 'static> Drop for Rhashtable<T, K> {
+    /* rhashtable_destroy - destroy hash table */
+    fn drop(&mut self) {
+        let work = &self.ht.run_work;
+
+        work.state.lock().unwrap().exit = true;
+        work.cond.notify_all();
+        if let Some(thread) = work.thread.lock().unwrap().take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+
+/**
+ * struct RhashtableIter - Hash table iterator
+ *
+ * Between rhashtable_walk_start() and rhashtable_walk_stop() the walk
+ * holds the RCU read lock. A resize in between invalidates the table
+ * the walk was on, which the next start detects by the table id.
+ */
+pub struct RhashtableIter<'a, T: Send + Sync + 'static, K: 'static> {
+    ht: &'a Rhashtable<T, K>,
+    tbl: *mut BucketTable<T>,
+    tbl_id: u64,
+    slot: usize,
+    skip: usize,
+    end_of_table: bool,
+    guard: Option<RcuReadGuard>,
+}
+
+#[allow(dead_code)]
+impl<T: Send + Sync + 'static, K: Hash + Eq + 'static> RhashtableIter<'_, T, K> {
+    /**
+     * rhashtable_walk_start - Start a hash table walk
+     *
+     * Start a hash table walk at the current iterator position. Note that we
+     * take the RCU lock in all cases including when we return an error.
+     * So you must always call rhashtable_walk_stop to clean up.
+     *
+     * Return: 0 if successful, -EAGAIN if resize event occurred. Note that
+     * the iterator will rewind back to the beginning and you may use it
+     * immediately by calling rhashtable_walk_next.
+     */
+    pub fn rhashtable_walk_start(&mut self) -> Result<(), i32> {
+        assert!(self.guard.is_none(), "rhashtable_walk_start: walk already started");
+        self.guard = Some(rcu_read_lock());
+
+        if self.end_of_table {
+            return Ok(());
+        }
+
+        match self.ht.ht.rht_find_table(self.tbl_id) {
+            Some(tbl) => {
+                self.tbl = tbl;
+                Ok(())
+            }
+            None => {
+                self.tbl = self.ht.ht.tbl.load(Ordering::Acquire);
+                self.tbl_id = unsafe { (*self.tbl).id };
+                self.slot = 0;
+                self.skip = 0;
+                Err(-EAGAIN)
+            }
+        }
+    }
+
+
+    /**
+     * rhashtable_walk_next - Return the next object and advance the iterator
+     *
+     * Note that you must call rhashtable_walk_stop when you are finished
+     * with the walk.
+     *
+     * Return: the next object or %None if no more objects in the table,
+     * or -EAGAIN if resize event occurred. The iterator then rewinds to
+     * the beginning of the new table and objects may be seen twice.
+     */
+    pub fn rhashtable_walk_next(&mut self) -> Result<Option<&T>, i32> {
+        assert!(self.guard.is_some(), "rhashtable_walk_next: walk not started");
+
+        if self.end_of_table {
+            return Ok(None);
+        }
+
+        let tbl = unsafe { &*self.tbl };
+        while self.slot < tbl.size {
+            let mut pos = tbl.buckets[self.slot].load(Ordering::Acquire);
+            let mut skip = self.skip;
+
+            while !pos.is_null() && skip > 0 {
+                pos = unsafe { (*pos).next.load(Ordering::Acquire) };
+                skip -= 1;
+            }
+            if !pos.is_null() {
+                self.skip += 1;
+                return Ok(Some(unsafe { &(*pos).obj }));
+            }
+
+            self.skip = 0;
+            self.slot += 1;
+        }
+
+        /* Ensure we see any new tables. */
+        let future = tbl.future_tbl.load(Ordering::Acquire);
+        if future.is_null() {
+            self.end_of_table = true;
+            return Ok(None);
+        }
+
+        self.tbl = future;
+        self.tbl_id = unsafe { (*future).id };
+        self.slot = 0;
+        self.skip = 0;
+        Err(-EAGAIN)
+    }
+
+
+    /**
+     * rhashtable_walk_stop - Finish a hash table walk
+     *
+     * Finish a hash table walk. Does not reset the iterator to the start
+     * of the hash table.
+     */
+    pub fn rhashtable_walk_stop(&mut self) {
+        self.guard = None;
+    }
+}
+
+
+/* Sections for Unit tests */
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use std::sync::atomic::AtomicBool;
+
+    use super::*;
+
+    #[derive(Clone, Debug, PartialEq)]
+    struct TestObj {
+        key: u32,
+        value: u32,
+    }
+
+
+    fn test_params() -> RhashtableParams<TestObj, u32> {
+        RhashtableParams::new(|obj: &TestObj| &obj.key)
+    }
+
+
+    fn obj(key: u32) -> TestObj {
+        TestObj { key, value: key * 10 }
+    }
+
+
+    #[test]
+    fn test_rhashtable_insert_lookup_remove() {
+        let ht = Rhashtable::rhashtable_init(test_params());
+
+        for key in 0..32 {
+            assert_eq!(ht.rhashtable_insert_fast(obj(key)), Ok(()));
+        }
+        assert_eq!(ht.rhashtable_insert_fast(obj(7)), Err(-EEXIST));
+        assert_eq!(ht.nelems(), 32);
+
+        assert_eq!(ht.rhashtable_lookup_fast(&7), Some(obj(7)));
+        assert_eq!(ht.rhashtable_lookup_fast(&99), None);
+        {
+            let guard = rcu_read_lock();
+            assert_eq!(ht.rhashtable_lookup(&31, &guard).unwrap().value, 310);
+        }
+
+        assert_eq!(ht.rhashtable_remove_fast(&7).unwrap().rcu_retire_sync(), obj(7));
+        assert!(matches!(ht.rhashtable_remove_fast(&7), Err(e) if e == -ENOENT));
+        assert_eq!(ht.rhashtable_lookup_fast(&7), None);
+        assert_eq!(ht.nelems(), 31);
+    }
+
+
+    #[test]
+    fn test_rhashtable_grow_shrink() {
+        let mut params = test_params();
+        params.min_size = 8;
+        params.nelem_hint = 4;
+        params.automatic_shrinking = true;
+        let ht = Rhashtable::rhashtable_init(params);
+        assert_eq!(ht.size(), 8);
+
+        for key in 0..1000 {
+            ht.rhashtable_insert_fast(obj(key)).unwrap();
+        }
+        ht.rhashtable_flush_work();
+        assert!(ht.size() >= 1024);
+        for key in 0..1000 {
+            assert_eq!(ht.rhashtable_lookup_fast(&key), Some(obj(key)));
+        }
+
+        for key in 10..1000 {
+            ht.rhashtable_remove_fast(&key).unwrap();
+        }
+        ht.rhashtable_flush_work();
+        assert!(ht.size() <= 16);
+        for key in 0..10 {
+            assert_eq!(ht.rhashtable_lookup_fast(&key), Some(obj(key)));
+        }
+    }
+
+
+    #[test]
+    fn test_rhashtable_incremental_rehash() {
+        let ht = Rhashtable::rhashtable_init(test_params());
+        let inner = &*ht.ht;
+
+        for key in 0..200 {
+            ht.rhashtable_insert_fast(obj(key)).unwrap();
+        }
+
+        /* Stop half way through a rehash, both tables hold objects */
+        let _mutex = inner.mutex.lock().unwrap();
+        let old_tbl = inner.tbl.load(Ordering::Acquire);
+        inner.rhashtable_rehash_alloc(old_tbl, 256).unwrap();
+        for old_hash in 0..32 {
+            inner.rhashtable_rehash_chain(unsafe { &*old_tbl }, old_hash);
+        }
+
+        for key in 200..300 {
+            ht.rhashtable_insert_fast(obj(key)).unwrap();
+        }
+        ht.rhashtable_remove_fast(&5).unwrap();
+        ht.rhashtable_remove_fast(&250).unwrap();
+        for key in 0..300 {
+            let expect = (key != 5 && key != 250).then(|| obj(key));
+            assert_eq!(ht.rhashtable_lookup_fast(&key), expect);
+        }
+
+        inner.rhashtable_rehash_table().unwrap();
+        assert_eq!(ht.size(), 256);
+        for key in 0..300 {
+            assert_eq!(ht.rhashtable_lookup_fast(&key).is_some(), key != 5 && key != 250);
+        }
+    }
+
+
+    #[test]
+    fn test_rhashtable_walk() {
+        let ht = Rhashtable::rhashtable_init(test_params());
+        for key in 0..40 {
+            ht.rhashtable_insert_fast(obj(key)).unwrap();
+        }
+
+        let mut seen = HashSet::new();
+        let mut iter = ht.rhashtable_walk_enter();
+        assert_eq!(iter.rhashtable_walk_start(), Ok(()));
+        for _ in 0..10 {
+            seen.insert(iter.rhashtable_walk_next().unwrap().unwrap().key);
+        }
+        iter.rhashtable_walk_stop();
+
+        /* Resize while the walk is stopped */
+        for key in 40..200 {
+            ht.rhashtable_insert_fast(obj(key)).unwrap();
+        }
+        ht.rhashtable_flush_work();
+
+        assert_eq!(iter.rhashtable_walk_start(), Err(-EAGAIN));
+        loop {
+            match iter.rhashtable_walk_next() {
+                Ok(Some(obj)) => {
+                    seen.insert(obj.key);
+                }
+                Ok(None) => break,
+                Err(err) => assert_eq!(err, -EAGAIN),
+            }
+        }
+        iter.rhashtable_walk_stop();
+
+        assert_eq!(seen, (0..200).collect());
+    }
+
+
+    #[test]
+    fn test_rhashtable_params() {
+        let mut params: RhashtableParams<String, String> = RhashtableParams::new(|s: &String| s);
+        params.hashfn = |key, seed| rht_default_hashfn(&key.to_ascii_lowercase(), seed);
+        params.obj_cmpfn = Some(|key, obj| key.eq_ignore_ascii_case(obj));
+        params.max_size = 4;
+        let ht = Rhashtable::rhashtable_init(params);
+
+        ht.rhashtable_insert_fast("Hello".to_string()).unwrap();
+        assert_eq!(ht.rhashtable_insert_fast("HELLO".to_string()), Err(-EEXIST));
+        assert_eq!(ht.rhashtable_lookup_fast(&"hello".to_string()), Some("Hello".to_string()));
+
+        /* max_size buckets hold at most twice as many objects */
+        for i in 0..7 {
+            ht.rhashtable_insert_fast(i.to_string()).unwrap();
+        }
+        assert_eq!(ht.rhashtable_insert_fast("x".to_string()), Err(-E2BIG));
+        ht.rhashtable_flush_work();
+        assert_eq!(ht.size(), 4);
+    }
+
+
+    #[test]
+    fn test_rhashtable_concurrent() {
+        const KEYS: u32 = 1000;
+        let ht = Arc::new(Rhashtable::rhashtable_init({
+            let mut params = test_params();
+            params.min_size = 4;
+            params.nelem_hint = 4;
+            params.automatic_shrinking = true;
+            params
+        }));
+        let done = Arc::new(AtomicBool::new(false));
+
+        /* Even keys stay in the table the whole time */
+        for key in (0..KEYS).step_by(2) {
+            ht.rhashtable_insert_fast(obj(key)).unwrap();
+        }
+
+        let readers: Vec<_> = (0..2)
+            .map(|_| {
+                let ht = ht.clone();
+                let done = done.clone();
+                thread::spawn(move || {
+                    while !done.load(Ordering::Relaxed) {
+                        let guard = rcu_read_lock();
+                        for key in (0..KEYS).step_by(2) {
+                            assert_eq!(ht.rhashtable_lookup(&key, &guard).unwrap().value, key * 10);
+                        }
+                        drop(guard);
+                        thread::yield_now();
+                    }
+                })
+            })
+            .collect();
+
+        for _ in 0..2 {
+            for key in (1..KEYS).step_by(2) {
+                ht.rhashtable_insert_fast(obj(key)).unwrap();
+            }
+            for key in (1..KEYS).step_by(2) {
+                ht.rhashtable_remove_fast(&key).unwrap();
+            }
+        }
+        ht.rhashtable_flush_work();
+        done.store(true, Ordering::Relaxed);
+
+        for reader in readers {
+            reader.join().unwrap();
+        }
+        assert_eq!(ht.nelems(), KEYS as usize / 2);
+    }
+}
@@ -0,0 +1,989 @@
+use std::cell::UnsafeCell;
+use std::ptr;
+use std::sync::atomic::{fence, AtomicBool, AtomicPtr, AtomicU32, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Instant;
+
+use crate::bitmap::PAGE_SIZE;
+use crate::errno::{E2BIG, EBUSY, EINVAL};
+use crate::sync_list::{SyncListHead, SyncListNode};
+
+/*
+ * Ring buffer for event tracing
+ *
+ * Modelled after kernel/trace/ring_buffer.c. Every shard, the stand-in for
+ * a CPU, owns a ring of preallocated pages kept on a SyncListHead in age
+ * order, plus one reader page that lives outside of the ring:
+ *
+ *   reader page      ring: [ oldest ... tail ]
+ *
+ * The single writer of a shard only appends behind the commit index of
+ * its tail page, so writing an event takes no lock. Only when the tail
+ * page is full the writer takes the oldest page off the ring, which is
+ * where events are overwritten, and puts it back as the new tail.
+ *
+ * Readers are serialized by the reader lock and never read a page that
+ * is still on the ring: once the reader page is exhausted, the oldest
+ * page holding events is swapped for it under the list lock. The writer
+ * claims a page by deleting it from the ring and the reader by replacing
+ * it, so a page is never recycled and consumed at the same time.
+ *
+ * Page data is made of atomic words, and recycling a page bumps its
+ * generation, so the non-consuming iterators may read pages the writer
+ * is overwriting and throw away what they read, seqlock style.
+ */
+
+/* Overwrite the oldest events when the buffer is full */
+pub const RB_FL_OVERWRITE: u32 = 1 << 0;
+
+/* Header of a buffer_data_page: time stamp and commit */
+const BUF_PAGE_HDR_SIZE: usize = 16;
+pub const BUF_PAGE_SIZE: usize = PAGE_SIZE - BUF_PAGE_HDR_SIZE;
+const BUF_PAGE_WORDS: usize = BUF_PAGE_SIZE / RB_EVNT_HDR_SIZE;
+
+const RB_EVNT_HDR_SIZE: usize = 4;
+/* Largest payload: room for a time extend, the header and the length */
+pub const BUF_MAX_DATA_SIZE: usize = BUF_PAGE_SIZE - RB_EVNT_HDR_SIZE * 4;
+
+const RINGBUF_TYPE_DATA_TYPE_LEN_MAX: usize = 28;
+const RINGBUF_TYPE_TIME_EXTEND: u32 = 30;
+const RB_TYPE_LEN_MASK: u32 = (1 << 5) - 1;
+
+const TS_SHIFT: u32 = 27;
+const TS_MASK: u64 = (1 << TS_SHIFT) - 1;
+
+
+pub type RingBufferClock = fn() -> u64;
+
+/**
+ * trace_clock_local - default ring buffer clock
+ *
+ * Nanoseconds since the clock was first read in this process.
+ */
+pub fn trace_clock_local() -> u64 {
+    static EPOCH: OnceLock<Instant> = OnceLock::new();
+    EPOCH.get_or_init(Instant::now).elapsed().as_nanos() as u64
+}
+
+
+/**
+ * struct RingBufferEvent - an event copied out of the ring buffer
+ * @ts: time stamp of the event
+ * @data: payload of the event
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RingBufferEvent {
+    pub ts: u64,
+    pub data: Vec<u8>,
+}
+
+
+/*
+ * A decoded event. Data events are headed by type_len and a 27 bit time
+ * delta: a type_len of 1..=28 gives the payload length in words, 0 means
+ * the length in bytes follows in the next word. A time extend carries the
+ * bits of a delta that did not fit in the header of the next event.
+ */
+enum RbEntry {
+    Data(usize, u64, Vec<u8>),
+    TimeExtend(u64),
+    Invalid,
+}
+
+const RB_TIME_EXTEND_WORDS: usize = 2;
+
+
+/* Whether the payload length can be encoded in type_len */
+fn rb_small_event(len: usize) -> bool {
+    len > 0 && len.is_multiple_of(RB_EVNT_HDR_SIZE) && len / RB_EVNT_HDR_SIZE <= RINGBUF_TYPE_DATA_TYPE_LEN_MAX
+}
+
+
+fn rb_event_words(len: usize) -> usize {
+    if rb_small_event(len) {
+        1 + len / RB_EVNT_HDR_SIZE
+    } else {
+        2 + len.div_ceil(RB_EVNT_HDR_SIZE)
+    }
+}
+
+
+/**
+ * struct BufferPage - a page of the ring
+ * @index: slot of the page in RingBufferPerCpu.all_pages
+ * @time_stamp: time stamp of the first event on the page
+ * @commit: number of committed words
+ * @entries: number of events on the page
+ * @generation: bumped every time the page is reset
+ * @data: the events
+ */
+struct BufferPage {
+    index: usize,
+    time_stamp: AtomicU64,
+    commit: AtomicUsize,
+    entries: AtomicUsize,
+    generation: AtomicU64,
+    data: Box<[AtomicU32]>,
+}
+
+impl BufferPage {
+    fn new(index: usize) -> Self {
+        BufferPage {
+            index,
+            time_stamp: AtomicU64::new(0),
+            commit: AtomicUsize::new(0),
+            entries: AtomicUsize::new(0),
+            generation: AtomicU64::new(0),
+            data: (0..BUF_PAGE_WORDS).map(|_| AtomicU32::new(0)).collect(),
+        }
+    }
+
+
+    /* Empty a page which was taken off the ring */
+    fn rb_reset_page(&self) {
+        self.generation.fetch_add(1, Ordering::Relaxed);
+        fence(Ordering::Release);
+        self.commit.store(0, Ordering::Relaxed);
+        self.entries.store(0, Ordering::Relaxed);
+    }
+
+
+    fn rb_write_event(&self, off: usize, delta: u64, data: &[u8]) {
+        let mut pos = off + 1;
+
+        if rb_small_event(data.len()) {
+            let type_len = (data.len() / RB_EVNT_HDR_SIZE) as u32;
+            self.data[off].store(type_len | (delta as u32) << 5, Ordering::Relaxed);
+        } else {
+            self.data[off].store((delta as u32) << 5, Ordering::Relaxed);
+            self.data[pos].store(data.len() as u32, Ordering::Relaxed);
+            pos += 1;
+        }
+
+        for chunk in data.chunks(RB_EVNT_HDR_SIZE) {
+            let mut word = [0; RB_EVNT_HDR_SIZE];
+            word[..chunk.len()].copy_from_slice(chunk);
+            self.data[pos].store(u32::from_le_bytes(word), Ordering::Relaxed);
+            pos += 1;
+        }
+    }
+
+
+    fn rb_write_time_extend(&self, off: usize, delta: u64) {
+        self.data[off].store(RINGBUF_TYPE_TIME_EXTEND | ((delta & TS_MASK) as u32) << 5, Ordering::Relaxed);
+        self.data[off + 1].store((delta >> TS_SHIFT) as u32, Ordering::Relaxed);
+    }
+
+
+    /* Decode the event at @off, which must be below a commit index already read */
+    fn rb_read_event(&self, off: usize) -> RbEntry {
+        let hdr = self.data[off].load(Ordering::Relaxed);
+        let delta = (hdr >> 5) as u64;
+
+        if off + 1 >= BUF_PAGE_WORDS {
+            return RbEntry::Invalid;
+        }
+        let (len, start) = match hdr & RB_TYPE_LEN_MASK {
+            RINGBUF_TYPE_TIME_EXTEND => {
+                let high = self.data[off + 1].load(Ordering::Relaxed) as u64;
+                return RbEntry::TimeExtend(delta | high << TS_SHIFT);
+            }
+            0 => (self.data[off + 1].load(Ordering::Relaxed) as usize, off + 2),
+            type_len if type_len as usize <= RINGBUF_TYPE_DATA_TYPE_LEN_MAX => {
+                (type_len as usize * RB_EVNT_HDR_SIZE, off + 1)
+            }
+            _ => return RbEntry::Invalid,
+        };
+
+        /* Iterators may decode a page while it is overwritten */
+        let end = start + len.div_ceil(RB_EVNT_HDR_SIZE);
+        if len > BUF_MAX_DATA_SIZE || end > BUF_PAGE_WORDS {
+            return RbEntry::Invalid;
+        }
+
+        let mut data = Vec::with_capacity(len);
+        for word in self.data[start..end].iter() {
+            data.extend_from_slice(&word.load(Ordering::Relaxed).to_le_bytes());
+        }
+        data.truncate(len);
+        RbEntry::Data(end - off, delta, data)
+    }
+}
+
+
+type BufferPageNode = SyncListNode<BufferPage>;
+
+/* Writer side of a shard, only touched by the RingBufferWriter holding it */
+struct RbWriter {
+    tail: Arc<BufferPageNode>,
+    write: usize,
+    write_stamp: u64,
+}
+
+/* Reader side of a shard, protected by the reader lock */
+struct RbReader {
+    reader_page: Arc<BufferPageNode>,
+    read: usize,
+    read_stamp: u64,
+}
+
+
+/**
+ * struct RingBufferPerCpu - one shard of a TraceBuffer
+ * @pages: the ring, oldest page first
+ * @all_pages: every page of the shard, the reader page included
+ * @tail_page: the page the writer is on, only used for comparison
+ * @entries: number of events written
+ * @overrun: number of events overwritten
+ * @read: number of events consumed
+ * @dropped_events: number of events dropped when not overwriting
+ */
+struct RingBufferPerCpu {
+    pages: SyncListHead<BufferPage>,
+    all_pages: Box<[Arc<BufferPageNode>]>,
+    tail_page: AtomicPtr<BufferPageNode>,
+    writer: UnsafeCell<RbWriter>,
+    writer_claimed: AtomicBool,
+    reader_lock: Mutex<RbReader>,
+    entries: AtomicUsize,
+    overrun: AtomicUsize,
+    read: AtomicUsize,
+    dropped_events: AtomicUsize,
+}
+
+/* @writer is only used by the one RingBufferWriter that claimed it */
+unsafe impl Sync for RingBufferPerCpu {}
+
+impl RingBufferPerCpu {
+    fn rb_allocate_cpu_buffer(nr_pages: usize) -> Self {
+        let all_pages: Box<[Arc<BufferPageNode>]> =
+            (0..=nr_pages).map(|index| SyncListNode::new(BufferPage::new(index))).collect();
+        let pages = SyncListHead::new();
+
+        for page in all_pages[..nr_pages].iter() {
+            pages.list_add_tail(page.clone());
+        }
+        let tail = all_pages[nr_pages - 1].clone();
+
+        RingBufferPerCpu {
+            pages,
+            tail_page: AtomicPtr::new(Arc::as_ptr(&tail) as *mut _),
+            writer: UnsafeCell::new(RbWriter {
+                tail,
+                write: 0,
+                write_stamp: 0,
+            }),
+            writer_claimed: AtomicBool::new(false),
+            reader_lock: Mutex::new(RbReader {
+                reader_page: all_pages[nr_pages].clone(),
+                read: 0,
+                read_stamp: 0,
+            }),
+            all_pages,
+            entries: AtomicUsize::new(0),
+            overrun: AtomicUsize::new(0),
+            read: AtomicUsize::new(0),
+            dropped_events: AtomicUsize::new(0),
+        }
+    }
+
+
+    fn entries(&self) -> usize {
+        self.entries.load(Ordering::Relaxed)
+            - self.overrun.load(Ordering::Relaxed)
+            - self.read.load(Ordering::Relaxed)
+    }
+
+
+    /* Move the writer to the oldest page of the ring, overwriting it if needed */
+    fn rb_move_tail(&self, w: &mut RbWriter, overwrite: bool) -> Result<(), i32> {
+        loop {
+            let next = self.pages.list_first_entry().expect("ring buffer: empty ring");
+
+            /* The reader may be swapping this very page out */
+            let Some(next) = self.pages.list_del_init(&next) else {
+                continue;
+            };
+
+            let page = &next.item;
+            if page.commit.load(Ordering::Relaxed) != 0 {
+                if !overwrite {
+                    self.pages.list_add(next);
+                    self.dropped_events.fetch_add(1, Ordering::Relaxed);
+                    return Err(-EBUSY);
+                }
+                self.overrun.fetch_add(page.entries.load(Ordering::Relaxed), Ordering::Relaxed);
+            }
+            page.rb_reset_page();
+
+            self.pages.list_add_tail(next.clone());
+            self.tail_page.store(Arc::as_ptr(&next) as *mut _, Ordering::Release);
+            w.tail = next;
+            w.write = 0;
+            return Ok(());
+        }
+    }
+
+
+    /*
+     * Swap the reader page for the oldest page of the ring holding events.
+     * The writer left the exhausted reader page, so it can be emptied
+     * before it goes back on the ring, and finding the oldest page and
+     * putting the reader page in its place is one operation: a writer
+     * recycling pages in between would otherwise hand us its newest page.
+     */
+    fn rb_get_reader_page(&self, r: &mut RbReader) -> bool {
+        r.reader_page.item.rb_reset_page();
+        r.read = 0;
+
+        let Ok(page) = self
+            .pages
+            .list_replace_first(|page| page.commit.load(Ordering::Acquire) != 0, r.reader_page.clone())
+        else {
+            return false;
+        };
+
+        r.reader_page = page;
+        r.read_stamp = r.reader_page.item.time_stamp.load(Ordering::Relaxed);
+        true
+    }
+
+
+    fn rb_buffer_next(&self, r: &mut RbReader, consume: bool) -> Option<RingBufferEvent> {
+        loop {
+            let page = &r.reader_page.item;
+
+            if r.read >= page.commit.load(Ordering::Acquire) {
+                /* Nothing newer exists while the writer is on the reader page */
+                if ptr::eq(self.tail_page.load(Ordering::Acquire), Arc::as_ptr(&r.reader_page)) {
+                    return None;
+                }
+                /* The writer left, its last commit is visible now */
+                if r.read >= page.commit.load(Ordering::Acquire) {
+                    if !self.rb_get_reader_page(r) {
+                        return None;
+                    }
+                    continue;
+                }
+            }
+
+            match page.rb_read_event(r.read) {
+                RbEntry::TimeExtend(delta) => {
+                    r.read += RB_TIME_EXTEND_WORDS;
+                    r.read_stamp += delta;
+                }
+                RbEntry::Data(words, delta, data) => {
+                    let ts = r.read_stamp + delta;
+                    if consume {
+                        r.read += words;
+                        r.read_stamp = ts;
+                        self.read.fetch_add(1, Ordering::Relaxed);
+                    }
+                    return Some(RingBufferEvent { ts, data });
+                }
+                RbEntry::Invalid => unreachable!("ring buffer: corrupted reader page"),
+            }
+        }
+    }
+}
+
+
+/**
+ * struct TraceBuffer - sharded ring buffer
+ * @flags: RB_FL_OVERWRITE or 0
+ * @clock: source of the event time stamps
+ * @buffers: one ring per shard
+ */
+pub struct TraceBuffer {
+    flags: u32,
+    clock: RingBufferClock,
+    buffers: Box<[RingBufferPerCpu]>,
+}
+
+#[allow(dead_code)]
+impl TraceBuffer {
+    /**
+     * ring_buffer_alloc - allocate a new ring_buffer
+     * @size: the size in bytes per shard that is needed.
+     * @nr_cpus: the number of shards
+     * @flags: attributes to set for the ring buffer.
+     *
+     * Every page is allocated up front, the size is rounded up to whole
+     * pages and to at least two pages per shard.
+     */
+    pub fn ring_buffer_alloc(size: usize, nr_cpus: usize, flags: u32) -> Result<Self, i32> {
+        if nr_cpus == 0 || flags & !RB_FL_OVERWRITE != 0 {
+            return Err(-EINVAL);
+        }
+        let nr_pages = size.div_ceil(BUF_PAGE_SIZE).max(2);
+
+        Ok(TraceBuffer {
+            flags,
+            clock: trace_clock_local,
+            buffers: (0..nr_cpus).map(|_| RingBufferPerCpu::rb_allocate_cpu_buffer(nr_pages)).collect(),
+        })
+    }
+
+
+    /**
+     * ring_buffer_set_clock - set the clock of the time stamps
+     */
+    pub fn ring_buffer_set_clock(&mut self, clock: RingBufferClock) {
+        self.clock = clock;
+    }
+
+
+    pub fn nr_cpus(&self) -> usize {
+        self.buffers.len()
+    }
+
+
+    /**
+     * ring_buffer_writer - claim the writer side of a shard
+     * @cpu: the shard to write to
+     *
+     * Return: -EBUSY if the shard already has a writer.
+     */
+    pub fn ring_buffer_writer(&self, cpu: usize) -> Result<RingBufferWriter<'_>, i32> {
+        let cpu_buffer = self.buffers.get(cpu).ok_or(-EINVAL)?;
+
+        if cpu_buffer.writer_claimed.swap(true, Ordering::Acquire) {
+            return Err(-EBUSY);
+        }
+        Ok(RingBufferWriter { buffer: self, cpu_buffer })
+    }
+
+
+    /**
+     * ring_buffer_consume - return an event and consume it
+     * @cpu: the shard to read
+     */
+    pub fn ring_buffer_consume(&self, cpu: usize) -> Option<RingBufferEvent> {
+        let cpu_buffer = self.buffers.get(cpu)?;
+        let mut reader = cpu_buffer.reader_lock.lock().unwrap();
+
+        cpu_buffer.rb_buffer_next(&mut reader, true)
+    }
+
+
+    /**
+     * ring_buffer_peek - peek at the next event to be read
+     * @cpu: the shard to peek at
+     *
+     * This will return the event that will be read next, but does not
+     * consume the data.
+     */
+    pub fn ring_buffer_peek(&self, cpu: usize) -> Option<RingBufferEvent> {
+        let cpu_buffer = self.buffers.get(cpu)?;
+        let mut reader = cpu_buffer.reader_lock.lock().unwrap();
+
+        cpu_buffer.rb_buffer_next(&mut reader, false)
+    }
+
+
+    /**
+     * ring_buffer_read_start - start a non consuming read of the buffer
+     * @cpu: the shard to iterate over
+     *
+     * The iterator starts at the next event to be consumed. Events written
+     * afterwards on the current tail page are seen too.
+     */
+    pub fn ring_buffer_read_start(&self, cpu: usize) -> Result<RingBufferIter<'_>, i32> {
+        let cpu_buffer = self.buffers.get(cpu).ok_or(-EINVAL)?;
+        let reader = cpu_buffer.reader_lock.lock().unwrap();
+        let mut pages = Vec::with_capacity(cpu_buffer.all_pages.len());
+
+        let page = &reader.reader_page.item;
+        pages.push((page.index, page.generation.load(Ordering::Acquire)));
+        cpu_buffer.pages.list_for_each_entry(|page| {
+            pages.push((page.item.index, page.item.generation.load(Ordering::Acquire)));
+        });
+
+        Ok(RingBufferIter {
+            cpu_buffer,
+            pages,
+            idx: 0,
+            off: reader.read,
+            stamp: Some(reader.read_stamp),
+            dropped: false,
+        })
+    }
+
+
+    pub fn ring_buffer_entries_cpu(&self, cpu: usize) -> usize {
+        self.buffers.get(cpu).map_or(0, RingBufferPerCpu::entries)
+    }
+
+
+    pub fn ring_buffer_overrun_cpu(&self, cpu: usize) -> usize {
+        self.buffers.get(cpu).map_or(0, |buffer| buffer.overrun.load(Ordering::Relaxed))
+    }
+
+
+    pub fn ring_buffer_dropped_events_cpu(&self, cpu: usize) -> usize {
+        self.buffers.get(cpu).map_or(0, |buffer| buffer.dropped_events.load(Ordering::Relaxed))
+    }
+
+
+    pub fn ring_buffer_read_events_cpu(&self, cpu: usize) -> usize {
+        self.buffers.get(cpu).map_or(0, |buffer| buffer.read.load(Ordering::Relaxed))
+    }
+
+
+    pub fn ring_buffer_empty_cpu(&self, cpu: usize) -> bool {
+        self.ring_buffer_peek(cpu).is_none()
+    }
+
+
+    /**
+     * ring_buffer_entries - get the number of entries in a buffer
+     *
+     * Returns the total number of entries in the ring buffer (all shards)
+     */
+    pub fn ring_buffer_entries(&self) -> usize {
+        self.buffers.iter().map(RingBufferPerCpu::entries).sum()
+    }
+
+
+    /**
+     * ring_buffer_overruns - get the number of overruns in buffer
+     */
+    pub fn ring_buffer_overruns(&self) -> usize {
+        self.buffers.iter().map(|buffer| buffer.overrun.load(Ordering::Relaxed)).sum()
+    }
+}
+
+
+/**
+ * struct RingBufferWriter - the writer of one shard
+ *
+ * There is at most one writer per shard, it is released when dropped.
+ */
+pub struct RingBufferWriter<'a> {
+    buffer: &'a TraceBuffer,
+    cpu_buffer: &'a RingBufferPerCpu,
+}
+
+#[allow(dead_code)]
+impl RingBufferWriter<'_> {
+    /**
+     * ring_buffer_write - write data to the buffer without reserving
+     * @data: the payload of the event
+     *
+     * Return: -E2BIG if @data does not fit in a page, -EBUSY if the buffer
+     * is full and not in overwrite mode.
+     */
+    pub fn ring_buffer_write(&mut self, data: &[u8]) -> Result<(), i32> {
+        let cpu_buffer = self.cpu_buffer;
+        let w = unsafe { &mut *cpu_buffer.writer.get() };
+
+        if data.len() > BUF_MAX_DATA_SIZE {
+            return Err(-E2BIG);
+        }
+
+        let ts = (self.buffer.clock)();
+        let mut delta = ts.saturating_sub(w.write_stamp);
+        let mut words = rb_event_words(data.len());
+        if w.write != 0 && delta > TS_MASK {
+            words += RB_TIME_EXTEND_WORDS;
+        }
+
+        if w.write + words > BUF_PAGE_WORDS {
+            cpu_buffer.rb_move_tail(w, self.buffer.flags & RB_FL_OVERWRITE != 0)?;
+        }
+
+        let page = &w.tail.item;
+        if w.write == 0 {
+            /* The first event of a page is stamped by the page itself */
+            page.time_stamp.store(ts, Ordering::Relaxed);
+            delta = 0;
+        } else if delta > TS_MASK {
+            page.rb_write_time_extend(w.write, delta);
+            w.write += RB_TIME_EXTEND_WORDS;
+            delta = 0;
+        }
+
+        page.rb_write_event(w.write, delta, data);
+        w.write += rb_event_words(data.len());
+        w.write_stamp = ts;
+
+        page.entries.fetch_add(1, Ordering::Relaxed);
+        page.commit.store(w.write, Ordering::Release);
+        cpu_buffer.entries.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+}
+
+impl Drop for RingBufferWriter<'_> {
+    fn drop(&mut self) {
+        self.cpu_buffer.writer_claimed.store(false, Ordering::Release);
+    }
+}
+
+
+/**
+ * struct RingBufferIter - non consuming iterator over a shard
+ * @pages: index and generation of the pages, in age order, when started
+ * @idx: position in @pages
+ * @off: word offset in the current page
+ * @stamp: time stamp of the last event, %None before reading a new page
+ * @dropped: some events were overwritten before the iterator got to them
+ *
+ * Events the writer overwrites while the iterator runs are skipped.
+ */
+pub struct RingBufferIter<'a> {
+    cpu_buffer: &'a RingBufferPerCpu,
+    pages: Vec<(usize, u64)>,
+    idx: usize,
+    off: usize,
+    stamp: Option<u64>,
+    dropped: bool,
+}
+
+#[allow(dead_code)]
+impl RingBufferIter<'_> {
+    fn rb_iter_next_page(&mut self) {
+        self.idx += 1;
+        self.off = 0;
+        self.stamp = None;
+    }
+
+
+    fn rb_iter_next(&mut self, advance: bool) -> Option<RingBufferEvent> {
+        while let Some(&(index, generation)) = self.pages.get(self.idx) {
+            let page = &self.cpu_buffer.all_pages[index].item;
+
+            if page.generation.load(Ordering::Acquire) != generation {
+                self.dropped = true;
+                self.rb_iter_next_page();
+                continue;
+            }
+            if self.off >= page.commit.load(Ordering::Acquire) {
+                /* The last page may still grow */
+                if self.idx + 1 == self.pages.len() {
+                    return None;
+                }
+                self.rb_iter_next_page();
+                continue;
+            }
+
+            let stamp = self.stamp.unwrap_or_else(|| page.time_stamp.load(Ordering::Relaxed));
+            let entry = page.rb_read_event(self.off);
+            fence(Ordering::Acquire);
+            if page.generation.load(Ordering::Relaxed) != generation {
+                self.dropped = true;
+                self.rb_iter_next_page();
+                continue;
+            }
+
+            match entry {
+                RbEntry::TimeExtend(delta) => {
+                    self.off += RB_TIME_EXTEND_WORDS;
+                    self.stamp = Some(stamp + delta);
+                }
+                RbEntry::Data(words, delta, data) => {
+                    let ts = stamp + delta;
+                    self.stamp = Some(stamp);
+                    if advance {
+                        self.off += words;
+                        self.stamp = Some(ts);
+                    }
+                    return Some(RingBufferEvent { ts, data });
+                }
+                RbEntry::Invalid => unreachable!("ring buffer: corrupted page"),
+            }
+        }
+        None
+    }
+
+
+    /**
+     * ring_buffer_iter_peek - peek at the next event to be read
+     */
+    pub fn ring_buffer_iter_peek(&mut self) -> Option<RingBufferEvent> {
+        self.rb_iter_next(false)
+    }
+
+
+    /**
+     * ring_buffer_iter_advance - advance the iterator to the next location
+     */
+    pub fn ring_buffer_iter_advance(&mut self) {
+        self.rb_iter_next(true);
+    }
+
+
+    /**
+     * ring_buffer_iter_dropped - report if there are dropped events
+     */
+    pub fn ring_buffer_iter_dropped(&self) -> bool {
+        self.dropped
+    }
+}
+
+impl Iterator for RingBufferIter<'_> {
+    type Item = RingBufferEvent;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.rb_iter_next(true)
+    }
+}
+
+
+/* Sections for Unit tests */
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+
+    fn event(seq: u32, len: usize) -> Vec<u8> {
+        let mut data = seq.to_le_bytes().to_vec();
+        data.resize(len.max(4), seq as u8);
+        data
+    }
+
+
+    fn event_seq(event: &RingBufferEvent) -> u32 {
+        u32::from_le_bytes(event.data[..4].try_into().unwrap())
+    }
+
+
+    #[test]
+    fn test_ring_buffer_write_consume() {
+        static NOW: AtomicU64 = AtomicU64::new(1000);
+        let mut buffer = TraceBuffer::ring_buffer_alloc(PAGE_SIZE * 16, 1, RB_FL_OVERWRITE).unwrap();
+        buffer.ring_buffer_set_clock(|| NOW.load(Ordering::Relaxed));
+        let mut writer = buffer.ring_buffer_writer(0).unwrap();
+
+        assert_eq!(buffer.ring_buffer_writer(0).err(), Some(-EBUSY));
+        assert_eq!(buffer.ring_buffer_writer(1).err(), Some(-EINVAL));
+        assert!(buffer.ring_buffer_consume(0).is_none());
+
+        /* Every length, some of them with a delta needing a time extend */
+        let mut expected = Vec::new();
+        for len in 0..300 {
+            NOW.fetch_add(if len % 50 == 0 { 1 << 30 } else { len as u64 }, Ordering::Relaxed);
+            let data = vec![len as u8; len];
+            writer.ring_buffer_write(&data).unwrap();
+            expected.push(RingBufferEvent { ts: NOW.load(Ordering::Relaxed), data });
+        }
+        assert_eq!(buffer.ring_buffer_entries(), 300);
+
+        assert_eq!(buffer.ring_buffer_peek(0).as_ref(), expected.first());
+        for event in expected.iter() {
+            assert_eq!(buffer.ring_buffer_consume(0).as_ref(), Some(event));
+        }
+        assert!(buffer.ring_buffer_consume(0).is_none());
+        assert_eq!(buffer.ring_buffer_entries_cpu(0), 0);
+        assert_eq!(buffer.ring_buffer_read_events_cpu(0), 300);
+
+        assert_eq!(writer.ring_buffer_write(&[0; BUF_MAX_DATA_SIZE + 1]), Err(-E2BIG));
+        writer.ring_buffer_write(&[7; BUF_MAX_DATA_SIZE]).unwrap();
+        assert_eq!(buffer.ring_buffer_consume(0).unwrap().data.len(), BUF_MAX_DATA_SIZE);
+    }
+
+
+    #[test]
+    fn test_ring_buffer_overwrite() {
+        let buffer = TraceBuffer::ring_buffer_alloc(BUF_PAGE_SIZE * 3, 2, RB_FL_OVERWRITE).unwrap();
+        let mut writer = buffer.ring_buffer_writer(1).unwrap();
+
+        for seq in 0..2000 {
+            writer.ring_buffer_write(&event(seq, 24)).unwrap();
+        }
+        let overrun = buffer.ring_buffer_overrun_cpu(1);
+        let entries = buffer.ring_buffer_entries_cpu(1);
+        assert!(overrun > 0);
+        assert_eq!(overrun + entries, 2000);
+        assert_eq!(buffer.ring_buffer_overruns(), overrun);
+        assert_eq!(buffer.ring_buffer_entries_cpu(0), 0);
+
+        /* What is left is the newest events, in order */
+        let events: Vec<_> = std::iter::from_fn(|| buffer.ring_buffer_consume(1)).map(|e| event_seq(&e)).collect();
+        assert_eq!(events.len(), entries);
+        assert!(events.iter().copied().eq(overrun as u32..2000));
+    }
+
+
+    #[test]
+    fn test_ring_buffer_no_overwrite() {
+        let buffer = TraceBuffer::ring_buffer_alloc(BUF_PAGE_SIZE * 2, 1, 0).unwrap();
+        let mut writer = buffer.ring_buffer_writer(0).unwrap();
+
+        let mut written = 0;
+        while writer.ring_buffer_write(&event(written, 100)).is_ok() {
+            written += 1;
+        }
+        assert_eq!(writer.ring_buffer_write(&event(written, 100)), Err(-EBUSY));
+        assert_eq!(buffer.ring_buffer_dropped_events_cpu(0), 2);
+        assert_eq!(buffer.ring_buffer_overrun_cpu(0), 0);
+
+        /* Reading a page frees it up for the writer */
+        let mut next = 0;
+        while next < written / 2 + 1 {
+            assert_eq!(event_seq(&buffer.ring_buffer_consume(0).unwrap()), next);
+            next += 1;
+        }
+        writer.ring_buffer_write(&event(written, 100)).unwrap();
+        let rest: Vec<_> = std::iter::from_fn(|| buffer.ring_buffer_consume(0)).map(|e| event_seq(&e)).collect();
+        assert!(rest.into_iter().eq(next..=written));
+    }
+
+
+    #[test]
+    fn test_ring_buffer_iter() {
+        let buffer = TraceBuffer::ring_buffer_alloc(BUF_PAGE_SIZE * 3, 1, RB_FL_OVERWRITE).unwrap();
+        let mut writer = buffer.ring_buffer_writer(0).unwrap();
+
+        for seq in 0..200 {
+            writer.ring_buffer_write(&event(seq, 40)).unwrap();
+        }
+        for seq in 0..50 {
+            assert_eq!(event_seq(&buffer.ring_buffer_consume(0).unwrap()), seq);
+        }
+
+        /* Iterating does not consume, and starts at the reader */
+        let mut iter = buffer.ring_buffer_read_start(0).unwrap();
+        assert_eq!(iter.ring_buffer_iter_peek().map(|e| event_seq(&e)), Some(50));
+        assert_eq!(iter.ring_buffer_iter_peek().map(|e| event_seq(&e)), Some(50));
+        iter.ring_buffer_iter_advance();
+        assert!(iter.by_ref().map(|e| event_seq(&e)).eq(51..200));
+        assert!(!iter.ring_buffer_iter_dropped());
+
+        /* New events on the tail page show up */
+        writer.ring_buffer_write(&event(200, 40)).unwrap();
+        assert_eq!(iter.next().map(|e| event_seq(&e)), Some(200));
+
+        assert_eq!(buffer.ring_buffer_entries_cpu(0), 151);
+        let events: Vec<_> = std::iter::from_fn(|| buffer.ring_buffer_consume(0)).collect();
+        let iterated: Vec<_> = buffer.ring_buffer_read_start(0).unwrap().collect();
+        assert_eq!(events.len(), 151);
+        assert!(iterated.is_empty());
+    }
+
+
+    #[test]
+    fn test_ring_buffer_iter_overwritten() {
+        let buffer = TraceBuffer::ring_buffer_alloc(BUF_PAGE_SIZE * 2, 1, RB_FL_OVERWRITE).unwrap();
+        let mut writer = buffer.ring_buffer_writer(0).unwrap();
+
+        for seq in 0..300 {
+            writer.ring_buffer_write(&event(seq, 32)).unwrap();
+        }
+        let mut iter = buffer.ring_buffer_read_start(0).unwrap();
+        let first = event_seq(&iter.next().unwrap());
+
+        /* Lap the iterator, it skips what it lost and stays in order */
+        for seq in 300..600 {
+            writer.ring_buffer_write(&event(seq, 32)).unwrap();
+        }
+        let mut prev = first;
+        for event in iter.by_ref() {
+            assert!(event_seq(&event) > prev);
+            prev = event_seq(&event);
+        }
+        assert!(iter.ring_buffer_iter_dropped());
+    }
+
+
+    #[test]
+    fn test_ring_buffer_concurrent_recycle() {
+        const EVENTS: u32 = 100000;
+        let buffer = TraceBuffer::ring_buffer_alloc(BUF_PAGE_SIZE * 2, 1, RB_FL_OVERWRITE).unwrap();
+        let mut writer = buffer.ring_buffer_writer(0).unwrap();
+
+        /* With two pages the writer recycles the page the reader is after */
+        thread::scope(|s| {
+            s.spawn(move || {
+                for seq in 0..EVENTS {
+                    writer.ring_buffer_write(&event(seq, 200)).unwrap();
+                    if seq % 8 == 0 {
+                        thread::yield_now();
+                    }
+                }
+            });
+
+            let mut last = None;
+            while last != Some(EVENTS - 1) {
+                match buffer.ring_buffer_consume(0) {
+                    Some(event) => {
+                        let seq = event_seq(&event);
+                        assert!(last.is_none_or(|last| seq > last), "{seq} after {last:?}");
+                        last = Some(seq);
+                    }
+                    None => thread::yield_now(),
+                }
+            }
+        });
+
+        assert_eq!(
+            buffer.ring_buffer_read_events_cpu(0) + buffer.ring_buffer_overrun_cpu(0),
+            EVENTS as usize
+        );
+    }
+
+
+    #[test]
+    fn test_ring_buffer_concurrent() {
+        const EVENTS: u32 = 20000;
+        let buffer = TraceBuffer::ring_buffer_alloc(BUF_PAGE_SIZE * 4, 2, RB_FL_OVERWRITE).unwrap();
+
+        thread::scope(|s| {
+            for cpu in 0..2 {
+                let mut writer = buffer.ring_buffer_writer(cpu).unwrap();
+                s.spawn(move || {
+                    for seq in 0..EVENTS {
+                        writer.ring_buffer_write(&event(seq, 8 + seq as usize % 64)).unwrap();
+                        if seq % 256 == 0 {
+                            thread::yield_now();
+                        }
+                    }
+                });
+            }
+
+            /* One consumer per shard and a non consuming reader */
+            for cpu in 0..2 {
+                let buffer = &buffer;
+                s.spawn(move || {
+                    let mut last = None;
+                    let mut last_ts = 0;
+                    let mut consumed = 0;
+                    while last != Some(EVENTS - 1) {
+                        match buffer.ring_buffer_consume(cpu) {
+                            Some(event) => {
+                                let seq = event_seq(&event);
+                                assert!(last.is_none_or(|last| seq > last));
+                                assert!(event.ts >= last_ts);
+                                assert_eq!(event.data.len(), 8 + seq as usize % 64);
+                                last = Some(seq);
+                                last_ts = event.ts;
+                                consumed += 1;
+                            }
+                            None => thread::yield_now(),
+                        }
+                    }
+                    assert!(consumed > 0);
+                });
+            }
+            s.spawn(|| {
+                for _ in 0..50 {
+                    let mut prev = None;
+                    for event in buffer.ring_buffer_read_start(0).unwrap() {
+                        assert!(prev.is_none_or(|prev| event_seq(&event) > prev));
+                        prev = Some(event_seq(&event));
+                    }
+                    thread::yield_now();
+                }
+            });
+        });
+
+        for cpu in 0..2 {
+            assert_eq!(buffer.ring_buffer_entries_cpu(cpu), 0);
+            assert_eq!(
+                buffer.ring_buffer_read_events_cpu(cpu) + buffer.ring_buffer_overrun_cpu(cpu),
+                EVENTS as usize
+            );
+        }
+    }
+}
@@ -0,0 +1,768 @@
+use std::collections::VecDeque;
+use std::ops::Deref;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, Thread};
+
+use rand::Rng;
+
+use crate::bitmap::BITS_PER_LONG;
+use crate::errno::EINVAL;
+
+/* Number of wait queues an sbitmap_queue spreads its waiters over */
+pub const SBQ_WAIT_QUEUES: usize = 8;
+/* Upper bound of the number of bits freed before waking up waiters */
+pub const SBQ_WAKE_BATCH: u32 = 8;
+
+
+#[repr(align(64))]
+struct CachelineAligned<T>(T);
+
+impl<T> Deref for CachelineAligned<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+
+/* Index of the per-thread allocation hint, the userspace stand-in for the CPU id */
+fn sbitmap_hint_slot(nr_slots: usize) -> usize {
+    static NEXT_SLOT: AtomicUsize = AtomicUsize::new(0);
+    thread_local! {
+        static SLOT: usize = NEXT_SLOT.fetch_add(1, Ordering::Relaxed);
+    }
+    SLOT.with(|slot| *slot % nr_slots)
+}
+
+
+/**
+ * struct SbitmapWord - Word in a &struct sbitmap.
+ * @word: word holding free bits
+ * @cleared: word holding cleared bits
+ *
+ * Bits freed with sbitmap_deferred_clear_bit() are collected in @cleared
+ * and only moved back to @word when an allocation runs out of free bits,
+ * so frees don't bounce the cacheline allocators are hammering on.
+ */
+#[repr(align(64))]
+struct SbitmapWord {
+    word: AtomicUsize,
+    cleared: CachelineAligned<AtomicUsize>,
+}
+
+
+/**
+ * struct Sbitmap - Scalable bitmap.
+ *
+ * A &struct sbitmap is spread over multiple cachelines to avoid ping-pong.
+ * This trades off higher memory usage for better scalability.
+ */
+pub struct Sbitmap {
+    /* Number of bits used in the whole bitmap */
+    depth: u32,
+    /* log2(number of bits used per word) */
+    shift: u32,
+    /* Allocation is done in round robin order instead of per-thread hints */
+    round_robin: bool,
+    map: Box<[SbitmapWord]>,
+    /* Cache of last successfully allocated or freed bit, one per thread slot */
+    alloc_hint: Box<[CachelineAligned<AtomicU32>]>,
+}
+
+#[allow(dead_code)]
+impl Sbitmap {
+    /**
+     * sbitmap_calculate_shift - calculate the shift for a bitmap of @depth bits
+     *
+     * If the bitmap is small, shrink the number of bits per word so we
+     * spread over a few cachelines, at least. If less than 4 bits, just
+     * forget about it, it's not going to work optimally anyway.
+     */
+    pub fn sbitmap_calculate_shift(depth: u32) -> u32 {
+        let mut shift = BITS_PER_LONG.ilog2();
+
+        if depth >= 4 {
+            while (4u32 << shift) > depth {
+                shift -= 1;
+            }
+        }
+        shift
+    }
+
+
+    /**
+     * sbitmap_init_node() - Initialize a &struct sbitmap.
+     * @depth: Number of bits to allocate.
+     * @shift: Use 2^@shift bits per word in the bitmap; if %None, a good
+     *         default is chosen.
+     * @round_robin: If true, be stricter about allocation order; always
+     *               allocate starting from the last allocated bit. This
+     *               is less efficient than the default behavior (false).
+     *
+     * Return: the bitmap on success or -EINVAL if @shift is too big.
+     */
+    pub fn sbitmap_init_node(depth: u32, shift: Option<u32>, round_robin: bool) -> Result<Self, i32> {
+        let shift = shift.unwrap_or_else(|| Sbitmap::sbitmap_calculate_shift(depth));
+        if shift > BITS_PER_LONG.ilog2() {
+            return Err(-EINVAL);
+        }
+
+        let map_nr = depth.div_ceil(1 << shift) as usize;
+        let nr_slots = thread::available_parallelism().map_or(1, |n| n.get());
+
+        Ok(Sbitmap {
+            depth,
+            shift,
+            round_robin,
+            map: (0..map_nr)
+                .map(|_| SbitmapWord {
+                    word: AtomicUsize::new(0),
+                    cleared: CachelineAligned(AtomicUsize::new(0)),
+                })
+                .collect(),
+            alloc_hint: (0..nr_slots)
+                .map(|_| CachelineAligned(AtomicU32::new(if depth > 0 { rand::thread_rng().gen_range(0..depth) } else { 0 })))
+                .collect(),
+        })
+    }
+
+
+    pub fn depth(&self) -> u32 {
+        self.depth
+    }
+
+
+    fn this_hint(&self) -> &AtomicU32 {
+        &self.alloc_hint[sbitmap_hint_slot(self.alloc_hint.len())]
+    }
+
+
+    fn nr_to_index(&self, bitnr: u32) -> usize {
+        (bitnr >> self.shift) as usize
+    }
+
+
+    fn nr_to_bit(&self, bitnr: u32) -> u32 {
+        bitnr & ((1 << self.shift) - 1)
+    }
+
+
+    /* Number of valid bits in the word at @index */
+    fn map_depth(&self, index: usize) -> u32 {
+        if index == self.map.len() - 1 {
+            self.depth - ((index as u32) << self.shift)
+        } else {
+            1 << self.shift
+        }
+    }
+
+
+    /* Move the bits of @cleared back to @word, return false if there were none */
+    fn sbitmap_deferred_clear(map: &SbitmapWord) -> bool {
+        if map.cleared.load(Ordering::Relaxed) == 0 {
+            return false;
+        }
+
+        let mask = map.cleared.swap(0, Ordering::Acquire);
+        map.word.fetch_and(!mask, Ordering::Release);
+        true
+    }
+
+
+    fn __sbitmap_get_word(word: &AtomicUsize, depth: u32, mut hint: u32, wrap: bool) -> Option<u32> {
+        /* don't wrap if starting from 0 */
+        let wrap = wrap && hint != 0;
+
+        loop {
+            /* find_next_zero_bit(), bits past @depth are never set */
+            let free = (!word.load(Ordering::Relaxed)).checked_shr(hint).unwrap_or(0);
+            let nr = if hint >= depth || free == 0 { depth } else { (hint + free.trailing_zeros()).min(depth) };
+
+            if nr >= depth {
+                if hint != 0 && wrap {
+                    hint = 0;
+                    continue;
+                }
+                return None;
+            }
+
+            /* test_and_set_bit_lock() */
+            if word.fetch_or(1 << nr, Ordering::Acquire) & (1 << nr) == 0 {
+                return Some(nr);
+            }
+
+            hint = nr + 1;
+            if hint >= depth - 1 {
+                hint = 0;
+            }
+        }
+    }
+
+
+    fn sbitmap_find_bit_in_word(map: &SbitmapWord, depth: u32, alloc_hint: u32, wrap: bool) -> Option<u32> {
+        loop {
+            let nr = Sbitmap::__sbitmap_get_word(&map.word, depth, alloc_hint, wrap);
+            if nr.is_some() || !Sbitmap::sbitmap_deferred_clear(map) {
+                return nr;
+            }
+        }
+    }
+
+
+    fn sbitmap_find_bit(&self, mut index: usize, mut alloc_hint: u32, wrap: bool) -> Option<u32> {
+        for _ in 0..self.map.len() {
+            if let Some(nr) = Sbitmap::sbitmap_find_bit_in_word(&self.map[index], self.map_depth(index), alloc_hint, wrap) {
+                return Some(nr + ((index as u32) << self.shift));
+            }
+
+            /* Jump to next index. */
+            alloc_hint = 0;
+            index += 1;
+            if index >= self.map.len() {
+                index = 0;
+            }
+        }
+        None
+    }
+
+
+    /**
+     * sbitmap_get() - Try to allocate a free bit from a &struct sbitmap.
+     *
+     * Return: Non-negative allocated bit number if successful, %None otherwise.
+     */
+    pub fn sbitmap_get(&self) -> Option<u32> {
+        if self.depth == 0 {
+            return None;
+        }
+
+        /* update_alloc_hint_before_get() */
+        let hint_slot = self.this_hint();
+        let mut hint = hint_slot.load(Ordering::Relaxed);
+        if hint >= self.depth {
+            hint = rand::thread_rng().gen_range(0..self.depth);
+            hint_slot.store(hint, Ordering::Relaxed);
+        }
+
+        /*
+         * Unless we're doing round robin tag allocation, just use the
+         * alloc_hint to find the right word index. No point in looping
+         * twice in find_next_zero_bit() for that case.
+         */
+        let index = self.nr_to_index(hint);
+        let word_hint = if self.round_robin { self.nr_to_bit(hint) } else { 0 };
+        let nr = self.sbitmap_find_bit(index, word_hint, !self.round_robin);
+
+        /* update_alloc_hint_after_get() */
+        match nr {
+            /* If the map is full, a hint won't do us much good. */
+            None => hint_slot.store(0, Ordering::Relaxed),
+            /* Only update the hint if we used it. */
+            Some(nr) if nr == hint || self.round_robin => {
+                let next = if nr + 1 >= self.depth - 1 { 0 } else { nr + 1 };
+                hint_slot.store(next, Ordering::Relaxed);
+            }
+            Some(_) => {}
+        }
+        nr
+    }
+
+
+    /**
+     * sbitmap_test_bit - check whether @bitnr is allocated
+     */
+    pub fn sbitmap_test_bit(&self, bitnr: u32) -> bool {
+        let map = &self.map[self.nr_to_index(bitnr)];
+        let mask = 1 << self.nr_to_bit(bitnr);
+
+        map.word.load(Ordering::Relaxed) & !map.cleared.load(Ordering::Relaxed) & mask != 0
+    }
+
+
+    /**
+     * sbitmap_clear_bit - free @bitnr right away
+     */
+    pub fn sbitmap_clear_bit(&self, bitnr: u32) {
+        let map = &self.map[self.nr_to_index(bitnr)];
+        map.word.fetch_and(!(1 << self.nr_to_bit(bitnr)), Ordering::Release);
+    }
+
+
+    /**
+     * sbitmap_deferred_clear_bit - free @bitnr the next time its word runs out
+     *
+     * This one is special, since it doesn't actually clear the bit, rather it
+     * sets the corresponding bit in the ->cleared mask instead. Paired with
+     * the caller doing sbitmap_deferred_clear() if a given index is full,
+     * which will clear the previously freed entries in the corresponding
+     * ->word.
+     */
+    pub fn sbitmap_deferred_clear_bit(&self, bitnr: u32) {
+        let map = &self.map[self.nr_to_index(bitnr)];
+        map.cleared.fetch_or(1 << self.nr_to_bit(bitnr), Ordering::Release);
+    }
+
+
+    /* Pass on @tag to the current thread's next allocation */
+    fn sbitmap_update_cpu_hint(&self, tag: u32) {
+        if !self.round_robin && tag < self.depth {
+            self.this_hint().store(tag, Ordering::Relaxed);
+        }
+    }
+
+
+    /**
+     * sbitmap_any_bit_set() - Check for a set bit in a &struct sbitmap.
+     *
+     * Return: true if any bit in the bitmap is set, false otherwise.
+     */
+    pub fn sbitmap_any_bit_set(&self) -> bool {
+        self.map
+            .iter()
+            .any(|map| map.word.load(Ordering::Relaxed) & !map.cleared.load(Ordering::Relaxed) != 0)
+    }
+
+
+    /**
+     * sbitmap_weight() - Return how many set and not cleared bits in a
+     * &struct sbitmap.
+     */
+    pub fn sbitmap_weight(&self) -> u32 {
+        self.map
+            .iter()
+            .map(|map| (map.word.load(Ordering::Relaxed) & !map.cleared.load(Ordering::Relaxed)).count_ones())
+            .sum()
+    }
+}
+
+
+struct SbqWaiter {
+    thread: Thread,
+    woken: AtomicBool,
+}
+
+
+/**
+ * struct SbqWaitState - Wait queue in a &struct sbitmap_queue.
+ */
+struct SbqWaitState {
+    wait: Mutex<VecDeque<Arc<SbqWaiter>>>,
+}
+
+impl SbqWaitState {
+    /* wake_up_nr(), returns how many waiters were woken */
+    fn wake_up_nr(&self, nr: u32) -> u32 {
+        let mut wait = self.wait.lock().unwrap();
+        let mut woken = 0;
+
+        while woken < nr {
+            let Some(waiter) = wait.pop_front() else {
+                break;
+            };
+            waiter.woken.store(true, Ordering::Release);
+            waiter.thread.unpark();
+            woken += 1;
+        }
+        woken
+    }
+}
+
+
+/**
+ * struct SbitmapQueue - Scalable bitmap with the added ability to wait on
+ * free bits.
+ *
+ * A &struct sbitmap_queue uses multiple wait queues and rolling wakeups to
+ * avoid contention on the wait queue spinlock. This ensures that we don't
+ * hammer on one wait queue while other wait queues are not being utilized.
+ */
+pub struct SbitmapQueue {
+    sb: Sbitmap,
+    /* Number of bits which must be freed before we wake up any waiters */
+    wake_batch: AtomicU32,
+    /* Next wait queue in @ws to wake up */
+    wake_index: AtomicUsize,
+    /* Next wait queue in @ws to sleep on */
+    wait_index: AtomicUsize,
+    ws: [SbqWaitState; SBQ_WAIT_QUEUES],
+    /* Count of currently active ws waitqueues */
+    ws_active: AtomicUsize,
+    /* Number of bits cleared */
+    completion_cnt: AtomicU32,
+    /* Number of thread wake ups issued */
+    wakeup_cnt: AtomicU32,
+}
+
+#[allow(dead_code)]
+impl SbitmapQueue {
+    /**
+     * sbitmap_queue_init_node() - Initialize a &struct sbitmap_queue.
+     * @depth: See sbitmap_init_node().
+     * @shift: See sbitmap_init_node().
+     * @round_robin: See sbitmap_init_node().
+     *
+     * Return: the queue on success or negative errno on failure.
+     */
+    pub fn sbitmap_queue_init_node(depth: u32, shift: Option<u32>, round_robin: bool) -> Result<Self, i32> {
+        let sb = Sbitmap::sbitmap_init_node(depth, shift, round_robin)?;
+
+        Ok(SbitmapQueue {
+            wake_batch: AtomicU32::new(SbitmapQueue::sbq_calc_wake_batch(depth)),
+            sb,
+            wake_index: AtomicUsize::new(0),
+            wait_index: AtomicUsize::new(0),
+            ws: std::array::from_fn(|_| SbqWaitState { wait: Mutex::new(VecDeque::new()) }),
+            ws_active: AtomicUsize::new(0),
+            completion_cnt: AtomicU32::new(0),
+            wakeup_cnt: AtomicU32::new(0),
+        })
+    }
+
+
+    /*
+     * For each batch, we wake up one queue. We need to make sure that our
+     * batch size is small enough that the full depth of the bitmap is
+     * enough to wake up all of the queues.
+     */
+    fn sbq_calc_wake_batch(depth: u32) -> u32 {
+        (depth / SBQ_WAIT_QUEUES as u32).clamp(1, SBQ_WAKE_BATCH)
+    }
+
+
+    pub fn sb(&self) -> &Sbitmap {
+        &self.sb
+    }
+
+
+    pub fn wake_batch(&self) -> u32 {
+        self.wake_batch.load(Ordering::Relaxed)
+    }
+
+
+    /**
+     * sbitmap_queue_get() - Try to allocate a free bit from a &struct
+     * sbitmap_queue, without blocking.
+     *
+     * Return: Non-negative allocated bit number if successful, %None otherwise.
+     */
+    pub fn sbitmap_queue_get(&self) -> Option<u32> {
+        self.sb.sbitmap_get()
+    }
+
+
+    /* sbq_wait_ptr() - Get the next wait queue to use for a waiter */
+    fn sbq_wait_ptr(&self) -> &SbqWaitState {
+        &self.ws[self.wait_index.fetch_add(1, Ordering::Relaxed) % SBQ_WAIT_QUEUES]
+    }
+
+
+    /*
+     * Wrapper around prepare_to_wait_exclusive(), which maintains some extra
+     * internal state.
+     */
+    fn sbitmap_prepare_to_wait(&self, ws: &SbqWaitState, waiter: &Arc<SbqWaiter>) {
+        self.ws_active.fetch_add(1, Ordering::SeqCst);
+        ws.wait.lock().unwrap().push_back(waiter.clone());
+    }
+
+
+    /*
+     * Wrapper around finish_wait(), which maintains some extra internal
+     * state.
+     */
+    fn sbitmap_finish_wait(&self, ws: &SbqWaitState, waiter: &Arc<SbqWaiter>) {
+        ws.wait.lock().unwrap().retain(|w| !Arc::ptr_eq(w, waiter));
+        self.ws_active.fetch_sub(1, Ordering::SeqCst);
+    }
+
+
+    /**
+     * sbitmap_queue_get_wait() - Allocate a free bit, sleeping until one is
+     * freed if all of them are in use.
+     *
+     * Return: the allocated bit number.
+     */
+    pub fn sbitmap_queue_get_wait(&self) -> u32 {
+        if let Some(nr) = self.sbitmap_queue_get() {
+            return nr;
+        }
+
+        loop {
+            let ws = self.sbq_wait_ptr();
+            let waiter = Arc::new(SbqWaiter {
+                thread: thread::current(),
+                woken: AtomicBool::new(false),
+            });
+
+            self.sbitmap_prepare_to_wait(ws, &waiter);
+
+            /* Retry once we are visible to wakers, a bit may have been freed meanwhile */
+            if let Some(nr) = self.sbitmap_queue_get() {
+                self.sbitmap_finish_wait(ws, &waiter);
+                return nr;
+            }
+
+            while !waiter.woken.load(Ordering::Acquire) {
+                thread::park();
+            }
+            self.sbitmap_finish_wait(ws, &waiter);
+
+            if let Some(nr) = self.sbitmap_queue_get() {
+                return nr;
+            }
+        }
+    }
+
+
+    fn __sbitmap_queue_wake_up(&self, mut nr: u32) {
+        if self.ws_active.load(Ordering::SeqCst) == 0 {
+            return;
+        }
+
+        let mut wake_index = self.wake_index.load(Ordering::Relaxed);
+        for _ in 0..SBQ_WAIT_QUEUES {
+            let ws = &self.ws[wake_index];
+
+            /*
+             * Advance the index before checking the current queue.
+             * It improves fairness, by ensuring the queue doesn't
+             * need to be fully emptied before trying to wake up
+             * from the next one.
+             */
+            wake_index = (wake_index + 1) % SBQ_WAIT_QUEUES;
+
+            let woken = ws.wake_up_nr(nr);
+            if woken == nr {
+                break;
+            }
+            nr -= woken;
+        }
+
+        if wake_index != self.wake_index.load(Ordering::Relaxed) {
+            self.wake_index.store(wake_index, Ordering::Relaxed);
+        }
+    }
+
+
+    /**
+     * sbitmap_queue_wake_up() - Wake up some of waiters in one waitqueue
+     * on a &struct sbitmap_queue.
+     * @nr: Number of bits cleared.
+     */
+    pub fn sbitmap_queue_wake_up(&self, nr: u32) {
+        let wake_batch = self.wake_batch.load(Ordering::Relaxed);
+
+        if self.ws_active.load(Ordering::SeqCst) == 0 {
+            return;
+        }
+
+        self.completion_cnt.fetch_add(nr, Ordering::SeqCst);
+        let mut wakeups = self.wakeup_cnt.load(Ordering::SeqCst);
+
+        loop {
+            if self.completion_cnt.load(Ordering::SeqCst).wrapping_sub(wakeups) < wake_batch {
+                return;
+            }
+            match self.wakeup_cnt.compare_exchange(wakeups, wakeups.wrapping_add(wake_batch), Ordering::SeqCst, Ordering::SeqCst) {
+                Ok(_) => break,
+                Err(cur) => wakeups = cur,
+            }
+        }
+
+        self.__sbitmap_queue_wake_up(wake_batch);
+    }
+
+
+    /**
+     * sbitmap_queue_clear() - Free an allocated bit and wake up waiters on a
+     * &struct sbitmap_queue.
+     * @nr: Bit number to free.
+     */
+    pub fn sbitmap_queue_clear(&self, nr: u32) {
+        /*
+         * Once the clear bit is set, the bit may be allocated out.
+         *
+         * Orders READ/WRITE on the associated instance (such as request
+         * of blk_mq) by this bit for avoiding race with re-allocation,
+         * and its pair is the memory barrier implied in __sbitmap_get_word.
+         *
+         * One invariant is that the clear bit has to be zero when the bit
+         * is in use.
+         */
+        self.sb.sbitmap_deferred_clear_bit(nr);
+
+        /*
+         * Pairs with the memory barrier in sbitmap_prepare_to_wait() to
+         * ensure that a waiter either sees the bit free or gets woken up.
+         */
+        std::sync::atomic::fence(Ordering::SeqCst);
+        self.sbitmap_queue_wake_up(1);
+        self.sb.sbitmap_update_cpu_hint(nr);
+    }
+
+
+    /**
+     * sbitmap_queue_wake_all() - Wake up everything waiting on a &struct
+     * sbitmap_queue.
+     */
+    pub fn sbitmap_queue_wake_all(&self) {
+        let mut wake_index = self.wake_index.load(Ordering::Relaxed);
+
+        for _ in 0..SBQ_WAIT_QUEUES {
+            self.ws[wake_index].wake_up_nr(u32::MAX);
+            wake_index = (wake_index + 1) % SBQ_WAIT_QUEUES;
+        }
+    }
+}
+
+
+/* Sections for Unit tests */
+#[cfg(test)]
+mod tests {
+    use crate::bench::{bench_run, bench_threads};
+    use crate::bitmap::{Bitmap, BitmapOps};
+
+    use super::*;
+
+    #[test]
+    fn test_sbitmap_init() {
+        let sb = Sbitmap::sbitmap_init_node(100, None, false).unwrap();
+        assert_eq!(sb.shift, 4);
+        assert_eq!(sb.map.len(), 7);
+        assert_eq!(sb.map_depth(6), 4);
+        assert_eq!(std::mem::align_of::<SbitmapWord>(), 64);
+
+        assert_eq!(Sbitmap::sbitmap_calculate_shift(2), BITS_PER_LONG.ilog2());
+        assert!(Sbitmap::sbitmap_init_node(64, Some(BITS_PER_LONG.ilog2() + 1), false).is_err());
+        assert_eq!(Sbitmap::sbitmap_init_node(0, None, false).unwrap().sbitmap_get(), None);
+    }
+
+
+    #[test]
+    fn test_sbitmap_get_all() {
+        for round_robin in [false, true] {
+            let sb = Sbitmap::sbitmap_init_node(100, None, round_robin).unwrap();
+            let mut seen = Bitmap::bitmap_zalloc(100);
+
+            for _ in 0..100 {
+                let nr = sb.sbitmap_get().unwrap();
+                assert!(!seen.test_and_set_bit(nr as usize));
+                assert!(sb.sbitmap_test_bit(nr));
+            }
+            assert_eq!(sb.sbitmap_get(), None);
+            assert_eq!(sb.sbitmap_weight(), 100);
+
+            sb.sbitmap_clear_bit(42);
+            assert_eq!(sb.sbitmap_get(), Some(42));
+        }
+    }
+
+
+    #[test]
+    fn test_sbitmap_deferred_clear() {
+        let sb = Sbitmap::sbitmap_init_node(8, Some(2), false).unwrap();
+
+        for _ in 0..8 {
+            sb.sbitmap_get().unwrap();
+        }
+        sb.sbitmap_deferred_clear_bit(5);
+        assert!(!sb.sbitmap_test_bit(5));
+        assert_eq!(sb.sbitmap_weight(), 7);
+
+        /* The word runs out and picks up the cleared bit */
+        assert_eq!(sb.sbitmap_get(), Some(5));
+        assert_eq!(sb.sbitmap_get(), None);
+        assert!(sb.sbitmap_any_bit_set());
+    }
+
+
+    #[test]
+    fn test_sbitmap_queue_wake_batch() {
+        let sbq = SbitmapQueue::sbitmap_queue_init_node(4, None, false).unwrap();
+        assert_eq!(sbq.wake_batch(), 1);
+        assert_eq!(SbitmapQueue::sbitmap_queue_init_node(1024, None, false).unwrap().wake_batch(), SBQ_WAKE_BATCH);
+
+        let tags: Vec<u32> = (0..4).map(|_| sbq.sbitmap_queue_get().unwrap()).collect();
+        assert_eq!(sbq.sbitmap_queue_get(), None);
+
+        let sbq = Arc::new(sbq);
+        let waiter = {
+            let sbq = sbq.clone();
+            thread::spawn(move || sbq.sbitmap_queue_get_wait())
+        };
+        while sbq.ws_active.load(Ordering::SeqCst) == 0 {
+            thread::yield_now();
+        }
+
+        sbq.sbitmap_queue_clear(tags[2]);
+        assert_eq!(waiter.join().unwrap(), tags[2]);
+    }
+
+
+    #[test]
+    fn test_sbitmap_queue_concurrent() {
+        const THREADS: usize = 6;
+        const LOOPS: usize = 500;
+        let sbq = Arc::new(SbitmapQueue::sbitmap_queue_init_node(4, None, false).unwrap());
+        let in_use = Arc::new(Mutex::new(Bitmap::bitmap_zalloc(4)));
+
+        let threads: Vec<_> = (0..THREADS)
+            .map(|_| {
+                let sbq = sbq.clone();
+                let in_use = in_use.clone();
+                thread::spawn(move || {
+                    for _ in 0..LOOPS {
+                        let tag = sbq.sbitmap_queue_get_wait();
+                        assert!(!in_use.lock().unwrap().test_and_set_bit(tag as usize));
+                        thread::yield_now();
+                        assert!(in_use.lock().unwrap().test_and_clear_bit(tag as usize));
+                        sbq.sbitmap_queue_clear(tag);
+                    }
+                })
+            })
+            .collect();
+
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        assert_eq!(sbq.sb().sbitmap_weight(), 0);
+        assert_eq!(sbq.ws_active.load(Ordering::SeqCst), 0);
+    }
+
+
+    /* Run with `cargo test --release -- --ignored --nocapture bench_sbitmap` */
+    #[test]
+    #[ignore]
+    fn bench_sbitmap_vs_mutex_bitmap() {
+        const DEPTH: u32 = 256;
+        const LOOPS: usize = 200_000;
+        let threads = bench_threads();
+
+        let sbq = Arc::new(SbitmapQueue::sbitmap_queue_init_node(DEPTH, None, false).unwrap());
+        let sbitmap_time = bench_run(threads, move || {
+            for _ in 0..LOOPS {
+                let tag = sbq.sbitmap_queue_get_wait();
+                sbq.sbitmap_queue_clear(tag);
+            }
+        });
+
+        let map = Arc::new(Mutex::new(Bitmap::bitmap_zalloc(DEPTH as usize)));
+        let mutex_time = bench_run(threads, move || {
+            for _ in 0..LOOPS {
+                let tag = {
+                    let mut map = map.lock().unwrap();
+                    let tag = map.find_first_zero_bit();
+                    map.set_bit(tag);
+                    tag
+                };
+                map.lock().unwrap().clear_bit(tag);
+            }
+        });
+
+        println!("{} threads x {} get/put on {} bits", threads, LOOPS, DEPTH);
+        println!("sbitmap_queue: {:?}", sbitmap_time);
+        println!("Mutex<Bitmap>: {:?}", mutex_time);
+    }
+}
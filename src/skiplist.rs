@@ -0,0 +1,505 @@
+use std::borrow::Borrow;
+use std::marker::PhantomData;
+use std::mem::MaybeUninit;
+use std::ops::{Bound, RangeBounds};
+use std::ptr;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+/* Should be enough for 2^64 elements */
+pub const SKIPLIST_MAXLEVEL: usize = 32;
+
+
+/**
+ * skiplist_random_level - turn random bits into a node level
+ * @bits: uniformly distributed random bits
+ *
+ * Every pair of low zero bits promotes the node one level up, so a node
+ * reaches level n + 1 with probability 1/4 once it reaches level n.
+ */
+pub fn skiplist_random_level(bits: u64) -> usize {
+    (1 + bits.trailing_zeros() as usize / 2).min(SKIPLIST_MAXLEVEL)
+}
+
+
+struct SkipNode<T> {
+    item: MaybeUninit<T>,
+    /* Level 0 predecessor, null for the first node */
+    prev: *mut SkipNode<T>,
+    next: Box<[*mut SkipNode<T>]>,
+}
+
+
+/**
+ * struct SkipList - probabilistic ordered set
+ *
+ * Elements are kept sorted in a level 0 doubly linked list, with sparser
+ * forward lists on top of it for O(log n) expected search. Levels are
+ * drawn from a seedable generator, so the same seed and the same sequence
+ * of operations always build the same structure.
+ */
+pub struct SkipList<T> {
+    /* Sentinel whose item is never initialized */
+    head: Box<SkipNode<T>>,
+    tail: *mut SkipNode<T>,
+    level: usize,
+    len: usize,
+    rng: StdRng,
+}
+
+unsafe impl<T: Send> Send for SkipList<T> {}
+unsafe impl<T: Sync> Sync for SkipList<T> {}
+
+impl<T: Ord> Default for SkipList<T> {
+    fn default() -> Self {
+        SkipList::new()
+    }
+}
+
+#[allow(dead_code)]
+impl<T: Ord> SkipList<T> {
+    pub fn new() -> Self {
+        SkipList::with_seed(rand::thread_rng().gen())
+    }
+
+
+    /**
+     * with_seed - create an empty skip list with a reproducible level generator
+     * @seed: seed of the level generator
+     */
+    pub fn with_seed(seed: u64) -> Self {
+        SkipList {
+            head: Box::new(SkipNode {
+                item: MaybeUninit::uninit(),
+                prev: ptr::null_mut(),
+                next: vec![ptr::null_mut(); SKIPLIST_MAXLEVEL].into_boxed_slice(),
+            }),
+            tail: ptr::null_mut(),
+            level: 1,
+            len: 0,
+            rng: StdRng::seed_from_u64(seed),
+        }
+    }
+
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+
+    fn head_ptr(&self) -> *mut SkipNode<T> {
+        &*self.head as *const SkipNode<T> as *mut SkipNode<T>
+    }
+
+
+    unsafe fn item<'a>(node: *const SkipNode<T>) -> &'a T {
+        (*node).item.assume_init_ref()
+    }
+
+
+    /* The last node whose item satisfies @before, head if there is none */
+    fn find_last<F: Fn(&T) -> bool>(&self, before: F) -> *mut SkipNode<T> {
+        self.find_update(before)[0]
+    }
+
+
+    /* For every level, the last node whose item satisfies @before */
+    fn find_update<F: Fn(&T) -> bool>(&self, before: F) -> [*mut SkipNode<T>; SKIPLIST_MAXLEVEL] {
+        let head = self.head_ptr();
+        let mut update = [head; SKIPLIST_MAXLEVEL];
+        let mut x = head;
+
+        for i in (0..self.level).rev() {
+            unsafe {
+                loop {
+                    let next = (*x).next[i];
+                    if next.is_null() || !before(SkipList::item(next)) {
+                        break;
+                    }
+                    x = next;
+                }
+            }
+            update[i] = x;
+        }
+        update
+    }
+
+
+    fn node_ref(&self, node: *mut SkipNode<T>) -> Option<&T> {
+        (!node.is_null() && node != self.head_ptr()).then(|| unsafe { SkipList::item(node) })
+    }
+
+
+    /**
+     * insert - add an element, keeping the list sorted
+     * @item: the element to add
+     *
+     * Return: false if an equal element is already present, in which case
+     * @item is dropped.
+     */
+    pub fn insert(&mut self, item: T) -> bool {
+        let head = self.head_ptr();
+        let mut update = self.find_update(|x| x < &item);
+
+        unsafe {
+            let next = (*update[0]).next[0];
+            if !next.is_null() && SkipList::item(next) == &item {
+                return false;
+            }
+
+            let level = skiplist_random_level(self.rng.gen());
+            if level > self.level {
+                update[self.level..level].fill(head);
+                self.level = level;
+            }
+
+            let node = Box::into_raw(Box::new(SkipNode {
+                item: MaybeUninit::new(item),
+                prev: if update[0] == head { ptr::null_mut() } else { update[0] },
+                next: vec![ptr::null_mut(); level].into_boxed_slice(),
+            }));
+            for (i, &prev) in update.iter().enumerate().take(level) {
+                (*node).next[i] = (*prev).next[i];
+                (*prev).next[i] = node;
+            }
+
+            if next.is_null() {
+                self.tail = node;
+            } else {
+                (*next).prev = node;
+            }
+        }
+        self.len += 1;
+        true
+    }
+
+
+    /**
+     * remove - remove the element equal to @key
+     *
+     * Return: the removed element, %None if there is none.
+     */
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<T>
+    where
+        T: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let update = self.find_update(|x| x.borrow() < key);
+
+        unsafe {
+            let node = (*update[0]).next[0];
+            if node.is_null() || SkipList::item(node).borrow() != key {
+                return None;
+            }
+
+            for (i, &prev) in update.iter().enumerate().take(self.level) {
+                if (*prev).next[i] != node {
+                    break;
+                }
+                (*prev).next[i] = (*node).next[i];
+            }
+
+            let next = (*node).next[0];
+            if next.is_null() {
+                self.tail = (*node).prev;
+            } else {
+                (*next).prev = (*node).prev;
+            }
+
+            while self.level > 1 && self.head.next[self.level - 1].is_null() {
+                self.level -= 1;
+            }
+            self.len -= 1;
+
+            let node = Box::from_raw(node);
+            Some(node.item.assume_init_read())
+        }
+    }
+
+
+    /**
+     * find - look up the element equal to @key
+     */
+    pub fn find<Q>(&self, key: &Q) -> Option<&T>
+    where
+        T: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        self.lower_bound(key).filter(|item| (*item).borrow() == key)
+    }
+
+
+    /**
+     * lower_bound - the first element that is not less than @key
+     */
+    pub fn lower_bound<Q>(&self, key: &Q) -> Option<&T>
+    where
+        T: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let node = self.find_last(|x| x.borrow() < key);
+        self.node_ref(unsafe { (*node).next[0] })
+    }
+
+
+    /**
+     * upper_bound - the first element that is greater than @key
+     */
+    pub fn upper_bound<Q>(&self, key: &Q) -> Option<&T>
+    where
+        T: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let node = self.find_last(|x| x.borrow() <= key);
+        self.node_ref(unsafe { (*node).next[0] })
+    }
+
+
+    pub fn first(&self) -> Option<&T> {
+        self.node_ref(self.head.next[0])
+    }
+
+
+    pub fn last(&self) -> Option<&T> {
+        self.node_ref(self.tail)
+    }
+
+
+    /**
+     * range - iterate over the elements within @range
+     *
+     * The iterator is double ended, so the elements can be visited in
+     * ascending or, with rev(), descending order.
+     */
+    pub fn range<Q, R>(&self, range: R) -> SkipListRange<'_, T>
+    where
+        T: Borrow<Q>,
+        Q: Ord + ?Sized,
+        R: RangeBounds<Q>,
+    {
+        let front = match range.start_bound() {
+            Bound::Included(start) => unsafe { (*self.find_last(|x| x.borrow() < start)).next[0] },
+            Bound::Excluded(start) => unsafe { (*self.find_last(|x| x.borrow() <= start)).next[0] },
+            Bound::Unbounded => self.head.next[0],
+        };
+        let back = match range.end_bound() {
+            Bound::Included(end) => self.find_last(|x| x.borrow() <= end),
+            Bound::Excluded(end) => self.find_last(|x| x.borrow() < end),
+            Bound::Unbounded => self.tail,
+        };
+
+        let empty = front.is_null()
+            || back.is_null()
+            || back == self.head_ptr()
+            || unsafe { SkipList::item(front) > SkipList::item(back) };
+        if empty {
+            return SkipListRange {
+                front: ptr::null_mut(),
+                back: ptr::null_mut(),
+                _marker: PhantomData,
+            };
+        }
+        SkipListRange {
+            front,
+            back,
+            _marker: PhantomData,
+        }
+    }
+
+
+    /**
+     * iter - iterate over all the elements in order
+     */
+    pub fn iter(&self) -> SkipListRange<'_, T> {
+        self.range::<T, _>(..)
+    }
+}
+
+impl<T> Drop for SkipList<T> {
+    fn drop(&mut self) {
+        let mut pos = self.head.next[0];
+
+        while !pos.is_null() {
+            unsafe {
+                let mut node = Box::from_raw(pos);
+                pos = node.next[0];
+                node.item.assume_init_drop();
+            }
+        }
+    }
+}
+
+
+pub struct SkipListRange<'a, T> {
+    front: *mut SkipNode<T>,
+    back: *mut SkipNode<T>,
+    _marker: PhantomData<&'a T>,
+}
+
+impl<'a, T> Iterator for SkipListRange<'a, T> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.front.is_null() {
+            return None;
+        }
+
+        let node = self.front;
+        if node == self.back {
+            self.front = ptr::null_mut();
+            self.back = ptr::null_mut();
+        } else {
+            self.front = unsafe { (*node).next[0] };
+        }
+        Some(unsafe { (*node).item.assume_init_ref() })
+    }
+}
+
+impl<T> DoubleEndedIterator for SkipListRange<'_, T> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.back.is_null() {
+            return None;
+        }
+
+        let node = self.back;
+        if node == self.front {
+            self.front = ptr::null_mut();
+            self.back = ptr::null_mut();
+        } else {
+            self.back = unsafe { (*node).prev };
+        }
+        Some(unsafe { (*node).item.assume_init_ref() })
+    }
+}
+
+
+/* Sections for Unit tests */
+#[cfg(test)]
+mod tests {
+    use sorted_vec::SortedSet;
+
+    use super::*;
+
+    fn levels<T: Ord>(list: &SkipList<T>) -> Vec<usize> {
+        let mut levels = Vec::new();
+        let mut pos = list.head.next[0];
+
+        while !pos.is_null() {
+            unsafe {
+                let node = &*pos;
+                levels.push(node.next.len());
+                pos = node.next[0];
+            }
+        }
+        levels
+    }
+
+
+    #[test]
+    fn test_skiplist_random_level() {
+        assert_eq!(skiplist_random_level(1), 1);
+        assert_eq!(skiplist_random_level(0b100), 2);
+        assert_eq!(skiplist_random_level(0b1000), 2);
+        assert_eq!(skiplist_random_level(0), SKIPLIST_MAXLEVEL);
+    }
+
+
+    #[test]
+    fn test_skiplist_insert_remove() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let mut list = SkipList::with_seed(7);
+        let mut set = SortedSet::new();
+
+        for _ in 0..2000 {
+            let num: i32 = rng.gen_range(0..500);
+            if rng.gen_bool(0.6) {
+                assert_eq!(list.insert(num), set.find_or_insert(num).is_inserted());
+            } else {
+                assert_eq!(list.remove(&num), set.remove_item(&num));
+            }
+        }
+
+        assert_eq!(list.len(), set.len());
+        assert!(list.iter().eq(set.iter()));
+        assert!(list.iter().rev().eq(set.iter().rev()));
+        assert_eq!(list.first(), set.first());
+        assert_eq!(list.last(), set.last());
+    }
+
+
+    #[test]
+    fn test_skiplist_find() {
+        let mut list = SkipList::with_seed(1);
+
+        for num in (0..100).step_by(10) {
+            assert!(list.insert(num));
+        }
+        assert!(!list.insert(50));
+
+        assert_eq!(list.find(&30), Some(&30));
+        assert_eq!(list.find(&35), None);
+        assert_eq!(list.lower_bound(&30), Some(&30));
+        assert_eq!(list.lower_bound(&31), Some(&40));
+        assert_eq!(list.lower_bound(&91), None);
+        assert_eq!(list.upper_bound(&30), Some(&40));
+        assert_eq!(list.upper_bound(&-5), Some(&0));
+    }
+
+
+    #[test]
+    fn test_skiplist_range() {
+        let mut list = SkipList::with_seed(2);
+        for num in 0..20 {
+            list.insert(num * 2);
+        }
+
+        assert!(list.range(5..=11).eq([6, 8, 10].iter()));
+        assert!(list.range(6..10).rev().eq([8, 6].iter()));
+        assert!(list.range((Bound::Excluded(6), Bound::Unbounded)).take(2).eq([8, 10].iter()));
+        assert!(list.range(..4).eq([0, 2].iter()));
+        assert_eq!(list.range(11..12).count(), 0);
+        assert_eq!(list.range(100..).count(), 0);
+
+        /* Both ends meet in the middle */
+        let mut range = list.range(10..=16);
+        assert_eq!(range.next(), Some(&10));
+        assert_eq!(range.next_back(), Some(&16));
+        assert_eq!(range.next_back(), Some(&14));
+        assert_eq!(range.next(), Some(&12));
+        assert_eq!(range.next(), None);
+        assert_eq!(range.next_back(), None);
+    }
+
+
+    #[test]
+    fn test_skiplist_seed() {
+        let mut a = SkipList::with_seed(42);
+        let mut b = SkipList::with_seed(42);
+
+        for num in 0..500 {
+            a.insert(num);
+            b.insert(num);
+        }
+        assert_eq!(levels(&a), levels(&b));
+        assert!(levels(&a).iter().any(|&level| level > 2));
+    }
+
+
+    #[test]
+    fn test_skiplist_str() {
+        let mut list = SkipList::with_seed(3);
+
+        for word in ["pear", "apple", "fig", "kiwi"] {
+            list.insert(word.to_string());
+        }
+        assert_eq!(list.find("fig").map(String::as_str), Some("fig"));
+        assert_eq!(list.remove("apple"), Some("apple".to_string()));
+        assert!(list.iter().map(String::as_str).eq(["fig", "kiwi", "pear"]));
+    }
+}
@@ -0,0 +1,583 @@
+use std::borrow::Borrow;
+use std::marker::PhantomData;
+use std::mem::MaybeUninit;
+use std::ptr;
+use std::sync::atomic::{fence, AtomicU64, AtomicUsize, Ordering};
+
+use crate::rcu::{rcu_read_lock, RcuReadGuard, RcuRetired};
+use crate::skiplist::{skiplist_random_level, SKIPLIST_MAXLEVEL};
+
+/*
+ * Lock-free skip list
+ *
+ * Every level is a Harris style list: a node is logically deleted by
+ * setting the low bit of its own next pointers, top level first, and then
+ * physically unlinked by whoever walks past it. Searches run under
+ * rcu_read_lock() and never block, removed nodes are freed after a grace
+ * period.
+ *
+ * An inserter may still be linking the upper levels of a node when the
+ * node gets removed, so the node holds two references, one for the
+ * inserter and one for the removal. Both drop theirs only after they made
+ * sure the node is unlinked from every level they could have linked it
+ * to, the last one retires the node.
+ */
+
+const SKIPLIST_MARK: usize = 1;
+
+fn lf_ptr<T>(next: usize) -> *mut LfNode<T> {
+    (next & !SKIPLIST_MARK) as *mut LfNode<T>
+}
+
+fn lf_marked(next: usize) -> bool {
+    next & SKIPLIST_MARK != 0
+}
+
+
+struct LfNode<T> {
+    item: MaybeUninit<T>,
+    refs: AtomicUsize,
+    next: Box<[AtomicUsize]>,
+}
+
+impl<T> LfNode<T> {
+    fn alloc(item: MaybeUninit<T>, level: usize) -> *mut LfNode<T> {
+        Box::into_raw(Box::new(LfNode {
+            item,
+            refs: AtomicUsize::new(2),
+            next: (0..level).map(|_| AtomicUsize::new(0)).collect(),
+        }))
+    }
+
+
+    unsafe fn into_inner(node: *mut ()) -> T {
+        Box::from_raw(node as *mut LfNode<T>).item.assume_init_read()
+    }
+}
+
+
+type LfPath<T> = [*mut LfNode<T>; SKIPLIST_MAXLEVEL];
+
+
+/**
+ * struct LockFreeSkipList - concurrent ordered set
+ *
+ * insert() and remove() are lock-free, lookups and iteration are
+ * wait-free. Lookups hand out references bound to an RCU read-side
+ * critical section, and iteration only goes forward.
+ */
+pub struct LockFreeSkipList<T> {
+    /* Sentinel whose item is never initialized */
+    head: Box<LfNode<T>>,
+    seed: AtomicU64,
+    len: AtomicUsize,
+}
+
+unsafe impl<T: Send + Sync> Send for LockFreeSkipList<T> {}
+unsafe impl<T: Send + Sync> Sync for LockFreeSkipList<T> {}
+
+impl<T: Ord + Send + Sync + 'static> Default for LockFreeSkipList<T> {
+    fn default() -> Self {
+        LockFreeSkipList::new()
+    }
+}
+
+#[allow(dead_code)]
+impl<T: Ord + Send + Sync + 'static> LockFreeSkipList<T> {
+    /**
+     * with_seed - create an empty list with a reproducible level generator
+     * @seed: seed of the level generator
+     *
+     * Levels only come out the same across runs when the insertions do not
+     * race with each other.
+     */
+    pub fn with_seed(seed: u64) -> Self {
+        LockFreeSkipList {
+            head: Box::new(LfNode {
+                item: MaybeUninit::uninit(),
+                refs: AtomicUsize::new(1),
+                next: (0..SKIPLIST_MAXLEVEL).map(|_| AtomicUsize::new(0)).collect(),
+            }),
+            seed: AtomicU64::new(seed),
+            len: AtomicUsize::new(0),
+        }
+    }
+
+
+    pub fn new() -> Self {
+        LockFreeSkipList::with_seed(rand::random())
+    }
+
+
+    pub fn len(&self) -> usize {
+        self.len.load(Ordering::Relaxed)
+    }
+
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+
+    /* splitmix64 on a shared counter, so drawing a level never blocks */
+    fn random_level(&self) -> usize {
+        const GOLDEN: u64 = 0x9e37_79b9_7f4a_7c15;
+        let mut z = self.seed.fetch_add(GOLDEN, Ordering::Relaxed).wrapping_add(GOLDEN);
+
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        skiplist_random_level(z ^ (z >> 31))
+    }
+
+
+    fn head_ptr(&self) -> *mut LfNode<T> {
+        &*self.head as *const LfNode<T> as *mut LfNode<T>
+    }
+
+
+    unsafe fn item<'a>(node: *const LfNode<T>) -> &'a T {
+        (*node).item.assume_init_ref()
+    }
+
+
+    /*
+     * Fill @preds and @succs with the nodes around @key on every level,
+     * unlinking the marked nodes met on the way. Must be called under
+     * rcu_read_lock().
+     */
+    fn find<Q>(&self, key: &Q, preds: &mut LfPath<T>, succs: &mut LfPath<T>) -> bool
+    where
+        T: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        'retry: loop {
+            let mut pred = self.head_ptr();
+
+            for level in (0..SKIPLIST_MAXLEVEL).rev() {
+                unsafe {
+                    let mut curr = lf_ptr::<T>((*pred).next[level].load(Ordering::Acquire));
+                    while !curr.is_null() {
+                        let succ = (*curr).next[level].load(Ordering::Acquire);
+                        if lf_marked(succ) {
+                            if (*pred).next[level]
+                                .compare_exchange(curr as usize, succ & !SKIPLIST_MARK,
+                                                  Ordering::AcqRel, Ordering::Acquire)
+                                .is_err()
+                            {
+                                continue 'retry;
+                            }
+                            curr = lf_ptr(succ);
+                            continue;
+                        }
+
+                        if Self::item(curr).borrow() >= key {
+                            break;
+                        }
+                        pred = curr;
+                        curr = lf_ptr(succ);
+                    }
+                    preds[level] = pred;
+                    succs[level] = curr;
+                }
+            }
+
+            return !succs[0].is_null() && unsafe { Self::item(succs[0]).borrow() == key };
+        }
+    }
+
+
+    fn release(&self, node: *mut LfNode<T>) {
+        if unsafe { (*node).refs.fetch_sub(1, Ordering::AcqRel) } == 1 {
+            drop(unsafe { RcuRetired::new(node as *mut (), LfNode::<T>::into_inner) });
+        }
+    }
+
+
+    /**
+     * insert - add an element, keeping the list sorted
+     * @item: the element to add
+     *
+     * Return: false if an equal element is already present, in which case
+     * @item is dropped.
+     */
+    pub fn insert(&self, item: T) -> bool {
+        let _guard = rcu_read_lock();
+        let mut preds = [ptr::null_mut(); SKIPLIST_MAXLEVEL];
+        let mut succs = [ptr::null_mut(); SKIPLIST_MAXLEVEL];
+        let level = self.random_level();
+        let node = LfNode::alloc(MaybeUninit::new(item), level);
+        let key = unsafe { Self::item(node) };
+
+        /* Count it first, a racing remove() must not take len below zero */
+        self.len.fetch_add(1, Ordering::Relaxed);
+        loop {
+            if self.find(key, &mut preds, &mut succs) {
+                self.len.fetch_sub(1, Ordering::Relaxed);
+                unsafe { drop(LfNode::<T>::into_inner(node as *mut ())) };
+                return false;
+            }
+
+            unsafe {
+                for (i, &succ) in succs.iter().enumerate().take(level) {
+                    (*node).next[i].store(succ as usize, Ordering::Relaxed);
+                }
+                if (*preds[0]).next[0]
+                    .compare_exchange(succs[0] as usize, node as usize, Ordering::AcqRel, Ordering::Acquire)
+                    .is_ok()
+                {
+                    break;
+                }
+            }
+        }
+
+        /* The node is in the set, the upper levels are only shortcuts */
+        'link: for i in 1..level {
+            loop {
+                unsafe {
+                    let next = (*node).next[i].load(Ordering::Acquire);
+                    if lf_marked(next) {
+                        break 'link;
+                    }
+                    if lf_ptr(next) != succs[i]
+                        && (*node).next[i]
+                            .compare_exchange(next, succs[i] as usize, Ordering::AcqRel, Ordering::Acquire)
+                            .is_err()
+                    {
+                        break 'link;
+                    }
+                    if (*preds[i]).next[i]
+                        .compare_exchange(succs[i] as usize, node as usize, Ordering::AcqRel, Ordering::Acquire)
+                        .is_ok()
+                    {
+                        break;
+                    }
+                }
+
+                self.find(key, &mut preds, &mut succs);
+                if succs[0] != node {
+                    break 'link;
+                }
+            }
+        }
+
+        /*
+         * Removed while linking, make sure no level still points to it.
+         * Pairs with the fence in remove(): either the remover's find()
+         * sees the levels linked above, or this load sees its mark.
+         */
+        fence(Ordering::SeqCst);
+        if lf_marked(unsafe { (*node).next[0].load(Ordering::Acquire) }) {
+            self.find(key, &mut preds, &mut succs);
+        }
+        self.release(node);
+        true
+    }
+
+
+    /**
+     * remove - remove the element equal to @key
+     *
+     * The element is dropped once the readers that may still see it are
+     * gone.
+     *
+     * Return: true if the element was found and removed.
+     */
+    pub fn remove<Q>(&self, key: &Q) -> bool
+    where
+        T: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let _guard = rcu_read_lock();
+        let mut preds = [ptr::null_mut(); SKIPLIST_MAXLEVEL];
+        let mut succs = [ptr::null_mut(); SKIPLIST_MAXLEVEL];
+
+        if !self.find(key, &mut preds, &mut succs) {
+            return false;
+        }
+
+        let node = succs[0];
+        let next = unsafe { &(*node).next };
+        for i in (1..next.len()).rev() {
+            next[i].fetch_or(SKIPLIST_MARK, Ordering::AcqRel);
+        }
+
+        /* Whoever marks level 0 owns the removal */
+        if lf_marked(next[0].fetch_or(SKIPLIST_MARK, Ordering::AcqRel)) {
+            return false;
+        }
+        self.len.fetch_sub(1, Ordering::Relaxed);
+
+        /* Pairs with the fence in insert(), see there */
+        fence(Ordering::SeqCst);
+        self.find(key, &mut preds, &mut succs);
+        self.release(node);
+        true
+    }
+
+
+    /* The first live node at level 0 not less than @key, null if none */
+    fn lower_bound_node<Q>(&self, key: &Q) -> *mut LfNode<T>
+    where
+        T: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let mut pred = self.head_ptr();
+
+        unsafe {
+            for level in (1..SKIPLIST_MAXLEVEL).rev() {
+                loop {
+                    let curr = lf_ptr::<T>((*pred).next[level].load(Ordering::Acquire));
+                    if curr.is_null() || Self::item(curr).borrow() >= key {
+                        break;
+                    }
+                    pred = curr;
+                }
+            }
+        }
+        self.next_live(pred)
+    }
+
+
+    /* The first node after @node at level 0 that is not deleted */
+    fn next_live(&self, node: *mut LfNode<T>) -> *mut LfNode<T> {
+        let mut curr = lf_ptr::<T>(unsafe { (*node).next[0].load(Ordering::Acquire) });
+
+        unsafe {
+            while !curr.is_null() {
+                let next = (*curr).next[0].load(Ordering::Acquire);
+                if !lf_marked(next) {
+                    break;
+                }
+                curr = lf_ptr(next);
+            }
+        }
+        curr
+    }
+
+
+    /* Like lower_bound_node(), but skips what stale upper levels led past */
+    fn lower_bound_live<Q>(&self, key: &Q) -> *mut LfNode<T>
+    where
+        T: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let mut node = self.lower_bound_node(key);
+
+        /* Marked predecessors can hide nodes below @key */
+        while !node.is_null() && unsafe { Self::item(node).borrow() < key } {
+            node = self.next_live(node);
+        }
+        node
+    }
+
+
+    /**
+     * lower_bound - the first element that is not less than @key
+     */
+    pub fn lower_bound<'a, Q>(&'a self, key: &Q, _guard: &'a RcuReadGuard) -> Option<&'a T>
+    where
+        T: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let node = self.lower_bound_live(key);
+        (!node.is_null()).then(|| unsafe { Self::item(node) })
+    }
+
+
+    /**
+     * find_item - look up the element equal to @key
+     */
+    pub fn find_item<'a, Q>(&'a self, key: &Q, guard: &'a RcuReadGuard) -> Option<&'a T>
+    where
+        T: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        self.lower_bound(key, guard).filter(|item| (*item).borrow() == key)
+    }
+
+
+    pub fn contains<Q>(&self, key: &Q) -> bool
+    where
+        T: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let guard = rcu_read_lock();
+        self.find_item(key, &guard).is_some()
+    }
+
+
+    /**
+     * iter_from - iterate in ascending order from the first element not less than @key
+     *
+     * Elements inserted or removed concurrently may or may not be seen.
+     */
+    pub fn iter_from<'a, Q>(&'a self, key: &Q, _guard: &'a RcuReadGuard) -> LockFreeSkipListIter<'a, T>
+    where
+        T: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        LockFreeSkipListIter {
+            list: self,
+            pos: self.lower_bound_live(key),
+            _marker: PhantomData,
+        }
+    }
+
+
+    pub fn iter<'a>(&'a self, _guard: &'a RcuReadGuard) -> LockFreeSkipListIter<'a, T> {
+        LockFreeSkipListIter {
+            list: self,
+            pos: self.next_live(self.head_ptr()),
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T> Drop for LockFreeSkipList<T> {
+    fn drop(&mut self) {
+        let mut pos = lf_ptr::<T>(*self.head.next[0].get_mut());
+
+        while !pos.is_null() {
+            unsafe {
+                let mut node = Box::from_raw(pos);
+                pos = lf_ptr(*node.next[0].get_mut());
+                node.item.assume_init_drop();
+            }
+        }
+    }
+}
+
+
+pub struct LockFreeSkipListIter<'a, T> {
+    list: &'a LockFreeSkipList<T>,
+    pos: *mut LfNode<T>,
+    _marker: PhantomData<&'a RcuReadGuard>,
+}
+
+impl<'a, T: Ord + Send + Sync + 'static> Iterator for LockFreeSkipListIter<'a, T> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.pos.is_null() {
+            return None;
+        }
+
+        let node = self.pos;
+        self.pos = self.list.next_live(node);
+        Some(unsafe { LockFreeSkipList::item(node) })
+    }
+}
+
+
+/* Sections for Unit tests */
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::thread;
+
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+    use sorted_vec::SortedSet;
+
+    use super::*;
+
+    #[test]
+    fn test_lockfree_skiplist_single() {
+        let mut rng = StdRng::seed_from_u64(11);
+        let list = LockFreeSkipList::with_seed(11);
+        let mut set = SortedSet::new();
+
+        for _ in 0..2000 {
+            let num: u32 = rng.gen_range(0..300);
+            if rng.gen_bool(0.6) {
+                assert_eq!(list.insert(num), set.find_or_insert(num).is_inserted());
+            } else {
+                assert_eq!(list.remove(&num), set.remove_item(&num).is_some());
+            }
+        }
+
+        let guard = rcu_read_lock();
+        assert_eq!(list.len(), set.len());
+        assert!(list.iter(&guard).eq(set.iter()));
+        assert_eq!(list.find_item(&set[0], &guard), Some(&set[0]));
+        assert!(list.iter_from(&100, &guard).eq(set.iter().filter(|&&num| num >= 100)));
+    }
+
+
+    #[test]
+    fn test_lockfree_skiplist_concurrent() {
+        const THREADS: u32 = 4;
+        const KEYS: u32 = 500;
+        let list = Arc::new(LockFreeSkipList::with_seed(5));
+
+        /* Every thread inserts its own keys and removes the odd ones */
+        let handles: Vec<_> = (0..THREADS)
+            .map(|t| {
+                let list = list.clone();
+                thread::spawn(move || {
+                    for num in 0..KEYS {
+                        assert!(list.insert(num * THREADS + t));
+                        if num.is_multiple_of(16) {
+                            thread::yield_now();
+                        }
+                    }
+                    for num in (1..KEYS).step_by(2) {
+                        assert!(list.remove(&(num * THREADS + t)));
+                    }
+                })
+            })
+            .collect();
+
+        /* Readers must always see a sorted list */
+        for _ in 0..20 {
+            let guard = rcu_read_lock();
+            let items: Vec<_> = list.iter(&guard).copied().collect();
+            assert!(items.windows(2).all(|pair| pair[0] < pair[1]));
+            drop(guard);
+            thread::yield_now();
+        }
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        let guard = rcu_read_lock();
+        let expected: Vec<u32> = (0..KEYS * THREADS).filter(|num| (num / THREADS).is_multiple_of(2)).collect();
+        assert_eq!(list.len(), expected.len());
+        assert!(list.iter(&guard).eq(expected.iter()));
+    }
+
+
+    #[test]
+    fn test_lockfree_skiplist_contended() {
+        let list = Arc::new(LockFreeSkipList::with_seed(9));
+
+        /* All threads fight over the same few keys */
+        let handles: Vec<_> = (0..4)
+            .map(|t| {
+                let list = list.clone();
+                thread::spawn(move || {
+                    let mut rng = StdRng::seed_from_u64(t);
+                    for _ in 0..2000 {
+                        let num: u32 = rng.gen_range(0..16);
+                        if rng.gen_bool(0.5) {
+                            list.insert(num);
+                        } else {
+                            list.remove(&num);
+                        }
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        let guard = rcu_read_lock();
+        let items: Vec<_> = list.iter(&guard).copied().collect();
+        assert!(items.windows(2).all(|pair| pair[0] < pair[1]));
+        assert_eq!(items.len(), list.len());
+        for num in 0..16 {
+            assert_eq!(list.contains(&num), items.contains(&num));
+        }
+    }
+}
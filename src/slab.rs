@@ -0,0 +1,886 @@
+use std::cell::{Cell, UnsafeCell};
+use std::fmt;
+use std::mem::{self, size_of, ManuallyDrop, MaybeUninit};
+use std::ops::{Deref, DerefMut};
+use std::ptr::{self, NonNull};
+use std::sync::atomic::{self, AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use crate::bitmap::PAGE_SIZE;
+use crate::errno::{EINVAL, ENOMEM};
+use crate::spinlock::SpinLock;
+use crate::sync_list::{SyncListHead, SyncListNode};
+
+/*
+ * Object cache allocator
+ *
+ * The kmem_cache interface of mm/slab_common.c, modelled on the SLAB
+ * allocator that lived in mm/slab.c until v6.8. A cache hands out
+ * objects of a single type from slabs, page sized arrays of slots. The
+ * free slots of a slab are chained through the slots themselves, each
+ * free slot holding the index of the next one like the freepointer of
+ * SLUB, and the slabs sit on the partial, full and free lists of the
+ * cache, which are SyncListHeads protected by the list lock.
+ *
+ * In front of the slabs, every cpu has an array cache, a magazine of free
+ * slots that is refilled from and flushed to the slabs in batches, so
+ * most allocations and frees only touch the magazine of the current cpu.
+ * A thread is bound to a cpu the first time it uses a cache.
+ *
+ * Objects are owned through a KmemObj, or shared through a KmemArc, which
+ * is also how the nodes of a SyncListHead are allocated from a cache.
+ */
+
+
+/* Number of cpus handed out so far, see smp_processor_id() */
+static NR_CPU_IDS: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    static CPU_ID: usize = NR_CPU_IDS.fetch_add(1, Ordering::Relaxed);
+}
+
+
+/* Id of the current thread, which picks its per cpu state in a cache or a pool */
+pub(crate) fn smp_processor_id() -> usize {
+    CPU_ID.with(|id| *id)
+}
+
+
+/* Index terminating the freelist of a slab */
+const SLAB_FREELIST_END: u32 = u32::MAX;
+
+
+/*
+ * A slot of a slab, either an object or, while free, the index of the
+ * next free slot of the slab. The object sits at offset 0, so a pointer
+ * to the slot is a pointer to the object.
+ */
+#[repr(C)]
+union KmemObjSlot<T> {
+    obj: ManuallyDrop<MaybeUninit<T>>,
+    next_free: u32,
+}
+
+
+/*
+ * A slab of @objs. @freelist is the index of the first free slot and
+ * @nr_free the length of the chain, both only touched under the list
+ * lock of the cache.
+ */
+struct Slab<T> {
+    objs: Box<[UnsafeCell<KmemObjSlot<T>>]>,
+    freelist: Cell<u32>,
+    nr_free: Cell<usize>,
+}
+
+/* Slots are owned by whoever allocated them, the freelist by the list lock */
+unsafe impl<T: Send> Send for Slab<T> {}
+unsafe impl<T: Send> Sync for Slab<T> {}
+
+impl<T> Slab<T> {
+    /* Take the first free slot, list lock held */
+    fn slab_get_obj(&self) -> Option<u32> {
+        let idx = self.freelist.get();
+
+        if idx == SLAB_FREELIST_END {
+            return None;
+        }
+        self.freelist.set(unsafe { (*self.objs[idx as usize].get()).next_free });
+        self.nr_free.set(self.nr_free.get() - 1);
+        Some(idx)
+    }
+
+
+    /* Put the slot @idx back at the head of the freelist, list lock held */
+    fn slab_put_obj(&self, idx: u32) {
+        unsafe { (*self.objs[idx as usize].get()).next_free = self.freelist.get() };
+        self.freelist.set(idx);
+        self.nr_free.set(self.nr_free.get() + 1);
+    }
+}
+
+
+/*
+ * A slot of a slab. While a slot is allocated or cached in an array
+ * cache its slab is in use, so the slab stays on the lists of the cache
+ * and @slab stays valid.
+ */
+struct KmemSlot<T> {
+    slab: *const SyncListNode<Slab<T>>,
+    idx: u32,
+}
+
+impl<T> Clone for KmemSlot<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for KmemSlot<T> {}
+
+unsafe impl<T: Send> Send for KmemSlot<T> {}
+
+impl<T> KmemSlot<T> {
+    fn obj(&self) -> *mut T {
+        unsafe { (*self.slab).item.objs[self.idx as usize].get().cast() }
+    }
+}
+
+
+/*
+ * struct ArrayCache - per cpu magazine of free slots
+ * @entries: the cached slots, the most recently freed last
+ */
+#[repr(align(64))]
+struct ArrayCache<T> {
+    entries: SpinLock<Vec<KmemSlot<T>>>,
+}
+
+
+/*
+ * Counters of the slab lists, protected by the list lock.
+ */
+struct KmemCacheNode {
+    num_slabs: usize,
+    free_slabs: usize,
+    free_objects: usize,
+}
+
+
+/**
+ * struct KmemCacheStats - snapshot of the state of a cache
+ * @active_objs: objects currently handed out
+ * @num_objs: slots in all slabs
+ * @cached_objs: free slots sitting in the array caches
+ * @active_slabs: slabs with at least one slot in use or cached
+ * @num_slabs: all slabs
+ * @objs_per_slab: slots per slab
+ */
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct KmemCacheStats {
+    pub active_objs: usize,
+    pub num_objs: usize,
+    pub cached_objs: usize,
+    pub active_slabs: usize,
+    pub num_slabs: usize,
+    pub objs_per_slab: usize,
+}
+
+
+/**
+ * struct KmemCache - cache of objects of type T
+ * @name: name of the cache
+ * @num: slots per slab
+ * @limit: capacity of an array cache
+ * @batchcount: slots moved between an array cache and the slabs at once
+ * @free_limit: free slots kept in the slabs before free slabs are released
+ * @array: the per cpu array caches
+ * @list_lock: protects the slab lists and the freelists of the slabs
+ * @active_objs: objects currently handed out
+ */
+pub struct KmemCache<T> {
+    name: &'static str,
+    num: usize,
+    limit: usize,
+    batchcount: usize,
+    free_limit: usize,
+    array: Box<[ArrayCache<T>]>,
+    list_lock: SpinLock<KmemCacheNode>,
+    slabs_partial: SyncListHead<Slab<T>>,
+    slabs_full: SyncListHead<Slab<T>>,
+    slabs_free: SyncListHead<Slab<T>>,
+    active_objs: AtomicUsize,
+}
+
+
+/**
+ * struct KmemObj - an object allocated from a KmemCache
+ *
+ * Owns its object like a Box does. Dropping it drops the object and
+ * gives the slot back to the cache, which it keeps alive meanwhile.
+ */
+pub struct KmemObj<T> {
+    cache: Arc<KmemCache<T>>,
+    slot: KmemSlot<T>,
+    obj: NonNull<T>,
+}
+
+unsafe impl<T: Send> Send for KmemObj<T> {}
+unsafe impl<T: Sync> Sync for KmemObj<T> {}
+
+
+#[allow(dead_code)]
+impl<T> KmemCache<T> {
+    /**
+     * kmem_cache_create - create a cache
+     * @name: name of the cache, for the statistics
+     * @nr_cpus: number of array caches
+     *
+     * Threads are spread over the @nr_cpus array caches in the order
+     * they first allocate.
+     *
+     * Return: the cache, or -EINVAL if @nr_cpus is 0.
+     */
+    pub fn kmem_cache_create(name: &'static str, nr_cpus: usize) -> Result<Arc<Self>, i32> {
+        if nr_cpus == 0 {
+            return Err(-EINVAL);
+        }
+
+        let size = size_of::<KmemObjSlot<T>>();
+        let num = (PAGE_SIZE / size).clamp(1, SLAB_FREELIST_END as usize);
+        /* Same sizing as enable_cpucache() */
+        let limit: usize = match size {
+            s if s > 131072 => 1,
+            s if s > PAGE_SIZE => 8,
+            s if s > 1024 => 24,
+            s if s > 256 => 54,
+            _ => 120,
+        };
+        let batchcount = limit.div_ceil(2);
+
+        Ok(Arc::new(KmemCache {
+            name,
+            num,
+            limit,
+            batchcount,
+            free_limit: (1 + nr_cpus) * batchcount + num,
+            array: (0..nr_cpus)
+                .map(|_| ArrayCache {
+                    entries: SpinLock::new(Vec::with_capacity(limit)),
+                })
+                .collect(),
+            list_lock: SpinLock::new(KmemCacheNode {
+                num_slabs: 0,
+                free_slabs: 0,
+                free_objects: 0,
+            }),
+            slabs_partial: SyncListHead::new(),
+            slabs_full: SyncListHead::new(),
+            slabs_free: SyncListHead::new(),
+            active_objs: AtomicUsize::new(0),
+        }))
+    }
+
+
+    pub fn kmem_cache_name(&self) -> &'static str {
+        self.name
+    }
+
+
+    pub fn kmem_cache_size(&self) -> usize {
+        size_of::<T>()
+    }
+
+
+    fn cpu_cache(&self) -> &ArrayCache<T> {
+        &self.array[smp_processor_id() % self.array.len()]
+    }
+
+
+    /* The slab list for a slab with @free free slots */
+    fn slab_list(&self, free: usize) -> &SyncListHead<Slab<T>> {
+        match free {
+            0 => &self.slabs_full,
+            f if f == self.num => &self.slabs_free,
+            _ => &self.slabs_partial,
+        }
+    }
+
+
+    /* Move @slab to the list matching its freelist, which had @old free slots */
+    fn fixup_slab_list(&self, n: &mut KmemCacheNode, slab: &SyncListNode<Slab<T>>, old: usize) {
+        let free = slab.item.nr_free.get();
+        let (from, to) = (self.slab_list(old), self.slab_list(free));
+
+        if !ptr::eq(from, to) {
+            let slab = from.list_del_init(slab).unwrap();
+            to.list_add(slab);
+        }
+        if old == self.num {
+            n.free_slabs -= 1;
+        }
+        if free == self.num {
+            n.free_slabs += 1;
+        }
+    }
+
+
+    /* Allocate a new free slab, list lock held */
+    fn cache_grow(&self, n: &mut KmemCacheNode) -> Result<(), i32> {
+        let mut objs = Vec::new();
+
+        objs.try_reserve_exact(self.num).map_err(|_| -ENOMEM)?;
+        /* Chain the slots in address order, the last one ends the freelist */
+        objs.extend((1..=self.num as u32).map(|next| {
+            UnsafeCell::new(KmemObjSlot {
+                next_free: if next == self.num as u32 { SLAB_FREELIST_END } else { next },
+            })
+        }));
+
+        self.slabs_free.list_add_tail(SyncListNode::new(Slab {
+            objs: objs.into_boxed_slice(),
+            freelist: Cell::new(0),
+            nr_free: Cell::new(self.num),
+        }));
+        n.num_slabs += 1;
+        n.free_slabs += 1;
+        n.free_objects += self.num;
+        Ok(())
+    }
+
+
+    /* Refill the empty array cache @ac with up to batchcount slots */
+    fn cache_alloc_refill(&self, ac: &mut Vec<KmemSlot<T>>) -> Result<(), i32> {
+        let mut n = self.list_lock.spin_lock();
+
+        while ac.len() < self.batchcount {
+            let slab = match self.slabs_partial.list_first_entry().or_else(|| self.slabs_free.list_first_entry()) {
+                Some(slab) => slab,
+                None => match self.cache_grow(&mut n) {
+                    Ok(()) => continue,
+                    Err(_) if !ac.is_empty() => break,
+                    Err(err) => return Err(err),
+                },
+            };
+
+            let old = slab.item.nr_free.get();
+            while ac.len() < self.batchcount {
+                let Some(idx) = slab.item.slab_get_obj() else {
+                    break;
+                };
+                ac.push(KmemSlot { slab: Arc::as_ptr(&slab), idx });
+                n.free_objects -= 1;
+            }
+            self.fixup_slab_list(&mut n, &slab, old);
+        }
+        Ok(())
+    }
+
+
+    /* Give @slots back to their slabs and release surplus free slabs */
+    fn free_block<I: IntoIterator<Item = KmemSlot<T>>>(&self, slots: I) {
+        let mut n = self.list_lock.spin_lock();
+
+        for slot in slots {
+            let slab = unsafe { &*slot.slab };
+            let old = slab.item.nr_free.get();
+
+            slab.item.slab_put_obj(slot.idx);
+            n.free_objects += 1;
+            self.fixup_slab_list(&mut n, slab, old);
+        }
+
+        while n.free_objects > self.free_limit {
+            if self.slabs_destroy_one(&mut n).is_none() {
+                break;
+            }
+        }
+    }
+
+
+    /* Release the first free slab, list lock held */
+    fn slabs_destroy_one(&self, n: &mut KmemCacheNode) -> Option<()> {
+        let slab = self.slabs_free.list_first_entry()?;
+
+        self.slabs_free.list_del_init(&slab)?;
+        n.num_slabs -= 1;
+        n.free_slabs -= 1;
+        n.free_objects -= self.num;
+        Some(())
+    }
+
+
+    /**
+     * kmem_cache_alloc - allocate an object
+     * @value: the initial value of the object
+     *
+     * Return: the object, or -ENOMEM if a new slab can't be allocated.
+     */
+    pub fn kmem_cache_alloc(self: &Arc<Self>, value: T) -> Result<KmemObj<T>, i32> {
+        let slot = self.cache_alloc()?;
+        let obj = slot.obj();
+
+        unsafe { obj.write(value) };
+        Ok(KmemObj {
+            cache: self.clone(),
+            slot,
+            obj: unsafe { NonNull::new_unchecked(obj) },
+        })
+    }
+
+
+    /* Take a slot from the array cache, the caller initializes the object */
+    fn cache_alloc(&self) -> Result<KmemSlot<T>, i32> {
+        let mut ac = self.cpu_cache().entries.spin_lock();
+
+        if ac.is_empty() {
+            self.cache_alloc_refill(&mut ac)?;
+        }
+        self.active_objs.fetch_add(1, Ordering::Relaxed);
+        Ok(ac.pop().unwrap())
+    }
+
+
+    /* Return the slot of a dropped object to the array cache */
+    fn kmem_cache_free(&self, slot: KmemSlot<T>) {
+        let mut ac = self.cpu_cache().entries.spin_lock();
+
+        self.active_objs.fetch_sub(1, Ordering::Relaxed);
+        if ac.len() >= self.limit {
+            /* cache_flusharray(): the oldest slots go back first */
+            let batch = self.batchcount.min(ac.len());
+            self.free_block(ac.drain(..batch));
+        }
+        ac.push(slot);
+    }
+
+
+    /**
+     * kmem_cache_shrink - release the unused memory of the cache
+     *
+     * Drains the array caches of every cpu and releases every free slab.
+     *
+     * Return: the number of slabs released.
+     */
+    pub fn kmem_cache_shrink(&self) -> usize {
+        for cpu in self.array.iter() {
+            let mut ac = cpu.entries.spin_lock();
+            self.free_block(ac.drain(..));
+        }
+
+        let mut n = self.list_lock.spin_lock();
+        let mut released = 0;
+        while self.slabs_destroy_one(&mut n).is_some() {
+            released += 1;
+        }
+        released
+    }
+
+
+    /**
+     * kmem_cache_stats - snapshot of the counters of the cache
+     *
+     * The counters are only consistent with each other if no object is
+     * allocated or freed meanwhile.
+     */
+    pub fn kmem_cache_stats(&self) -> KmemCacheStats {
+        let cached_objs = self.array.iter().map(|cpu| cpu.entries.spin_lock().len()).sum();
+        let n = self.list_lock.spin_lock();
+
+        KmemCacheStats {
+            active_objs: self.active_objs.load(Ordering::Relaxed),
+            num_objs: n.num_slabs * self.num,
+            cached_objs,
+            active_slabs: n.num_slabs - n.free_slabs,
+            num_slabs: n.num_slabs,
+            objs_per_slab: self.num,
+        }
+    }
+}
+
+
+#[allow(dead_code)]
+impl<T> KmemObj<T> {
+    /**
+     * kmem_obj_cache - the cache the object was allocated from
+     */
+    pub fn kmem_obj_cache(this: &Self) -> &Arc<KmemCache<T>> {
+        &this.cache
+    }
+
+
+    /**
+     * into_inner - move the value out and free the object
+     */
+    pub fn into_inner(this: Self) -> T {
+        let this = mem::ManuallyDrop::new(this);
+        let value = unsafe { this.obj.as_ptr().read() };
+        let cache = unsafe { ptr::read(&this.cache) };
+
+        cache.kmem_cache_free(this.slot);
+        value
+    }
+}
+
+
+impl<T> Deref for KmemObj<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { self.obj.as_ref() }
+    }
+}
+
+
+impl<T> DerefMut for KmemObj<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { self.obj.as_mut() }
+    }
+}
+
+
+impl<T: fmt::Debug> fmt::Debug for KmemObj<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+
+impl<T> Drop for KmemObj<T> {
+    fn drop(&mut self) {
+        unsafe { ptr::drop_in_place(self.obj.as_ptr()) };
+        self.cache.kmem_cache_free(self.slot);
+    }
+}
+
+
+/**
+ * struct KmemArcInner - the object behind a KmemArc
+ *
+ * @data comes first, so a pointer to the object is a pointer to @data.
+ * The object keeps its own slot and its cache alive, so the last
+ * reference can give the slot back.
+ */
+#[repr(C)]
+pub struct KmemArcInner<T> {
+    data: T,
+    count: AtomicUsize,
+    slot: KmemSlot<KmemArcInner<T>>,
+    cache: Arc<KmemArcCache<T>>,
+}
+
+
+/* A cache of objects shared through KmemArc */
+pub type KmemArcCache<T> = KmemCache<KmemArcInner<T>>;
+
+
+/**
+ * struct KmemArc - a reference counted object allocated from a KmemCache
+ *
+ * Shares its object like an Arc does. Dropping the last reference drops
+ * the object and gives the slot back to the cache.
+ */
+pub struct KmemArc<T> {
+    ptr: NonNull<KmemArcInner<T>>,
+}
+
+unsafe impl<T: Send + Sync> Send for KmemArc<T> {}
+unsafe impl<T: Send + Sync> Sync for KmemArc<T> {}
+
+
+#[allow(dead_code)]
+impl<T> KmemArc<T> {
+    /**
+     * new_in - allocate a shared object from a cache
+     * @cache: the cache to allocate from
+     * @data: the initial value of the object
+     *
+     * Return: the only reference to the object, or -ENOMEM if a new slab
+     * can't be allocated.
+     */
+    pub fn new_in(cache: &Arc<KmemArcCache<T>>, data: T) -> Result<Self, i32> {
+        let slot = cache.cache_alloc()?;
+        let obj = slot.obj();
+
+        unsafe {
+            obj.write(KmemArcInner {
+                data,
+                count: AtomicUsize::new(1),
+                slot,
+                cache: cache.clone(),
+            });
+        }
+        Ok(KmemArc {
+            ptr: unsafe { NonNull::new_unchecked(obj) },
+        })
+    }
+
+
+    fn inner(&self) -> &KmemArcInner<T> {
+        unsafe { self.ptr.as_ref() }
+    }
+
+
+    pub fn strong_count(this: &Self) -> usize {
+        this.inner().count.load(Ordering::Relaxed)
+    }
+
+
+    pub fn ptr_eq(this: &Self, other: &Self) -> bool {
+        this.ptr == other.ptr
+    }
+
+
+    /**
+     * into_raw - leak the reference as a pointer to the object
+     */
+    pub fn into_raw(this: Self) -> *const T {
+        mem::ManuallyDrop::new(this).ptr.as_ptr() as *const T
+    }
+
+
+    /**
+     * from_raw - take back a reference leaked by into_raw()
+     *
+     * # Safety
+     *
+     * @ptr must come from into_raw() and its reference is consumed.
+     */
+    pub unsafe fn from_raw(ptr: *const T) -> Self {
+        KmemArc {
+            ptr: NonNull::new_unchecked(ptr as *mut KmemArcInner<T>),
+        }
+    }
+
+
+    /**
+     * increment_strong_count - take a new reference through a raw pointer
+     *
+     * # Safety
+     *
+     * @ptr must come from into_raw() and its reference must still be leaked.
+     */
+    pub unsafe fn increment_strong_count(ptr: *const T) {
+        (*(ptr as *const KmemArcInner<T>)).count.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+
+impl<T> Clone for KmemArc<T> {
+    fn clone(&self) -> Self {
+        self.inner().count.fetch_add(1, Ordering::Relaxed);
+        KmemArc { ptr: self.ptr }
+    }
+}
+
+
+impl<T> Deref for KmemArc<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.inner().data
+    }
+}
+
+
+impl<T: fmt::Debug> fmt::Debug for KmemArc<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+
+impl<T> Drop for KmemArc<T> {
+    fn drop(&mut self) {
+        if self.inner().count.fetch_sub(1, Ordering::Release) != 1 {
+            return;
+        }
+        /* Pairs with the release above, like the drop of an Arc */
+        atomic::fence(Ordering::Acquire);
+
+        unsafe {
+            let inner = self.ptr.as_ptr();
+            let cache = ptr::read(&(*inner).cache);
+            let slot = (*inner).slot;
+
+            ptr::drop_in_place(&mut (*inner).data);
+            cache.kmem_cache_free(slot);
+        }
+    }
+}
+
+
+/* Sections for Unit tests */
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use std::thread;
+
+    use super::*;
+
+    #[test]
+    fn test_kmem_cache_alloc() {
+        let cache = KmemCache::<u64>::kmem_cache_create("u64", 1).unwrap();
+
+        assert_eq!(KmemCache::<u64>::kmem_cache_create("u64", 0).err(), Some(-EINVAL));
+        assert_eq!(cache.kmem_cache_name(), "u64");
+        assert_eq!(cache.kmem_cache_stats(), KmemCacheStats { objs_per_slab: 512, ..Default::default() });
+
+        let mut a = cache.kmem_cache_alloc(1).unwrap();
+        let b = cache.kmem_cache_alloc(2).unwrap();
+        *a += 10;
+        assert_eq!((*a, *b), (11, 2));
+
+        /* The first refill took batchcount slots of one slab */
+        let stats = cache.kmem_cache_stats();
+        assert_eq!(stats.active_objs, 2);
+        assert_eq!(stats.cached_objs, 58);
+        assert_eq!((stats.active_slabs, stats.num_slabs, stats.num_objs), (1, 1, 512));
+
+        drop(a);
+        assert_eq!(KmemObj::into_inner(b), 2);
+        let stats = cache.kmem_cache_stats();
+        assert_eq!((stats.active_objs, stats.cached_objs), (0, 60));
+    }
+
+
+    #[test]
+    fn test_kmem_cache_reuse() {
+        let cache = KmemCache::<[u8; 100]>::kmem_cache_create("buf", 1).unwrap();
+        let mut objs: Vec<_> = (0..100).map(|i| cache.kmem_cache_alloc([i; 100]).unwrap()).collect();
+
+        let addrs: HashSet<_> = objs.iter().map(|obj| &**obj as *const _).collect();
+        assert_eq!(addrs.len(), 100);
+        for (i, obj) in objs.iter().enumerate() {
+            assert!(obj.iter().all(|&b| b == i as u8));
+        }
+
+        /* A freed slot is the next one handed out on the same cpu */
+        let freed = objs.swap_remove(42);
+        let addr = &*freed as *const [u8; 100];
+        drop(freed);
+        let obj = cache.kmem_cache_alloc([0; 100]).unwrap();
+        assert_eq!(&*obj as *const _, addr);
+    }
+
+
+    #[test]
+    fn test_kmem_cache_drop() {
+        let cache = KmemCache::<Arc<()>>::kmem_cache_create("arc", 2).unwrap();
+        let tracker = Arc::new(());
+
+        let mut objs: Vec<_> = (0..1000).map(|_| cache.kmem_cache_alloc(tracker.clone()).unwrap()).collect();
+        assert_eq!(Arc::strong_count(&tracker), 1001);
+        let kept: Vec<Arc<()>> = objs[..10].iter().map(|obj| Arc::clone(obj)).collect();
+        assert_eq!(Arc::strong_count(&tracker), 1011);
+        objs.truncate(10);
+        assert_eq!(Arc::strong_count(&tracker), 21);
+        drop(objs);
+        assert_eq!(Arc::strong_count(&tracker), 11);
+
+        let value = KmemObj::into_inner(cache.kmem_cache_alloc(tracker.clone()).unwrap());
+        assert_eq!(Arc::strong_count(&tracker), 12);
+        drop((value, kept));
+        assert_eq!(Arc::strong_count(&tracker), 1);
+        assert_eq!(cache.kmem_cache_stats().active_objs, 0);
+    }
+
+
+    #[test]
+    fn test_kmem_cache_shrink() {
+        let cache = KmemCache::<u64>::kmem_cache_create("u64", 1).unwrap();
+        let mut objs: Vec<_> = (0..2000).map(|i| cache.kmem_cache_alloc(i).unwrap()).collect();
+
+        let stats = cache.kmem_cache_stats();
+        assert_eq!(stats.active_objs, 2000);
+        assert_eq!(stats.num_slabs, 4);
+        assert!(stats.cached_objs < 60);
+
+        /* Keep one object so that its slab survives the shrink */
+        let kept = objs.swap_remove(0);
+        drop(objs);
+        let stats = cache.kmem_cache_stats();
+        assert_eq!(stats.active_objs, 1);
+        assert!(stats.cached_objs <= 120);
+        assert!(stats.num_slabs < 4);
+
+        cache.kmem_cache_shrink();
+        let stats = cache.kmem_cache_stats();
+        assert_eq!((stats.cached_objs, stats.active_slabs, stats.num_slabs), (0, 1, 1));
+        assert_eq!(*kept, 0);
+
+        drop(kept);
+        assert_eq!(cache.kmem_cache_shrink(), 1);
+        assert_eq!(cache.kmem_cache_stats().num_slabs, 0);
+    }
+
+
+    struct StackNode {
+        val: i32,
+        next: Option<KmemObj<StackNode>>,
+    }
+
+
+    #[test]
+    fn test_kmem_cache_list_nodes() {
+        let cache = KmemCache::<StackNode>::kmem_cache_create("stack_node", 1).unwrap();
+        let mut head: Option<KmemObj<StackNode>> = None;
+
+        for val in 0..300 {
+            let next = head.take();
+            head = Some(cache.kmem_cache_alloc(StackNode { val, next }).unwrap());
+        }
+        assert_eq!(cache.kmem_cache_stats().active_objs, 300);
+
+        let mut expected = 299;
+        while let Some(node) = head {
+            let node = KmemObj::into_inner(node);
+            assert_eq!(node.val, expected);
+            expected -= 1;
+            head = node.next;
+        }
+        assert_eq!(expected, -1);
+        assert_eq!(cache.kmem_cache_stats().active_objs, 0);
+
+        /* The nodes of the crate's lists can come from a cache too */
+        let nodes = KmemArcCache::<SyncListNode<i32>>::kmem_cache_create("list_node", 1).unwrap();
+        let list: SyncListHead<i32, KmemArc<SyncListNode<i32>>> = SyncListHead::new();
+        for val in [5, 3, 9, 1, 7] {
+            assert!(list.list_add_tail(SyncListNode::new_in(&nodes, val).unwrap()));
+        }
+        assert_eq!(nodes.kmem_cache_stats().active_objs, 5);
+
+        /* Entries handed out by the list are references, not new objects */
+        let first = list.list_first_entry().unwrap();
+        assert_eq!((first.item, KmemArc::strong_count(&first)), (5, 2));
+        assert_eq!(nodes.kmem_cache_stats().active_objs, 5);
+
+        list.list_sort(|a, b| a.cmp(b));
+        let mut sorted = Vec::new();
+        list.list_for_each_entry(|node| sorted.push(node.item));
+        assert_eq!(sorted, vec![1, 3, 5, 7, 9]);
+
+        let removed = list.list_del_init(&first).unwrap();
+        assert!(KmemArc::ptr_eq(&first, &removed));
+        drop((list, removed));
+        assert_eq!(nodes.kmem_cache_stats().active_objs, 1);
+        assert_eq!(KmemArc::strong_count(&first), 1);
+        drop(first);
+        assert_eq!(nodes.kmem_cache_stats().active_objs, 0);
+    }
+
+
+    #[test]
+    fn test_kmem_cache_concurrent() {
+        let cache = KmemCache::<(usize, usize)>::kmem_cache_create("pair", 2).unwrap();
+
+        let handles: Vec<_> = (0..4)
+            .map(|t| {
+                let cache = cache.clone();
+                thread::spawn(move || {
+                    let mut objs = Vec::new();
+                    for i in 0..2000 {
+                        objs.push(cache.kmem_cache_alloc((t, i)).unwrap());
+                        if i % 3 == 0 {
+                            objs.swap_remove(i % objs.len());
+                        }
+                        if i % 100 == 0 {
+                            thread::yield_now();
+                        }
+                    }
+                    assert!(objs.iter().all(|obj| obj.0 == t));
+                    objs
+                })
+            })
+            .collect();
+
+        let objs: Vec<_> = handles.into_iter().flat_map(|h| h.join().unwrap()).collect();
+        let addrs: HashSet<_> = objs.iter().map(|obj| &**obj as *const _).collect();
+        assert_eq!(addrs.len(), objs.len());
+        assert_eq!(cache.kmem_cache_stats().active_objs, objs.len());
+
+        drop(objs);
+        cache.kmem_cache_shrink();
+        assert_eq!(cache.kmem_cache_stats(), KmemCacheStats { objs_per_slab: 256, ..Default::default() });
+    }
+}
@@ -0,0 +1,173 @@
+use std::cell::UnsafeCell;
+use std::hint;
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+
+/* Spin this many times before yielding the CPU to the lock holder */
+const SPIN_LIMIT: u32 = 64;
+
+
+/**
+ * struct SpinLock - busy-waiting mutual exclusion lock
+ *
+ * The userspace counterpart of spinlock_t. Meant for short critical
+ * sections that never sleep; a waiter that spins for too long yields so
+ * that a preempted lock holder can make progress.
+ */
+pub struct SpinLock<T: ?Sized> {
+    locked: AtomicBool,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: ?Sized + Send> Send for SpinLock<T> {}
+unsafe impl<T: ?Sized + Send> Sync for SpinLock<T> {}
+
+impl<T: Default> Default for SpinLock<T> {
+    fn default() -> Self {
+        SpinLock::new(T::default())
+    }
+}
+
+#[allow(dead_code)]
+impl<T> SpinLock<T> {
+    /**
+     * spin_lock_init - initialize a spinlock protecting @data
+     */
+    pub const fn new(data: T) -> Self {
+        SpinLock {
+            locked: AtomicBool::new(false),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+
+    pub fn into_inner(self) -> T {
+        self.data.into_inner()
+    }
+}
+
+#[allow(dead_code)]
+impl<T: ?Sized> SpinLock<T> {
+    /**
+     * spin_lock - acquire the lock, spinning until it is available
+     *
+     * The lock is released when the returned guard is dropped.
+     */
+    pub fn spin_lock(&self) -> SpinLockGuard<'_, T> {
+        let mut spins = 0;
+
+        while self
+            .locked
+            .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            while self.locked.load(Ordering::Relaxed) {
+                if spins < SPIN_LIMIT {
+                    spins += 1;
+                    hint::spin_loop();
+                } else {
+                    thread::yield_now();
+                }
+            }
+        }
+        SpinLockGuard { lock: self }
+    }
+
+
+    /**
+     * spin_trylock - try to acquire the lock without spinning
+     *
+     * Return: the guard on success, %None if the lock is held.
+     */
+    pub fn spin_trylock(&self) -> Option<SpinLockGuard<'_, T>> {
+        self.locked
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .ok()
+            .map(|_| SpinLockGuard { lock: self })
+    }
+
+
+    /**
+     * spin_is_locked - check whether the lock is currently held
+     */
+    pub fn spin_is_locked(&self) -> bool {
+        self.locked.load(Ordering::Relaxed)
+    }
+
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.data.get_mut()
+    }
+}
+
+
+pub struct SpinLockGuard<'a, T: ?Sized> {
+    lock: &'a SpinLock<T>,
+}
+
+impl<T: ?Sized> Deref for SpinLockGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized> DerefMut for SpinLockGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for SpinLockGuard<'_, T> {
+    /* spin_unlock */
+    fn drop(&mut self) {
+        self.lock.locked.store(false, Ordering::Release);
+    }
+}
+
+
+/* Sections for Unit tests */
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+
+    #[test]
+    fn test_spin_trylock() {
+        let lock = SpinLock::new(0);
+
+        let guard = lock.spin_lock();
+        assert!(lock.spin_is_locked());
+        assert!(lock.spin_trylock().is_none());
+        drop(guard);
+
+        *lock.spin_trylock().unwrap() += 1;
+        assert!(!lock.spin_is_locked());
+        assert_eq!(lock.into_inner(), 1);
+    }
+
+
+    #[test]
+    fn test_spin_lock_contention() {
+        let lock = Arc::new(SpinLock::new(0usize));
+
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                let lock = lock.clone();
+                thread::spawn(move || {
+                    for _ in 0..10_000 {
+                        *lock.spin_lock() += 1;
+                    }
+                })
+            })
+            .collect();
+
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        assert_eq!(*lock.spin_lock(), 40_000);
+    }
+}
@@ -0,0 +1,1022 @@
+use std::cell::Cell;
+use std::cmp::Ordering;
+use std::marker::PhantomData;
+use std::ops::Deref;
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+use std::sync::atomic::{self, AtomicPtr};
+use std::sync::Arc;
+
+use crate::container_of;
+use crate::slab::{KmemArc, KmemArcCache};
+use crate::spinlock::SpinLock;
+
+
+/*
+ * The links of a node. They are only read or written while holding the
+ * lock of the list the node is on, which is recorded in @owner so that a
+ * list never touches a node linked on another list.
+ */
+struct SyncListLinks {
+    next: Cell<*const SyncListLinks>,
+    prev: Cell<*const SyncListLinks>,
+    owner: AtomicPtr<SyncListLinks>,
+}
+
+impl SyncListLinks {
+    fn new() -> Self {
+        SyncListLinks {
+            next: Cell::new(ptr::null()),
+            prev: Cell::new(ptr::null()),
+            owner: AtomicPtr::new(ptr::null_mut()),
+        }
+    }
+}
+
+
+/**
+ * struct SyncListNode - an entry of a SyncListHead
+ * @item: the payload of the entry
+ *
+ * Nodes are reference counted. While a node is linked, its list holds one
+ * reference to it.
+ */
+pub struct SyncListNode<T> {
+    links: SyncListLinks,
+    pub item: T,
+}
+
+/* The links are protected by the lock of the owning SyncListHead */
+unsafe impl<T: Send + Sync> Send for SyncListNode<T> {}
+unsafe impl<T: Send + Sync> Sync for SyncListNode<T> {}
+
+#[allow(dead_code)]
+impl<T> SyncListNode<T> {
+    pub fn new(item: T) -> Arc<Self> {
+        Arc::new(SyncListNode {
+            links: SyncListLinks::new(),
+            item,
+        })
+    }
+
+
+    /**
+     * new_in - allocate a node from a cache
+     * @cache: the cache to allocate the node from
+     * @item: the payload of the node
+     *
+     * The node is put in a SyncListHead<T, KmemArc<SyncListNode<T>>>.
+     *
+     * Return: the node, or -ENOMEM if the cache can't grow.
+     */
+    pub fn new_in(cache: &Arc<KmemArcCache<Self>>, item: T) -> Result<KmemArc<Self>, i32> {
+        KmemArc::new_in(
+            cache,
+            SyncListNode {
+                links: SyncListLinks::new(),
+                item,
+            },
+        )
+    }
+
+
+    /**
+     * list_unlinked - tests whether the node is on no list at all
+     *
+     * The answer may be stale by the time it is used, unless the caller
+     * serializes with every list the node could be added to.
+     */
+    pub fn list_unlinked(&self) -> bool {
+        self.links.owner.load(atomic::Ordering::Relaxed).is_null()
+    }
+}
+
+
+/**
+ * trait SyncListPtr - owning pointer through which a list holds its nodes
+ *
+ * Arc is the default. Other reference counted pointers, like a KmemArc
+ * allocated from a KmemCache, let the nodes themselves come from a cache.
+ *
+ * # Safety
+ *
+ * into_raw() must leak one reference, which keeps the node alive until
+ * it is given back by from_raw().
+ */
+pub unsafe trait SyncListPtr<T>: Deref<Target = SyncListNode<T>> + Sized {
+    fn into_raw(this: Self) -> *const SyncListNode<T>;
+
+    /**
+     * from_raw - take back a reference leaked by into_raw()
+     *
+     * # Safety
+     *
+     * @ptr must come from into_raw() and its reference is consumed.
+     */
+    unsafe fn from_raw(ptr: *const SyncListNode<T>) -> Self;
+
+    /**
+     * clone_raw - take a new reference to a node
+     *
+     * # Safety
+     *
+     * @ptr must come from into_raw() and its reference must still be leaked.
+     */
+    unsafe fn clone_raw(ptr: *const SyncListNode<T>) -> Self;
+}
+
+unsafe impl<T> SyncListPtr<T> for Arc<SyncListNode<T>> {
+    fn into_raw(this: Self) -> *const SyncListNode<T> {
+        Arc::into_raw(this)
+    }
+
+    unsafe fn from_raw(ptr: *const SyncListNode<T>) -> Self {
+        Arc::from_raw(ptr)
+    }
+
+    unsafe fn clone_raw(ptr: *const SyncListNode<T>) -> Self {
+        Arc::increment_strong_count(ptr);
+        Arc::from_raw(ptr)
+    }
+}
+
+unsafe impl<T> SyncListPtr<T> for KmemArc<SyncListNode<T>> {
+    fn into_raw(this: Self) -> *const SyncListNode<T> {
+        KmemArc::into_raw(this)
+    }
+
+    unsafe fn from_raw(ptr: *const SyncListNode<T>) -> Self {
+        KmemArc::from_raw(ptr)
+    }
+
+    unsafe fn clone_raw(ptr: *const SyncListNode<T>) -> Self {
+        KmemArc::increment_strong_count(ptr);
+        KmemArc::from_raw(ptr)
+    }
+}
+
+
+/**
+ * struct SyncListHead - circular doubly linked list protected by a spinlock
+ *
+ * The thread-safe counterpart of ListHead. Every operation takes the
+ * head-level lock, the same way kernel code pairs a list_head with a
+ * spinlock_t, so lists and their nodes can be shared between threads.
+ * The list holds its nodes through @P, see SyncListPtr.
+ */
+pub struct SyncListHead<T, P: SyncListPtr<T> = Arc<SyncListNode<T>>> {
+    lock: SpinLock<()>,
+    head: Box<SyncListLinks>,
+    _marker: PhantomData<(P, SyncListNode<T>)>,
+}
+
+unsafe impl<T: Send + Sync, P: SyncListPtr<T> + Send + Sync> Send for SyncListHead<T, P> {}
+unsafe impl<T: Send + Sync, P: SyncListPtr<T> + Send + Sync> Sync for SyncListHead<T, P> {}
+
+impl<T, P: SyncListPtr<T>> Default for SyncListHead<T, P> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[allow(dead_code)]
+impl<T, P: SyncListPtr<T>> SyncListHead<T, P> {
+    /**
+     * init_list_head - Initialize a SyncListHead structure
+     *
+     * The result is an empty list.
+     */
+    pub fn new() -> Self {
+        let head = Box::new(SyncListLinks::new());
+
+        head.next.set(&*head);
+        head.prev.set(&*head);
+        SyncListHead {
+            lock: SpinLock::new(()),
+            head,
+            _marker: PhantomData,
+        }
+    }
+
+
+    fn head(&self) -> *const SyncListLinks {
+        &*self.head
+    }
+
+
+    fn owned(&self, entry: &SyncListNode<T>) -> bool {
+        ptr::eq(entry.links.owner.load(atomic::Ordering::Relaxed), self.head())
+    }
+
+
+    /* Convert a linked entry back to its node */
+    unsafe fn entry(links: *const SyncListLinks) -> *const SyncListNode<T> {
+        container_of!(links, SyncListNode<T>, links)
+    }
+
+
+    /* Take a new reference to a linked entry */
+    unsafe fn entry_get(links: *const SyncListLinks) -> P {
+        P::clone_raw(Self::entry(links))
+    }
+
+
+    /*
+     * Insert a new entry between two known consecutive entries.
+     *
+     * This is only for internal list manipulation where we know
+     * the prev/next entries already and hold the lock!
+     */
+    unsafe fn __list_add(new: *const SyncListLinks, prev: *const SyncListLinks, next: *const SyncListLinks) {
+        (*next).prev.set(new);
+        (*new).next.set(next);
+        (*new).prev.set(prev);
+        (*prev).next.set(new);
+    }
+
+
+    unsafe fn __list_del_entry(entry: *const SyncListLinks) {
+        let prev = (*entry).prev.get();
+        let next = (*entry).next.get();
+
+        (*next).prev.set(prev);
+        (*prev).next.set(next);
+    }
+
+
+    /*
+     * Link a node which is on no list, the lock must be held. The acquire
+     * pairs with the release in list_del_init(), as the list the node was
+     * deleted from used a different lock.
+     */
+    fn __list_add_valid(&self, new: P, tail: bool) -> bool {
+        if new
+            .links
+            .owner
+            .compare_exchange(ptr::null_mut(), self.head() as *mut _, atomic::Ordering::Acquire, atomic::Ordering::Relaxed)
+            .is_err()
+        {
+            return false;
+        }
+
+        let new = P::into_raw(new);
+        unsafe {
+            let links = &(*new).links as *const SyncListLinks;
+            let head = self.head();
+            if tail {
+                Self::__list_add(links, (*head).prev.get(), head);
+            } else {
+                Self::__list_add(links, head, (*head).next.get());
+            }
+        }
+        true
+    }
+
+
+    /**
+     * list_empty - tests whether a list is empty
+     */
+    pub fn list_empty(&self) -> bool {
+        let _guard = self.lock.spin_lock();
+        ptr::eq(self.head.next.get(), self.head())
+    }
+
+
+    /**
+     * list_add - add a new entry
+     * @new: new entry to be added
+     *
+     * Insert a new entry after the head.
+     * This is good for implementing stacks.
+     *
+     * Return: false if @new is already on a list, in which case nothing
+     * is done.
+     */
+    pub fn list_add(&self, new: P) -> bool {
+        let _guard = self.lock.spin_lock();
+        self.__list_add_valid(new, false)
+    }
+
+
+    /**
+     * list_add_tail - add a new entry
+     * @new: new entry to be added
+     *
+     * Insert a new entry before the head.
+     * This is useful for implementing queues.
+     *
+     * Return: false if @new is already on a list, in which case nothing
+     * is done.
+     */
+    pub fn list_add_tail(&self, new: P) -> bool {
+        let _guard = self.lock.spin_lock();
+        self.__list_add_valid(new, true)
+    }
+
+
+    /**
+     * list_del_init - deletes entry from list and reinitialize it.
+     * @entry: the element to delete from the list.
+     *
+     * Return: the reference the list held on @entry, or %None if @entry
+     * is not on this list.
+     */
+    pub fn list_del_init(&self, entry: &SyncListNode<T>) -> Option<P> {
+        let _guard = self.lock.spin_lock();
+
+        if !self.owned(entry) {
+            return None;
+        }
+        unsafe {
+            Self::__list_del_entry(&entry.links);
+            entry.links.next.set(ptr::null());
+            entry.links.prev.set(ptr::null());
+            entry.links.owner.store(ptr::null_mut(), atomic::Ordering::Release);
+            Some(P::from_raw(entry))
+        }
+    }
+
+
+    /**
+     * list_replace_first - replace the first entry matching a predicate
+     * @pred: tells whether an entry is the one to replace
+     * @new: the entry to put in its place, which must be on no list
+     *
+     * Looking the entry up and replacing it is one operation under the
+     * lock, so no other thread can move the entry in between.
+     *
+     * Return: the reference the list held on the replaced entry, or @new
+     * back if no entry matched or @new is already on a list.
+     */
+    pub fn list_replace_first<F: FnMut(&T) -> bool>(&self, mut pred: F, new: P) -> Result<P, P> {
+        let _guard = self.lock.spin_lock();
+        let head = self.head();
+        let mut pos = self.head.next.get();
+
+        while !ptr::eq(pos, head) {
+            let old = unsafe { &*Self::entry(pos) };
+            if pred(&old.item) {
+                break;
+            }
+            pos = old.links.next.get();
+        }
+        if ptr::eq(pos, head)
+            || new
+                .links
+                .owner
+                .compare_exchange(ptr::null_mut(), head as *mut _, atomic::Ordering::Acquire, atomic::Ordering::Relaxed)
+                .is_err()
+        {
+            return Err(new);
+        }
+
+        let new = P::into_raw(new);
+        unsafe {
+            let old = Self::entry(pos);
+            Self::__list_add(&(*new).links, (*pos).prev.get(), pos);
+            Self::__list_del_entry(pos);
+            (*pos).next.set(ptr::null());
+            (*pos).prev.set(ptr::null());
+            (*pos).owner.store(ptr::null_mut(), atomic::Ordering::Release);
+            Ok(P::from_raw(old))
+        }
+    }
+
+
+    /**
+     * list_move - move an entry to the front of the list
+     * @entry: the entry to move, which must be on this list
+     *
+     * Return: false if @entry is not on this list.
+     */
+    pub fn list_move(&self, entry: &SyncListNode<T>) -> bool {
+        let _guard = self.lock.spin_lock();
+
+        if !self.owned(entry) {
+            return false;
+        }
+        unsafe {
+            Self::__list_del_entry(&entry.links);
+            Self::__list_add(&entry.links, self.head(), self.head.next.get());
+        }
+        true
+    }
+
+
+    /**
+     * list_move_tail - move an entry to the back of the list
+     * @entry: the entry to move, which must be on this list
+     *
+     * Return: false if @entry is not on this list.
+     */
+    pub fn list_move_tail(&self, entry: &SyncListNode<T>) -> bool {
+        let _guard = self.lock.spin_lock();
+
+        if !self.owned(entry) {
+            return false;
+        }
+        unsafe {
+            Self::__list_del_entry(&entry.links);
+            Self::__list_add(&entry.links, self.head.prev.get(), self.head());
+        }
+        true
+    }
+
+
+    /* Move every entry of @list between @prev and @next, both locks held */
+    unsafe fn __list_splice(&self, list: &Self, prev: *const SyncListLinks, next: *const SyncListLinks) {
+        let first = list.head.next.get();
+        let last = list.head.prev.get();
+
+        let mut pos = first;
+        while !ptr::eq(pos, list.head()) {
+            (*pos).owner.store(self.head() as *mut _, atomic::Ordering::Relaxed);
+            pos = (*pos).next.get();
+        }
+
+        (*first).prev.set(prev);
+        (*prev).next.set(first);
+        (*last).next.set(next);
+        (*next).prev.set(last);
+
+        list.head.next.set(list.head());
+        list.head.prev.set(list.head());
+    }
+
+
+    /* Lock both lists in address order so that concurrent splices cannot deadlock */
+    fn splice_locked(&self, list: &Self, tail: bool) {
+        if ptr::eq(self, list) {
+            return;
+        }
+        let (_first, _second) = if self.head() < list.head() {
+            (self.lock.spin_lock(), list.lock.spin_lock())
+        } else {
+            (list.lock.spin_lock(), self.lock.spin_lock())
+        };
+
+        if ptr::eq(list.head.next.get(), list.head()) {
+            return;
+        }
+        unsafe {
+            if tail {
+                self.__list_splice(list, self.head.prev.get(), self.head());
+            } else {
+                self.__list_splice(list, self.head(), self.head.next.get());
+            }
+        }
+    }
+
+
+    /**
+     * list_splice - join two lists, this is designed for stacks
+     * @list: the new list to add.
+     *
+     * The entries of @list are added at the front of this list and @list
+     * is reinitialised, as its entries are now protected by this list's
+     * lock. This is O(n) in the length of @list.
+     */
+    pub fn list_splice(&self, list: &Self) {
+        self.splice_locked(list, false);
+    }
+
+
+    /**
+     * list_splice_tail - join two lists, each list being a queue
+     * @list: the new list to add.
+     *
+     * The entries of @list are added at the back of this list and @list
+     * is reinitialised.
+     */
+    pub fn list_splice_tail(&self, list: &Self) {
+        self.splice_locked(list, true);
+    }
+
+
+    /**
+     * list_first_entry - get the first element from a list
+     */
+    pub fn list_first_entry(&self) -> Option<P> {
+        let _guard = self.lock.spin_lock();
+        let first = self.head.next.get();
+
+        (!ptr::eq(first, self.head())).then(|| unsafe { Self::entry_get(first) })
+    }
+
+
+    /**
+     * list_last_entry - get the last element from a list
+     */
+    pub fn list_last_entry(&self) -> Option<P> {
+        let _guard = self.lock.spin_lock();
+        let last = self.head.prev.get();
+
+        (!ptr::eq(last, self.head())).then(|| unsafe { Self::entry_get(last) })
+    }
+
+
+    /**
+     * list_next_entry - get the next element in list
+     * @entry: the current entry, which must be on this list
+     *
+     * Return: %None if @entry is the last entry or not on this list.
+     */
+    pub fn list_next_entry(&self, entry: &SyncListNode<T>) -> Option<P> {
+        let _guard = self.lock.spin_lock();
+
+        if !self.owned(entry) {
+            return None;
+        }
+        let next = entry.links.next.get();
+        (!ptr::eq(next, self.head())).then(|| unsafe { Self::entry_get(next) })
+    }
+
+
+    /**
+     * list_for_each_entry - iterate over list of given type
+     * @f: called with each entry, under the list lock
+     *
+     * @f must not call back into this list, the lock is not recursive.
+     */
+    pub fn list_for_each_entry<F: FnMut(&SyncListNode<T>)>(&self, mut f: F) {
+        let _guard = self.lock.spin_lock();
+
+        let mut pos = self.head.next.get();
+        while !ptr::eq(pos, self.head()) {
+            unsafe {
+                f(&*Self::entry(pos));
+                pos = (*pos).next.get();
+            }
+        }
+    }
+
+
+    /**
+     * list_count_nodes - count nodes in the list
+     */
+    pub fn list_count_nodes(&self) -> usize {
+        let mut count = 0;
+        self.list_for_each_entry(|_| count += 1);
+        count
+    }
+
+
+    /*
+     * Returns a list organized in an intermediate format suited
+     * to chaining of merge() calls: null-terminated, no reserved or
+     * sentinel head node, "prev" links not maintained.
+     */
+    unsafe fn merge<F>(cmp: &mut F, mut a: *const SyncListLinks, mut b: *const SyncListLinks) -> *const SyncListLinks
+    where
+        F: FnMut(&T, &T) -> Ordering,
+    {
+        let head = Cell::new(ptr::null());
+        let mut tail = &head;
+
+        loop {
+            /* if equal, take 'a' -- important for sort stability */
+            if cmp(&(*Self::entry(a)).item, &(*Self::entry(b)).item) != Ordering::Greater {
+                tail.set(a);
+                tail = &(*a).next;
+                a = (*a).next.get();
+                if a.is_null() {
+                    tail.set(b);
+                    break;
+                }
+            } else {
+                tail.set(b);
+                tail = &(*b).next;
+                b = (*b).next.get();
+                if b.is_null() {
+                    tail.set(a);
+                    break;
+                }
+            }
+        }
+        head.get()
+    }
+
+
+    /*
+     * Combine final list merge with restoration of standard doubly-linked
+     * list structure. This approach duplicates code from merge(), but
+     * runs faster than the tidier alternatives of either a separate final
+     * prev-link restoration pass, or maintaining the prev links
+     * throughout.
+     */
+    unsafe fn merge_final<F>(cmp: &mut F, head: *const SyncListLinks, mut a: *const SyncListLinks, mut b: *const SyncListLinks)
+    where
+        F: FnMut(&T, &T) -> Ordering,
+    {
+        let mut tail = head;
+
+        loop {
+            /* if equal, take 'a' -- important for sort stability */
+            if cmp(&(*Self::entry(a)).item, &(*Self::entry(b)).item) != Ordering::Greater {
+                (*tail).next.set(a);
+                (*a).prev.set(tail);
+                tail = a;
+                a = (*a).next.get();
+                if a.is_null() {
+                    break;
+                }
+            } else {
+                (*tail).next.set(b);
+                (*b).prev.set(tail);
+                tail = b;
+                b = (*b).next.get();
+                if b.is_null() {
+                    b = a;
+                    break;
+                }
+            }
+        }
+
+        /* Finish linking remainder of list b on to tail */
+        (*tail).next.set(b);
+        loop {
+            (*b).prev.set(tail);
+            tail = b;
+            b = (*b).next.get();
+            if b.is_null() {
+                break;
+            }
+        }
+
+        /* And the final links to make a circular doubly-linked list */
+        (*tail).next.set(head);
+        (*head).prev.set(tail);
+    }
+
+
+    /**
+     * list_sort - sort a list
+     * @cmp: the elements comparison function
+     *
+     * The comparison function @cmp must return > 0 if @a should sort after
+     * @b ("@a > @b" if you want an ascending sort), and <= 0 if @a should
+     * sort before @b *or* their original order should be preserved. The
+     * sort is stable, and it is the same bottom-up merge sort as the one of
+     * lib/list_sort.c, done in place under the list lock.
+     *
+     * If @cmp panics, the remaining comparisons keep the original order,
+     * and the panic resumes once the list is whole again.
+     */
+    pub fn list_sort<F>(&self, mut cmp: F)
+    where
+        F: FnMut(&T, &T) -> Ordering,
+    {
+        let _guard = self.lock.spin_lock();
+        let head = self.head();
+        let mut list = self.head.next.get();
+        let pending: Cell<*const SyncListLinks> = Cell::new(ptr::null());
+        let mut count: usize = 0;
+
+        if ptr::eq(list, self.head.prev.get()) {
+            /* Zero or one elements */
+            return;
+        }
+
+        /*
+         * Unwinding out of the sort would leave the list null-terminated,
+         * its prev links pointing into the pending stack. Catch a panic of
+         * @cmp and finish the sort without calling it again instead.
+         */
+        let mut panicked = None;
+        let mut cmp = |a: &T, b: &T| {
+            if panicked.is_some() {
+                return Ordering::Less;
+            }
+            panic::catch_unwind(AssertUnwindSafe(|| cmp(a, b))).unwrap_or_else(|payload| {
+                panicked = Some(payload);
+                Ordering::Less
+            })
+        };
+
+        unsafe {
+            /* Convert to a null-terminated singly-linked list. */
+            (*self.head.prev.get()).next.set(ptr::null());
+
+            /*
+             * Data structure invariants:
+             * - All lists are singly linked and null-terminated; prev
+             *   pointers are not maintained.
+             * - pending is a prev-linked "list of lists" of sorted
+             *   sublists awaiting further merging.
+             * - Each of the sorted sublists is power-of-two in size.
+             * - Sublists are sorted by size and age, smallest & newest at front.
+             * - There are zero to two sublists of each size.
+             */
+            loop {
+                let mut bits = count;
+                let mut tail = &pending;
+
+                /* Find the least-significant clear bit in count */
+                while bits & 1 == 1 {
+                    tail = &(*tail.get()).prev;
+                    bits >>= 1;
+                }
+                /* Do the indicated merge */
+                if bits != 0 {
+                    let a = tail.get();
+                    let b = (*a).prev.get();
+
+                    let merged = Self::merge(&mut cmp, b, a);
+                    /* Install the merged result in place of the inputs */
+                    (*merged).prev.set((*b).prev.get());
+                    tail.set(merged);
+                }
+
+                /* Move one element from input list to pending */
+                (*list).prev.set(pending.get());
+                pending.set(list);
+                list = (*list).next.get();
+                (*pending.get()).next.set(ptr::null());
+                count += 1;
+
+                if list.is_null() {
+                    break;
+                }
+            }
+
+            /* End of input; merge together all the pending lists. */
+            list = pending.get();
+            let mut pending = (*pending.get()).prev.get();
+            loop {
+                let next = (*pending).prev.get();
+
+                if next.is_null() {
+                    break;
+                }
+                list = Self::merge(&mut cmp, pending, list);
+                pending = next;
+            }
+            /* The final merge, rebuilding prev links */
+            Self::merge_final(&mut cmp, head, pending, list);
+        }
+
+        if let Some(payload) = panicked {
+            panic::resume_unwind(payload);
+        }
+    }
+}
+
+impl<T, P: SyncListPtr<T>> Drop for SyncListHead<T, P> {
+    fn drop(&mut self) {
+        let head = self.head();
+        let mut pos = self.head.next.get();
+
+        while !ptr::eq(pos, head) {
+            unsafe {
+                let next = (*pos).next.get();
+                (*pos).owner.store(ptr::null_mut(), atomic::Ordering::Release);
+                drop(P::from_raw(Self::entry(pos)));
+                pos = next;
+            }
+        }
+    }
+}
+
+
+/* Sections for Unit tests */
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use rand::Rng;
+    use sorted_vec::SortedVec;
+
+    use super::*;
+
+    fn items(list: &SyncListHead<i32>) -> Vec<i32> {
+        let mut items = Vec::new();
+        list.list_for_each_entry(|node| items.push(node.item));
+        items
+    }
+
+
+    #[test]
+    fn test_list_add() {
+        let list = SyncListHead::new();
+        let a = SyncListNode::new(1);
+        let b = SyncListNode::new(2);
+
+        assert!(list.list_empty());
+        assert!(list.list_add(a.clone()));
+        assert!(list.list_add(b.clone()));
+        assert!(list.list_add_tail(SyncListNode::new(3)));
+
+        /* Adding a node twice is refused */
+        assert!(!list.list_add(a.clone()));
+        assert_eq!(items(&list), vec![2, 1, 3]);
+        assert!(Arc::ptr_eq(&list.list_first_entry().unwrap(), &b));
+        assert_eq!(list.list_last_entry().unwrap().item, 3);
+        assert_eq!(Arc::strong_count(&a), 2);
+    }
+
+
+    #[test]
+    fn test_list_del_init() {
+        let list1 = SyncListHead::new();
+        let list2 = SyncListHead::new();
+        let a = SyncListNode::new(1);
+
+        list1.list_add(a.clone());
+        list1.list_add(SyncListNode::new(2));
+
+        assert!(list2.list_del_init(&a).is_none());
+        assert!(list1.list_del_init(&a).is_some());
+        assert!(a.list_unlinked());
+        assert!(list1.list_del_init(&a).is_none());
+        assert_eq!(Arc::strong_count(&a), 1);
+
+        assert!(list2.list_add(a.clone()));
+        assert_eq!(items(&list1), vec![2]);
+        assert_eq!(items(&list2), vec![1]);
+
+        drop(list2);
+        assert!(a.list_unlinked());
+        assert_eq!(Arc::strong_count(&a), 1);
+    }
+
+
+    #[test]
+    fn test_list_move() {
+        let list = SyncListHead::new();
+        let nodes: Vec<_> = (0..4).map(SyncListNode::new).collect();
+
+        for node in &nodes {
+            list.list_add_tail(node.clone());
+        }
+
+        assert!(list.list_move(&nodes[2]));
+        assert_eq!(items(&list), vec![2, 0, 1, 3]);
+        assert!(list.list_move_tail(&nodes[0]));
+        assert_eq!(items(&list), vec![2, 1, 3, 0]);
+        assert!(!SyncListHead::<i32>::new().list_move(&nodes[0]));
+
+        assert!(Arc::ptr_eq(&list.list_next_entry(&nodes[1]).unwrap(), &nodes[3]));
+        assert!(list.list_next_entry(&nodes[0]).is_none());
+        assert!(SyncListHead::<i32>::new().list_next_entry(&nodes[1]).is_none());
+    }
+
+
+    #[test]
+    fn test_list_replace_first() {
+        let list = SyncListHead::new();
+        let nodes: Vec<_> = (0..4).map(SyncListNode::new).collect();
+
+        for node in &nodes[..3] {
+            list.list_add_tail(node.clone());
+        }
+
+        let Ok(old) = list.list_replace_first(|item| item % 2 == 1, nodes[3].clone()) else {
+            panic!("no odd entry");
+        };
+        assert!(Arc::ptr_eq(&old, &nodes[1]));
+        assert!(old.list_unlinked());
+        assert_eq!(items(&list), vec![0, 3, 2]);
+
+        /* No match, or a replacement already on a list, changes nothing */
+        let new = SyncListNode::new(5);
+        let Err(back) = list.list_replace_first(|item| *item > 10, new.clone()) else {
+            panic!("replaced without a match");
+        };
+        assert!(Arc::ptr_eq(&back, &new));
+        drop(back);
+        assert!(new.list_unlinked());
+        assert!(list.list_replace_first(|_| true, nodes[2].clone()).is_err());
+        assert_eq!(items(&list), vec![0, 3, 2]);
+        assert_eq!(Arc::strong_count(&nodes[1]), 2);
+    }
+
+
+    #[test]
+    fn test_list_splice() {
+        let list1 = SyncListHead::new();
+        let list2 = SyncListHead::new();
+        let a = SyncListNode::new(10);
+
+        for i in 0..3 {
+            list1.list_add_tail(SyncListNode::new(i));
+        }
+        list2.list_add_tail(a.clone());
+        list2.list_add_tail(SyncListNode::new(11));
+
+        list1.list_splice(&list2);
+        assert!(list2.list_empty());
+        assert_eq!(items(&list1), vec![10, 11, 0, 1, 2]);
+
+        /* The spliced entries now belong to list1 */
+        assert!(list2.list_del_init(&a).is_none());
+        assert!(list1.list_del_init(&a).is_some());
+
+        list2.list_add(SyncListNode::new(20));
+        list1.list_splice_tail(&list2);
+        list1.list_splice_tail(&list2);
+        assert_eq!(items(&list1), vec![11, 0, 1, 2, 20]);
+        assert_eq!(list1.list_count_nodes(), 5);
+    }
+
+
+    #[test]
+    fn test_list_sort() {
+        let mut rng = rand::thread_rng();
+        let mut map = SortedVec::new();
+        let list = SyncListHead::new();
+
+        for _ in 0..100 {
+            let num: i32 = rng.gen();
+            map.insert(num);
+            list.list_add_tail(SyncListNode::new(num));
+        }
+
+        list.list_sort(|a, b| a.cmp(b));
+        assert_eq!(items(&list), map.to_vec());
+
+        /* prev links are rebuilt */
+        let last = list.list_last_entry().unwrap();
+        assert_eq!(last.item, map[99]);
+    }
+
+
+    #[test]
+    fn test_list_sort_stable() {
+        let list = SyncListHead::new();
+
+        for i in 0..50 {
+            list.list_add_tail(SyncListNode::new((i % 5, i)));
+        }
+        list.list_sort(|a, b| a.0.cmp(&b.0));
+
+        let mut sorted = Vec::new();
+        list.list_for_each_entry(|node| sorted.push(node.item));
+        let mut expected: Vec<_> = (0..50).map(|i| (i % 5, i)).collect();
+        expected.sort_by_key(|&(k, _)| k);
+        assert_eq!(sorted, expected);
+    }
+
+
+    #[test]
+    fn test_list_sort_panic() {
+        let list = SyncListHead::new();
+        let nodes: Vec<_> = (0..100).map(|i| SyncListNode::new((i * 37) % 100)).collect();
+
+        for node in &nodes {
+            list.list_add_tail(node.clone());
+        }
+        let mut calls = 0;
+        let ret = panic::catch_unwind(AssertUnwindSafe(|| {
+            list.list_sort(|a, b| {
+                calls += 1;
+                assert!(calls < 200, "comparator gave up");
+                a.cmp(b)
+            })
+        }));
+        assert!(ret.is_err());
+        assert_eq!(calls, 200);
+
+        /* The list survived, every entry is still on it exactly once */
+        let mut seen = items(&list);
+        seen.sort();
+        assert!(seen.into_iter().eq(0..100));
+        assert_eq!(list.list_count_nodes(), 100);
+        assert!(list.list_del_init(&nodes[42]).is_some());
+        list.list_sort(|a, b| a.cmp(b));
+        assert!(items(&list).into_iter().eq((0..100).filter(|&i| i != (42 * 37) % 100)));
+    }
+
+
+    #[test]
+    fn test_list_threads() {
+        let list = Arc::new(SyncListHead::new());
+        let other = Arc::new(SyncListHead::new());
+
+        let threads: Vec<_> = (0..4)
+            .map(|t| {
+                let list = list.clone();
+                let other = other.clone();
+                thread::spawn(move || {
+                    for i in 0..1000 {
+                        let node = SyncListNode::new(t * 1000 + i);
+                        list.list_add_tail(node.clone());
+                        if i % 2 == 0 {
+                            assert!(list.list_del_init(&node).is_some());
+                            other.list_add(node);
+                        }
+                        if i % 100 == 0 {
+                            list.list_splice_tail(&other);
+                        }
+                    }
+                })
+            })
+            .collect();
+
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        list.list_splice_tail(&other);
+        list.list_sort(|a, b| a.cmp(b));
+        assert_eq!(items(&list), (0..4000).collect::<Vec<_>>());
+    }
+}
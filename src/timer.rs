@@ -0,0 +1,729 @@
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::time::Instant;
+
+use crate::bitmap::BitmapOps;
+use crate::declare_bitmap;
+use crate::errno::EBUSY;
+use crate::spinlock::SpinLock;
+use crate::sync_list::{SyncListHead, SyncListNode};
+
+/*
+ * Hierarchical timer wheel
+ *
+ * Port of the timer_list wheel of kernel/time/timer.c. The wheel has
+ * LVL_DEPTH levels of LVL_SIZE buckets each. Level 0 has a granularity
+ * of one jiffy and every further level is LVL_CLK_DIV times coarser, so
+ * a timer is hashed into the level whose range covers its timeout and
+ * never cascades to lower levels later on. The price is slack: a timer
+ * in level n fires up to LVL_GRAN(n) jiffies late, at most about 12%
+ * of its timeout, but never early.
+ *
+ * HZ 1000:
+ * Level Offset  Granularity            Range
+ *  0      0         1 ms                0 ms -         63 ms
+ *  1     64         8 ms               64 ms -        511 ms
+ *  2    128        64 ms              512 ms -       4095 ms (512ms - ~4s)
+ *  3    192       512 ms             4096 ms -      32767 ms (~4s - ~32s)
+ *  4    256      4096 ms (~4s)      32768 ms -     262143 ms (~32s - ~4m)
+ *  5    320     32768 ms (~32s)    262144 ms -    2097151 ms (~4m - ~34m)
+ *  6    384    262144 ms (~4m)    2097152 ms -   16777215 ms (~34m - ~4h)
+ *  7    448   2097152 ms (~34m)  16777216 ms -  134217727 ms (~4h - ~1d)
+ *  8    512  16777216 ms (~4h)  134217728 ms - 1073741822 ms (~1d - ~12d)
+ *
+ * Every bucket is a list of timers and a bitmap tells which buckets are
+ * not empty, so finding the next expiring bucket does not touch the
+ * lists at all.
+ */
+
+pub const HZ: u64 = 1000;
+
+const LVL_CLK_SHIFT: u32 = 3;
+const LVL_CLK_DIV: u64 = 1 << LVL_CLK_SHIFT;
+const LVL_CLK_MASK: u64 = LVL_CLK_DIV - 1;
+
+const fn lvl_shift(n: usize) -> u32 {
+    n as u32 * LVL_CLK_SHIFT
+}
+
+const fn lvl_gran(n: usize) -> u64 {
+    1 << lvl_shift(n)
+}
+
+/* The time start value for each level to select the bucket at enqueue time */
+const fn lvl_start(n: usize) -> u64 {
+    (LVL_SIZE as u64 - 1) << lvl_shift(n - 1)
+}
+
+const LVL_BITS: u32 = 6;
+const LVL_SIZE: usize = 1 << LVL_BITS;
+const LVL_MASK: u64 = LVL_SIZE as u64 - 1;
+
+const fn lvl_offs(n: usize) -> usize {
+    n * LVL_SIZE
+}
+
+const LVL_DEPTH: usize = 9;
+const WHEEL_SIZE: usize = LVL_SIZE * LVL_DEPTH;
+
+/* Timeouts beyond the cutoff are capped to WHEEL_TIMEOUT_MAX */
+pub const WHEEL_TIMEOUT_CUTOFF: u64 = lvl_start(LVL_DEPTH);
+pub const WHEEL_TIMEOUT_MAX: u64 = WHEEL_TIMEOUT_CUTOFF - lvl_gran(LVL_DEPTH - 1);
+
+const NEXT_TIMER_MAX_DELTA: u64 = (1 << 30) - 1;
+
+
+fn time_after_eq(a: u64, b: u64) -> bool {
+    a.wrapping_sub(b) as i64 >= 0
+}
+
+
+fn time_before(a: u64, b: u64) -> bool {
+    (a.wrapping_sub(b) as i64) < 0
+}
+
+
+pub type TimerClock = fn() -> u64;
+
+/**
+ * jiffies - default timer clock
+ *
+ * Ticks of 1/HZ seconds since the clock was first read in this process.
+ */
+pub fn jiffies() -> u64 {
+    static EPOCH: OnceLock<Instant> = OnceLock::new();
+    (EPOCH.get_or_init(Instant::now).elapsed().as_micros() as u64) * HZ / 1_000_000
+}
+
+
+/**
+ * struct TimerList - a timer
+ * @expires: jiffy at which the timer expires
+ * @idx: wheel bucket the timer is queued on
+ * @function: called with the timer when it expires
+ */
+pub struct TimerList {
+    expires: AtomicU64,
+    idx: AtomicUsize,
+    function: Box<dyn Fn(&Timer) + Send + Sync>,
+}
+
+pub type Timer = Arc<SyncListNode<TimerList>>;
+
+#[allow(dead_code)]
+impl TimerList {
+    pub fn expires(&self) -> u64 {
+        self.expires.load(Ordering::Relaxed)
+    }
+}
+
+
+/**
+ * timer_setup - prepare a timer for first use
+ * @function: the callback, it may re-arm the timer with mod_timer()
+ *
+ * A callback that keeps an Arc of the wheel forms a cycle while the timer
+ * is queued, use a Weak reference to re-arm.
+ */
+pub fn timer_setup<F>(function: F) -> Timer
+where
+    F: Fn(&Timer) + Send + Sync + 'static,
+{
+    SyncListNode::new(TimerList {
+        expires: AtomicU64::new(0),
+        idx: AtomicUsize::new(0),
+        function: Box::new(function),
+    })
+}
+
+
+/**
+ * timer_pending - is a timer pending?
+ *
+ * A timer is pending from the time it is queued until its callback is
+ * about to be called.
+ */
+pub fn timer_pending(timer: &Timer) -> bool {
+    !timer.list_unlinked()
+}
+
+
+/*
+ * Wheel state protected by the base lock.
+ * @clk: the next jiffy to process, one ahead of the last processed one
+ * @next_expiry: expiry of the first pending bucket
+ * @next_expiry_recalc: @next_expiry may be stale after a removal
+ * @timers_pending: any timer is queued
+ * @pending_map: buckets that are not empty
+ */
+struct TimerBase {
+    clk: u64,
+    next_expiry: u64,
+    next_expiry_recalc: bool,
+    timers_pending: bool,
+    pending_map: declare_bitmap!(WHEEL_SIZE),
+}
+
+
+/**
+ * struct TimerWheel - a timer base
+ * @base: clock and bucket bitmap
+ * @vectors: the buckets, only changed with @base locked
+ * @expiring: timers collected from expired buckets, about to be called
+ * @clock: source of the current jiffy
+ */
+pub struct TimerWheel {
+    base: SpinLock<TimerBase>,
+    vectors: Box<[SyncListHead<TimerList>]>,
+    expiring: SyncListHead<TimerList>,
+    clock: TimerClock,
+}
+
+
+/*
+ * Helper function to calculate the array index for a given expiry
+ * time. The timer never fires early: the expiry is rounded up to the
+ * granularity of the level, @bucket_expiry is the resulting jiffy.
+ */
+fn calc_index(expires: u64, lvl: usize) -> (usize, u64) {
+    let expires = (expires >> lvl_shift(lvl)) + 1;
+
+    (lvl_offs(lvl) + (expires & LVL_MASK) as usize, expires << lvl_shift(lvl))
+}
+
+
+fn calc_wheel_index(expires: u64, clk: u64) -> (usize, u64) {
+    let delta = expires.wrapping_sub(clk);
+
+    /* Already expired, fire on the next tick */
+    if (delta as i64) < 0 {
+        return ((clk & LVL_MASK) as usize, clk);
+    }
+    for lvl in 0..LVL_DEPTH - 1 {
+        if delta < lvl_start(lvl + 1) {
+            return calc_index(expires, lvl);
+        }
+    }
+    /* Force expire obscene large timeouts to expire at the capacity limit of the wheel */
+    let expires = if delta >= WHEEL_TIMEOUT_CUTOFF { clk + WHEEL_TIMEOUT_MAX } else { expires };
+    calc_index(expires, LVL_DEPTH - 1)
+}
+
+
+impl Default for TimerWheel {
+    fn default() -> Self {
+        TimerWheel::new()
+    }
+}
+
+#[allow(dead_code)]
+impl TimerWheel {
+    /**
+     * new - create an empty wheel driven by jiffies()
+     */
+    pub fn new() -> Self {
+        TimerWheel::with_clock(jiffies)
+    }
+
+
+    /**
+     * with_clock - create an empty wheel driven by @clock
+     *
+     * The wheel starts at the current value of @clock.
+     */
+    pub fn with_clock(clock: TimerClock) -> Self {
+        let clk = clock();
+
+        TimerWheel {
+            base: SpinLock::new(TimerBase {
+                clk,
+                next_expiry: clk.wrapping_add(NEXT_TIMER_MAX_DELTA),
+                next_expiry_recalc: false,
+                timers_pending: false,
+                pending_map: Default::default(),
+            }),
+            vectors: (0..WHEEL_SIZE).map(|_| SyncListHead::new()).collect(),
+            expiring: SyncListHead::new(),
+            clock,
+        }
+    }
+
+
+    /**
+     * jiffies - current value of the wheel's clock
+     */
+    pub fn jiffies(&self) -> u64 {
+        (self.clock)()
+    }
+
+
+    /* Enqueue the timer into the bucket and mark it pending in the bitmap */
+    fn enqueue_timer(&self, base: &mut TimerBase, timer: &Timer, idx: usize, bucket_expiry: u64) {
+        self.vectors[idx].list_add(timer.clone());
+        base.pending_map.set_bit(idx);
+        timer.item.idx.store(idx, Ordering::Relaxed);
+
+        /*
+         * Check whether this is the new first expiring timer. The
+         * effective expiry time of the timer is required here
+         * (bucket_expiry) instead of timer->expires.
+         */
+        if time_before(bucket_expiry, base.next_expiry) {
+            base.next_expiry = bucket_expiry;
+            base.timers_pending = true;
+            base.next_expiry_recalc = false;
+        }
+    }
+
+
+    fn internal_add_timer(&self, base: &mut TimerBase, timer: &Timer) {
+        let (idx, bucket_expiry) = calc_wheel_index(timer.item.expires(), base.clk);
+
+        self.enqueue_timer(base, timer, idx, bucket_expiry);
+    }
+
+
+    fn detach_if_pending(&self, base: &mut TimerBase, timer: &Timer) -> bool {
+        let idx = timer.item.idx.load(Ordering::Relaxed);
+
+        if self.vectors[idx].list_del_init(timer).is_some() {
+            if self.vectors[idx].list_empty() {
+                base.pending_map.clear_bit(idx);
+                base.next_expiry_recalc = true;
+            }
+            return true;
+        }
+        /* Collected for expiry, but not called yet */
+        self.expiring.list_del_init(timer).is_some()
+    }
+
+
+    /*
+     * Bring the wheel clock up to the current jiffy before queueing, so
+     * the timeout is hashed relative to now and not to the last time the
+     * wheel ran.
+     */
+    fn forward_timer_base(&self, base: &mut TimerBase) {
+        let jnow = self.jiffies();
+
+        /*
+         * No need to forward if we are close enough below jiffies.
+         * Also while executing timers, base->clk is 1 offset ahead of jiffies
+         * to avoid endless requeuing to current jiffies.
+         */
+        if (jnow.wrapping_sub(base.clk) as i64) < 1 {
+            return;
+        }
+
+        /*
+         * If the next expiry value is > jiffies, then we fast forward to
+         * jiffies otherwise we forward to the next expiry value.
+         */
+        if time_before(jnow, base.next_expiry) {
+            base.clk = jnow;
+        } else if !time_before(base.next_expiry, base.clk) {
+            base.clk = base.next_expiry;
+        }
+    }
+
+
+    /**
+     * add_timer - start a timer
+     * @timer: the timer to be added
+     * @expires: the jiffy the timer expires at
+     *
+     * Return: -EBUSY if @timer is already pending.
+     */
+    pub fn add_timer(&self, timer: &Timer, expires: u64) -> Result<(), i32> {
+        let mut base = self.base.spin_lock();
+
+        if timer_pending(timer) {
+            return Err(-EBUSY);
+        }
+        self.forward_timer_base(&mut base);
+        timer.item.expires.store(expires, Ordering::Relaxed);
+        self.internal_add_timer(&mut base, timer);
+        Ok(())
+    }
+
+
+    /**
+     * mod_timer - modify a timer's timeout
+     * @timer: the timer to be modified
+     * @expires: new absolute timeout in jiffies
+     *
+     * Equivalent to del_timer(timer); add_timer(timer, expires), and the
+     * way to re-arm a timer from its own callback.
+     *
+     * Return: whether @timer was pending.
+     */
+    pub fn mod_timer(&self, timer: &Timer, expires: u64) -> bool {
+        let mut base = self.base.spin_lock();
+
+        /*
+         * This is a common optimization triggered by the networking code -
+         * if the timer is re-modified to have the same timeout or ends up
+         * in the same array bucket then just return:
+         */
+        if timer_pending(timer) && timer.item.expires() == expires {
+            return true;
+        }
+
+        let pending = self.detach_if_pending(&mut base, timer);
+        self.forward_timer_base(&mut base);
+        timer.item.expires.store(expires, Ordering::Relaxed);
+        self.internal_add_timer(&mut base, timer);
+        pending
+    }
+
+
+    /**
+     * del_timer - deactivate a timer
+     *
+     * The callback may still be running on another thread when this
+     * returns.
+     *
+     * Return: whether @timer was pending.
+     */
+    pub fn del_timer(&self, timer: &Timer) -> bool {
+        let mut base = self.base.spin_lock();
+
+        self.detach_if_pending(&mut base, timer)
+    }
+
+
+    /*
+     * Find the distance to the next pending bucket of a level, starting
+     * at @clk and wrapping around, or None if the level is empty.
+     */
+    fn next_pending_bucket(base: &TimerBase, offset: usize, clk: usize) -> Option<u64> {
+        let start = offset + clk;
+        let end = offset + LVL_SIZE;
+
+        let pos = base.pending_map.find_next(end, start, false);
+        if pos < end {
+            return Some((pos - start) as u64);
+        }
+        let pos = base.pending_map.find_next(start, offset, false);
+        (pos < start).then(|| (pos + LVL_SIZE - start) as u64)
+    }
+
+
+    /*
+     * Search the first expiring timer in the various clock levels.
+     */
+    fn next_timer_interrupt(base: &mut TimerBase) -> u64 {
+        let mut next = base.clk.wrapping_add(NEXT_TIMER_MAX_DELTA);
+        let mut clk = base.clk;
+
+        for lvl in 0..LVL_DEPTH {
+            let pos = TimerWheel::next_pending_bucket(base, lvl_offs(lvl), (clk & LVL_MASK) as usize);
+            let lvl_clk = clk & LVL_CLK_MASK;
+
+            if let Some(pos) = pos {
+                let tmp = clk.wrapping_add(pos) << lvl_shift(lvl);
+
+                if time_before(tmp, next) {
+                    next = tmp;
+                }
+
+                /*
+                 * If the next expiration happens before we reach
+                 * the next level, no need to check further.
+                 */
+                if pos <= (LVL_CLK_DIV - lvl_clk) & LVL_CLK_MASK {
+                    break;
+                }
+            }
+
+            /*
+             * Clock for the next level. If the current level clock lower
+             * bits are zero, we look at the next level as is. If not we
+             * need to advance it by one because that's going to be the
+             * next expiring bucket in that level.
+             */
+            let adj = (lvl_clk != 0) as u64;
+            clk >>= LVL_CLK_SHIFT;
+            clk += adj;
+        }
+
+        base.next_expiry_recalc = false;
+        base.timers_pending = next != base.clk.wrapping_add(NEXT_TIMER_MAX_DELTA);
+        next
+    }
+
+
+    /*
+     * Move the expired buckets of every level to @expiring, higher levels
+     * first. A level is only due when all lower level clock bits are 0.
+     */
+    fn collect_expired_timers(&self, base: &mut TimerBase) {
+        let mut clk = base.next_expiry;
+        let mut heads = [0; LVL_DEPTH];
+        let mut levels = 0;
+
+        base.clk = clk;
+        for lvl in 0..LVL_DEPTH {
+            let idx = (clk & LVL_MASK) as usize + lvl * LVL_SIZE;
+
+            if base.pending_map.test_and_clear_bit(idx) {
+                heads[levels] = idx;
+                levels += 1;
+            }
+            /* Is it time to look at the next level? */
+            if clk & LVL_CLK_MASK != 0 {
+                break;
+            }
+            /* Shift clock for the next level granularity */
+            clk >>= LVL_CLK_SHIFT;
+        }
+
+        for &idx in heads[..levels].iter().rev() {
+            self.expiring.list_splice_tail(&self.vectors[idx]);
+        }
+    }
+
+
+    /**
+     * advance - run all timers that expired by @now
+     * @now: the current jiffy, normally read from the wheel's clock
+     *
+     * The callbacks run without the wheel locked, so they may add, modify
+     * or delete timers of this wheel.
+     *
+     * Return: number of callbacks called.
+     */
+    pub fn advance(&self, now: u64) -> usize {
+        let mut base = self.base.spin_lock();
+        let mut fired = 0;
+
+        if base.next_expiry_recalc {
+            base.next_expiry = TimerWheel::next_timer_interrupt(&mut base);
+        }
+
+        while time_after_eq(now, base.clk) && time_after_eq(now, base.next_expiry) {
+            self.collect_expired_timers(&mut base);
+            base.clk = base.clk.wrapping_add(1);
+            base.next_expiry = TimerWheel::next_timer_interrupt(&mut base);
+
+            while let Some(timer) = self.expiring.list_first_entry() {
+                self.expiring.list_del_init(&timer);
+                drop(base);
+                (timer.item.function)(&timer);
+                fired += 1;
+                base = self.base.spin_lock();
+            }
+            if base.next_expiry_recalc {
+                base.next_expiry = TimerWheel::next_timer_interrupt(&mut base);
+            }
+        }
+        fired
+    }
+
+
+    /**
+     * run_timers - run all timers that expired by the current jiffy
+     */
+    pub fn run_timers(&self) -> usize {
+        self.advance(self.jiffies())
+    }
+
+
+    /**
+     * timer_next_expiry - jiffy at which the next timer fires
+     *
+     * Return: %None if no timer is pending.
+     */
+    pub fn timer_next_expiry(&self) -> Option<u64> {
+        let mut base = self.base.spin_lock();
+
+        if base.next_expiry_recalc {
+            base.next_expiry = TimerWheel::next_timer_interrupt(&mut base);
+        }
+        base.timers_pending.then_some(base.next_expiry)
+    }
+}
+
+
+/* Sections for Unit tests */
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    use super::*;
+
+    /* Timer recording the jiffies it fired at */
+    fn recording_timer(fired: &Arc<Mutex<Vec<u64>>>, now: &'static AtomicU64) -> Timer {
+        let fired = fired.clone();
+        timer_setup(move |_| fired.lock().unwrap().push(now.load(Ordering::Relaxed)))
+    }
+
+
+    /* Advance the wheel one jiffy at a time up to @until */
+    fn tick_until(wheel: &TimerWheel, now: &AtomicU64, until: u64) -> usize {
+        let mut fired = 0;
+
+        while now.load(Ordering::Relaxed) < until {
+            let jnow = now.fetch_add(1, Ordering::Relaxed) + 1;
+            fired += wheel.advance(jnow);
+        }
+        fired
+    }
+
+
+    #[test]
+    fn test_timer_levels() {
+        static NOW: AtomicU64 = AtomicU64::new(0);
+        let wheel = TimerWheel::with_clock(|| NOW.load(Ordering::Relaxed));
+
+        assert_eq!(calc_wheel_index(10, 0), (11, 11));
+        assert_eq!(calc_wheel_index(100, 0), (lvl_offs(1) + 13, 104));
+        assert_eq!(calc_wheel_index(5000, 0).0 / LVL_SIZE, 3);
+        assert_eq!(calc_wheel_index(0, 10), (10, 10));
+
+        /* Every timer fires within the granularity of its level, never early */
+        for (delta, lvl) in [(1, 0), (62, 0), (63, 1), (500, 1), (600, 2), (4000, 2), (5000, 3), (40000, 4)] {
+            let fired = Arc::new(Mutex::new(Vec::new()));
+            let timer = recording_timer(&fired, &NOW);
+            /* Step past the jiffy the last timer fired at, the wheel clock is one ahead */
+            let expires = NOW.fetch_add(1, Ordering::Relaxed) + 1 + delta;
+
+            wheel.add_timer(&timer, expires).unwrap();
+            assert!(timer_pending(&timer));
+            assert_eq!(timer.item.idx.load(Ordering::Relaxed) / LVL_SIZE, lvl);
+            assert_eq!(tick_until(&wheel, &NOW, expires + lvl_gran(lvl)), 1);
+            let at = fired.lock().unwrap()[0];
+            assert!(at >= expires && at <= expires + lvl_gran(lvl));
+            assert!(!timer_pending(&timer));
+        }
+        assert_eq!(wheel.timer_next_expiry(), None);
+    }
+
+
+    #[test]
+    fn test_timer_mod_del() {
+        static NOW: AtomicU64 = AtomicU64::new(1000);
+        let wheel = TimerWheel::with_clock(|| NOW.load(Ordering::Relaxed));
+        let fired = Arc::new(Mutex::new(Vec::new()));
+        let timer = recording_timer(&fired, &NOW);
+        let other = recording_timer(&fired, &NOW);
+
+        assert!(!wheel.del_timer(&timer));
+        assert!(!wheel.mod_timer(&timer, 1020));
+        assert_eq!(wheel.add_timer(&timer, 1030), Err(-EBUSY));
+        wheel.add_timer(&other, 1010).unwrap();
+        assert_eq!(wheel.timer_next_expiry(), Some(1011));
+
+        /* Pushing the timer out, then pulling it in again */
+        assert!(wheel.mod_timer(&timer, 1500));
+        assert!(wheel.mod_timer(&timer, 1015));
+        assert_eq!(timer.item.expires(), 1015);
+        assert!(wheel.del_timer(&other));
+        assert!(!timer_pending(&other));
+        assert_eq!(wheel.timer_next_expiry(), Some(1016));
+
+        assert_eq!(tick_until(&wheel, &NOW, 1015), 0);
+        assert_eq!(tick_until(&wheel, &NOW, 1100), 1);
+        assert_eq!(*fired.lock().unwrap(), vec![1016]);
+        assert!(!wheel.del_timer(&timer));
+    }
+
+
+    #[test]
+    fn test_timer_rearm() {
+        static NOW: AtomicU64 = AtomicU64::new(0);
+        let wheel = Arc::new(TimerWheel::with_clock(|| NOW.load(Ordering::Relaxed)));
+        let weak = Arc::downgrade(&wheel);
+        let fired = Arc::new(Mutex::new(Vec::new()));
+
+        /* A periodic timer re-arming itself every 10 jiffies, 5 times */
+        let log = fired.clone();
+        let timer = timer_setup(move |timer| {
+            let mut log = log.lock().unwrap();
+            log.push(NOW.load(Ordering::Relaxed));
+            if log.len() < 5 {
+                weak.upgrade().unwrap().mod_timer(timer, timer.item.expires() + 10);
+            }
+        });
+
+        wheel.add_timer(&timer, 10).unwrap();
+        assert_eq!(tick_until(&wheel, &NOW, 200), 5);
+        assert_eq!(*fired.lock().unwrap(), vec![11, 21, 31, 41, 51]);
+        assert!(!timer_pending(&timer));
+    }
+
+
+    #[test]
+    fn test_timer_idle_forward() {
+        static NOW: AtomicU64 = AtomicU64::new(0);
+        let wheel = TimerWheel::with_clock(|| NOW.load(Ordering::Relaxed));
+        let fired = Arc::new(Mutex::new(Vec::new()));
+        let timer = recording_timer(&fired, &NOW);
+
+        /* The wheel slept for a long time, a short timeout stays short */
+        NOW.store(1_000_000, Ordering::Relaxed);
+        wheel.add_timer(&timer, 1_000_005).unwrap();
+        assert_eq!(timer.item.idx.load(Ordering::Relaxed) / LVL_SIZE, 0);
+        assert_eq!(wheel.timer_next_expiry(), Some(1_000_006));
+
+        /* Far beyond the wheel's range the timeout is capped */
+        let far = recording_timer(&fired, &NOW);
+        wheel.add_timer(&far, NOW.load(Ordering::Relaxed) + 2 * WHEEL_TIMEOUT_CUTOFF).unwrap();
+        assert!(wheel.del_timer(&timer));
+        let next = wheel.timer_next_expiry().unwrap();
+        assert!(next > 1_000_000 + WHEEL_TIMEOUT_MAX && next <= 1_000_000 + WHEEL_TIMEOUT_CUTOFF);
+
+        /* Jumping straight there fires it */
+        NOW.store(next, Ordering::Relaxed);
+        assert_eq!(wheel.run_timers(), 1);
+        assert_eq!(*fired.lock().unwrap(), vec![next]);
+    }
+
+
+    #[test]
+    fn test_timer_many() {
+        static NOW: AtomicU64 = AtomicU64::new(0);
+        const TIMERS: usize = 5000;
+        let wheel = TimerWheel::with_clock(|| NOW.load(Ordering::Relaxed));
+        let mut rng = StdRng::seed_from_u64(44);
+        let fired_at: Arc<Vec<AtomicU64>> = Arc::new((0..TIMERS).map(|_| AtomicU64::new(0)).collect());
+        let mut timers = Vec::new();
+
+        for i in 0..TIMERS {
+            let fired_at = fired_at.clone();
+            let timer = timer_setup(move |_| {
+                assert_eq!(fired_at[i].swap(NOW.load(Ordering::Relaxed), Ordering::Relaxed), 0);
+            });
+            let expires = rng.gen_range(1..50_000);
+            wheel.add_timer(&timer, expires).unwrap();
+            timers.push((timer, expires));
+        }
+
+        /* Cancel a third of them */
+        for (timer, _) in timers.iter().step_by(3) {
+            assert!(wheel.del_timer(timer));
+        }
+
+        /* Advance in uneven steps */
+        let mut fired = 0;
+        while NOW.load(Ordering::Relaxed) < 60_000 {
+            let step = rng.gen_range(1..20);
+            fired += wheel.advance(NOW.fetch_add(step, Ordering::Relaxed) + step);
+        }
+
+        assert_eq!(fired, TIMERS - TIMERS.div_ceil(3));
+        for (i, (_, expires)) in timers.iter().enumerate() {
+            let at = fired_at[i].load(Ordering::Relaxed);
+            if i % 3 == 0 {
+                assert_eq!(at, 0);
+            } else {
+                /* Never early, late by at most the level slack plus one step */
+                assert!(at >= *expires);
+                assert!(at - expires <= expires / 7 + 20);
+            }
+        }
+    }
+}
@@ -0,0 +1,715 @@
+/*
+ * Ordered queue of timers
+ *
+ * Port of lib/timerqueue.c. The timers are kept in a red-black tree
+ * ordered by expiry, and the tree caches its leftmost node, like
+ * rb_root_cached, so the earliest timer is found in O(1) while adding
+ * and deleting are O(log n). Timers with the same expiry are kept in
+ * the order they were added.
+ *
+ * The nodes live in an arena owned by the queue and are linked by u32
+ * indices instead of pointers. Slot 0 is the black sentinel the
+ * red-black tree algorithms of CLRS work with, so a missing child or
+ * parent is simply index 0. Callers refer to a queued timer through a
+ * TimerqueueHandle, which carries the generation of its slot and goes
+ * stale once the timer is deleted.
+ */
+
+const RB_NIL: u32 = 0;
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum RbColor {
+    Red,
+    Black,
+}
+
+
+/*
+ * A slot of the arena. @data is %None for the sentinel and for free
+ * slots, @gen is bumped every time the slot is freed.
+ */
+struct TimerqueueNode<T> {
+    expires: u64,
+    parent: u32,
+    left: u32,
+    right: u32,
+    color: RbColor,
+    gen: u32,
+    data: Option<T>,
+}
+
+
+/**
+ * struct TimerqueueHandle - reference to a queued timer
+ *
+ * Only valid for the queue that returned it, and only until the timer
+ * is deleted from it.
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct TimerqueueHandle {
+    idx: u32,
+    gen: u32,
+}
+
+
+/**
+ * struct TimerqueueHead - queue of timers ordered by expiry
+ * @nodes: the arena, slot 0 being the sentinel
+ * @free: free slots of @nodes
+ * @root: root of the tree
+ * @leftmost: the earliest timer, cached
+ * @len: number of queued timers
+ */
+pub struct TimerqueueHead<T> {
+    nodes: Vec<TimerqueueNode<T>>,
+    free: Vec<u32>,
+    root: u32,
+    leftmost: u32,
+    len: usize,
+}
+
+
+impl<T> Default for TimerqueueHead<T> {
+    fn default() -> Self {
+        TimerqueueHead::timerqueue_init()
+    }
+}
+
+#[allow(dead_code)]
+impl<T> TimerqueueHead<T> {
+    /**
+     * timerqueue_init - create an empty timer queue
+     */
+    pub fn timerqueue_init() -> Self {
+        TimerqueueHead {
+            nodes: vec![TimerqueueNode {
+                expires: 0,
+                parent: RB_NIL,
+                left: RB_NIL,
+                right: RB_NIL,
+                color: RbColor::Black,
+                gen: 0,
+                data: None,
+            }],
+            free: Vec::new(),
+            root: RB_NIL,
+            leftmost: RB_NIL,
+            len: 0,
+        }
+    }
+
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+
+    fn node(&self, idx: u32) -> &TimerqueueNode<T> {
+        &self.nodes[idx as usize]
+    }
+
+
+    fn node_mut(&mut self, idx: u32) -> &mut TimerqueueNode<T> {
+        &mut self.nodes[idx as usize]
+    }
+
+
+    /* Slot of a handle that still refers to a queued timer */
+    fn lookup(&self, handle: TimerqueueHandle) -> Option<u32> {
+        let node = self.nodes.get(handle.idx as usize)?;
+
+        (handle.idx != RB_NIL && node.gen == handle.gen && node.data.is_some()).then_some(handle.idx)
+    }
+
+
+    fn handle(&self, idx: u32) -> Option<TimerqueueHandle> {
+        (idx != RB_NIL).then(|| TimerqueueHandle {
+            idx,
+            gen: self.node(idx).gen,
+        })
+    }
+
+
+    fn is_red(&self, idx: u32) -> bool {
+        self.node(idx).color == RbColor::Red
+    }
+
+
+    fn rb_rotate_left(&mut self, x: u32) {
+        let y = self.node(x).right;
+        let y_left = self.node(y).left;
+        let parent = self.node(x).parent;
+
+        self.node_mut(x).right = y_left;
+        if y_left != RB_NIL {
+            self.node_mut(y_left).parent = x;
+        }
+        self.node_mut(y).parent = parent;
+        if parent == RB_NIL {
+            self.root = y;
+        } else if x == self.node(parent).left {
+            self.node_mut(parent).left = y;
+        } else {
+            self.node_mut(parent).right = y;
+        }
+        self.node_mut(y).left = x;
+        self.node_mut(x).parent = y;
+    }
+
+
+    fn rb_rotate_right(&mut self, x: u32) {
+        let y = self.node(x).left;
+        let y_right = self.node(y).right;
+        let parent = self.node(x).parent;
+
+        self.node_mut(x).left = y_right;
+        if y_right != RB_NIL {
+            self.node_mut(y_right).parent = x;
+        }
+        self.node_mut(y).parent = parent;
+        if parent == RB_NIL {
+            self.root = y;
+        } else if x == self.node(parent).right {
+            self.node_mut(parent).right = y;
+        } else {
+            self.node_mut(parent).left = y;
+        }
+        self.node_mut(y).right = x;
+        self.node_mut(x).parent = y;
+    }
+
+
+    /* Restore the red-black properties after linking the red node @z */
+    fn rb_insert_color(&mut self, mut z: u32) {
+        while self.is_red(self.node(z).parent) {
+            let parent = self.node(z).parent;
+            let gparent = self.node(parent).parent;
+
+            if parent == self.node(gparent).left {
+                let uncle = self.node(gparent).right;
+                if self.is_red(uncle) {
+                    self.node_mut(parent).color = RbColor::Black;
+                    self.node_mut(uncle).color = RbColor::Black;
+                    self.node_mut(gparent).color = RbColor::Red;
+                    z = gparent;
+                    continue;
+                }
+                if z == self.node(parent).right {
+                    z = parent;
+                    self.rb_rotate_left(z);
+                }
+                let parent = self.node(z).parent;
+                self.node_mut(parent).color = RbColor::Black;
+                self.node_mut(gparent).color = RbColor::Red;
+                self.rb_rotate_right(gparent);
+            } else {
+                let uncle = self.node(gparent).left;
+                if self.is_red(uncle) {
+                    self.node_mut(parent).color = RbColor::Black;
+                    self.node_mut(uncle).color = RbColor::Black;
+                    self.node_mut(gparent).color = RbColor::Red;
+                    z = gparent;
+                    continue;
+                }
+                if z == self.node(parent).left {
+                    z = parent;
+                    self.rb_rotate_right(z);
+                }
+                let parent = self.node(z).parent;
+                self.node_mut(parent).color = RbColor::Black;
+                self.node_mut(gparent).color = RbColor::Red;
+                self.rb_rotate_left(gparent);
+            }
+        }
+        let root = self.root;
+        self.node_mut(root).color = RbColor::Black;
+    }
+
+
+    /* Replace the subtree at @u by the one at @v, which may be the sentinel */
+    fn rb_transplant(&mut self, u: u32, v: u32) {
+        let parent = self.node(u).parent;
+
+        if parent == RB_NIL {
+            self.root = v;
+        } else if u == self.node(parent).left {
+            self.node_mut(parent).left = v;
+        } else {
+            self.node_mut(parent).right = v;
+        }
+        self.node_mut(v).parent = parent;
+    }
+
+
+    fn rb_first(&self, mut idx: u32) -> u32 {
+        while self.node(idx).left != RB_NIL {
+            idx = self.node(idx).left;
+        }
+        idx
+    }
+
+
+    fn rb_next(&self, mut idx: u32) -> u32 {
+        if self.node(idx).right != RB_NIL {
+            return self.rb_first(self.node(idx).right);
+        }
+        let mut parent = self.node(idx).parent;
+        while parent != RB_NIL && idx == self.node(parent).right {
+            idx = parent;
+            parent = self.node(idx).parent;
+        }
+        parent
+    }
+
+
+    /* Restore the red-black properties after a black node left above @x */
+    fn rb_erase_color(&mut self, mut x: u32) {
+        while x != self.root && !self.is_red(x) {
+            let parent = self.node(x).parent;
+
+            if x == self.node(parent).left {
+                let mut w = self.node(parent).right;
+                if self.is_red(w) {
+                    self.node_mut(w).color = RbColor::Black;
+                    self.node_mut(parent).color = RbColor::Red;
+                    self.rb_rotate_left(parent);
+                    w = self.node(parent).right;
+                }
+                if !self.is_red(self.node(w).left) && !self.is_red(self.node(w).right) {
+                    self.node_mut(w).color = RbColor::Red;
+                    x = parent;
+                    continue;
+                }
+                if !self.is_red(self.node(w).right) {
+                    let w_left = self.node(w).left;
+                    self.node_mut(w_left).color = RbColor::Black;
+                    self.node_mut(w).color = RbColor::Red;
+                    self.rb_rotate_right(w);
+                    w = self.node(parent).right;
+                }
+                self.node_mut(w).color = self.node(parent).color;
+                self.node_mut(parent).color = RbColor::Black;
+                let w_right = self.node(w).right;
+                self.node_mut(w_right).color = RbColor::Black;
+                self.rb_rotate_left(parent);
+            } else {
+                let mut w = self.node(parent).left;
+                if self.is_red(w) {
+                    self.node_mut(w).color = RbColor::Black;
+                    self.node_mut(parent).color = RbColor::Red;
+                    self.rb_rotate_right(parent);
+                    w = self.node(parent).left;
+                }
+                if !self.is_red(self.node(w).left) && !self.is_red(self.node(w).right) {
+                    self.node_mut(w).color = RbColor::Red;
+                    x = parent;
+                    continue;
+                }
+                if !self.is_red(self.node(w).left) {
+                    let w_right = self.node(w).right;
+                    self.node_mut(w_right).color = RbColor::Black;
+                    self.node_mut(w).color = RbColor::Red;
+                    self.rb_rotate_left(w);
+                    w = self.node(parent).left;
+                }
+                self.node_mut(w).color = self.node(parent).color;
+                self.node_mut(parent).color = RbColor::Black;
+                let w_left = self.node(w).left;
+                self.node_mut(w_left).color = RbColor::Black;
+                self.rb_rotate_right(parent);
+            }
+            x = self.root;
+        }
+        self.node_mut(x).color = RbColor::Black;
+    }
+
+
+    /* Unlink @z from the tree, updating the cached leftmost node */
+    fn rb_erase_cached(&mut self, z: u32) {
+        if self.leftmost == z {
+            self.leftmost = self.rb_next(z);
+        }
+
+        let (left, right) = (self.node(z).left, self.node(z).right);
+        let mut orig_color = self.node(z).color;
+        let x;
+
+        if left == RB_NIL {
+            x = right;
+            self.rb_transplant(z, right);
+        } else if right == RB_NIL {
+            x = left;
+            self.rb_transplant(z, left);
+        } else {
+            let y = self.rb_first(right);
+            orig_color = self.node(y).color;
+            x = self.node(y).right;
+            if self.node(y).parent == z {
+                self.node_mut(x).parent = y;
+            } else {
+                self.rb_transplant(y, x);
+                self.node_mut(y).right = right;
+                self.node_mut(right).parent = y;
+            }
+            self.rb_transplant(z, y);
+            self.node_mut(y).left = left;
+            self.node_mut(left).parent = y;
+            self.node_mut(y).color = self.node(z).color;
+        }
+        if orig_color == RbColor::Black {
+            self.rb_erase_color(x);
+        }
+        /* The sentinel may have been given a parent on the way */
+        self.node_mut(RB_NIL).parent = RB_NIL;
+    }
+
+
+    /**
+     * timerqueue_add - Adds timer to timerqueue.
+     * @expires: expiry of the timer
+     * @data: payload of the timer
+     *
+     * Adds the timer into the timerqueue, a timer expiring at the same
+     * time as queued ones goes after them.
+     *
+     * Return: the handle of the timer, and whether it is the new
+     * earliest timer.
+     */
+    pub fn timerqueue_add(&mut self, expires: u64, data: T) -> (TimerqueueHandle, bool) {
+        let z = match self.free.pop() {
+            Some(idx) => idx,
+            None => {
+                self.nodes.push(TimerqueueNode {
+                    expires: 0,
+                    parent: RB_NIL,
+                    left: RB_NIL,
+                    right: RB_NIL,
+                    color: RbColor::Red,
+                    gen: 0,
+                    data: None,
+                });
+                (self.nodes.len() - 1) as u32
+            }
+        };
+
+        let mut parent = RB_NIL;
+        let mut link = self.root;
+        let mut leftmost = true;
+        while link != RB_NIL {
+            parent = link;
+            if expires < self.node(link).expires {
+                link = self.node(link).left;
+            } else {
+                link = self.node(link).right;
+                leftmost = false;
+            }
+        }
+
+        let node = self.node_mut(z);
+        node.expires = expires;
+        node.parent = parent;
+        node.left = RB_NIL;
+        node.right = RB_NIL;
+        node.color = RbColor::Red;
+        node.data = Some(data);
+        if parent == RB_NIL {
+            self.root = z;
+        } else if expires < self.node(parent).expires {
+            self.node_mut(parent).left = z;
+        } else {
+            self.node_mut(parent).right = z;
+        }
+        if leftmost {
+            self.leftmost = z;
+        }
+        self.rb_insert_color(z);
+        self.len += 1;
+
+        (self.handle(z).unwrap(), leftmost)
+    }
+
+
+    /**
+     * timerqueue_del - Removes a timer from the timerqueue.
+     * @handle: timer to be removed
+     *
+     * Return: the expiry and payload of the timer, or %None if @handle
+     * is stale.
+     */
+    pub fn timerqueue_del(&mut self, handle: TimerqueueHandle) -> Option<(u64, T)> {
+        let z = self.lookup(handle)?;
+
+        self.rb_erase_cached(z);
+        self.len -= 1;
+
+        let node = self.node_mut(z);
+        node.gen = node.gen.wrapping_add(1);
+        let data = node.data.take()?;
+        let expires = node.expires;
+        self.free.push(z);
+        Some((expires, data))
+    }
+
+
+    /**
+     * timerqueue_getnext - Returns the timer with the earliest expiration time
+     *
+     * Return: %None if the queue is empty.
+     */
+    pub fn timerqueue_getnext(&self) -> Option<TimerqueueHandle> {
+        self.handle(self.leftmost)
+    }
+
+
+    /**
+     * timerqueue_iterate_next - Returns the timer after the provided timer
+     * @handle: the timer to start from
+     *
+     * Together with timerqueue_getnext() this walks the queue in expiry
+     * order.
+     */
+    pub fn timerqueue_iterate_next(&self, handle: TimerqueueHandle) -> Option<TimerqueueHandle> {
+        let idx = self.lookup(handle)?;
+
+        self.handle(self.rb_next(idx))
+    }
+
+
+    /**
+     * timerqueue_node_queued - whether @handle refers to a queued timer
+     */
+    pub fn timerqueue_node_queued(&self, handle: TimerqueueHandle) -> bool {
+        self.lookup(handle).is_some()
+    }
+
+
+    /**
+     * timerqueue_expires - expiry of a queued timer
+     */
+    pub fn timerqueue_expires(&self, handle: TimerqueueHandle) -> Option<u64> {
+        self.lookup(handle).map(|idx| self.node(idx).expires)
+    }
+
+
+    pub fn get(&self, handle: TimerqueueHandle) -> Option<&T> {
+        let idx = self.lookup(handle)?;
+        self.node(idx).data.as_ref()
+    }
+
+
+    pub fn get_mut(&mut self, handle: TimerqueueHandle) -> Option<&mut T> {
+        let idx = self.lookup(handle)?;
+        self.node_mut(idx).data.as_mut()
+    }
+
+
+    /**
+     * timerqueue_expire - remove the earliest timer if it expired by @now
+     * @now: current time, in the unit of the expiries
+     *
+     * Called in a loop, this runs the expired timers in expiry order.
+     */
+    pub fn timerqueue_expire(&mut self, now: u64) -> Option<(u64, T)> {
+        let next = self.timerqueue_getnext()?;
+
+        if self.node(next.idx).expires > now {
+            return None;
+        }
+        self.timerqueue_del(next)
+    }
+}
+
+
+/* Sections for Unit tests */
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    use super::*;
+
+    /* Check the tree structure, returning the black height of @idx */
+    fn check_subtree<T>(head: &TimerqueueHead<T>, idx: u32, count: &mut usize) -> usize {
+        if idx == RB_NIL {
+            return 1;
+        }
+        let node = head.node(idx);
+
+        *count += 1;
+        assert!(node.data.is_some());
+        for child in [node.left, node.right] {
+            if child != RB_NIL {
+                assert_eq!(head.node(child).parent, idx);
+                assert!(!(head.is_red(idx) && head.is_red(child)), "red node with red child");
+            }
+        }
+        if node.left != RB_NIL {
+            assert!(head.node(node.left).expires <= node.expires);
+        }
+        if node.right != RB_NIL {
+            assert!(head.node(node.right).expires >= node.expires);
+        }
+
+        let left = check_subtree(head, node.left, count);
+        let right = check_subtree(head, node.right, count);
+        assert_eq!(left, right, "black height mismatch");
+        left + !head.is_red(idx) as usize
+    }
+
+
+    fn check_tree<T>(head: &TimerqueueHead<T>) {
+        let mut count = 0;
+
+        assert!(!head.is_red(head.root));
+        assert_eq!(head.node(head.root).parent, RB_NIL);
+        check_subtree(head, head.root, &mut count);
+        assert_eq!(count, head.len());
+        let first = if head.root == RB_NIL { RB_NIL } else { head.rb_first(head.root) };
+        assert_eq!(head.leftmost, first);
+    }
+
+
+    fn expiries<T>(head: &TimerqueueHead<T>) -> Vec<u64> {
+        let mut expiries = Vec::new();
+        let mut pos = head.timerqueue_getnext();
+
+        while let Some(handle) = pos {
+            expiries.push(head.timerqueue_expires(handle).unwrap());
+            pos = head.timerqueue_iterate_next(handle);
+        }
+        expiries
+    }
+
+
+    #[test]
+    fn test_timerqueue_add_del() {
+        let mut head = TimerqueueHead::timerqueue_init();
+
+        assert!(head.timerqueue_getnext().is_none());
+        let (h50, first) = head.timerqueue_add(50, "a");
+        assert!(first);
+        let (h70, first) = head.timerqueue_add(70, "b");
+        assert!(!first);
+        let (h10, first) = head.timerqueue_add(10, "c");
+        assert!(first);
+        let (h30, _) = head.timerqueue_add(30, "d");
+
+        assert_eq!(head.timerqueue_getnext(), Some(h10));
+        assert_eq!(expiries(&head), vec![10, 30, 50, 70]);
+        assert_eq!(head.get(h30), Some(&"d"));
+
+        assert_eq!(head.timerqueue_del(h10), Some((10, "c")));
+        assert_eq!(head.timerqueue_getnext(), Some(h30));
+        assert_eq!(head.timerqueue_del(h10), None);
+        assert!(!head.timerqueue_node_queued(h10));
+        *head.get_mut(h50).unwrap() = "e";
+        assert_eq!(head.timerqueue_del(h70), Some((70, "b")));
+        assert_eq!(head.timerqueue_del(h50), Some((50, "e")));
+        assert_eq!(expiries(&head), vec![30]);
+        check_tree(&head);
+    }
+
+
+    #[test]
+    fn test_timerqueue_stale_handle() {
+        let mut head = TimerqueueHead::timerqueue_init();
+        let (old, _) = head.timerqueue_add(5, 1);
+
+        head.timerqueue_del(old);
+        /* The slot is reused, the old handle must not reach the new timer */
+        let (new, _) = head.timerqueue_add(5, 2);
+        assert_eq!(new.idx, old.idx);
+        assert_ne!(new, old);
+        assert_eq!(head.get(old), None);
+        assert_eq!(head.timerqueue_iterate_next(old), None);
+        assert_eq!(head.get(new), Some(&2));
+    }
+
+
+    #[test]
+    fn test_timerqueue_equal_expiry() {
+        let mut head = TimerqueueHead::timerqueue_init();
+
+        for i in 0..10 {
+            head.timerqueue_add(100 + (i % 2), i);
+        }
+        let mut order = Vec::new();
+        while let Some((_, i)) = head.timerqueue_expire(u64::MAX) {
+            order.push(i);
+        }
+        assert_eq!(order, vec![0, 2, 4, 6, 8, 1, 3, 5, 7, 9]);
+    }
+
+
+    #[test]
+    fn test_timerqueue_mock_clock() {
+        static NOW: AtomicU64 = AtomicU64::new(0);
+        let clock = || NOW.load(Ordering::Relaxed);
+        let mut head = TimerqueueHead::timerqueue_init();
+        let mut fired = Vec::new();
+
+        for (i, delay) in [300, 100, 200, 100, 500].into_iter().enumerate() {
+            head.timerqueue_add(clock() + delay, i);
+        }
+
+        for step in [50, 100, 150, 400] {
+            NOW.store(step, Ordering::Relaxed);
+            while let Some((expires, i)) = head.timerqueue_expire(clock()) {
+                assert!(expires <= clock());
+                fired.push((clock(), i));
+            }
+        }
+        assert_eq!(fired, vec![(100, 1), (100, 3), (400, 2), (400, 0)]);
+        assert_eq!(head.len(), 1);
+        assert_eq!(head.timerqueue_expires(head.timerqueue_getnext().unwrap()), Some(500));
+    }
+
+
+    #[test]
+    fn test_timerqueue_random() {
+        let mut rng = StdRng::seed_from_u64(45);
+        let mut head = TimerqueueHead::timerqueue_init();
+        /* Reference keyed by (expiry, insertion sequence) */
+        let mut reference = BTreeMap::new();
+        let mut handles = Vec::new();
+
+        for seq in 0..4000u32 {
+            if handles.is_empty() || rng.gen_bool(0.6) {
+                let expires = rng.gen_range(0..500);
+                let (handle, first) = head.timerqueue_add(expires, seq);
+                assert_eq!(first, reference.keys().next().is_none_or(|&(e, _)| expires < e));
+                reference.insert((expires, seq), handle);
+                handles.push((expires, seq, handle));
+            } else {
+                let (expires, seq, handle) = handles.swap_remove(rng.gen_range(0..handles.len()));
+                assert_eq!(head.timerqueue_del(handle), Some((expires, seq)));
+                reference.remove(&(expires, seq));
+            }
+
+            if seq % 100 == 0 {
+                check_tree(&head);
+            }
+            assert_eq!(
+                head.timerqueue_getnext(),
+                reference.values().next().copied()
+            );
+        }
+
+        check_tree(&head);
+        let mut pos = head.timerqueue_getnext();
+        for &handle in reference.values() {
+            assert_eq!(pos, Some(handle));
+            pos = head.timerqueue_iterate_next(handle);
+        }
+        assert_eq!(pos, None);
+    }
+}
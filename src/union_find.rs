@@ -0,0 +1,369 @@
+use std::cell::{Cell, RefCell};
+use std::cmp::Ordering;
+use std::rc::Rc;
+
+/*
+ * Union-find (disjoint-set) data structure
+ *
+ * Port of lib/union_find.c. Every set is a tree whose root represents the
+ * set. uf_find() halves the path it walks, every visited node is hooked
+ * to its grandparent, and uf_union() hangs the lower ranked tree below
+ * the higher ranked one, which keeps both operations close to O(1)
+ * amortized.
+ */
+
+
+/**
+ * struct UfNode - union-find node
+ * @parent: parent node, %None for the root of a set
+ * @rank: upper bound of the height of the tree below this node
+ * @size: number of nodes in the set, only maintained at the root
+ */
+pub struct UfNode {
+    parent: RefCell<Option<Rc<UfNode>>>,
+    rank: Cell<u32>,
+    size: Cell<usize>,
+}
+
+#[allow(dead_code)]
+impl UfNode {
+    /**
+     * uf_node_init - create a node forming a set of its own
+     */
+    pub fn uf_node_init() -> Rc<UfNode> {
+        Rc::new(UfNode {
+            parent: RefCell::new(None),
+            rank: Cell::new(0),
+            size: Cell::new(1),
+        })
+    }
+
+
+    pub fn rank(&self) -> u32 {
+        self.rank.get()
+    }
+}
+
+
+/**
+ * uf_find - Find the root of a node and perform path compression
+ * @node: Node whose root is to be found
+ */
+pub fn uf_find(node: &Rc<UfNode>) -> Rc<UfNode> {
+    let mut node = node.clone();
+
+    loop {
+        let parent = node.parent.borrow().clone();
+        let Some(parent) = parent else {
+            return node;
+        };
+        if let Some(grandparent) = parent.parent.borrow().clone() {
+            *node.parent.borrow_mut() = Some(grandparent);
+        }
+        node = parent;
+    }
+}
+
+
+/**
+ * uf_union - Merge two sets, using union by rank
+ * @node1: the first node
+ * @node2: the second node
+ *
+ * Return: false if both nodes were already in the same set.
+ */
+pub fn uf_union(node1: &Rc<UfNode>, node2: &Rc<UfNode>) -> bool {
+    let root1 = uf_find(node1);
+    let root2 = uf_find(node2);
+
+    if Rc::ptr_eq(&root1, &root2) {
+        return false;
+    }
+
+    let (root, child) = match root1.rank.get().cmp(&root2.rank.get()) {
+        Ordering::Less => (root2, root1),
+        Ordering::Greater => (root1, root2),
+        Ordering::Equal => {
+            root1.rank.set(root1.rank.get() + 1);
+            (root1, root2)
+        }
+    };
+    root.size.set(root.size.get() + child.size.get());
+    *child.parent.borrow_mut() = Some(root);
+    true
+}
+
+
+/**
+ * uf_same - Check whether two nodes belong to the same set
+ */
+pub fn uf_same(node1: &Rc<UfNode>, node2: &Rc<UfNode>) -> bool {
+    Rc::ptr_eq(&uf_find(node1), &uf_find(node2))
+}
+
+
+/**
+ * uf_size - Number of nodes in the set of @node
+ */
+pub fn uf_size(node: &Rc<UfNode>) -> usize {
+    uf_find(node).size.get()
+}
+
+
+/**
+ * struct DisjointSet - owned union-find over the indices 0..len
+ * @parent: parent index of every element, the element itself for a root
+ * @rank: rank of every element
+ * @size: set size of every root
+ * @count: number of disjoint sets
+ *
+ * Same algorithms as &struct UfNode, with the nodes stored in vectors so
+ * a whole partition lives in one allocation and can be sent across
+ * threads.
+ */
+pub struct DisjointSet {
+    parent: Vec<usize>,
+    rank: Vec<u32>,
+    size: Vec<usize>,
+    count: usize,
+}
+
+#[allow(dead_code)]
+impl DisjointSet {
+    /**
+     * new - create @len singleton sets
+     */
+    pub fn new(len: usize) -> Self {
+        DisjointSet {
+            parent: (0..len).collect(),
+            rank: vec![0; len],
+            size: vec![1; len],
+            count: len,
+        }
+    }
+
+
+    pub fn len(&self) -> usize {
+        self.parent.len()
+    }
+
+
+    pub fn is_empty(&self) -> bool {
+        self.parent.is_empty()
+    }
+
+
+    /**
+     * count - number of disjoint sets
+     */
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+
+    /**
+     * push - add a new singleton set
+     *
+     * Return: the index of the new element.
+     */
+    pub fn push(&mut self) -> usize {
+        let idx = self.parent.len();
+
+        self.parent.push(idx);
+        self.rank.push(0);
+        self.size.push(1);
+        self.count += 1;
+        idx
+    }
+
+
+    /**
+     * find - index of the root of the set holding @x, halving the path
+     */
+    pub fn find(&mut self, mut x: usize) -> usize {
+        while self.parent[x] != x {
+            let parent = self.parent[x];
+            self.parent[x] = self.parent[parent];
+            x = parent;
+        }
+        x
+    }
+
+
+    /**
+     * union - merge the sets holding @x and @y
+     *
+     * Return: false if they were already the same set.
+     */
+    pub fn union(&mut self, x: usize, y: usize) -> bool {
+        let root1 = self.find(x);
+        let root2 = self.find(y);
+
+        if root1 == root2 {
+            return false;
+        }
+
+        let (root, child) = match self.rank[root1].cmp(&self.rank[root2]) {
+            Ordering::Less => (root2, root1),
+            Ordering::Greater => (root1, root2),
+            Ordering::Equal => {
+                self.rank[root1] += 1;
+                (root1, root2)
+            }
+        };
+        self.parent[child] = root;
+        self.size[root] += self.size[child];
+        self.count -= 1;
+        true
+    }
+
+
+    pub fn same_set(&mut self, x: usize, y: usize) -> bool {
+        self.find(x) == self.find(y)
+    }
+
+
+    /**
+     * set_size - number of elements in the set holding @x
+     */
+    pub fn set_size(&mut self, x: usize) -> usize {
+        let root = self.find(x);
+        self.size[root]
+    }
+
+
+    /**
+     * groups - the elements of every set
+     *
+     * Sets come in the order of their smallest element, and the elements
+     * of a set are sorted.
+     */
+    pub fn groups(&mut self) -> Vec<Vec<usize>> {
+        let mut slot = vec![usize::MAX; self.len()];
+        let mut groups: Vec<Vec<usize>> = Vec::with_capacity(self.count);
+
+        for x in 0..self.len() {
+            let root = self.find(x);
+            if slot[root] == usize::MAX {
+                slot[root] = groups.len();
+                groups.push(Vec::with_capacity(self.size[root]));
+            }
+            groups[slot[root]].push(x);
+        }
+        groups
+    }
+}
+
+
+/* Sections for Unit tests */
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    use super::*;
+
+    #[test]
+    fn test_uf_union_find() {
+        let nodes: Vec<_> = (0..8).map(|_| UfNode::uf_node_init()).collect();
+
+        for node in nodes.iter() {
+            assert!(Rc::ptr_eq(&uf_find(node), node));
+            assert_eq!(uf_size(node), 1);
+        }
+
+        assert!(uf_union(&nodes[0], &nodes[1]));
+        assert!(uf_union(&nodes[2], &nodes[3]));
+        assert!(uf_union(&nodes[1], &nodes[3]));
+        assert!(!uf_union(&nodes[0], &nodes[2]));
+        assert!(uf_union(&nodes[5], &nodes[6]));
+
+        assert!(uf_same(&nodes[0], &nodes[3]));
+        assert!(!uf_same(&nodes[0], &nodes[5]));
+        assert_eq!(uf_size(&nodes[2]), 4);
+        assert_eq!(uf_size(&nodes[6]), 2);
+        assert_eq!(uf_size(&nodes[7]), 1);
+    }
+
+
+    #[test]
+    fn test_uf_rank() {
+        let a = UfNode::uf_node_init();
+        let b = UfNode::uf_node_init();
+        let c = UfNode::uf_node_init();
+
+        /* Equal ranks, the first root wins and grows */
+        uf_union(&a, &b);
+        assert!(Rc::ptr_eq(&uf_find(&b), &a));
+        assert_eq!(a.rank(), 1);
+
+        /* Lower rank goes below higher rank, whatever the order */
+        uf_union(&c, &b);
+        assert!(Rc::ptr_eq(&uf_find(&c), &a));
+        assert_eq!(a.rank(), 1);
+    }
+
+
+    #[test]
+    fn test_uf_path_compression() {
+        let nodes: Vec<_> = (0..4).map(|_| UfNode::uf_node_init()).collect();
+
+        /* Build the chain 0 -> 1 -> 2 -> 3 by hand */
+        for i in 0..3 {
+            *nodes[i].parent.borrow_mut() = Some(nodes[i + 1].clone());
+        }
+        assert!(Rc::ptr_eq(&uf_find(&nodes[0]), &nodes[3]));
+
+        /* Every other node was hooked to its grandparent */
+        assert!(Rc::ptr_eq(nodes[0].parent.borrow().as_ref().unwrap(), &nodes[2]));
+        assert!(Rc::ptr_eq(nodes[2].parent.borrow().as_ref().unwrap(), &nodes[3]));
+    }
+
+
+    #[test]
+    fn test_disjoint_set() {
+        let mut set = DisjointSet::new(6);
+
+        assert_eq!(set.count(), 6);
+        assert!(set.union(0, 1));
+        assert!(set.union(4, 5));
+        assert!(set.union(1, 5));
+        assert!(!set.union(0, 4));
+        assert_eq!(set.count(), 3);
+        assert_eq!(set.set_size(5), 4);
+        assert!(set.same_set(0, 4));
+        assert!(!set.same_set(2, 3));
+
+        let idx = set.push();
+        assert_eq!(idx, 6);
+        set.union(idx, 3);
+        assert_eq!(set.groups(), vec![vec![0, 1, 4, 5], vec![2], vec![3, 6]]);
+    }
+
+
+    #[test]
+    fn test_disjoint_set_components() {
+        const N: usize = 500;
+        let mut rng = StdRng::seed_from_u64(39);
+        let mut set = DisjointSet::new(N);
+        /* Naive labelling as reference */
+        let mut label: Vec<usize> = (0..N).collect();
+
+        for _ in 0..400 {
+            let (x, y) = (rng.gen_range(0..N), rng.gen_range(0..N));
+            let (from, to) = (label[x], label[y]);
+            assert_eq!(set.union(x, y), from != to);
+            label.iter_mut().filter(|l| **l == from).for_each(|l| *l = to);
+        }
+
+        let mut labels = label.clone();
+        labels.sort();
+        labels.dedup();
+        assert_eq!(set.count(), labels.len());
+        for x in 0..N {
+            assert_eq!(set.set_size(x), label.iter().filter(|&&l| l == label[x]).count());
+            assert_eq!(set.same_set(x, 0), label[x] == label[0]);
+        }
+        assert_eq!(set.groups().iter().map(Vec::len).sum::<usize>(), N);
+    }
+}